@@ -2,11 +2,15 @@ extern crate criterion;
 use criterion::{criterion_group, criterion_main, Bencher, BenchmarkId, Criterion};
 extern crate camillalib;
 
+use camillalib::audiodevice::AudioChunk;
 use camillalib::biquad::{Biquad, BiquadCoefficients};
+use camillalib::config::Configuration;
 use camillalib::diffeq::DiffEq;
 use camillalib::fftconv::FftConv;
-use camillalib::filters::Filter;
+use camillalib::filters::{Filter, Pipeline};
 use camillalib::PrcFmt;
+use camillalib::ProcessingParameters;
+use std::sync::{Arc, RwLock};
 
 /// Bench a single convolution
 fn run_conv(b: &mut Bencher, len: usize, chunksize: usize) {
@@ -18,17 +22,42 @@ fn run_conv(b: &mut Bencher, len: usize, chunksize: usize) {
     b.iter(|| conv.process_waveform(&mut waveform));
 }
 
+/// Bench a single direct convolution, for comparison
+fn run_direct_conv(b: &mut Bencher, len: usize, chunksize: usize) {
+    let filter = vec![0.0 as PrcFmt; len];
+    let mut history = vec![0.0 as PrcFmt; len + chunksize];
+    let mut waveform = vec![0.0 as PrcFmt; chunksize];
+
+    b.iter(|| {
+        history.copy_within(chunksize.., 0);
+        history[len..].copy_from_slice(&waveform);
+        for (n, item) in waveform.iter_mut().enumerate() {
+            *item = filter
+                .iter()
+                .zip(history[n + 1..n + 1 + len].iter().rev())
+                .map(|(f, x)| f * x)
+                .sum();
+        }
+    });
+}
+
 /// Run all convolution benches
 fn bench_conv(c: &mut Criterion) {
     let mut group = c.benchmark_group("Conv");
     let chunksize = 1024;
-    for filterlen in [chunksize, 4 * chunksize, 16 * chunksize].iter() {
+    for filterlen in [chunksize, 4 * chunksize, 16 * chunksize, 32 * chunksize].iter() {
         group.bench_with_input(
             BenchmarkId::new("FftConv", filterlen),
             filterlen,
             |b, filterlen| run_conv(b, *filterlen, chunksize),
         );
     }
+    let filterlen = 32 * chunksize;
+    group.bench_with_input(
+        BenchmarkId::new("DirectConv", filterlen),
+        &filterlen,
+        |b, filterlen| run_direct_conv(b, *filterlen, chunksize),
+    );
     group.finish();
 }
 
@@ -61,6 +90,64 @@ fn bench_diffeq(c: &mut Criterion) {
     c.bench_function("DiffEq", |b| b.iter(|| de.process_waveform(&mut waveform)));
 }
 
-criterion_group!(benches, bench_conv, bench_biquad, bench_diffeq);
+/// Bench a pipeline with a long FIR filter on each of 8 channels
+fn run_pipeline(b: &mut Bencher, worker_threads: usize) {
+    let chunksize = 1024;
+    let yaml = format!(
+        "
+devices:
+  samplerate: 48000
+  chunksize: {}
+  worker_threads: {}
+  capture:
+    type: Stdin
+    channels: 8
+    format: S32LE
+  playback:
+    type: Stdout
+    channels: 8
+    format: S32LE
+filters:
+  fir:
+    type: Conv
+    parameters:
+      type: Values
+      values: [1.0]
+      length: 32768
+pipeline:
+  - type: Filter
+    channels: all
+    names: [fir]
+",
+        chunksize, worker_threads
+    );
+    let conf: Configuration = serde_yaml::from_str(&yaml).unwrap();
+    let status = Arc::new(RwLock::new(ProcessingParameters::default()));
+    let mut pipeline = Pipeline::from_config(conf, status);
+    let waveforms = vec![vec![0.0 as PrcFmt; chunksize]; 8];
+    let mut chunk = Some(AudioChunk::new(waveforms, 0.0, 0.0, chunksize, chunksize));
+    b.iter(|| chunk = Some(pipeline.process_chunk(chunk.take().unwrap())));
+}
+
+/// Compare serial and parallel processing of an 8-channel pipeline
+fn bench_pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Pipeline 8 channels");
+    for worker_threads in [1, 2, 4, 8].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("worker_threads", worker_threads),
+            worker_threads,
+            |b, worker_threads| run_pipeline(b, *worker_threads),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_conv,
+    bench_biquad,
+    bench_diffeq,
+    bench_pipeline
+);
 
 criterion_main!(benches);
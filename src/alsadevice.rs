@@ -17,7 +17,7 @@ use std::fmt::Debug;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier, Mutex, RwLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::CommandMessage;
 use crate::PrcFmt;
@@ -50,6 +50,9 @@ pub struct AlsaPlaybackDevice {
     pub target_level: usize,
     pub adjust_period: f32,
     pub enable_rate_adjust: bool,
+    pub open_retries: usize,
+    pub retry_delay_ms: u64,
+    pub startup_silence_ms: usize,
 }
 
 pub struct AlsaCaptureDevice {
@@ -60,11 +63,16 @@ pub struct AlsaCaptureDevice {
     pub resampler_conf: config::Resampler,
     pub chunksize: usize,
     pub channels: usize,
+    pub channel_adjust: config::ChannelAdjust,
+    pub soft_start_ms: usize,
     pub sample_format: SampleFormat,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
+    pub open_retries: usize,
+    pub retry_delay_ms: u64,
 }
 
 struct CaptureChannels {
@@ -79,9 +87,14 @@ struct PlaybackChannels {
 }
 
 struct CaptureParams {
+    /// The number of channels of the device
     channels: usize,
+    /// The number of channels sent to the pipeline
+    pipeline_channels: usize,
     sample_format: SampleFormat,
+    soft_start_ms: usize,
     silence_timeout: PrcFmt,
+    silence_mode: config::SilenceMode,
     silence_threshold: PrcFmt,
     chunksize: usize,
     store_bytes_per_sample: usize,
@@ -101,13 +114,22 @@ struct PlaybackParams {
     playback_status: Arc<RwLock<PlaybackStatus>>,
     chunksize: usize,
     samplerate: usize,
+    startup_silence_frames: usize,
 }
 
 enum CaptureResult {
     Normal,
+    /// The buffer was captured after recovering from an overrun.
+    Xrun,
     RecoverableError,
 }
 
+enum PlaybackResult {
+    Normal,
+    /// The buffer was played after recovering from an underrun.
+    Xrun,
+}
+
 fn state_desc(state: u32) -> String {
     match state {
         alsa_sys::SND_PCM_STATE_OPEN => "SND_PCM_STATE_OPEN, Open".to_string(),
@@ -140,7 +162,8 @@ fn play_buffer(
     target_delay: u64,
     millis_per_chunk: usize,
     frames_to_write: usize,
-) -> Res<()> {
+) -> Res<PlaybackResult> {
+    let mut result = PlaybackResult::Normal;
     let playback_state = pcmdevice.state_raw();
     //trace!("Playback state {:?}", playback_state);
     if playback_state < 0 {
@@ -155,6 +178,7 @@ fn play_buffer(
     } else if playback_state == alsa_sys::SND_PCM_STATE_XRUN as i32 {
         warn!("Prepare playback after buffer underrun");
         pcmdevice.prepare()?;
+        result = PlaybackResult::Xrun;
         thread::sleep(Duration::from_millis(target_delay));
     } else if playback_state == alsa_sys::SND_PCM_STATE_PREPARED as i32 {
         info!("Starting playback from Prepared state");
@@ -215,12 +239,15 @@ fn play_buffer(
                 // Would recover() be better than prepare()?
                 pcmdevice.prepare()?;
             }
+            if err.nix_error() == alsa::nix::errno::Errno::EPIPE {
+                result = PlaybackResult::Xrun;
+            }
             thread::sleep(Duration::from_millis(target_delay));
             io.writei(buffer)?
         }
     };
     trace!("Wrote {} frames to playback device", frames);
-    Ok(())
+    Ok(result)
 }
 
 /// Capture a buffer.
@@ -231,10 +258,12 @@ fn capture_buffer(
     samplerate: usize,
     frames_to_read: usize,
 ) -> Res<CaptureResult> {
+    let mut result = CaptureResult::Normal;
     let capture_state = pcmdevice.state_raw();
     if capture_state == alsa_sys::SND_PCM_STATE_XRUN as i32 {
-        warn!("Prepare capture device");
+        warn!("Prepare capture device after buffer overrun");
         pcmdevice.prepare()?;
+        result = CaptureResult::Xrun;
     } else if capture_state < 0 {
         // This should never happen but sometimes does anyway,
         // for example if a USB device is unplugged.
@@ -288,7 +317,7 @@ fn capture_buffer(
             }
         },
     };
-    Ok(CaptureResult::Normal)
+    Ok(result)
 }
 
 fn list_samplerates(hwp: &HwParams) -> Res<SupportedValues> {
@@ -340,6 +369,27 @@ fn list_nbr_channels(hwp: &HwParams) -> Res<(u32, u32, Vec<u32>)> {
     Ok((min_channels, max_channels, channels))
 }
 
+/// Get the number of channels to open a device with.
+/// This is the configured number if the device supports it, otherwise the closest
+/// supported number that the channel adjust policy accepts.
+fn device_channels(hwp: &HwParams, channels: u32, channel_adjust: &config::ChannelAdjust) -> u32 {
+    if *channel_adjust == config::ChannelAdjust::Strict || hwp.test_channels(channels).is_ok() {
+        return channels;
+    }
+    let supported = match list_nbr_channels(hwp) {
+        Ok((_, _, supported)) => supported,
+        Err(_) => return channels,
+    };
+    let accepted = supported
+        .into_iter()
+        .filter(|chan| channel_adjust.accepts(*chan as usize, channels as usize));
+    let closest = match channel_adjust {
+        config::ChannelAdjust::Truncate => accepted.min(),
+        _ => accepted.max(),
+    };
+    closest.unwrap_or(channels)
+}
+
 fn list_channels_as_text(hwp: &HwParams) -> String {
     let supported_channels_res = list_nbr_channels(hwp);
     if let Ok((min_ch, max_ch, ch_list)) = supported_channels_res {
@@ -386,12 +436,14 @@ fn list_formats_as_text(hwp: &HwParams) -> String {
 }
 
 /// Open an Alsa PCM device
+#[allow(clippy::too_many_arguments)]
 fn open_pcm(
     devname: String,
     samplerate: u32,
     chunksize: Frames,
     bufsize: Frames,
     channels: u32,
+    channel_adjust: &config::ChannelAdjust,
     sample_format: &SampleFormat,
     capture: bool,
 ) -> Res<alsa::PCM> {
@@ -410,6 +462,7 @@ fn open_pcm(
 
         // Set number of channels
         debug!("{}: {}", direction, list_channels_as_text(&hwp));
+        let channels = device_channels(&hwp, channels, channel_adjust);
         debug!("{}: setting channels to {}", direction, channels);
         hwp.set_channels(channels)?;
 
@@ -428,6 +481,9 @@ fn open_pcm(
             SampleFormat::S32LE => hwp.set_format(Format::s32())?,
             SampleFormat::FLOAT32LE => hwp.set_format(Format::float())?,
             SampleFormat::FLOAT64LE => hwp.set_format(Format::float64())?,
+            SampleFormat::S16BE => hwp.set_format(Format::S16BE)?,
+            SampleFormat::S24BE => hwp.set_format(Format::S24BE)?,
+            SampleFormat::S32BE => hwp.set_format(Format::S32BE)?,
         }
 
         // Set access mode, buffersize and periods
@@ -474,16 +530,33 @@ fn playback_loop_bytes(
     let mut chunk_stats;
     let mut buffer_avg = countertimer::Averager::new();
     let mut conversion_result;
+    let mut xruns = 0;
     let adjust = params.adjust_period > 0.0 && params.adjust_enabled;
     let target_delay = 1000 * (params.target_level as u64) / srate as u64;
     let millis_per_chunk = 1000 * params.chunksize / params.samplerate;
+    if params.startup_silence_frames > 0 {
+        // Zero bytes are silence for all sample formats
+        let bytes_per_frame = buffer.len() / params.chunksize;
+        let silence = vec![0u8; params.startup_silence_frames * bytes_per_frame];
+        debug!(
+            "Writing {} frames of silence before starting playback",
+            params.startup_silence_frames
+        );
+        if let Err(err) = io.writei(&silence) {
+            warn!("Could not write startup silence, error: {}", err);
+        }
+    }
     loop {
         match channels.audio.recv() {
             Ok(AudioMessage::Audio(chunk)) => {
                 conversion_result =
                     chunk_to_buffer_rawbytes(&chunk, &mut buffer, &params.sample_format);
-                if conversion_result.1 > 0 {
-                    params.playback_status.write().unwrap().clipped_samples += conversion_result.1;
+                if conversion_result.1.total() > 0 {
+                    params
+                        .playback_status
+                        .write()
+                        .unwrap()
+                        .add_clipped(&conversion_result.1);
                 }
                 if let Ok(status) = pcmdevice.status() {
                     buffer_avg.add_value(status.get_delay() as f64)
@@ -504,6 +577,12 @@ fn playback_loop_bytes(
                                 .send(StatusMessage::SetSpeed(speed))
                                 .unwrap_or(());
                         }
+                        channels
+                            .status
+                            .send(StatusMessage::PlaybackLatency(
+                                1000.0 * av_delay as f32 / srate as f32,
+                            ))
+                            .unwrap_or(());
                         let mut pb_stat = params.playback_status.write().unwrap();
                         pb_stat.buffer_level = av_delay as usize;
                         debug!(
@@ -526,7 +605,17 @@ fn playback_loop_bytes(
                     params.chunksize,
                 );
                 match playback_res {
-                    Ok(_) => {}
+                    Ok(PlaybackResult::Normal) => {}
+                    Ok(PlaybackResult::Xrun) => {
+                        xruns += 1;
+                        channels
+                            .status
+                            .send(StatusMessage::PlaybackXrun {
+                                count: xruns,
+                                time: SystemTime::now(),
+                            })
+                            .unwrap_or(());
+                    }
                     Err(msg) => {
                         channels
                             .status
@@ -607,13 +696,20 @@ fn capture_loop_bytes(
     let mut silence_counter = countertimer::SilenceCounter::new(
         params.silence_threshold,
         params.silence_timeout,
+        &params.silence_mode,
         params.capture_samplerate,
         params.chunksize,
     );
+    let mut soft_start = CaptureSoftStart::new(
+        params.capture_samplerate,
+        params.soft_start_ms,
+        params.pipeline_channels,
+    );
     let mut state = ProcessingState::Running;
     let mut value_range = 0.0;
     let mut chunk_stats;
     let mut card_inactive = false;
+    let mut xruns = 0;
     loop {
         match channels.command.try_recv() {
             Ok(CommandMessage::Exit) => {
@@ -678,8 +774,18 @@ fn capture_loop_bytes(
             params.capture_samplerate,
             capture_frames as usize,
         );
+        if let Ok(CaptureResult::Xrun) = capture_res {
+            xruns += 1;
+            channels
+                .status
+                .send(StatusMessage::CaptureXrun {
+                    count: xruns,
+                    time: SystemTime::now(),
+                })
+                .unwrap_or(());
+        }
         match capture_res {
-            Ok(CaptureResult::Normal) => {
+            Ok(CaptureResult::Normal) | Ok(CaptureResult::Xrun) => {
                 //trace!("Captured {} bytes", capture_bytes);
                 averager.add_value(capture_bytes);
                 if averager.larger_than_millis(
@@ -737,13 +843,21 @@ fn capture_loop_bytes(
                 return;
             }
         };
+        let used_channels = device_used_channels(
+            &params.capture_status.read().unwrap().used_channels,
+            params.channels,
+        );
         let mut chunk = buffer_to_chunk_rawbytes(
             &buffer[0..capture_bytes],
             params.channels,
             &params.sample_format,
             capture_bytes,
-            &params.capture_status.read().unwrap().used_channels,
+            &used_channels,
         );
+        if params.pipeline_channels != params.channels {
+            chunk.adjust_channels(params.pipeline_channels);
+        }
+        soft_start.process(&mut chunk);
         chunk_stats = chunk.get_stats();
         params.capture_status.write().unwrap().signal_rms = chunk_stats.rms_db();
         params.capture_status.write().unwrap().signal_peak = chunk_stats.peak_db();
@@ -752,6 +866,15 @@ fn capture_loop_bytes(
             state = ProcessingState::Stalled;
         } else {
             state = silence_counter.update(value_range);
+            if let Some(silent) = silence_counter.transition() {
+                channels
+                    .status
+                    .send(StatusMessage::CaptureSilence {
+                        silent,
+                        time: SystemTime::now(),
+                    })
+                    .unwrap_or(());
+            }
         }
         if state == ProcessingState::Running {
             if let Some(resampl) = &mut resampler {
@@ -834,18 +957,24 @@ impl PlaybackDevice for AlsaPlaybackDevice {
         let channels = self.channels;
         let bytes_per_sample = self.sample_format.bytes_per_sample();
         let sample_format = self.sample_format.clone();
+        let open_retries = self.open_retries;
+        let retry_delay_ms = self.retry_delay_ms;
+        let startup_silence_frames = self.startup_silence_ms * samplerate / 1000;
         let handle = thread::Builder::new()
             .name("AlsaPlayback".to_string())
             .spawn(move || {
-                match open_pcm(
-                    devname,
-                    samplerate as u32,
-                    chunksize as Frames,
-                    chunksize as Frames,
-                    channels as u32,
-                    &sample_format,
-                    false,
-                ) {
+                match open_with_retries(open_retries, retry_delay_ms, || {
+                    open_pcm(
+                        devname.clone(),
+                        samplerate as u32,
+                        chunksize as Frames,
+                        chunksize as Frames,
+                        channels as u32,
+                        &config::ChannelAdjust::Strict,
+                        &sample_format,
+                        false,
+                    )
+                }) {
                     Ok(pcmdevice) => {
                         match status_channel.send(StatusMessage::PlaybackReady) {
                             Ok(()) => {}
@@ -862,6 +991,7 @@ impl PlaybackDevice for AlsaPlaybackDevice {
                             playback_status,
                             chunksize,
                             samplerate,
+                            startup_silence_frames,
                         };
                         let pb_channels = PlaybackChannels {
                             audio: channel,
@@ -908,8 +1038,11 @@ impl CaptureDevice for AlsaCaptureDevice {
         ) as usize;
         debug!("Buffer frames {}", buffer_frames);
         let channels = self.channels;
+        let channel_adjust = self.channel_adjust.clone();
+        let soft_start_ms = self.soft_start_ms;
         let store_bytes_per_sample = self.sample_format.bytes_per_sample();
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
         let sample_format = self.sample_format.clone();
         let enable_resampling = self.enable_resampling;
@@ -917,6 +1050,8 @@ impl CaptureDevice for AlsaCaptureDevice {
         let async_src = resampler_is_async(&resampler_conf);
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = self.rate_measure_interval;
+        let open_retries = self.open_retries;
+        let retry_delay_ms = self.retry_delay_ms;
         let handle = thread::Builder::new()
             .name("AlsaCapture".to_string())
             .spawn(move || {
@@ -932,16 +1067,30 @@ impl CaptureDevice for AlsaCaptureDevice {
                 } else {
                     None
                 };
-                match open_pcm(
-                    devname,
-                    capture_samplerate as u32,
-                    chunksize as Frames,
-                    buffer_frames as Frames,
-                    channels as u32,
-                    &sample_format,
-                    true,
-                ) {
+                match open_with_retries(open_retries, retry_delay_ms, || {
+                    open_pcm(
+                        devname.clone(),
+                        capture_samplerate as u32,
+                        chunksize as Frames,
+                        buffer_frames as Frames,
+                        channels as u32,
+                        &channel_adjust,
+                        &sample_format,
+                        true,
+                    )
+                }) {
                     Ok(pcmdevice) => {
+                        let device_channels = pcmdevice
+                            .hw_params_current()
+                            .and_then(|hwp| hwp.get_channels())
+                            .map(|chan| chan as usize)
+                            .unwrap_or(channels);
+                        if device_channels != channels {
+                            info!(
+                                "Capture device opened with {} channels, adjusting to {} channels",
+                                device_channels, channels
+                            );
+                        }
                         match status_channel.send(StatusMessage::CaptureReady) {
                             Ok(()) => {}
                             Err(_err) => {}
@@ -949,9 +1098,12 @@ impl CaptureDevice for AlsaCaptureDevice {
                         barrier.wait();
                         debug!("Starting captureloop");
                         let cap_params = CaptureParams {
-                            channels,
+                            channels: device_channels,
+                            pipeline_channels: channels,
                             sample_format,
+                            soft_start_ms,
                             silence_timeout,
+                            silence_mode,
                             silence_threshold,
                             chunksize,
                             store_bytes_per_sample,
@@ -968,7 +1120,8 @@ impl CaptureDevice for AlsaCaptureDevice {
                             command: command_channel,
                         };
                         let io = pcmdevice.io_bytes();
-                        let buffer = vec![0u8; channels * buffer_frames * store_bytes_per_sample];
+                        let buffer =
+                            vec![0u8; device_channels * buffer_frames * store_bytes_per_sample];
                         capture_loop_bytes(
                             cap_channels,
                             buffer,
@@ -0,0 +1,119 @@
+use crate::config;
+use crate::fft::{DefaultFft, RealFft};
+use crate::PrcFmt;
+use num_complex::Complex;
+
+/// Build a window of the given length. The window is periodic, as used for spectral analysis.
+pub fn make_window(length: usize, window: &config::SpectrumWindow) -> Vec<PrcFmt> {
+    let pi = std::f64::consts::PI as PrcFmt;
+    (0..length)
+        .map(|n| {
+            let x = 2.0 * pi * n as PrcFmt / length as PrcFmt;
+            match window {
+                config::SpectrumWindow::Rectangular => 1.0,
+                config::SpectrumWindow::Hann => 0.5 - 0.5 * x.cos(),
+                config::SpectrumWindow::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+            }
+        })
+        .collect()
+}
+
+/// Magnitude spectrum of one channel, calculated from consecutive blocks of samples.
+pub struct SpectrumAnalyzer {
+    fft: DefaultFft,
+    window: Vec<PrcFmt>,
+    scale: PrcFmt,
+    buffer: Vec<PrcFmt>,
+    input: Vec<PrcFmt>,
+    output: Vec<Complex<PrcFmt>>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(fft_size: usize, window: &config::SpectrumWindow) -> Self {
+        let fft = DefaultFft::new(fft_size);
+        let window = make_window(fft_size, window);
+        // Scale so that a full scale sine at the center of a bin gives 0 dB
+        let scale = 2.0 / window.iter().sum::<PrcFmt>();
+        let input = fft.make_input_vec();
+        let output = fft.make_output_vec();
+        SpectrumAnalyzer {
+            fft,
+            window,
+            scale,
+            buffer: Vec::with_capacity(fft_size),
+            input,
+            output,
+        }
+    }
+
+    /// Add samples, and return the magnitude spectrum in dB each time a full block has been collected.
+    /// The spectrum has fft_size/2 + 1 values, where value k is at the frequency k * samplerate / fft_size.
+    pub fn push(&mut self, samples: &[PrcFmt]) -> Option<Vec<f32>> {
+        let mut spectrum = None;
+        for sample in samples {
+            self.buffer.push(*sample);
+            if self.buffer.len() == self.window.len() {
+                spectrum = Some(self.transform());
+                self.buffer.clear();
+            }
+        }
+        spectrum
+    }
+
+    fn transform(&mut self) -> Vec<f32> {
+        for ((inp, sample), win) in self
+            .input
+            .iter_mut()
+            .zip(self.buffer.iter())
+            .zip(self.window.iter())
+        {
+            *inp = sample * win;
+        }
+        self.fft.forward(&mut self.input, &mut self.output).unwrap();
+        self.output
+            .iter()
+            .map(|val| (20.0 * (self.scale * val.norm()).max(1.0e-10).log10()) as f32)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::SpectrumAnalyzer;
+    use crate::config::SpectrumWindow;
+    use crate::PrcFmt;
+
+    #[test]
+    fn sine_peak() {
+        let fft_size = 1024;
+        let fs = 48000.0;
+        // Put the sine at the center of bin 64
+        let freq = 64.0 * fs / fft_size as PrcFmt;
+        let pi = std::f64::consts::PI as PrcFmt;
+        let sine: Vec<PrcFmt> = (0..1500)
+            .map(|n| 0.5 * (2.0 * pi * freq * n as PrcFmt / fs).sin())
+            .collect();
+        for window in [
+            SpectrumWindow::Rectangular,
+            SpectrumWindow::Hann,
+            SpectrumWindow::Blackman,
+        ] {
+            let mut analyzer = SpectrumAnalyzer::new(fft_size, &window);
+            assert!(analyzer.push(&sine[0..1000]).is_none());
+            let spectrum = analyzer.push(&sine[1000..]).unwrap();
+            assert_eq!(spectrum.len(), fft_size / 2 + 1);
+            let (peak_bin, peak) =
+                spectrum
+                    .iter()
+                    .enumerate()
+                    .fold(
+                        (0, -1000.0),
+                        |max, (n, val)| if *val > max.1 { (n, *val) } else { max },
+                    );
+            assert_eq!(peak_bin, 64);
+            // A sine with amplitude 0.5 is at -6 dB
+            assert!((peak + 6.02).abs() < 0.1, "peak {} dB", peak);
+            assert!(spectrum[300] < -60.0);
+        }
+    }
+}
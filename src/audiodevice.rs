@@ -1,12 +1,14 @@
 // Traits for audio devices
 #[cfg(target_os = "linux")]
 use crate::alsadevice;
+use crate::basicfilters;
 use crate::config;
 #[cfg(target_os = "macos")]
 use crate::coreaudiodevice;
 #[cfg(feature = "cpal-backend")]
 use crate::cpaldevice;
 use crate::filedevice;
+use crate::generatordevice;
 #[cfg(feature = "pulse-backend")]
 use crate::pulsedevice;
 #[cfg(target_os = "windows")]
@@ -21,7 +23,7 @@ use std::fmt;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier, RwLock};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::CommandMessage;
 use crate::PrcFmt;
@@ -32,6 +34,11 @@ use crate::{CaptureStatus, PlaybackStatus};
 pub const RATE_CHANGE_THRESHOLD_COUNT: usize = 3;
 pub const RATE_CHANGE_THRESHOLD_VALUE: f32 = 0.04;
 
+/// Largest number of retries when opening a device.
+pub const MAX_OPEN_RETRIES: usize = 100;
+/// Largest delay between two retries when opening a device.
+pub const MAX_RETRY_DELAY_MS: u64 = 5000;
+
 #[derive(Debug)]
 pub struct DeviceError {
     desc: String,
@@ -156,6 +163,21 @@ impl AudioChunk {
         let peak: Vec<PrcFmt> = rms_peak.iter().map(|rp| rp.1).collect();
         ChunkStats { rms, peak }
     }
+
+    /// Change the number of channels, by dropping the last channels or adding silent ones.
+    pub fn adjust_channels(&mut self, channels: usize) {
+        let frames = self.frames;
+        self.waveforms.resize_with(channels, || vec![0.0; frames]);
+        self.channels = channels;
+    }
+}
+
+/// Get the used channels of a device whose channels are adjusted to the configured number of channels.
+/// Device channels beyond the configured ones are never used.
+pub fn device_used_channels(used_channels: &[bool], device_channels: usize) -> Vec<bool> {
+    (0..device_channels)
+        .map(|idx| used_channels.get(idx).copied().unwrap_or(false))
+        .collect()
 }
 
 /// Get RMS and peak value of a vector
@@ -198,23 +220,192 @@ pub trait CaptureDevice {
     ) -> Res<Box<thread::JoinHandle<()>>>;
 }
 
+/// Open a device, and retry up to `retries` times if opening fails.
+/// The delay before the first retry is `retry_delay_ms`, and it is doubled for every following retry,
+/// up to [MAX_RETRY_DELAY_MS].
+pub fn open_with_retries<T, F>(retries: usize, retry_delay_ms: u64, mut open: F) -> Res<T>
+where
+    F: FnMut() -> Res<T>,
+{
+    let mut delay = retry_delay_ms;
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Ok(device) => return Ok(device),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "Could not open device: {}. Retrying in {} ms, attempt {} of {}",
+                    err, delay, attempt, retries
+                );
+                thread::sleep(Duration::from_millis(delay));
+                delay = next_retry_delay(delay);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn next_retry_delay(delay: u64) -> u64 {
+    (2 * delay).min(MAX_RETRY_DELAY_MS)
+}
+
+/// Fade in the audio at the start of playback, and fade out the last chunk before it stops.
+/// For the fade-out, each chunk is held back until the next one arrives,
+/// so that the final chunk is known when the stream ends.
+pub struct PlaybackRamp {
+    fadein_frames: usize,
+    fadeout_frames: usize,
+    position: usize,
+    pending: Option<AudioChunk>,
+}
+
+impl PlaybackRamp {
+    pub fn new(samplerate: usize, fadein_ms: usize, fadeout_ms: usize) -> Self {
+        PlaybackRamp {
+            fadein_frames: fadein_ms * samplerate / 1000,
+            fadeout_frames: fadeout_ms * samplerate / 1000,
+            position: 0,
+            pending: None,
+        }
+    }
+
+    /// Take a new chunk, and return the chunk that is ready for playback, if any.
+    pub fn process(&mut self, mut chunk: AudioChunk) -> Option<AudioChunk> {
+        if self.position < self.fadein_frames {
+            for waveform in chunk.waveforms.iter_mut() {
+                for (n, value) in waveform.iter_mut().take(chunk.valid_frames).enumerate() {
+                    let pos = self.position + n;
+                    if pos < self.fadein_frames {
+                        *value *= pos as PrcFmt / self.fadein_frames as PrcFmt;
+                    }
+                }
+            }
+        }
+        self.position += chunk.valid_frames;
+        if self.fadeout_frames == 0 {
+            return Some(chunk);
+        }
+        self.pending.replace(chunk)
+    }
+
+    /// Return the held back chunk unchanged, used when playback pauses.
+    pub fn flush(&mut self) -> Option<AudioChunk> {
+        self.pending.take()
+    }
+
+    /// Return the held back chunk with the fade-out applied, used when the stream ends.
+    /// The last frame of the chunk is zero.
+    pub fn finish(&mut self) -> Option<AudioChunk> {
+        let mut chunk = self.pending.take()?;
+        let fade_frames = self.fadeout_frames.min(chunk.valid_frames);
+        let start = chunk.valid_frames - fade_frames;
+        for waveform in chunk.waveforms.iter_mut() {
+            for (n, value) in waveform
+                .iter_mut()
+                .take(chunk.valid_frames)
+                .skip(start)
+                .enumerate()
+            {
+                *value *= (fade_frames - 1 - n) as PrcFmt / fade_frames as PrcFmt;
+            }
+        }
+        Some(chunk)
+    }
+}
+
+/// Cutoff in Hz of the highpass filter used by [CaptureSoftStart].
+pub const SOFT_START_CUTOFF: PrcFmt = 20.0;
+
+/// Remove DC from the start of the capture stream, to avoid a thump from converters
+/// that give a DC step when the device is opened.
+/// A highpass filter is applied to the first `soft_start_ms` after the capture starts.
+/// It is fully engaged during the first half of this time,
+/// and then fades out during the second half so that its removal doesn't cause a step.
+/// The filter starts from the first captured sample of each channel,
+/// so that an initial DC level gives no step either.
+pub struct CaptureSoftStart {
+    frames: usize,
+    position: usize,
+    coeff: PrcFmt,
+    prev_input: Vec<Option<PrcFmt>>,
+    prev_output: Vec<PrcFmt>,
+}
+
+impl CaptureSoftStart {
+    pub fn new(samplerate: usize, soft_start_ms: usize, channels: usize) -> Self {
+        CaptureSoftStart {
+            frames: soft_start_ms * samplerate / 1000,
+            position: 0,
+            coeff: basicfilters::dcblock_coeff(SOFT_START_CUTOFF, samplerate),
+            prev_input: vec![None; channels],
+            prev_output: vec![0.0; channels],
+        }
+    }
+
+    /// True while the filter is still applied.
+    pub fn is_active(&self) -> bool {
+        self.position < self.frames
+    }
+
+    /// Filter a captured chunk, does nothing once the soft start is done.
+    pub fn process(&mut self, chunk: &mut AudioChunk) {
+        if !self.is_active() {
+            return;
+        }
+        let fade_start = self.frames / 2;
+        let fade_frames = (self.frames - fade_start) as PrcFmt;
+        for (channel, waveform) in chunk.waveforms.iter_mut().enumerate() {
+            if channel >= self.prev_input.len() {
+                break;
+            }
+            for (n, value) in waveform.iter_mut().take(chunk.valid_frames).enumerate() {
+                let pos = self.position + n;
+                if pos >= self.frames {
+                    break;
+                }
+                let input = *value;
+                let prev_input = self.prev_input[channel].unwrap_or(input);
+                let output = input - prev_input + self.coeff * self.prev_output[channel];
+                self.prev_input[channel] = Some(input);
+                self.prev_output[channel] = output;
+                *value = if pos < fade_start {
+                    output
+                } else {
+                    let dry = (pos - fade_start) as PrcFmt / fade_frames;
+                    dry * input + (1.0 - dry) * output
+                };
+            }
+        }
+        self.position += chunk.valid_frames;
+        if !self.is_active() {
+            debug!("Capture soft start done");
+        }
+    }
+}
+
 /// Create a playback device.
 pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
+    let target_level = conf.effective_target_level();
     match conf.playback {
         #[cfg(target_os = "linux")]
         config::PlaybackDevice::Alsa {
             channels,
             device,
             format,
+            startup_silence_ms,
         } => Box::new(alsadevice::AlsaPlaybackDevice {
             devname: device,
             samplerate: conf.samplerate,
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
-            target_level: conf.target_level,
+            target_level,
             adjust_period: conf.adjust_period,
             enable_rate_adjust: conf.enable_rate_adjust,
+            open_retries: conf.open_retries,
+            retry_delay_ms: conf.retry_delay_ms,
+            startup_silence_ms,
         }),
         #[cfg(feature = "pulse-backend")]
         config::PlaybackDevice::Pulse {
@@ -227,18 +418,21 @@ pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            open_retries: conf.open_retries,
+            retry_delay_ms: conf.retry_delay_ms,
         }),
         config::PlaybackDevice::File {
             channels,
             filename,
             format,
-            ..
+            wav_levels,
         } => Box::new(filedevice::FilePlaybackDevice {
             destination: filedevice::PlaybackDest::Filename(filename),
             samplerate: conf.samplerate,
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            wav_levels,
         }),
         config::PlaybackDevice::Stdout {
             channels, format, ..
@@ -248,6 +442,7 @@ pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            wav_levels: false,
         }),
         #[cfg(target_os = "macos")]
         config::PlaybackDevice::CoreAudio {
@@ -262,7 +457,7 @@ pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
-            target_level: conf.target_level,
+            target_level,
             adjust_period: conf.adjust_period,
             enable_rate_adjust: conf.enable_rate_adjust,
             change_format,
@@ -281,7 +476,7 @@ pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             exclusive,
             channels,
             sample_format: format,
-            target_level: conf.target_level,
+            target_level,
             adjust_period: conf.adjust_period,
             enable_rate_adjust: conf.enable_rate_adjust,
         }),
@@ -294,7 +489,7 @@ pub fn get_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
                 chunksize: conf.chunksize,
                 channels,
                 sample_format: config::SampleFormat::FLOAT32LE,
-                target_level: conf.target_level,
+                target_level,
                 adjust_period: conf.adjust_period,
                 enable_rate_adjust: conf.enable_rate_adjust,
             })
@@ -464,6 +659,8 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             channels,
             device,
             format,
+            channel_adjust,
+            soft_start_ms,
         } => Box::new(alsadevice::AlsaCaptureDevice {
             devname: device,
             samplerate: conf.samplerate,
@@ -472,17 +669,23 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             resampler_conf: conf.resampler_type,
             chunksize: conf.chunksize,
             channels,
+            channel_adjust,
+            soft_start_ms,
             sample_format: format,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
-            stop_on_rate_change: conf.stop_on_rate_change,
+            silence_mode: conf.silence_mode.clone(),
+            stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
             rate_measure_interval: conf.rate_measure_interval,
+            open_retries: conf.open_retries,
+            retry_delay_ms: conf.retry_delay_ms,
         }),
         #[cfg(feature = "pulse-backend")]
         config::CaptureDevice::Pulse {
             channels,
             device,
             format,
+            soft_start_ms,
         } => Box::new(pulsedevice::PulseCaptureDevice {
             devname: device,
             samplerate: conf.samplerate,
@@ -492,16 +695,23 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            soft_start_ms,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
+            silence_mode: conf.silence_mode.clone(),
+            open_retries: conf.open_retries,
+            retry_delay_ms: conf.retry_delay_ms,
         }),
         config::CaptureDevice::File {
             channels,
             filename,
             format,
+            channel_adjust,
             extra_samples,
             skip_bytes,
             read_bytes,
+            start_frame,
+            length_frames,
         } => Box::new(filedevice::FileCaptureDevice {
             source: filedevice::CaptureSource::Filename(filename),
             samplerate: conf.samplerate,
@@ -510,13 +720,17 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             resampler_conf: conf.resampler_type,
             chunksize: conf.chunksize,
             channels,
+            channel_adjust,
             sample_format: format,
             extra_samples,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
+            silence_mode: conf.silence_mode.clone(),
             skip_bytes,
             read_bytes,
-            stop_on_rate_change: conf.stop_on_rate_change,
+            start_frame,
+            length_frames,
+            stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
             rate_measure_interval: conf.rate_measure_interval,
         }),
         config::CaptureDevice::Stdin {
@@ -533,15 +747,30 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             resampler_conf: conf.resampler_type,
             chunksize: conf.chunksize,
             channels,
+            channel_adjust: config::ChannelAdjust::Strict,
             sample_format: format,
             extra_samples,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
+            silence_mode: conf.silence_mode.clone(),
             skip_bytes,
             read_bytes,
-            stop_on_rate_change: conf.stop_on_rate_change,
+            start_frame: 0,
+            length_frames: 0,
+            stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
             rate_measure_interval: conf.rate_measure_interval,
         }),
+        config::CaptureDevice::Signal {
+            channels,
+            signal,
+            duration,
+        } => Box::new(generatordevice::GeneratorCaptureDevice {
+            chunksize: conf.chunksize,
+            samplerate: conf.samplerate,
+            channels,
+            signal,
+            duration,
+        }),
         #[cfg(target_os = "macos")]
         config::CaptureDevice::CoreAudio {
             channels,
@@ -560,7 +789,8 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             change_format,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
-            stop_on_rate_change: conf.stop_on_rate_change,
+            silence_mode: conf.silence_mode.clone(),
+            stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
             rate_measure_interval: conf.rate_measure_interval,
         }),
         #[cfg(target_os = "windows")]
@@ -583,7 +813,8 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             sample_format: format,
             silence_threshold: conf.silence_threshold,
             silence_timeout: conf.silence_timeout,
-            stop_on_rate_change: conf.stop_on_rate_change,
+            silence_mode: conf.silence_mode.clone(),
+            stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
             rate_measure_interval: conf.rate_measure_interval,
         }),
         #[cfg(all(feature = "cpal-backend", feature = "jack-backend"))]
@@ -600,7 +831,8 @@ pub fn get_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
                 sample_format: config::SampleFormat::FLOAT32LE,
                 silence_threshold: conf.silence_threshold,
                 silence_timeout: conf.silence_timeout,
-                stop_on_rate_change: conf.stop_on_rate_change,
+                silence_mode: conf.silence_mode.clone(),
+                stop_on_rate_change: conf.stop_on_rate_change || conf.adapt_to_rate_change,
                 rate_measure_interval: conf.rate_measure_interval,
             })
         }
@@ -621,7 +853,10 @@ pub fn calculate_speed(avg_level: f64, target_level: usize, adjust_period: f32,
 
 #[cfg(test)]
 mod tests {
-    use crate::audiodevice::{rms_and_peak, AudioChunk, ChunkStats};
+    use crate::audiodevice::{
+        next_retry_delay, open_with_retries, rms_and_peak, AudioChunk, CaptureSoftStart,
+        ChunkStats, DeviceError, PlaybackRamp, MAX_RETRY_DELAY_MS,
+    };
 
     #[test]
     fn vec_rms_and_peak() {
@@ -654,4 +889,91 @@ mod tests {
         assert_eq!(0.0, stats.peak_db()[0]);
         assert!(stats.rms_db()[1] > -6.1 && stats.rms_db()[1] < -5.9);
     }
+
+    #[test]
+    fn retry_opening() {
+        let mut attempts = 0;
+        let result = open_with_retries(3, 1, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(DeviceError::new("busy").into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = open_with_retries(2, 1, || {
+            attempts += 1;
+            Err(DeviceError::new("busy").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(next_retry_delay(500), 1000);
+        assert_eq!(next_retry_delay(4000), MAX_RETRY_DELAY_MS);
+        assert_eq!(next_retry_delay(MAX_RETRY_DELAY_MS), MAX_RETRY_DELAY_MS);
+    }
+
+    #[test]
+    fn playback_ramp_fadein() {
+        // 10 ms at 1 kHz is a fade-in over 10 frames
+        let mut ramp = PlaybackRamp::new(1000, 10, 0);
+        let make_chunk = || AudioChunk::new(vec![vec![1.0; 8], Vec::new()], 1.0, -1.0, 8, 8);
+        let first = ramp.process(make_chunk()).unwrap();
+        assert_eq!(first.waveforms[0][0], 0.0);
+        assert_eq!(first.waveforms[0][5], 0.5);
+        assert!(first.waveforms[1].is_empty());
+        let second = ramp.process(make_chunk()).unwrap();
+        assert_eq!(second.waveforms[0][1], 0.9);
+        assert_eq!(second.waveforms[0][2..], [1.0; 6]);
+        assert!(ramp.finish().is_none());
+    }
+
+    #[test]
+    fn capture_soft_start() {
+        // 100 ms at 1 kHz, filtered for 50 frames and then faded out over 50 frames
+        let mut soft_start = CaptureSoftStart::new(1000, 100, 2);
+        let make_chunk =
+            |value| AudioChunk::new(vec![vec![value; 40], Vec::new()], 1.0, -1.0, 40, 40);
+        // An initial DC level is removed without a step
+        let mut first = make_chunk(0.5);
+        soft_start.process(&mut first);
+        assert_eq!(first.waveforms[0], vec![0.0; 40]);
+        assert!(first.waveforms[1].is_empty());
+        // A DC step is let through, and then decays
+        let mut second = make_chunk(1.0);
+        soft_start.process(&mut second);
+        assert_eq!(second.waveforms[0][0], 0.5);
+        assert!(second.waveforms[0][9] < 0.25);
+        // The fade-out ends with the unfiltered signal
+        let mut third = make_chunk(1.0);
+        soft_start.process(&mut third);
+        assert!(!soft_start.is_active());
+        assert!(third.waveforms[0][19] > 0.95);
+        assert_eq!(third.waveforms[0][20..], [1.0; 20]);
+        let mut fourth = make_chunk(1.0);
+        soft_start.process(&mut fourth);
+        assert_eq!(fourth.waveforms[0], vec![1.0; 40]);
+    }
+
+    #[test]
+    fn playback_ramp_holds_back() {
+        let mut ramp = PlaybackRamp::new(1000, 0, 4);
+        let make_chunk = || AudioChunk::new(vec![vec![1.0; 8]], 1.0, -1.0, 8, 8);
+        assert!(ramp.process(make_chunk()).is_none());
+        let first = ramp.process(make_chunk()).unwrap();
+        assert_eq!(first.waveforms[0], vec![1.0; 8]);
+        assert_eq!(ramp.flush().unwrap().waveforms[0], vec![1.0; 8]);
+        assert!(ramp.process(make_chunk()).is_none());
+        let last = ramp.finish().unwrap();
+        assert_eq!(
+            last.waveforms[0],
+            vec![1.0, 1.0, 1.0, 1.0, 0.75, 0.5, 0.25, 0.0]
+        );
+    }
 }
@@ -10,10 +10,14 @@ use crate::PrcFmt;
 use crate::ProcessingParameters;
 use crate::Res;
 
+/// The longest delay that a Delay filter can be configured for.
+const MAX_DELAY_SECONDS: PrcFmt = 10.0;
+
 #[derive(Clone, Debug)]
 pub struct Gain {
     pub name: String,
     pub gain: PrcFmt,
+    mute: bool,
 }
 
 pub struct Delay {
@@ -23,6 +27,32 @@ pub struct Delay {
     biquad: Option<Biquad>,
 }
 
+#[derive(Clone, Debug)]
+pub struct DcBlock {
+    pub name: String,
+    samplerate: usize,
+    coeff: PrcFmt,
+    prev_input: PrcFmt,
+    prev_output: PrcFmt,
+}
+
+#[derive(Clone, Debug)]
+pub struct Emphasis {
+    pub name: String,
+    samplerate: usize,
+    deemphasis: bool,
+    b0: PrcFmt,
+    b1: PrcFmt,
+    a1: PrcFmt,
+    prev_input: PrcFmt,
+    prev_output: PrcFmt,
+}
+
+#[derive(Clone, Debug)]
+pub struct Invert {
+    pub name: String,
+}
+
 pub struct Volume {
     pub name: String,
     ramptime_in_chunks: usize,
@@ -177,7 +207,10 @@ impl Filter for Volume {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Volume { parameters: conf } = conf {
+        if let config::Filter::Volume {
+            parameters: conf, ..
+        } = conf
+        {
             self.ramptime_in_chunks = (conf.ramp_time
                 / (1000.0 * self.chunksize as f32 / self.samplerate as f32))
                 .round() as usize;
@@ -199,15 +232,34 @@ impl Gain {
         if mute {
             gain = 0.0;
         }
-        Gain { name, gain }
+        Gain { name, gain, mute }
     }
 
     pub fn from_config(name: String, conf: config::GainParameters) -> Self {
-        let gain = conf.gain;
-        let inverted = conf.inverted;
+        let gain = linear_gain(&conf);
         let mute = conf.mute;
-        Gain::new(name, gain, inverted, mute)
+        Gain { name, gain, mute }
+    }
+}
+
+/// Get the gain as a linear factor, converting from dB if needed.
+fn gain_factor(conf: &config::GainParameters) -> PrcFmt {
+    match conf.scale {
+        config::GainScale::Decibel => PrcFmt::new(10.0).powf(conf.gain / 20.0),
+        config::GainScale::Linear => conf.gain,
+    }
+}
+
+/// Get the linear gain to apply, including inversion and muting.
+fn linear_gain(conf: &config::GainParameters) -> PrcFmt {
+    let mut gain = gain_factor(conf);
+    if conf.inverted {
+        gain = -gain;
+    }
+    if conf.mute {
+        gain = 0.0;
     }
+    gain
 }
 
 impl Filter for Gain {
@@ -216,6 +268,11 @@ impl Filter for Gain {
     }
 
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        if self.mute {
+            // No need to multiply, and this also silences any inf or NaN in the input
+            waveform.fill(0.0);
+            return Ok(());
+        }
         for item in waveform.iter_mut() {
             *item *= self.gain;
         }
@@ -223,18 +280,12 @@ impl Filter for Gain {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Gain { parameters: conf } = conf {
-            let gain_db = conf.gain;
-            let inverted = conf.inverted;
-            let mut gain: PrcFmt = 10.0;
-            gain = gain.powf(gain_db / 20.0);
-            if inverted {
-                gain = -gain;
-            }
-            if conf.mute {
-                gain = 0.0;
-            }
-            self.gain = gain;
+        if let config::Filter::Gain {
+            parameters: conf, ..
+        } = conf
+        {
+            self.gain = linear_gain(&conf);
+            self.mute = conf.mute;
         } else {
             // This should never happen unless there is a bug somewhere else
             panic!("Invalid config change!");
@@ -275,15 +326,20 @@ impl Delay {
     }
 
     pub fn from_config(name: String, samplerate: usize, conf: config::DelayParameters) -> Self {
-        let delay_samples = match conf.unit {
-            config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (samplerate as PrcFmt),
-            config::TimeUnit::Millimetres => conf.delay / 1000.0 * (samplerate as PrcFmt) / 343.0,
-            config::TimeUnit::Samples => conf.delay,
-        };
+        let delay_samples = delay_in_samples(samplerate, &conf);
         Delay::new(name, samplerate, delay_samples, conf.subsample)
     }
 }
 
+/// Convert the delay of a Delay config to a number of samples.
+fn delay_in_samples(samplerate: usize, conf: &config::DelayParameters) -> PrcFmt {
+    match conf.unit {
+        config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (samplerate as PrcFmt),
+        config::TimeUnit::Millimetres => conf.delay / 1000.0 * (samplerate as PrcFmt) / 343.0,
+        config::TimeUnit::Samples => conf.delay,
+    }
+}
+
 impl Filter for Delay {
     fn name(&self) -> String {
         self.name.clone()
@@ -301,14 +357,11 @@ impl Filter for Delay {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Delay { parameters: conf } = conf {
-            let delay_samples = match conf.unit {
-                config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (self.samplerate as PrcFmt),
-                config::TimeUnit::Millimetres => {
-                    conf.delay / 1000.0 * (self.samplerate as PrcFmt) / 343.0
-                }
-                config::TimeUnit::Samples => conf.delay,
-            };
+        if let config::Filter::Delay {
+            parameters: conf, ..
+        } = conf
+        {
+            let delay_samples = delay_in_samples(self.samplerate, &conf);
             let (integerdelay, biquad) = if conf.subsample {
                 let full_samples = delay_samples.floor();
                 let fraction = delay_samples - full_samples;
@@ -339,11 +392,202 @@ impl Filter for Delay {
     }
 }
 
-/// Validate a Loudness config.
-pub fn validate_delay_config(conf: &config::DelayParameters) -> Res<()> {
+impl DcBlock {
+    /// Creates a DC blocking filter from a config struct
+    pub fn from_config(name: String, samplerate: usize, conf: config::DcBlockParameters) -> Self {
+        debug!(
+            "Creating DC block filter '{}', cutoff: {} Hz",
+            name, conf.cutoff
+        );
+        DcBlock {
+            name,
+            samplerate,
+            coeff: dcblock_coeff(conf.cutoff, samplerate),
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+}
+
+/// Get the pole position of a one-pole highpass filter with the given cutoff.
+pub fn dcblock_coeff(cutoff: PrcFmt, samplerate: usize) -> PrcFmt {
+    let pi = std::f64::consts::PI as PrcFmt;
+    (-2.0 * pi * cutoff / samplerate as PrcFmt).exp()
+}
+
+impl Filter for DcBlock {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            let output = *item - self.prev_input + self.coeff * self.prev_output;
+            self.prev_input = *item;
+            self.prev_output = output;
+            *item = output;
+        }
+        if self.prev_output.is_subnormal() {
+            self.prev_output = 0.0;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::DcBlock {
+            parameters: conf, ..
+        } = conf
+        {
+            self.coeff = dcblock_coeff(conf.cutoff, self.samplerate);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+impl Emphasis {
+    /// Creates a de-emphasis or pre-emphasis filter from a config struct
+    pub fn from_config(
+        name: String,
+        samplerate: usize,
+        conf: config::EmphasisParameters,
+        deemphasis: bool,
+    ) -> Self {
+        debug!(
+            "Creating {} filter '{}', time constants: {} / {} us",
+            if deemphasis {
+                "de-emphasis"
+            } else {
+                "pre-emphasis"
+            },
+            name,
+            conf.t1_us,
+            conf.t2_us
+        );
+        let (b0, b1, a1) = emphasis_coeffs(&conf, samplerate, deemphasis);
+        Emphasis {
+            name,
+            samplerate,
+            deemphasis,
+            b0,
+            b1,
+            a1,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+}
+
+/// Get the coefficients b0, b1 and a1 of a first order emphasis shelf.
+/// The analog de-emphasis is (1 + s*t2) / (1 + s*t1).
+/// The pole is placed at the prewarped 1/t1, and the zero so that the gain matches the analog
+/// response both at DC and at half the sample rate. A plain bilinear transform would instead
+/// squeeze the whole upper shelf below the Nyquist frequency and attenuate the treble too much.
+/// The pre-emphasis is the exact inverse of the de-emphasis.
+fn emphasis_coeffs(
+    conf: &config::EmphasisParameters,
+    samplerate: usize,
+    deemphasis: bool,
+) -> (PrcFmt, PrcFmt, PrcFmt) {
+    let pi = std::f64::consts::PI as PrcFmt;
+    let k = 2.0 * samplerate as PrcFmt;
+    let t1 = conf.t1_us / 1.0e6;
+    let t2 = conf.t2_us / 1.0e6;
+    let pole = k * (1.0 / (k * t1)).tan();
+    let a1 = (pole - k) / (k + pole);
+    let omega_nyquist = pi * samplerate as PrcFmt;
+    let gain_nyquist =
+        ((1.0 + (omega_nyquist * t2).powi(2)) / (1.0 + (omega_nyquist * t1).powi(2))).sqrt();
+    let sum = 1.0 + a1;
+    let diff = gain_nyquist * (1.0 - a1);
+    let b0 = (sum + diff) / 2.0;
+    let b1 = (sum - diff) / 2.0;
+    if deemphasis {
+        (b0, b1, a1)
+    } else {
+        (1.0 / b0, a1 / b0, b1 / b0)
+    }
+}
+
+impl Filter for Emphasis {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            let output = self.b0 * *item + self.b1 * self.prev_input - self.a1 * self.prev_output;
+            self.prev_input = *item;
+            self.prev_output = output;
+            *item = output;
+        }
+        if self.prev_output.is_subnormal() {
+            self.prev_output = 0.0;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        match conf {
+            config::Filter::Deemphasis {
+                parameters: conf, ..
+            } if self.deemphasis => {
+                (self.b0, self.b1, self.a1) = emphasis_coeffs(&conf, self.samplerate, true);
+            }
+            config::Filter::Preemphasis {
+                parameters: conf, ..
+            } if !self.deemphasis => {
+                (self.b0, self.b1, self.a1) = emphasis_coeffs(&conf, self.samplerate, false);
+            }
+            _ => {
+                // This should never happen unless there is a bug somewhere else
+                panic!("Invalid config change!");
+            }
+        }
+    }
+}
+
+impl Invert {
+    pub fn new(name: String) -> Self {
+        debug!("Creating polarity inversion filter '{}'", name);
+        Invert { name }
+    }
+}
+
+impl Filter for Invert {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            *item = -*item;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if !matches!(conf, config::Filter::Invert { .. }) {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a Delay config.
+pub fn validate_delay_config(samplerate: usize, conf: &config::DelayParameters) -> Res<()> {
     if conf.delay < 0.0 {
         return Err(config::ConfigError::new("Delay cannot be negative").into());
     }
+    let max_samples = MAX_DELAY_SECONDS * samplerate as PrcFmt;
+    if delay_in_samples(samplerate, conf) > max_samples {
+        let msg = format!(
+            "Delay cannot be longer than {} seconds ({} samples)",
+            MAX_DELAY_SECONDS, max_samples
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
     Ok(())
 }
 
@@ -357,25 +601,71 @@ pub fn validate_volume_config(conf: &config::VolumeParameters) -> Res<()> {
 
 /// Validate a Gain config.
 pub fn validate_gain_config(conf: &config::GainParameters) -> Res<()> {
-    if conf.gain < -150.0 {
+    // A linear gain is checked against the same limits, converted to dB
+    let gain_db = match conf.scale {
+        config::GainScale::Decibel => conf.gain,
+        config::GainScale::Linear => 20.0 * conf.gain.abs().log10(),
+    };
+    if gain_db.is_nan() || gain_db < -150.0 {
         return Err(config::ConfigError::new("Gain must be larger than -150 dB").into());
-    } else if conf.gain > 150.0 {
+    } else if gain_db > 150.0 {
         return Err(config::ConfigError::new("Gain must be less than +150 dB").into());
     }
     Ok(())
 }
 
+/// Validate an emphasis config.
+/// The upper corner frequency, given by t2, must be below half the sample rate.
+pub fn validate_emphasis_config(samplerate: usize, conf: &config::EmphasisParameters) -> Res<()> {
+    if conf.t2_us <= 0.0 {
+        return Err(config::ConfigError::new("Time constants must be positive").into());
+    } else if conf.t1_us <= conf.t2_us {
+        return Err(config::ConfigError::new("Time constant t1 must be larger than t2").into());
+    }
+    let pi = std::f64::consts::PI as PrcFmt;
+    let corner = 1.0e6 / (2.0 * pi * conf.t2_us);
+    if corner >= samplerate as PrcFmt / 2.0 {
+        let msg = format!(
+            "Sample rate {} is not supported, the emphasis needs a sample rate above {:.0} Hz",
+            samplerate,
+            2.0 * corner
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
+/// Validate a DcBlock config.
+pub fn validate_dcblock_config(samplerate: usize, conf: &config::DcBlockParameters) -> Res<()> {
+    if conf.cutoff <= 0.0 {
+        return Err(config::ConfigError::new("Cutoff frequency must be positive").into());
+    } else if conf.cutoff >= samplerate as PrcFmt / 2.0 {
+        return Err(config::ConfigError::new("Cutoff frequency must be < samplerate/2").into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::basicfilters::{Delay, Gain};
+    use crate::basicfilters::{
+        emphasis_coeffs, validate_dcblock_config, validate_delay_config, validate_emphasis_config,
+        validate_gain_config, DcBlock, Delay, Emphasis, Gain, Invert, Volume,
+    };
+    use crate::config;
+    use crate::config::{
+        DcBlockParameters, DelayParameters, EmphasisParameters, GainParameters, GainScale, TimeUnit,
+    };
     use crate::filters::Filter;
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::sync::{Arc, RwLock};
 
-    fn is_close(left: f64, right: f64, maxdiff: f64) -> bool {
+    fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {}", left, right);
         (left - right).abs() < maxdiff
     }
 
-    fn compare_waveforms(left: Vec<f64>, right: Vec<f64>, maxdiff: f64) -> bool {
+    fn compare_waveforms(left: Vec<PrcFmt>, right: Vec<PrcFmt>, maxdiff: PrcFmt) -> bool {
         for (val_l, val_r) in left.iter().zip(right.iter()) {
             if !is_close(*val_l, *val_r, maxdiff) {
                 return false;
@@ -402,6 +692,100 @@ mod tests {
         assert_eq!(waveform, waveform_ampl);
     }
 
+    #[test]
+    fn gain_scales() {
+        let conf: GainParameters = serde_yaml::from_str("gain: 20.0").unwrap();
+        assert_eq!(conf.scale, GainScale::Decibel);
+        let mut waveform = vec![-0.5, 0.0, 0.5];
+        Gain::from_config("test".to_string(), conf)
+            .process_waveform(&mut waveform)
+            .unwrap();
+        assert_eq!(waveform, vec![-5.0, 0.0, 5.0]);
+
+        let conf: GainParameters = serde_yaml::from_str("gain: 0.5\nscale: linear").unwrap();
+        assert_eq!(conf.scale, GainScale::Linear);
+        let mut waveform = vec![-0.5, 0.0, 0.5];
+        Gain::from_config("test".to_string(), conf)
+            .process_waveform(&mut waveform)
+            .unwrap();
+        assert_eq!(waveform, vec![-0.25, 0.0, 0.25]);
+    }
+
+    #[test]
+    fn check_gain_config() {
+        let make_conf = |gain, scale| GainParameters {
+            gain,
+            scale,
+            inverted: false,
+            mute: false,
+        };
+        assert!(validate_gain_config(&make_conf(-6.0, GainScale::Decibel)).is_ok());
+        assert!(validate_gain_config(&make_conf(-151.0, GainScale::Decibel)).is_err());
+        assert!(validate_gain_config(&make_conf(0.5, GainScale::Linear)).is_ok());
+        assert!(validate_gain_config(&make_conf(-2.0, GainScale::Linear)).is_ok());
+        assert!(validate_gain_config(&make_conf(0.0, GainScale::Linear)).is_err());
+        assert!(validate_gain_config(&make_conf(1.0e8, GainScale::Linear)).is_err());
+    }
+
+    #[test]
+    fn check_delay_config() {
+        let make_conf = |delay, unit| DelayParameters {
+            delay,
+            unit,
+            subsample: false,
+        };
+        assert!(validate_delay_config(48000, &make_conf(12.3, TimeUnit::Milliseconds)).is_ok());
+        assert!(validate_delay_config(48000, &make_conf(-1.0, TimeUnit::Milliseconds)).is_err());
+        assert!(validate_delay_config(48000, &make_conf(10000.0, TimeUnit::Milliseconds)).is_ok());
+        assert!(validate_delay_config(48000, &make_conf(10001.0, TimeUnit::Milliseconds)).is_err());
+        assert!(validate_delay_config(48000, &make_conf(480001.0, TimeUnit::Samples)).is_err());
+        assert!(validate_delay_config(48000, &make_conf(3430000.0, TimeUnit::Millimetres)).is_ok());
+    }
+
+    #[test]
+    fn gain_mute() {
+        let mut waveform = vec![-0.5, 0.0, 0.5, PrcFmt::NAN];
+        let mut gain = Gain::new("test".to_string(), 20.0, true, true);
+        gain.process_waveform(&mut waveform).unwrap();
+        assert_eq!(waveform, vec![0.0; 4]);
+    }
+
+    #[test]
+    fn volume_ramp() {
+        let params = Arc::new(RwLock::new(ProcessingParameters::default()));
+        // 4 samples per chunk at 1 kHz gives 4 ms chunks, a 16 ms ramp takes 4 chunks
+        let mut vol = Volume::new(
+            "test".to_string(),
+            16.0,
+            0.0,
+            false,
+            4,
+            1000,
+            params.clone(),
+        );
+        let mut waveform = vec![1.0; 4];
+        vol.process_waveform(&mut waveform).unwrap();
+        assert_eq!(waveform, vec![1.0; 4]);
+
+        params.write().unwrap().volume = -20.0;
+        let mut output: Vec<PrcFmt> = Vec::new();
+        for _ in 0..4 {
+            let mut waveform = vec![1.0; 4];
+            vol.process_waveform(&mut waveform).unwrap();
+            output.extend(waveform);
+        }
+        // The gain must decrease steadily without any jumps
+        for pair in output.windows(2) {
+            assert!(pair[1] < pair[0]);
+        }
+        assert!(*output.last().unwrap() > 0.1);
+
+        // Ramp is done, the target gain is applied
+        let mut waveform = vec![1.0; 4];
+        vol.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform, vec![0.1; 4], 1.0e-9));
+    }
+
     #[test]
     fn delay_small() {
         let mut waveform = vec![0.0, -0.5, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
@@ -442,4 +826,149 @@ mod tests {
         delay.process_waveform(&mut waveform).unwrap();
         assert!(compare_waveforms(waveform, waveform_delayed, 1.0e-6));
     }
+
+    #[test]
+    fn dcblock_removes_offset() {
+        let conf = DcBlockParameters { cutoff: 5.0 };
+        let mut filter = DcBlock::from_config("test".to_string(), 44100, conf);
+        let mut last_mean = 0.0;
+        // Process one second of a constant offset in chunks, state must be kept between them
+        for _ in 0..44 {
+            let mut waveform = vec![0.5; 1000];
+            filter.process_waveform(&mut waveform).unwrap();
+            last_mean = waveform.iter().sum::<PrcFmt>() / waveform.len() as PrcFmt;
+        }
+        assert!(last_mean.abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn dcblock_passes_audio() {
+        let conf = DcBlockParameters { cutoff: 5.0 };
+        let mut filter = DcBlock::from_config("test".to_string(), 44100, conf);
+        let pi = std::f64::consts::PI as PrcFmt;
+        let sine: Vec<PrcFmt> = (0..44100)
+            .map(|n| (2.0 * pi * 1000.0 * n as PrcFmt / 44100.0).sin())
+            .collect();
+        let mut waveform = sine.clone();
+        filter.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(
+            waveform[1000..].to_vec(),
+            sine[1000..].to_vec(),
+            0.01
+        ));
+    }
+
+    #[test]
+    fn check_dcblock_config() {
+        let okconf = DcBlockParameters { cutoff: 5.0 };
+        assert!(validate_dcblock_config(44100, &okconf).is_ok());
+        let badconf = DcBlockParameters { cutoff: 0.0 };
+        assert!(validate_dcblock_config(44100, &badconf).is_err());
+        let badconf = DcBlockParameters { cutoff: 30000.0 };
+        assert!(validate_dcblock_config(44100, &badconf).is_err());
+    }
+
+    fn emphasis_gain_db(
+        coeffs: (PrcFmt, PrcFmt, PrcFmt),
+        freq: PrcFmt,
+        samplerate: usize,
+    ) -> PrcFmt {
+        let (b0, b1, a1) = coeffs;
+        let omega = 2.0 * std::f64::consts::PI as PrcFmt * freq / samplerate as PrcFmt;
+        let num = (b0 + b1 * omega.cos()).hypot(b1 * omega.sin());
+        let den = (1.0 + a1 * omega.cos()).hypot(a1 * omega.sin());
+        20.0 * (num / den).log10()
+    }
+
+    #[test]
+    fn deemphasis_response() {
+        // Analog 50/15 us de-emphasis: 0 dB at DC, -0.37 dB at 1 kHz, -7.6 dB at 10 kHz
+        for samplerate in [44100, 48000, 96000] {
+            let coeffs = emphasis_coeffs(&EmphasisParameters::default(), samplerate, true);
+            assert!(is_close(
+                emphasis_gain_db(coeffs, 0.0, samplerate),
+                0.0,
+                1.0e-9
+            ));
+            assert!(is_close(
+                emphasis_gain_db(coeffs, 1000.0, samplerate),
+                -0.37,
+                0.05
+            ));
+            assert!(is_close(
+                emphasis_gain_db(coeffs, 10000.0, samplerate),
+                -7.6,
+                0.3
+            ));
+        }
+    }
+
+    #[test]
+    fn emphasis_pair_cancels() {
+        let mut pre = Emphasis::from_config(
+            "pre".to_string(),
+            44100,
+            EmphasisParameters::default(),
+            false,
+        );
+        let mut de =
+            Emphasis::from_config("de".to_string(), 44100, EmphasisParameters::default(), true);
+        let mut waveform = vec![0.0; 64];
+        waveform[0] = 1.0;
+        let impulse = waveform.clone();
+        pre.process_waveform(&mut waveform).unwrap();
+        assert!(!compare_waveforms(
+            waveform.clone(),
+            impulse.clone(),
+            1.0e-3
+        ));
+        de.process_waveform(&mut waveform).unwrap();
+        #[cfg(not(feature = "32bit"))]
+        let maxdiff = 1.0e-12;
+        #[cfg(feature = "32bit")]
+        let maxdiff = 1.0e-5;
+        assert!(compare_waveforms(waveform, impulse, maxdiff));
+    }
+
+    #[test]
+    fn check_emphasis_config() {
+        let okconf = EmphasisParameters::default();
+        assert!(validate_emphasis_config(44100, &okconf).is_ok());
+        assert!(validate_emphasis_config(32000, &okconf).is_ok());
+        assert!(validate_emphasis_config(16000, &okconf).is_err());
+        let badconf = EmphasisParameters {
+            t1_us: 15.0,
+            t2_us: 50.0,
+        };
+        assert!(validate_emphasis_config(44100, &badconf).is_err());
+        let badconf = EmphasisParameters {
+            t1_us: 50.0,
+            t2_us: 0.0,
+        };
+        assert!(validate_emphasis_config(44100, &badconf).is_err());
+    }
+
+    #[test]
+    fn invert_polarity() {
+        let mut filter = Invert::new("test".to_string());
+        let mut waveform = vec![-0.5, 0.0, 0.25, 1.0];
+        filter.process_waveform(&mut waveform).unwrap();
+        assert_eq!(waveform, vec![0.5, 0.0, -0.25, -1.0]);
+    }
+
+    #[test]
+    fn parse_invert() {
+        let conf: config::Filter = serde_yaml::from_str("type: Invert").unwrap();
+        assert_eq!(
+            conf,
+            config::Filter::Invert {
+                description: None,
+                bypassed: false
+            }
+        );
+        assert!(
+            serde_yaml::from_str::<config::Filter>("{type: Invert, parameters: {gain: 1}}")
+                .is_err()
+        );
+    }
 }
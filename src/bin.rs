@@ -32,6 +32,7 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier, Mutex, RwLock};
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use flexi_logger::DeferredNow;
 use log::Record;
@@ -41,6 +42,8 @@ use camillalib::Res;
 
 use camillalib::audiodevice;
 use camillalib::config;
+#[cfg(unix)]
+use camillalib::outputtap;
 use camillalib::processing;
 #[cfg(feature = "websocket")]
 use camillalib::socketserver;
@@ -48,9 +51,9 @@ use camillalib::socketserver;
 use std::net::IpAddr;
 
 use camillalib::{
-    list_supported_devices, CaptureStatus, CommandMessage, ExitRequest, ExitState, PlaybackStatus,
-    ProcessingParameters, ProcessingState, ProcessingStatus, StatusMessage, StatusStructs,
-    StopReason,
+    list_supported_devices, CaptureStatus, ClippedChannels, CommandMessage, ExitRequest, ExitState,
+    PlaybackStatus, ProcessingParameters, ProcessingState, ProcessingStatus, StatusMessage,
+    StatusStructs, StopReason, XrunCounts, PROCESSING_BITS,
 };
 
 const EXIT_BAD_CONFIG: i32 = 101; // Error in config file
@@ -103,6 +106,20 @@ pub fn custom_logger_format(
     )
 }
 
+/// Format a time in UTC for the log.
+fn format_timestamp(time: SystemTime) -> String {
+    time::OffsetDateTime::from(time)
+        .format(&TS)
+        .unwrap_or_else(|_| "unknown time".to_string())
+}
+
+/// Get a time in seconds since the Unix epoch.
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
 fn get_new_config(
     config_path: &Arc<Mutex<Option<String>>>,
     new_config_shared: &Arc<Mutex<Option<config::Configuration>>>,
@@ -125,6 +142,11 @@ fn get_new_config(
             }
         }
     } else if let Some(file) = path {
+        if file == config::STDIN_CONFIG {
+            let msg = "The config was read from stdin, it can't be reloaded";
+            error!("{}", msg);
+            return Err(config::ConfigError::new(msg).into());
+        }
         match config::load_config(&file) {
             Ok(mut conf) => match config::validate_config(&mut conf, Some(&file)) {
                 Ok(()) => {
@@ -149,6 +171,69 @@ fn get_new_config(
     }
 }
 
+/// Get a config for restarting after the capture sample rate changed.
+/// The new rate is stored as a samplerate override, so that it is kept when the config is reloaded later.
+/// The config file is loaded again when there is one, so that `$samplerate$` tokens get the new rate.
+/// Without a file, the active config is changed directly.
+fn config_for_new_rate(
+    config_path: &Arc<Mutex<Option<String>>>,
+    active_config: &config::Configuration,
+    measured_rate: usize,
+) -> Option<config::Configuration> {
+    let rate = match config::nearest_standard_samplerate(measured_rate) {
+        Some(rate) => rate,
+        None => {
+            warn!(
+                "Measured capture rate {} Hz is not close to any standard sample rate, not restarting",
+                measured_rate
+            );
+            return None;
+        }
+    };
+    info!("Restarting with a capture sample rate of {} Hz", rate);
+    config::OVERRIDES.write().unwrap().samplerate = Some(rate);
+    let path = config_path
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|file| file != config::STDIN_CONFIG);
+    let mut conf = match &path {
+        Some(file) => match config::load_config(file) {
+            Ok(conf) => conf,
+            Err(err) => {
+                error!("Config file error, not restarting: {}", err);
+                return None;
+            }
+        },
+        None => {
+            let mut conf = active_config.clone();
+            config::set_samplerate(&mut conf, rate);
+            conf
+        }
+    };
+    match config::validate_config(&mut conf, path.as_deref()) {
+        Ok(()) => Some(conf),
+        Err(err) => {
+            error!(
+                "Config is not valid for the new rate, not restarting: {}",
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Print the startup summary of a config as a single line of JSON.
+/// It goes to stderr when the processed audio is written to stdout.
+fn print_summary(conf: &config::Configuration) {
+    let summary = serde_json::to_string(&config::config_summary(conf)).unwrap();
+    if let config::PlaybackDevice::Stdout { .. } = conf.devices.playback {
+        eprintln!("{}", summary);
+    } else {
+        println!("{}", summary);
+    }
+}
+
 fn run(
     signal_reload: Arc<AtomicBool>,
     signal_exit: Arc<AtomicUsize>,
@@ -159,6 +244,7 @@ fn run(
     status_structs: StatusStructs,
 ) -> Res<ExitState> {
     status_structs.capture.write().unwrap().state = ProcessingState::Starting;
+    status_structs.playback.write().unwrap().reset_clipped();
     let mut is_starting = true;
     let conf = match new_config_shared.lock().unwrap().clone() {
         Some(cfg) => cfg,
@@ -172,6 +258,7 @@ fn run(
 
     let (tx_status, rx_status) = mpsc::channel();
     let tx_status_pb = tx_status.clone();
+    let tx_status_proc = tx_status.clone();
     let tx_status_cap = tx_status;
 
     let (tx_command_cap, rx_command_cap) = mpsc::channel();
@@ -200,13 +287,19 @@ fn run(
         tx_pb,
         rx_cap,
         rx_pipeconf,
-        status_structs.processing,
+        tx_status_proc,
+        status_structs.clone(),
     );
 
     // Playback thread
     let mut playback_dev = audiodevice::get_playback_device(conf_pb.devices);
     let pb_handle = playback_dev
-        .start(rx_pb, barrier_pb, tx_status_pb, status_structs.playback)
+        .start(
+            rx_pb,
+            barrier_pb,
+            tx_status_pb,
+            status_structs.playback.clone(),
+        )
         .unwrap();
 
     let used_channels = config::get_used_capture_channels(&active_config);
@@ -245,6 +338,8 @@ fn run(
 
             match new_config {
                 Ok(conf) => {
+                    // The clipping counts are since the config was loaded
+                    status_structs.playback.write().unwrap().reset_clipped();
                     let comp = config::config_diff(&active_config, &conf);
                     match comp {
                         config::ConfigChange::Pipeline
@@ -401,7 +496,12 @@ fn run(
                     status_structs.status.write().unwrap().stop_reason =
                         StopReason::CaptureFormatChange(rate);
                     pb_handle.join().unwrap();
-                    *new_config_shared.lock().unwrap() = None;
+                    *new_config_shared.lock().unwrap() =
+                        if active_config.devices.adapt_to_rate_change {
+                            config_for_new_rate(&config_path, &active_config, rate)
+                        } else {
+                            None
+                        };
                     *prev_config_shared.lock().unwrap() = Some(active_config);
                     trace!("All threads stopped, returning");
                     return Ok(ExitState::Restart);
@@ -428,6 +528,49 @@ fn run(
                         debug!("Capture thread has already exited");
                     }
                 }
+                StatusMessage::ProcessingTime(time) => {
+                    debug!(
+                        "Processing time: {:.2} ms per chunk, playback buffer level: {}",
+                        time,
+                        status_structs.playback.read().unwrap().buffer_level
+                    );
+                    status_structs.status.write().unwrap().processing_time = time;
+                }
+                StatusMessage::PlaybackLatency(latency) => {
+                    debug!("Playback latency: {:.1} ms", latency);
+                    status_structs.status.write().unwrap().playback_latency = latency;
+                }
+                StatusMessage::PlaybackXrun { count, time } => {
+                    warn!(
+                        "Playback buffer underrun at {} UTC, {} since the device was opened",
+                        format_timestamp(time),
+                        count
+                    );
+                    let mut status = status_structs.status.write().unwrap();
+                    status.xruns.playback += 1;
+                    status.xruns.last_playback_time = unix_seconds(time);
+                }
+                StatusMessage::CaptureXrun { count, time } => {
+                    warn!(
+                        "Capture buffer overrun at {} UTC, {} since the device was opened",
+                        format_timestamp(time),
+                        count
+                    );
+                    let mut status = status_structs.status.write().unwrap();
+                    status.xruns.capture += 1;
+                    status.xruns.last_capture_time = unix_seconds(time);
+                }
+                StatusMessage::Spectrum(spectrum) => {
+                    status_structs.status.write().unwrap().spectrum = spectrum;
+                }
+                StatusMessage::CaptureSilence { silent, time } => {
+                    let timestamp = format_timestamp(time);
+                    if silent {
+                        info!("Capture signal became silent at {} UTC", timestamp);
+                    } else {
+                        info!("Capture signal returned at {} UTC", timestamp);
+                    }
+                }
             },
             Err(mpsc::RecvTimeoutError::Timeout) => {}
             Err(mpsc::RecvTimeoutError::Disconnected) => {
@@ -503,6 +646,12 @@ fn main_process() -> i32 {
                 .long("check")
                 .requires("configfile"),
         )
+        .arg(
+            Arg::with_name("describe")
+                .help("Print a JSON summary of the config at startup")
+                .long("describe")
+                .requires("configfile"),
+        )
         .arg(
             Arg::with_name("verbosity")
                 .short("v")
@@ -613,8 +762,19 @@ fn main_process() -> i32 {
                 .possible_value("S32LE")
                 .possible_value("FLOAT32LE")
                 .possible_value("FLOAT64LE")
+                .possible_value("S16BE")
+                .possible_value("S24BE")
+                .possible_value("S32BE")
                 .help("Override sample format of capture device in config"),
         );
+    #[cfg(unix)]
+    let clapapp = clapapp.arg(
+        Arg::with_name("tap")
+            .help("Serve the processed output as raw float frames on a Unix domain socket at this path")
+            .long("tap")
+            .display_order(200)
+            .takes_value(true),
+    );
     #[cfg(feature = "websocket")]
     let clapapp = clapapp
         .arg(
@@ -633,14 +793,28 @@ fn main_process() -> i32 {
                     Err(String::from("Must be an integer between 0 and 65535"))
                 }),
         )
+        .arg(
+            Arg::with_name("tcpport")
+                .help("Port for plain TCP control server, taking one JSON command per line")
+                .long("tcpport")
+                .display_order(200)
+                .takes_value(true)
+                .validator(|v: String| -> Result<(), String> {
+                    if let Ok(port) = v.parse::<usize>() {
+                        if port > 0 && port < 65535 {
+                            return Ok(());
+                        }
+                    }
+                    Err(String::from("Must be an integer between 0 and 65535"))
+                }),
+        )
         .arg(
             Arg::with_name("address")
-                .help("IP address to bind websocket server to")
+                .help("IP address to bind websocket and TCP servers to")
                 .short("a")
                 .long("address")
                 .display_order(200)
                 .takes_value(true)
-                .requires("port")
                 .validator(|val: String| -> Result<(), String> {
                     if val.parse::<IpAddr>().is_ok() {
                         return Ok(());
@@ -714,6 +888,7 @@ fn main_process() -> i32 {
         std::env::consts::OS,
         std::env::consts::ARCH
     );
+    info!("Processing with {}-bit floats", PROCESSING_BITS);
     // logging examples
     //trace!("trace message"); //with -vv
     //debug!("debug message"); //with -v
@@ -755,8 +930,13 @@ fn main_process() -> i32 {
 
     if matches.is_present("check") {
         match config::load_validate_config(&configname.unwrap()) {
-            Ok(_) => {
-                println!("Config is valid");
+            Ok(conf) => {
+                // With --describe the output is only the JSON, the exit code tells that it is valid
+                if matches.is_present("describe") {
+                    print_summary(&conf);
+                } else {
+                    println!("Config is valid");
+                }
                 return EXIT_OK;
             }
             Err(err) => {
@@ -771,6 +951,9 @@ fn main_process() -> i32 {
         Some(path) => match config::load_validate_config(&path.clone()) {
             Ok(conf) => {
                 debug!("Config is valid");
+                if matches.is_present("describe") {
+                    print_summary(&conf);
+                }
                 Some(conf)
             }
             Err(err) => {
@@ -799,6 +982,7 @@ fn main_process() -> i32 {
     let playback_status = Arc::new(RwLock::new(PlaybackStatus {
         buffer_level: 0,
         clipped_samples: 0,
+        clipped_channels: ClippedChannels::default(),
         update_interval: 1000,
         signal_rms: Vec::new(),
         signal_peak: Vec::new(),
@@ -806,16 +990,36 @@ fn main_process() -> i32 {
     let processing_status = Arc::new(RwLock::new(ProcessingParameters {
         volume: initial_volume,
         mute: initial_mute,
+        ..Default::default()
     }));
     let status = Arc::new(RwLock::new(ProcessingStatus {
         stop_reason: StopReason::None,
+        processing_time: 0.0,
+        playback_latency: 0.0,
+        spectrum: Vec::new(),
+        xruns: XrunCounts::default(),
     }));
 
+    #[cfg(unix)]
+    let tap = matches.value_of("tap").and_then(|path| {
+        let tap = Arc::new(outputtap::OutputTap::new(outputtap::TAP_BUFFER_FRAMES));
+        match outputtap::start_tap_server(path, tap.clone()) {
+            Ok(()) => Some(tap),
+            Err(err) => {
+                error!("Could not start output tap on {}, error: {}", path, err);
+                None
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let tap = None;
+
     let status_structs = StatusStructs {
         capture: capture_status.clone(),
         playback: playback_status.clone(),
         processing: processing_status.clone(),
         status: status.clone(),
+        tap,
     };
     let active_config = Arc::new(Mutex::new(None));
     let new_config = Arc::new(Mutex::new(configuration));
@@ -825,21 +1029,25 @@ fn main_process() -> i32 {
 
     #[cfg(feature = "websocket")]
     {
+        let serveraddress = matches.value_of("address").unwrap_or("127.0.0.1");
+        let shared_data = socketserver::SharedData {
+            signal_reload: signal_reload.clone(),
+            signal_exit: signal_exit.clone(),
+            active_config: active_config.clone(),
+            active_config_path: active_config_path.clone(),
+            new_config: new_config.clone(),
+            previous_config: previous_config.clone(),
+            capture_status,
+            playback_status,
+            processing_status,
+            status,
+        };
+        if let Some(port_str) = matches.value_of("tcpport") {
+            let tcpport = port_str.parse::<usize>().unwrap();
+            socketserver::start_tcp_server(serveraddress, tcpport, shared_data.clone());
+        }
         if let Some(port_str) = matches.value_of("port") {
-            let serveraddress = matches.value_of("address").unwrap_or("127.0.0.1");
             let serverport = port_str.parse::<usize>().unwrap();
-            let shared_data = socketserver::SharedData {
-                signal_reload: signal_reload.clone(),
-                signal_exit: signal_exit.clone(),
-                active_config: active_config.clone(),
-                active_config_path: active_config_path.clone(),
-                new_config: new_config.clone(),
-                previous_config: previous_config.clone(),
-                capture_status,
-                playback_status,
-                processing_status,
-                status,
-            };
             let server_params = socketserver::ServerParameters {
                 port: serverport,
                 address: serveraddress,
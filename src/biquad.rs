@@ -4,6 +4,7 @@
 //mod filters;
 
 use crate::config;
+use crate::filters;
 use crate::filters::Filter;
 
 // Sample format
@@ -64,6 +65,11 @@ impl BiquadCoefficients {
     ///   The frequency specified is the middle of the slope
     /// - Lowshelf: shelving filter affecting low frequencies with arbitrary slope in between.
     ///   The frequency specified is the middle of the slope
+    /// - Notch: notch filter specified by frequency and Q-value or bandwidth.
+    /// - Bandpass: bandpass filter specified by frequency and Q-value or bandwidth.
+    ///   Uses the constant 0 dB peak gain variant, so the gain at the center frequency is unity.
+    /// - Allpass: second order allpass specified by frequency and Q-value or bandwidth.
+    ///   The phase shift is 180 degrees at the given frequency.
     pub fn from_config(fs: usize, parameters: config::BiquadParameters) -> Self {
         match parameters {
             config::BiquadParameters::Free { a1, a2, b0, b1, b2 } => {
@@ -425,12 +431,22 @@ impl Filter for Biquad {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Biquad { parameters: conf } = conf {
-            let coeffs = BiquadCoefficients::from_config(self.samplerate, conf);
-            self.coeffs = coeffs;
-        } else {
-            // This should never happen unless there is a bug somewhere else
-            panic!("Invalid config change!");
+        match conf {
+            config::Filter::Biquad {
+                parameters: conf, ..
+            } => {
+                self.coeffs = BiquadCoefficients::from_config(self.samplerate, conf);
+            }
+            config::Filter::ShelfGain {
+                parameters: conf, ..
+            } => {
+                let conf = filters::shelf_gain_biquad(&conf);
+                self.coeffs = BiquadCoefficients::from_config(self.samplerate, conf);
+            }
+            _ => {
+                // This should never happen unless there is a bug somewhere else
+                panic!("Invalid config change!");
+            }
         }
     }
 }
@@ -642,6 +658,30 @@ mod tests {
         assert!(is_close(gain_hf, 0.0, 0.1));
     }
 
+    #[test]
+    fn first_order_slope() {
+        let lowpass =
+            BiquadCoefficients::from_config(48000, BiquadParameters::LowpassFO { freq: 100.0 });
+        let highpass =
+            BiquadCoefficients::from_config(48000, BiquadParameters::HighpassFO { freq: 1000.0 });
+        for coeffs in [lowpass, highpass] {
+            assert_eq!(coeffs.a2, 0.0);
+            assert_eq!(coeffs.b2, 0.0);
+        }
+        // 6 dB per octave far from the corner frequency
+        let (gain_1k, _) = gain_and_phase(lowpass, 1000.0, 48000);
+        let (gain_2k, _) = gain_and_phase(lowpass, 2000.0, 48000);
+        assert!(is_close(gain_1k - gain_2k, 6.0, 0.1));
+        let (gain_50, _) = gain_and_phase(highpass, 50.0, 48000);
+        let (gain_100, _) = gain_and_phase(highpass, 100.0, 48000);
+        assert!(is_close(gain_100 - gain_50, 6.0, 0.1));
+        for freq in [0.0, 24000.0, 30000.0] {
+            assert!(validate_config(48000, &BiquadParameters::LowpassFO { freq }).is_err());
+            assert!(validate_config(48000, &BiquadParameters::HighpassFO { freq }).is_err());
+        }
+        assert!(validate_config(48000, &BiquadParameters::HighpassFO { freq: 23000.0 }).is_ok());
+    }
+
     #[test]
     fn make_peaking() {
         let conf = BiquadParameters::Peaking(PeakingWidth::Q {
@@ -710,6 +750,33 @@ mod tests {
         assert!(is_close(phase_hf, 0.0, 0.5));
     }
 
+    #[test]
+    fn allpass_flat_magnitude() {
+        let fs = 48000;
+        let confs = vec![
+            BiquadParameters::Allpass(NotchWidth::Q { freq: 80.0, q: 0.7 }),
+            BiquadParameters::Allpass(NotchWidth::Q {
+                freq: 2000.0,
+                q: 10.0,
+            }),
+            BiquadParameters::Allpass(NotchWidth::Bandwidth {
+                freq: 500.0,
+                bandwidth: 2.0,
+            }),
+            BiquadParameters::AllpassFO { freq: 300.0 },
+        ];
+        for conf in confs {
+            let coeffs = BiquadCoefficients::from_config(fs, conf);
+            assert!(coeffs.is_stable());
+            let mut f = 10.0;
+            while f < 23000.0 {
+                let (gain, _) = gain_and_phase(coeffs, f, fs);
+                assert!(gain.abs() < 1.0e-4, "gain {} dB at {} Hz", gain, f);
+                f *= 1.1;
+            }
+        }
+    }
+
     #[test]
     fn make_allpass_fo() {
         let conf = BiquadParameters::AllpassFO { freq: 100.0 };
@@ -948,6 +1015,42 @@ mod tests {
         assert!(validate_config(fs, &badconf3).is_err());
     }
 
+    #[test]
+    fn bandpass_peak_gain() {
+        for q in [0.5, 1.0, 5.0, 20.0] {
+            let conf = BiquadParameters::Bandpass(NotchWidth::Q { freq: 1000.0, q });
+            let coeffs = BiquadCoefficients::from_config(48000, conf);
+            let (gain_f0, phase_f0) = gain_and_phase(coeffs, 1000.0, 48000);
+            assert!(is_close(gain_f0, 0.0, 0.01));
+            assert!(is_close(phase_f0, 0.0, 0.1));
+        }
+        let badconf = BiquadParameters::Bandpass(NotchWidth::Q {
+            freq: 24000.0,
+            q: 1.0,
+        });
+        assert!(validate_config(48000, &badconf).is_err());
+    }
+
+    #[test]
+    fn check_notch_freq() {
+        let fs = 48000;
+        let okconf = BiquadParameters::Notch(NotchWidth::Q {
+            freq: 50.0,
+            q: 10.0,
+        });
+        assert!(validate_config(fs, &okconf).is_ok());
+        let badconf1 = BiquadParameters::Notch(NotchWidth::Q {
+            freq: 24000.0,
+            q: 10.0,
+        });
+        assert!(validate_config(fs, &badconf1).is_err());
+        let badconf2 = BiquadParameters::Notch(NotchWidth::Bandwidth {
+            freq: 30000.0,
+            bandwidth: 0.1,
+        });
+        assert!(validate_config(fs, &badconf2).is_err());
+    }
+
     #[test]
     fn check_slope() {
         let fs = 48000;
@@ -5,6 +5,7 @@
 
 use crate::biquad;
 use crate::config;
+use crate::filters;
 use crate::filters::Filter;
 
 // Sample format
@@ -12,6 +13,10 @@ use crate::filters::Filter;
 use crate::PrcFmt;
 use crate::Res;
 
+/// Frequency range covered by the shelves of a Tilt.
+const TILT_MIN_FREQ: PrcFmt = 10.0;
+const TILT_MAX_FREQ: PrcFmt = 20000.0;
+
 #[derive(Clone, Debug)]
 pub struct BiquadCombo {
     samplerate: usize,
@@ -19,6 +24,36 @@ pub struct BiquadCombo {
     filters: Vec<biquad::Biquad>,
 }
 
+/// Get the stages of a tilt, a cascade of first order shelves, one per octave.
+/// Highshelves raise the response above the pivot, and lowshelves lower it below.
+/// Each shelf is centered between two octaves, so that each pair on either side
+/// of the pivot cancels there and the pivot stays at 0 dB.
+pub fn tilt_stages(
+    samplerate: usize,
+    pivot_freq: PrcFmt,
+    slope_db_oct: PrcFmt,
+) -> Vec<config::BiquadParameters> {
+    let maxfreq = TILT_MAX_FREQ.min(0.4 * samplerate as PrcFmt);
+    let mut shelves = Vec::new();
+    let mut freq = pivot_freq * (2.0 as PrcFmt).sqrt();
+    while freq < maxfreq {
+        shelves.push(config::BiquadParameters::HighshelfFO {
+            freq,
+            gain: slope_db_oct,
+        });
+        freq *= 2.0;
+    }
+    let mut freq = pivot_freq / (2.0 as PrcFmt).sqrt();
+    while freq > TILT_MIN_FREQ {
+        shelves.push(config::BiquadParameters::LowshelfFO {
+            freq,
+            gain: -slope_db_oct,
+        });
+        freq /= 2.0;
+    }
+    shelves
+}
+
 impl BiquadCombo {
     fn butterworth_q(order: usize) -> Vec<PrcFmt> {
         let odd = order % 2 > 0;
@@ -114,6 +149,48 @@ impl BiquadCombo {
         filters
     }
 
+    fn make_peaking_bank(samplerate: usize, bands: &[config::PeakingBand]) -> Vec<biquad::Biquad> {
+        bands
+            .iter()
+            .map(|band| {
+                let filtconf = config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+                    freq: band.freq,
+                    q: band.q,
+                    gain: band.gain,
+                });
+                let coeffs = biquad::BiquadCoefficients::from_config(samplerate, filtconf);
+                biquad::Biquad::new("".to_string(), samplerate, coeffs)
+            })
+            .collect()
+    }
+
+    fn make_cascade(
+        samplerate: usize,
+        stages: Vec<config::BiquadParameters>,
+    ) -> Vec<biquad::Biquad> {
+        stages
+            .into_iter()
+            .map(|stage| {
+                let coeffs = biquad::BiquadCoefficients::from_config(samplerate, stage);
+                biquad::Biquad::new("".to_string(), samplerate, coeffs)
+            })
+            .collect()
+    }
+
+    fn make_tilt(
+        samplerate: usize,
+        pivot_freq: PrcFmt,
+        slope_db_oct: PrcFmt,
+    ) -> Vec<biquad::Biquad> {
+        tilt_stages(samplerate, pivot_freq, slope_db_oct)
+            .into_iter()
+            .map(|filtconf| {
+                let coeffs = biquad::BiquadCoefficients::from_config(samplerate, filtconf);
+                biquad::Biquad::new("".to_string(), samplerate, coeffs)
+            })
+            .collect()
+    }
+
     pub fn from_config(
         name: String,
         samplerate: usize,
@@ -156,6 +233,24 @@ impl BiquadCombo {
                     filters,
                 }
             }
+            config::BiquadComboParameters::HighpassSlope { slope_db_oct, freq } => {
+                let qvalues = BiquadCombo::butterworth_q(slope_db_oct / 6);
+                let filters = BiquadCombo::make_highpass(samplerate, freq, qvalues);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::LowpassSlope { slope_db_oct, freq } => {
+                let qvalues = BiquadCombo::butterworth_q(slope_db_oct / 6);
+                let filters = BiquadCombo::make_lowpass(samplerate, freq, qvalues);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
             config::BiquadComboParameters::FivePointPeq {
                 fls,
                 qls,
@@ -185,6 +280,42 @@ impl BiquadCombo {
                     filters,
                 }
             }
+            config::BiquadComboParameters::PeakingBank { bands } => {
+                let filters = BiquadCombo::make_peaking_bank(samplerate, &bands);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::Tilt {
+                pivot_freq,
+                slope_db_oct,
+            } => {
+                let filters = BiquadCombo::make_tilt(samplerate, pivot_freq, slope_db_oct);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::Cascade { stages } => {
+                let filters = BiquadCombo::make_cascade(samplerate, stages);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::File { filename } => {
+                let stages = filters::read_biquad_file(&filename).unwrap();
+                let filters = BiquadCombo::make_cascade(samplerate, stages);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
         }
     }
 }
@@ -202,7 +333,10 @@ impl Filter for BiquadCombo {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::BiquadCombo { parameters: conf } = conf {
+        if let config::Filter::BiquadCombo {
+            parameters: conf, ..
+        } = conf
+        {
             let name = self.name.clone();
             *self = BiquadCombo::from_config(name, self.samplerate, conf);
         } else {
@@ -244,6 +378,21 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
             }
             Ok(())
         }
+        config::BiquadComboParameters::HighpassSlope { freq, slope_db_oct }
+        | config::BiquadComboParameters::LowpassSlope { freq, slope_db_oct } => {
+            if *freq <= 0.0 {
+                return Err(config::ConfigError::new("Frequency must be > 0").into());
+            } else if *freq >= maxfreq {
+                return Err(config::ConfigError::new("Frequency must be < samplerate/2").into());
+            }
+            if (*slope_db_oct % 6 > 0) || (*slope_db_oct == 0) {
+                return Err(config::ConfigError::new(
+                    "Slope must be a non-zero multiple of 6 dB/oct",
+                )
+                .into());
+            }
+            Ok(())
+        }
         config::BiquadComboParameters::FivePointPeq {
             fls,
             qls,
@@ -259,24 +408,92 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
         } => {
             if *qls <= 0.0 || *qhs <= 0.0 || *qp1 <= 0.0 || *qp2 <= 0.0 || *qp3 <= 0.0 {
                 return Err(config::ConfigError::new("All Q-values must be > 0").into());
-            } else if *fls >= maxfreq
-                || *fhs >= maxfreq
-                || *fp1 >= maxfreq
-                || *fp2 >= maxfreq
-                || *fp3 >= maxfreq
-            {
+            }
+            let freqs = [fls, fp1, fp2, fp3, fhs];
+            if freqs.iter().any(|freq| **freq <= 0.0) {
                 return Err(config::ConfigError::new("All frequencies must be > 0").into());
+            } else if freqs.iter().any(|freq| **freq >= maxfreq) {
+                return Err(
+                    config::ConfigError::new("All frequencies must be < samplerate/2").into(),
+                );
             }
             Ok(())
         }
+        config::BiquadComboParameters::PeakingBank { bands } => {
+            if bands.is_empty() {
+                return Err(
+                    config::ConfigError::new("PeakingBank must have at least one band").into(),
+                );
+            }
+            for (n, band) in bands.iter().enumerate() {
+                let msg = if band.freq <= 0.0 {
+                    "Frequency must be > 0"
+                } else if band.freq >= maxfreq {
+                    "Frequency must be < samplerate/2"
+                } else if band.q <= 0.0 {
+                    "Q must be > 0"
+                } else {
+                    continue;
+                };
+                let msg = format!("Band {}: {}", n, msg);
+                return Err(config::ConfigError::new(&msg).into());
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::Tilt {
+            pivot_freq,
+            slope_db_oct,
+        } => {
+            if *pivot_freq <= 0.0 {
+                return Err(config::ConfigError::new("Pivot frequency must be > 0").into());
+            } else if *pivot_freq >= maxfreq {
+                return Err(
+                    config::ConfigError::new("Pivot frequency must be < samplerate/2").into(),
+                );
+            }
+            if !slope_db_oct.is_finite() {
+                return Err(config::ConfigError::new("Slope must be a finite number").into());
+            } else if slope_db_oct.abs() > 6.0 {
+                return Err(
+                    config::ConfigError::new("Slope must be in the range -6 to +6 dB/oct").into(),
+                );
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::Cascade { stages } => {
+            if stages.is_empty() {
+                return Err(
+                    config::ConfigError::new("Cascade must have at least one stage").into(),
+                );
+            }
+            validate_stages(samplerate, stages)
+        }
+        config::BiquadComboParameters::File { filename } => {
+            let stages = filters::read_biquad_file(filename)?;
+            validate_stages(samplerate, &stages)
+        }
     }
 }
 
+/// Validate each stage of a cascade as a Biquad filter.
+fn validate_stages(samplerate: usize, stages: &[config::BiquadParameters]) -> Res<()> {
+    for (n, stage) in stages.iter().enumerate() {
+        if let Err(err) = biquad::validate_config(samplerate, stage) {
+            let msg = format!("Stage {}: {}", n, err);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::biquad;
     use crate::biquadcombo;
     use crate::config;
+    use crate::filters::Filter;
     use crate::PrcFmt;
+    use num_complex::Complex;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {}", left, right);
@@ -315,6 +532,281 @@ mod tests {
         assert!(compare_vecs(q, expect, 0.01));
     }
 
+    fn impulse_response(filter: &mut biquadcombo::BiquadCombo, len: usize) -> Vec<PrcFmt> {
+        let mut waveform = vec![0.0; len];
+        waveform[0] = 1.0;
+        filter.process_waveform(&mut waveform).unwrap();
+        waveform
+    }
+
+    fn gain_at(impulse: &[PrcFmt], f: PrcFmt, fs: usize) -> PrcFmt {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let resp: Complex<PrcFmt> = impulse
+            .iter()
+            .enumerate()
+            .map(|(n, val)| {
+                *val * (-Complex::i() * 2.0 * pi * f * n as PrcFmt / fs as PrcFmt).exp()
+            })
+            .sum();
+        20.0 * resp.norm().log10()
+    }
+
+    #[test]
+    fn lr_sums_flat() {
+        let fs = 48000;
+        for order in [4, 8] {
+            let mut highpass = biquadcombo::BiquadCombo::from_config(
+                "hp".to_string(),
+                fs,
+                config::BiquadComboParameters::LinkwitzRileyHighpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let mut lowpass = biquadcombo::BiquadCombo::from_config(
+                "lp".to_string(),
+                fs,
+                config::BiquadComboParameters::LinkwitzRileyLowpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let hp = impulse_response(&mut highpass, 8192);
+            let lp = impulse_response(&mut lowpass, 8192);
+            let sum: Vec<PrcFmt> = hp.iter().zip(lp.iter()).map(|(h, l)| h + l).collect();
+            // Each part is 6 dB down at the crossover frequency
+            assert!(is_close(gain_at(&hp, 1000.0, fs), -6.02, 0.05));
+            assert!(is_close(gain_at(&lp, 1000.0, fs), -6.02, 0.05));
+            for f in [100.0, 500.0, 1000.0, 2000.0, 10000.0] {
+                assert!(is_close(gain_at(&sum, f, fs), 0.0, 0.01));
+            }
+        }
+    }
+
+    #[test]
+    fn butterworth_cutoff_gain() {
+        let fs = 48000;
+        for order in [1, 2, 3, 4, 5, 8] {
+            let mut highpass = biquadcombo::BiquadCombo::from_config(
+                "hp".to_string(),
+                fs,
+                config::BiquadComboParameters::ButterworthHighpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let mut lowpass = biquadcombo::BiquadCombo::from_config(
+                "lp".to_string(),
+                fs,
+                config::BiquadComboParameters::ButterworthLowpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let hp = impulse_response(&mut highpass, 8192);
+            let lp = impulse_response(&mut lowpass, 8192);
+            // Any order is 3 dB down at the cutoff, and the passband is flat
+            assert!(is_close(gain_at(&hp, 1000.0, fs), -3.01, 0.05));
+            assert!(is_close(gain_at(&lp, 1000.0, fs), -3.01, 0.05));
+            assert!(is_close(gain_at(&hp, 10000.0, fs), 0.0, 0.05));
+            assert!(is_close(gain_at(&lp, 100.0, fs), 0.0, 0.05));
+        }
+    }
+
+    #[test]
+    fn slope_above_cutoff() {
+        let fs = 48000;
+        for slope_db_oct in [6, 12, 18, 24, 48] {
+            let mut highpass = biquadcombo::BiquadCombo::from_config(
+                "hp".to_string(),
+                fs,
+                config::BiquadComboParameters::HighpassSlope {
+                    freq: 2000.0,
+                    slope_db_oct,
+                },
+            );
+            let mut lowpass = biquadcombo::BiquadCombo::from_config(
+                "lp".to_string(),
+                fs,
+                config::BiquadComboParameters::LowpassSlope {
+                    freq: 200.0,
+                    slope_db_oct,
+                },
+            );
+            let hp = impulse_response(&mut highpass, 32768);
+            let lp = impulse_response(&mut lowpass, 32768);
+            // Measure one octave in the stopband, well away from the cutoff
+            let expected = slope_db_oct as PrcFmt;
+            let hp_slope = gain_at(&hp, 250.0, fs) - gain_at(&hp, 125.0, fs);
+            let lp_slope = gain_at(&lp, 1600.0, fs) - gain_at(&lp, 3200.0, fs);
+            assert!(is_close(hp_slope, expected, 1.0));
+            assert!(is_close(lp_slope, expected, 1.0));
+        }
+    }
+
+    #[test]
+    fn check_slope() {
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::HighpassSlope {
+            freq: 1000.0,
+            slope_db_oct: 18,
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let badconf1 = config::BiquadComboParameters::LowpassSlope {
+            freq: 1000.0,
+            slope_db_oct: 10,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf1).is_err());
+        let badconf2 = config::BiquadComboParameters::LowpassSlope {
+            freq: 1000.0,
+            slope_db_oct: 0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf2).is_err());
+        let badconf3 = config::BiquadComboParameters::HighpassSlope {
+            freq: 25000.0,
+            slope_db_oct: 12,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf3).is_err());
+    }
+
+    #[test]
+    fn peaking_bank_gains() {
+        let fs = 48000;
+        let band = |freq, gain| config::PeakingBand { freq, gain, q: 4.0 };
+        let mut bank = biquadcombo::BiquadCombo::from_config(
+            "bank".to_string(),
+            fs,
+            config::BiquadComboParameters::PeakingBank {
+                bands: vec![band(100.0, 6.0), band(1000.0, -3.0), band(10000.0, 2.0)],
+            },
+        );
+        let impulse = impulse_response(&mut bank, 32768);
+        // The bands are far apart and narrow, each should give its own gain at its center
+        assert!(is_close(gain_at(&impulse, 100.0, fs), 6.0, 0.1));
+        assert!(is_close(gain_at(&impulse, 1000.0, fs), -3.0, 0.1));
+        assert!(is_close(gain_at(&impulse, 10000.0, fs), 2.0, 0.1));
+        assert!(is_close(gain_at(&impulse, 3000.0, fs), 0.0, 0.2));
+    }
+
+    #[test]
+    fn tilt_slope() {
+        let fs = 48000;
+        for slope_db_oct in [-3.0, 1.5] {
+            let mut tilt = biquadcombo::BiquadCombo::from_config(
+                "tilt".to_string(),
+                fs,
+                config::BiquadComboParameters::Tilt {
+                    pivot_freq: 500.0,
+                    slope_db_oct,
+                },
+            );
+            let impulse = impulse_response(&mut tilt, 65536);
+            // The pivot is unchanged, and the gain changes by the slope for each octave
+            assert!(is_close(gain_at(&impulse, 500.0, fs), 0.0, 0.1));
+            for octaves in [-3, -2, -1, 1, 2, 3] {
+                let freq = 500.0 * (2.0 as PrcFmt).powi(octaves);
+                let expected = slope_db_oct * octaves as PrcFmt;
+                assert!(is_close(gain_at(&impulse, freq, fs), expected, 0.3));
+            }
+        }
+    }
+
+    #[test]
+    fn check_tilt() {
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::Tilt {
+            pivot_freq: 1000.0,
+            slope_db_oct: -1.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let badconf1 = config::BiquadComboParameters::Tilt {
+            pivot_freq: 24000.0,
+            slope_db_oct: 1.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf1).is_err());
+        let badconf2 = config::BiquadComboParameters::Tilt {
+            pivot_freq: 1000.0,
+            slope_db_oct: PrcFmt::NAN,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf2).is_err());
+        let badconf3 = config::BiquadComboParameters::Tilt {
+            pivot_freq: 1000.0,
+            slope_db_oct: 10.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf3).is_err());
+    }
+
+    #[test]
+    fn cascade_matches_separate_biquads() {
+        let fs = 48000;
+        let stages = vec![
+            config::BiquadParameters::Highpass {
+                freq: 80.0,
+                q: 0.707,
+            },
+            config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+                freq: 1000.0,
+                gain: -4.0,
+                q: 2.0,
+            }),
+        ];
+        let mut cascade = biquadcombo::BiquadCombo::from_config(
+            "cascade".to_string(),
+            fs,
+            config::BiquadComboParameters::Cascade {
+                stages: stages.clone(),
+            },
+        );
+        let mut expected = vec![0.0; 1024];
+        expected[0] = 1.0;
+        for stage in stages {
+            let coeffs = biquad::BiquadCoefficients::from_config(fs, stage);
+            let mut filter = biquad::Biquad::new("stage".to_string(), fs, coeffs);
+            filter.process_waveform(&mut expected).unwrap();
+        }
+        assert_eq!(impulse_response(&mut cascade, 1024), expected);
+    }
+
+    #[test]
+    fn check_cascade() {
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::Cascade {
+            stages: vec![config::BiquadParameters::LowpassFO { freq: 1000.0 }],
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let empty = config::BiquadComboParameters::Cascade { stages: vec![] };
+        assert!(biquadcombo::validate_config(fs, &empty).is_err());
+        let badstage = config::BiquadComboParameters::Cascade {
+            stages: vec![
+                config::BiquadParameters::LowpassFO { freq: 1000.0 },
+                config::BiquadParameters::LowpassFO { freq: 30000.0 },
+            ],
+        };
+        let err = biquadcombo::validate_config(fs, &badstage).unwrap_err();
+        assert!(err.to_string().starts_with("Stage 1: "));
+    }
+
+    #[test]
+    fn check_peaking_bank() {
+        let fs = 48000;
+        let band = |freq, q| config::PeakingBand { freq, gain: 3.0, q };
+        let okconf = config::BiquadComboParameters::PeakingBank {
+            bands: vec![band(100.0, 1.0), band(1000.0, 2.0)],
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let empty = config::BiquadComboParameters::PeakingBank { bands: vec![] };
+        assert!(biquadcombo::validate_config(fs, &empty).is_err());
+        let badfreq = config::BiquadComboParameters::PeakingBank {
+            bands: vec![band(100.0, 1.0), band(25000.0, 1.0)],
+        };
+        let err = biquadcombo::validate_config(fs, &badfreq).unwrap_err();
+        assert_eq!(err.to_string(), "Band 1: Frequency must be < samplerate/2");
+        let badq = config::BiquadComboParameters::PeakingBank {
+            bands: vec![band(100.0, 0.0)],
+        };
+        assert!(biquadcombo::validate_config(fs, &badq).is_err());
+    }
+
     #[test]
     fn make_lr4() {
         let q = biquadcombo::BiquadCombo::linkwitzriley_q(4);
@@ -361,6 +853,33 @@ mod tests {
         assert!(biquadcombo::validate_config(fs, &badconf4).is_err());
     }
 
+    #[test]
+    fn check_peq5_frequencies() {
+        let fs = 48000;
+        let make_peq = |fls, fhs| config::BiquadComboParameters::FivePointPeq {
+            fls,
+            qls: 0.7,
+            gls: 3.0,
+            fp1: 200.0,
+            qp1: 1.0,
+            gp1: 0.0,
+            fp2: 1000.0,
+            qp2: 1.0,
+            gp2: 0.0,
+            fp3: 5000.0,
+            qp3: 1.0,
+            gp3: 0.0,
+            fhs,
+            qhs: 0.7,
+            ghs: -3.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &make_peq(50.0, 10000.0)).is_ok());
+        let err = biquadcombo::validate_config(fs, &make_peq(0.0, 10000.0)).unwrap_err();
+        assert_eq!(err.to_string(), "All frequencies must be > 0");
+        let err = biquadcombo::validate_config(fs, &make_peq(50.0, 30000.0)).unwrap_err();
+        assert_eq!(err.to_string(), "All frequencies must be < samplerate/2");
+    }
+
     #[test]
     fn check_butterworth() {
         let fs = 48000;
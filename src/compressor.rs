@@ -0,0 +1,224 @@
+use crate::config;
+use crate::filters::Filter;
+use crate::limiter::time_constant_coeff;
+
+use crate::NewValue;
+use crate::PrcFmt;
+use crate::Res;
+
+#[derive(Clone, Debug)]
+pub struct Compressor {
+    pub name: String,
+    samplerate: usize,
+    threshold: PrcFmt,
+    slope: PrcFmt,
+    attack: PrcFmt,
+    release: PrcFmt,
+    makeup_gain: PrcFmt,
+    envelope: PrcFmt,
+}
+
+impl Compressor {
+    /// Creates a Compressor from a config struct
+    pub fn from_config(
+        name: String,
+        samplerate: usize,
+        conf: config::CompressorParameters,
+    ) -> Self {
+        debug!(
+            "Creating compressor '{}', threshold: {} dB, ratio: {}, attack: {} ms, release: {} ms, makeup gain: {} dB",
+            name, conf.threshold_db, conf.ratio, conf.attack_ms, conf.release_ms, conf.makeup_gain_db
+        );
+        Compressor {
+            name,
+            samplerate,
+            threshold: conf.threshold_db,
+            slope: 1.0 - 1.0 / conf.ratio,
+            attack: time_constant_coeff(conf.attack_ms, samplerate),
+            release: time_constant_coeff(conf.release_ms, samplerate),
+            makeup_gain: PrcFmt::new(10.0).powf(conf.makeup_gain_db / 20.0),
+            envelope: 0.0,
+        }
+    }
+
+    /// Get the gain reduction in dB for a given envelope level.
+    fn gain_reduction(&self, envelope: PrcFmt) -> PrcFmt {
+        let level_db = 20.0 * envelope.log10();
+        if level_db > self.threshold {
+            self.slope * (self.threshold - level_db)
+        } else {
+            0.0
+        }
+    }
+
+    /// Process a single sample
+    fn process_single(&mut self, input: PrcFmt) -> PrcFmt {
+        let level = input.abs();
+        let coeff = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + coeff * (self.envelope - level);
+        let gain = PrcFmt::new(10.0).powf(self.gain_reduction(self.envelope) / 20.0);
+        input * gain * self.makeup_gain
+    }
+}
+
+impl Filter for Compressor {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            *item = self.process_single(*item);
+        }
+        if self.envelope.is_subnormal() {
+            self.envelope = 0.0;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::Compressor {
+            parameters: conf, ..
+        } = conf
+        {
+            self.threshold = conf.threshold_db;
+            self.slope = 1.0 - 1.0 / conf.ratio;
+            self.attack = time_constant_coeff(conf.attack_ms, self.samplerate);
+            self.release = time_constant_coeff(conf.release_ms, self.samplerate);
+            self.makeup_gain = PrcFmt::new(10.0).powf(conf.makeup_gain_db / 20.0);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a Compressor config.
+pub fn validate_config(conf: &config::CompressorParameters) -> Res<()> {
+    if conf.threshold_db < -100.0 {
+        return Err(config::ConfigError::new("Threshold must be larger than -100 dB").into());
+    } else if conf.threshold_db > 0.0 {
+        return Err(config::ConfigError::new("Threshold cannot be larger than 0 dB").into());
+    } else if conf.ratio < 1.0 {
+        return Err(config::ConfigError::new("Ratio must be 1 or larger").into());
+    } else if conf.attack_ms < 0.0 {
+        return Err(config::ConfigError::new("Attack time cannot be negative").into());
+    } else if conf.release_ms < 0.0 {
+        return Err(config::ConfigError::new("Release time cannot be negative").into());
+    } else if conf.makeup_gain_db.abs() > 50.0 {
+        return Err(
+            config::ConfigError::new("Makeup gain must be in the range -50 to +50 dB").into(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compressor::{validate_config, Compressor};
+    use crate::config::CompressorParameters;
+    use crate::filters::Filter;
+    use crate::PrcFmt;
+
+    fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
+        println!("{} - {}", left, right);
+        (left - right).abs() < maxdiff
+    }
+
+    fn make_sine(ampl: PrcFmt, freq: PrcFmt, fs: usize, len: usize) -> Vec<PrcFmt> {
+        let pi = std::f64::consts::PI as PrcFmt;
+        (0..len)
+            .map(|n| ampl * (2.0 * pi * freq * n as PrcFmt / fs as PrcFmt).sin())
+            .collect()
+    }
+
+    fn peak_db(waveform: &[PrcFmt]) -> PrcFmt {
+        let peak = waveform
+            .iter()
+            .fold(0.0, |max: PrcFmt, val| max.max(val.abs()));
+        20.0 * peak.log10()
+    }
+
+    fn run_chunked(comp: &mut Compressor, input: &[PrcFmt], chunksize: usize) -> Vec<PrcFmt> {
+        let mut output = Vec::with_capacity(input.len());
+        for chunk in input.chunks(chunksize) {
+            let mut waveform = chunk.to_vec();
+            comp.process_waveform(&mut waveform).unwrap();
+            output.extend(waveform);
+        }
+        output
+    }
+
+    #[test]
+    fn steady_state_ratio() {
+        let conf = CompressorParameters {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack_ms: 1.0,
+            release_ms: 500.0,
+            makeup_gain_db: 0.0,
+        };
+        let mut comp = Compressor::from_config("test".to_string(), 48000, conf);
+        // A 0 dB tone is 20 dB above the threshold, a ratio of 4 should give -15 dB
+        let sine = make_sine(1.0, 1000.0, 48000, 48000);
+        let output = run_chunked(&mut comp, &sine, 1024);
+        assert!(is_close(peak_db(&output[24000..]), -15.0, 0.2));
+    }
+
+    #[test]
+    fn makeup_gain_below_threshold() {
+        let conf = CompressorParameters {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack_ms: 1.0,
+            release_ms: 500.0,
+            makeup_gain_db: 6.0,
+        };
+        let mut comp = Compressor::from_config("test".to_string(), 48000, conf);
+        let sine = make_sine(0.01, 1000.0, 48000, 4800);
+        let output = run_chunked(&mut comp, &sine, 1024);
+        assert!(is_close(peak_db(&output), -34.0, 0.01));
+    }
+
+    #[test]
+    fn continuous_over_chunks() {
+        let conf = CompressorParameters {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            makeup_gain_db: 0.0,
+        };
+        let sine = make_sine(1.0, 1000.0, 48000, 9600);
+        let mut comp_whole = Compressor::from_config("test".to_string(), 48000, conf.clone());
+        let mut comp_chunked = Compressor::from_config("test".to_string(), 48000, conf);
+        let whole = run_chunked(&mut comp_whole, &sine, sine.len());
+        let chunked = run_chunked(&mut comp_chunked, &sine, 100);
+        assert_eq!(whole, chunked);
+    }
+
+    #[test]
+    fn check_config() {
+        let okconf = CompressorParameters {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            makeup_gain_db: 0.0,
+        };
+        assert!(validate_config(&okconf).is_ok());
+        let mut badconf = okconf.clone();
+        badconf.ratio = 0.5;
+        assert!(validate_config(&badconf).is_err());
+        let mut badconf = okconf.clone();
+        badconf.threshold_db = 3.0;
+        assert!(validate_config(&badconf).is_err());
+        let mut badconf = okconf;
+        badconf.release_ms = -1.0;
+        assert!(validate_config(&badconf).is_err());
+    }
+}
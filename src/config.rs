@@ -1,4 +1,8 @@
+use crate::audiodevice::{MAX_OPEN_RETRIES, MAX_RETRY_DELAY_MS, RATE_CHANGE_THRESHOLD_VALUE};
+use crate::biquadcombo;
+use crate::filedevice;
 use crate::filters;
+use crate::generatordevice;
 use crate::mixer;
 use serde::{de, Deserialize, Serialize};
 use serde_with;
@@ -66,6 +70,9 @@ pub enum SampleFormat {
     S32LE,
     FLOAT32LE,
     FLOAT64LE,
+    S16BE,
+    S24BE,
+    S32BE,
 }
 
 impl SampleFormat {
@@ -77,6 +84,9 @@ impl SampleFormat {
             SampleFormat::S32LE => 32,
             SampleFormat::FLOAT32LE => 32,
             SampleFormat::FLOAT64LE => 64,
+            SampleFormat::S16BE => 16,
+            SampleFormat::S24BE => 24,
+            SampleFormat::S32BE => 32,
         }
     }
 
@@ -88,9 +98,20 @@ impl SampleFormat {
             SampleFormat::S32LE => 4,
             SampleFormat::FLOAT32LE => 4,
             SampleFormat::FLOAT64LE => 8,
+            SampleFormat::S16BE => 2,
+            SampleFormat::S24BE => 4,
+            SampleFormat::S32BE => 4,
         }
     }
 
+    /// Big-endian formats are only supported by some backends.
+    pub fn is_big_endian(&self) -> bool {
+        matches!(
+            self,
+            SampleFormat::S16BE | SampleFormat::S24BE | SampleFormat::S32BE
+        )
+    }
+
     pub fn from_name(label: &str) -> Option<SampleFormat> {
         match label {
             "FLOAT32LE" => Some(SampleFormat::FLOAT32LE),
@@ -99,6 +120,9 @@ impl SampleFormat {
             "S24LE" => Some(SampleFormat::S24LE),
             "S24LE3" => Some(SampleFormat::S24LE3),
             "S32LE" => Some(SampleFormat::S32LE),
+            "S16BE" => Some(SampleFormat::S16BE),
+            "S24BE" => Some(SampleFormat::S24BE),
+            "S32BE" => Some(SampleFormat::S32BE),
             _ => None,
         }
     }
@@ -113,6 +137,9 @@ impl fmt::Display for SampleFormat {
             SampleFormat::S24LE => "S24LE",
             SampleFormat::S24LE3 => "S24LE3",
             SampleFormat::S32LE => "S32LE",
+            SampleFormat::S16BE => "S16BE",
+            SampleFormat::S24BE => "S24BE",
+            SampleFormat::S32BE => "S32BE",
         };
         write!(f, "{}", formatstr)
     }
@@ -129,6 +156,10 @@ pub enum CaptureDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        channel_adjust: ChannelAdjust,
+        #[serde(default)]
+        soft_start_ms: usize,
     },
     #[cfg(feature = "pulse-backend")]
     #[serde(alias = "PULSE", alias = "pulse")]
@@ -137,6 +168,8 @@ pub enum CaptureDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        soft_start_ms: usize,
     },
     #[serde(alias = "FILE", alias = "file")]
     File {
@@ -145,11 +178,17 @@ pub enum CaptureDevice {
         filename: String,
         format: SampleFormat,
         #[serde(default)]
+        channel_adjust: ChannelAdjust,
+        #[serde(default)]
         extra_samples: usize,
         #[serde(default)]
         skip_bytes: usize,
         #[serde(default)]
         read_bytes: usize,
+        #[serde(default)]
+        start_frame: usize,
+        #[serde(default)]
+        length_frames: usize,
     },
     #[serde(alias = "STDIN", alias = "stdin")]
     Stdin {
@@ -163,6 +202,14 @@ pub enum CaptureDevice {
         #[serde(default)]
         read_bytes: usize,
     },
+    #[serde(alias = "SIGNAL", alias = "signal")]
+    Signal {
+        #[serde(deserialize_with = "validate_nonzero_usize")]
+        channels: usize,
+        signal: Signal,
+        #[serde(default)]
+        duration: PrcFmt,
+    },
     #[cfg(target_os = "macos")]
     #[serde(alias = "COREAUDIO", alias = "coreaudio")]
     CoreAudio {
@@ -195,6 +242,29 @@ pub enum CaptureDevice {
     },
 }
 
+/// Test signals generated by the Signal capture device.
+/// The level is the peak amplitude in dB relative to full scale.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum Signal {
+    Sine {
+        freq: PrcFmt,
+        level: PrcFmt,
+    },
+    Sweep {
+        start_freq: PrcFmt,
+        end_freq: PrcFmt,
+        level: PrcFmt,
+    },
+    WhiteNoise {
+        level: PrcFmt,
+    },
+    PinkNoise {
+        level: PrcFmt,
+    },
+}
+
 impl CaptureDevice {
     pub fn channels(&self) -> usize {
         match self {
@@ -204,6 +274,7 @@ impl CaptureDevice {
             CaptureDevice::Pulse { channels, .. } => *channels,
             CaptureDevice::File { channels, .. } => *channels,
             CaptureDevice::Stdin { channels, .. } => *channels,
+            CaptureDevice::Signal { channels, .. } => *channels,
             #[cfg(target_os = "macos")]
             CaptureDevice::CoreAudio { channels, .. } => *channels,
             #[cfg(target_os = "windows")]
@@ -221,6 +292,7 @@ impl CaptureDevice {
             CaptureDevice::Pulse { format, .. } => format.clone(),
             CaptureDevice::File { format, .. } => format.clone(),
             CaptureDevice::Stdin { format, .. } => format.clone(),
+            CaptureDevice::Signal { .. } => SampleFormat::FLOAT64LE,
             #[cfg(target_os = "macos")]
             CaptureDevice::CoreAudio { format, .. } => format.clone(),
             #[cfg(target_os = "windows")]
@@ -242,6 +314,8 @@ pub enum PlaybackDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        startup_silence_ms: usize,
     },
     #[cfg(feature = "pulse-backend")]
     #[serde(alias = "PULSE", alias = "pulse")]
@@ -257,6 +331,9 @@ pub enum PlaybackDevice {
         channels: usize,
         filename: String,
         format: SampleFormat,
+        /// Store the peak and RMS levels of each channel as a comment in the metadata of a wav file.
+        #[serde(default)]
+        wav_levels: bool,
     },
     #[serde(alias = "STDOUT", alias = "stdout")]
     Stdout {
@@ -313,6 +390,23 @@ impl PlaybackDevice {
             PlaybackDevice::Jack { channels, .. } => *channels,
         }
     }
+
+    pub fn sampleformat(&self) -> SampleFormat {
+        match self {
+            #[cfg(target_os = "linux")]
+            PlaybackDevice::Alsa { format, .. } => format.clone(),
+            #[cfg(feature = "pulse-backend")]
+            PlaybackDevice::Pulse { format, .. } => format.clone(),
+            PlaybackDevice::File { format, .. } => format.clone(),
+            PlaybackDevice::Stdout { format, .. } => format.clone(),
+            #[cfg(target_os = "macos")]
+            PlaybackDevice::CoreAudio { format, .. } => format.clone(),
+            #[cfg(target_os = "windows")]
+            PlaybackDevice::Wasapi { format, .. } => format.clone(),
+            #[cfg(all(feature = "cpal-backend", feature = "jack-backend"))]
+            PlaybackDevice::Jack { .. } => SampleFormat::FLOAT32LE,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -328,12 +422,17 @@ pub struct Devices {
     pub silence_threshold: PrcFmt,
     #[serde(default)]
     pub silence_timeout: PrcFmt,
+    #[serde(default)]
+    pub silence_mode: SilenceMode,
     pub capture: CaptureDevice,
     pub playback: PlaybackDevice,
     #[serde(default)]
     pub enable_rate_adjust: bool,
     #[serde(default)]
     pub target_level: usize,
+    /// Target latency of the playback buffer, used instead of `target_level` when larger than zero.
+    #[serde(default)]
+    pub target_latency_ms: f32,
     #[serde(default = "default_period")]
     pub adjust_period: f32,
     #[serde(default)]
@@ -344,8 +443,145 @@ pub struct Devices {
     pub capture_samplerate: usize,
     #[serde(default)]
     pub stop_on_rate_change: bool,
+    /// Restart at the new rate when the capture sample rate changes, instead of only stopping.
+    #[serde(default)]
+    pub adapt_to_rate_change: bool,
     #[serde(default = "default_measure_interval")]
     pub rate_measure_interval: f32,
+    #[serde(default)]
+    pub dither: DeviceDither,
+    #[serde(default = "default_dither_amplitude")]
+    pub dither_amplitude: PrcFmt,
+    #[serde(default)]
+    pub dither_seed: Option<u64>,
+    #[serde(default)]
+    pub open_retries: usize,
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay_ms: u64,
+    #[serde(default)]
+    pub fadein_ms: usize,
+    #[serde(default)]
+    pub fadeout_ms: usize,
+    #[serde(default)]
+    pub reload_crossfade_ms: usize,
+    #[serde(default)]
+    pub ab_crossfade_ms: usize,
+    #[serde(default)]
+    pub input_gains: Vec<PrcFmt>,
+    #[serde(default)]
+    pub output_gains: Vec<PrcFmt>,
+    #[serde(default)]
+    pub capture_labels: Vec<String>,
+    #[serde(default)]
+    pub playback_labels: Vec<String>,
+    #[serde(default)]
+    pub input_log: Option<String>,
+    #[serde(default)]
+    pub output_clip_db: Option<PrcFmt>,
+    #[serde(default)]
+    pub probe: Option<SpectrumProbe>,
+    #[serde(default = "default_worker_threads")]
+    pub worker_threads: usize,
+}
+
+impl Devices {
+    /// The target level of the playback buffer in frames,
+    /// calculated from `target_latency_ms` if that is given.
+    pub fn effective_target_level(&self) -> usize {
+        if self.target_latency_ms > 0.0 {
+            (self.target_latency_ms * self.samplerate as f32 / 1000.0).round() as usize
+        } else {
+            self.target_level
+        }
+    }
+}
+
+/// What to do while the capture signal is silent.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum SilenceMode {
+    /// Pause processing until the signal returns.
+    Pause,
+    /// Keep processing the silent signal.
+    Continue,
+}
+
+impl Default for SilenceMode {
+    fn default() -> Self {
+        SilenceMode::Pause
+    }
+}
+
+/// What a capture device does when the device or file has a different number of channels than configured.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ChannelAdjust {
+    /// The number of channels must match.
+    Strict,
+    /// More channels are accepted, and only the first ones are used.
+    Truncate,
+    /// Fewer channels are accepted, and silent channels are added after them.
+    Pad,
+}
+
+impl Default for ChannelAdjust {
+    fn default() -> Self {
+        ChannelAdjust::Strict
+    }
+}
+
+impl ChannelAdjust {
+    /// Check if a device with `device_channels` channels can be used when `channels` are configured.
+    pub fn accepts(&self, device_channels: usize, channels: usize) -> bool {
+        match self {
+            ChannelAdjust::Strict => device_channels == channels,
+            ChannelAdjust::Truncate => device_channels >= channels,
+            ChannelAdjust::Pad => device_channels <= channels,
+        }
+    }
+}
+
+/// Point in the processing where the spectrum probe reads the signal.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ProbePoint {
+    /// The captured signal, before the pipeline.
+    Capture,
+    /// The processed signal, after the pipeline and before playback.
+    Playback,
+}
+
+impl Default for ProbePoint {
+    fn default() -> Self {
+        ProbePoint::Playback
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum SpectrumWindow {
+    Rectangular,
+    Hann,
+    Blackman,
+}
+
+impl Default for SpectrumWindow {
+    fn default() -> Self {
+        SpectrumWindow::Hann
+    }
+}
+
+/// Spectrum analysis of one channel.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SpectrumProbe {
+    pub channel: usize,
+    #[serde(default)]
+    pub point: ProbePoint,
+    #[serde(default = "default_fft_size")]
+    pub fft_size: usize,
+    #[serde(default)]
+    pub window: SpectrumWindow,
+}
+
+fn default_fft_size() -> usize {
+    4096
 }
 
 fn default_period() -> f32 {
@@ -360,6 +596,18 @@ fn default_measure_interval() -> f32 {
     1.0
 }
 
+fn default_retry_delay() -> u64 {
+    500
+}
+
+fn default_worker_threads() -> usize {
+    1
+}
+
+fn default_dither_amplitude() -> PrcFmt {
+    1.0
+}
+
 #[cfg(target_os = "macos")]
 fn default_ca_format() -> SampleFormat {
     SampleFormat::S32LE
@@ -387,6 +635,20 @@ impl Default for Resampler {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum DeviceDither {
+    None,
+    Flat,
+    Shaped,
+}
+
+impl Default for DeviceDither {
+    fn default() -> Self {
+        DeviceDither::None
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub enum WindowFunction {
@@ -411,33 +673,173 @@ pub enum InterpolationType {
 #[serde(deny_unknown_fields)]
 pub enum Filter {
     Conv {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         #[serde(default)]
         parameters: ConvParameters,
     },
     Biquad {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: BiquadParameters,
     },
     BiquadCombo {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: BiquadComboParameters,
     },
     Delay {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: DelayParameters,
     },
     Gain {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: GainParameters,
     },
     Volume {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: VolumeParameters,
     },
     Loudness {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: LoudnessParameters,
     },
     Dither {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: DitherParameters,
     },
     DiffEq {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
         parameters: DiffEqParameters,
     },
+    Limiter {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        parameters: LimiterParameters,
+    },
+    Compressor {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        parameters: CompressorParameters,
+    },
+    Saturation {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        parameters: SaturationParameters,
+    },
+    DcBlock {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        #[serde(default)]
+        parameters: DcBlockParameters,
+    },
+    ShelfGain {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        parameters: ShelfGainParameters,
+    },
+    Deemphasis {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        #[serde(default)]
+        parameters: EmphasisParameters,
+    },
+    Preemphasis {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+        #[serde(default)]
+        parameters: EmphasisParameters,
+    },
+    Invert {
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        bypassed: bool,
+    },
+}
+
+impl Filter {
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            Filter::Conv { description, .. }
+            | Filter::Biquad { description, .. }
+            | Filter::BiquadCombo { description, .. }
+            | Filter::Delay { description, .. }
+            | Filter::Gain { description, .. }
+            | Filter::Volume { description, .. }
+            | Filter::Loudness { description, .. }
+            | Filter::Dither { description, .. }
+            | Filter::DiffEq { description, .. }
+            | Filter::Limiter { description, .. }
+            | Filter::Compressor { description, .. }
+            | Filter::Saturation { description, .. }
+            | Filter::DcBlock { description, .. }
+            | Filter::ShelfGain { description, .. }
+            | Filter::Deemphasis { description, .. }
+            | Filter::Preemphasis { description, .. }
+            | Filter::Invert { description, .. } => description.as_deref(),
+        }
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        match self {
+            Filter::Conv { bypassed, .. }
+            | Filter::Biquad { bypassed, .. }
+            | Filter::BiquadCombo { bypassed, .. }
+            | Filter::Delay { bypassed, .. }
+            | Filter::Gain { bypassed, .. }
+            | Filter::Volume { bypassed, .. }
+            | Filter::Loudness { bypassed, .. }
+            | Filter::Dither { bypassed, .. }
+            | Filter::DiffEq { bypassed, .. }
+            | Filter::Limiter { bypassed, .. }
+            | Filter::Compressor { bypassed, .. }
+            | Filter::Saturation { bypassed, .. }
+            | Filter::DcBlock { bypassed, .. }
+            | Filter::ShelfGain { bypassed, .. }
+            | Filter::Deemphasis { bypassed, .. }
+            | Filter::Preemphasis { bypassed, .. }
+            | Filter::Invert { bypassed, .. } => *bypassed,
+        }
+    }
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -485,7 +887,7 @@ impl FileFormat {
 pub enum ConvParameters {
     #[serde(alias = "File")]
     Raw {
-        filename: String,
+        filename: CoeffFilename,
         #[serde(default)]
         format: FileFormat,
         #[serde(default)]
@@ -494,7 +896,7 @@ pub enum ConvParameters {
         read_bytes_lines: usize,
     },
     Wav {
-        filename: String,
+        filename: CoeffFilename,
         #[serde(default)]
         channel: usize,
     },
@@ -502,7 +904,90 @@ pub enum ConvParameters {
         values: Vec<PrcFmt>,
         #[serde(default)]
         length: usize,
+        #[serde(default)]
+        normalize: Option<ConvNormalization>,
     },
+    /// Several impulse responses applied in series,
+    /// convolved together into a single kernel when the filter is loaded.
+    Cascade { parts: Vec<ConvParameters> },
+}
+
+impl ConvParameters {
+    /// Get the coefficient files, including the ones of all the parts of a cascade.
+    pub fn coeff_filenames(&self) -> Vec<&CoeffFilename> {
+        match self {
+            ConvParameters::Raw { filename, .. } | ConvParameters::Wav { filename, .. } => {
+                vec![filename]
+            }
+            ConvParameters::Values { .. } => Vec::new(),
+            ConvParameters::Cascade { parts } => parts
+                .iter()
+                .flat_map(|part| part.coeff_filenames())
+                .collect(),
+        }
+    }
+
+    /// Get the coefficient files, for updating the paths.
+    pub fn coeff_filenames_mut(&mut self) -> Vec<&mut CoeffFilename> {
+        match self {
+            ConvParameters::Raw { filename, .. } | ConvParameters::Wav { filename, .. } => {
+                vec![filename]
+            }
+            ConvParameters::Values { .. } => Vec::new(),
+            ConvParameters::Cascade { parts } => parts
+                .iter_mut()
+                .flat_map(|part| part.coeff_filenames_mut())
+                .collect(),
+        }
+    }
+}
+
+/// The coefficient file of a Conv filter, either a single file used for all channels,
+/// or a list with one file for each channel of the filter step using the filter.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CoeffFilename {
+    Single(String),
+    PerChannel(Vec<String>),
+}
+
+impl CoeffFilename {
+    /// Get the file for the channel at `position` among the channels of the filter step.
+    pub fn for_position(&self, position: usize) -> &str {
+        match self {
+            CoeffFilename::Single(filename) => filename,
+            CoeffFilename::PerChannel(filenames) => &filenames[position],
+        }
+    }
+
+    /// Number of files.
+    pub fn len(&self) -> usize {
+        match self {
+            CoeffFilename::Single(_) => 1,
+            CoeffFilename::PerChannel(filenames) => filenames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get all the files, for updating the paths.
+    pub fn files_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            CoeffFilename::Single(filename) => vec![filename],
+            CoeffFilename::PerChannel(filenames) => filenames.iter_mut().collect(),
+        }
+    }
+}
+
+/// Normalization of the coefficients of a Conv filter.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ConvNormalization {
+    /// Scale to unity gain at DC, meaning that the coefficients sum to one.
+    Dc,
+    /// Scale so that the peak of the magnitude response is at 0 dB.
+    Peak,
 }
 
 impl Default for FileFormat {
@@ -516,6 +1001,7 @@ impl Default for ConvParameters {
         ConvParameters::Values {
             values: vec![1.0],
             length: 0,
+            normalize: None,
         }
     }
 }
@@ -628,6 +1114,14 @@ pub enum BiquadComboParameters {
         freq: PrcFmt,
         order: usize,
     },
+    HighpassSlope {
+        freq: PrcFmt,
+        slope_db_oct: usize,
+    },
+    LowpassSlope {
+        freq: PrcFmt,
+        slope_db_oct: usize,
+    },
     FivePointPeq {
         fls: PrcFmt,
         qls: PrcFmt,
@@ -645,6 +1139,27 @@ pub enum BiquadComboParameters {
         qhs: PrcFmt,
         ghs: PrcFmt,
     },
+    PeakingBank {
+        bands: Vec<PeakingBand>,
+    },
+    Tilt {
+        pivot_freq: PrcFmt,
+        slope_db_oct: PrcFmt,
+    },
+    Cascade {
+        stages: Vec<BiquadParameters>,
+    },
+    File {
+        filename: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PeakingBand {
+    pub freq: PrcFmt,
+    pub gain: PrcFmt,
+    pub q: PrcFmt,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -678,11 +1193,27 @@ fn default_ramp_time() -> f32 {
 pub struct GainParameters {
     pub gain: PrcFmt,
     #[serde(default)]
+    pub scale: GainScale,
+    #[serde(default)]
     pub inverted: bool,
     #[serde(default)]
     pub mute: bool,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum GainScale {
+    #[serde(rename = "linear")]
+    Linear,
+    #[serde(rename = "dB")]
+    Decibel,
+}
+impl Default for GainScale {
+    fn default() -> Self {
+        GainScale::Decibel
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct DelayParameters {
@@ -735,52 +1266,276 @@ pub struct DiffEqParameters {
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct MixerChannels {
-    #[serde(deserialize_with = "validate_nonzero_usize")]
-    pub r#in: usize,
-    #[serde(deserialize_with = "validate_nonzero_usize")]
-    pub out: usize,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-#[serde(deny_unknown_fields)]
-pub struct MixerSource {
-    pub channel: usize,
-    #[serde(default)]
-    pub gain: PrcFmt,
-    #[serde(default)]
-    pub inverted: bool,
-    #[serde(default)]
-    pub mute: bool,
+pub struct LimiterParameters {
+    pub threshold_db: PrcFmt,
+    #[serde(default = "default_limiter_attack")]
+    pub attack_ms: PrcFmt,
+    #[serde(default = "default_limiter_release")]
+    pub release_ms: PrcFmt,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct MixerMapping {
-    pub dest: usize,
-    pub sources: Vec<MixerSource>,
+pub struct CompressorParameters {
+    pub threshold_db: PrcFmt,
+    pub ratio: PrcFmt,
+    #[serde(default = "default_limiter_attack")]
+    pub attack_ms: PrcFmt,
+    #[serde(default = "default_limiter_release")]
+    pub release_ms: PrcFmt,
     #[serde(default)]
-    pub mute: bool,
+    pub makeup_gain_db: PrcFmt,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct Mixer {
-    pub channels: MixerChannels,
-    pub mapping: Vec<MixerMapping>,
+pub struct SaturationParameters {
+    pub drive_db: PrcFmt,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-#[serde(tag = "type")]
 #[serde(deny_unknown_fields)]
-pub enum PipelineStep {
-    Mixer { name: String },
-    Filter { channel: usize, names: Vec<String> },
+pub struct DcBlockParameters {
+    #[serde(default = "default_dcblock_cutoff")]
+    pub cutoff: PrcFmt,
+}
+
+impl Default for DcBlockParameters {
+    fn default() -> Self {
+        DcBlockParameters {
+            cutoff: default_dcblock_cutoff(),
+        }
+    }
+}
+
+fn default_dcblock_cutoff() -> PrcFmt {
+    5.0
+}
+
+/// Which side of the frequency a ShelfGain filter applies its gain to.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ShelfKind {
+    Low,
+    High,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ShelfGainParameters {
+    pub freq: PrcFmt,
+    pub gain_db: PrcFmt,
+    pub kind: ShelfKind,
+}
+
+/// Time constants of an emphasis filter, in microseconds.
+/// The defaults are the standard 50/15 µs used for CD pre-emphasis.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EmphasisParameters {
+    #[serde(default = "default_emphasis_t1")]
+    pub t1_us: PrcFmt,
+    #[serde(default = "default_emphasis_t2")]
+    pub t2_us: PrcFmt,
+}
+
+impl Default for EmphasisParameters {
+    fn default() -> Self {
+        EmphasisParameters {
+            t1_us: default_emphasis_t1(),
+            t2_us: default_emphasis_t2(),
+        }
+    }
+}
+
+fn default_emphasis_t1() -> PrcFmt {
+    50.0
+}
+
+fn default_emphasis_t2() -> PrcFmt {
+    15.0
+}
+
+fn default_limiter_attack() -> PrcFmt {
+    5.0
+}
+
+fn default_limiter_release() -> PrcFmt {
+    100.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MixerChannels {
+    #[serde(deserialize_with = "validate_nonzero_usize")]
+    pub r#in: usize,
+    #[serde(deserialize_with = "validate_nonzero_usize")]
+    pub out: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MixerSource {
+    /// Index of the source channel, either this or a label must be given.
+    #[serde(default)]
+    pub channel: Option<usize>,
+    /// Label of the source channel, replaced by the channel index when the config is validated.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub gain: PrcFmt,
+    #[serde(default)]
+    pub inverted: bool,
+    #[serde(default)]
+    pub mute: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MixerMapping {
+    pub dest: usize,
+    pub sources: Vec<MixerSource>,
+    #[serde(default)]
+    pub mute: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Mixer {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub channels: MixerChannels,
+    #[serde(default)]
+    pub mapping: Vec<MixerMapping>,
+    /// Compact form of a mixer that only reorders channels,
+    /// giving the source channel of each destination channel.
+    #[serde(default)]
+    pub swap: Option<Vec<usize>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum PipelineStep {
+    Mixer {
+        name: String,
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+    },
+    Filter {
+        #[serde(alias = "channels")]
+        channel: PipelineChannels,
+        names: Vec<String>,
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+    },
+    StereoWidth {
+        channels: [usize; 2],
+        width: PrcFmt,
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+    },
+    Crossfeed {
+        channels: [usize; 2],
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+        #[serde(default = "default_crossfeed_delay")]
+        delay_ms: PrcFmt,
+        #[serde(default = "default_crossfeed_attenuation")]
+        attenuation_db: PrcFmt,
+        #[serde(default = "default_crossfeed_cutoff")]
+        cutoff_freq: PrcFmt,
+    },
+    /// Reorder the channels, giving the current channel to use for each channel.
+    Reorder {
+        order: Vec<usize>,
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+    },
+    /// Add a channel with the sum of some channels, after the existing channels.
+    /// The filters in `names` are applied to the new channel.
+    Sum {
+        channels: Vec<usize>,
+        #[serde(default)]
+        gain: PrcFmt,
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default = "default_step_enabled")]
+        enabled: bool,
+    },
+}
+
+impl PipelineStep {
+    /// Disabled steps stay in the config but are skipped when processing.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            PipelineStep::Mixer { enabled, .. }
+            | PipelineStep::Filter { enabled, .. }
+            | PipelineStep::StereoWidth { enabled, .. }
+            | PipelineStep::Crossfeed { enabled, .. }
+            | PipelineStep::Reorder { enabled, .. }
+            | PipelineStep::Sum { enabled, .. } => *enabled,
+        }
+    }
+}
+
+fn default_step_enabled() -> bool {
+    true
+}
+
+fn default_crossfeed_delay() -> PrcFmt {
+    0.3
+}
+
+fn default_crossfeed_attenuation() -> PrcFmt {
+    6.0
+}
+
+fn default_crossfeed_cutoff() -> PrcFmt {
+    700.0
+}
+
+/// The channels that a Filter step is applied to,
+/// either a single channel, a list of channels, or all channels.
+/// Channels can also be given by their labels, these are replaced by the channel indices
+/// when the config is validated.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum PipelineChannels {
+    Single(usize),
+    List(Vec<usize>),
+    All(AllChannels),
+    Label(String),
+    Labels(Vec<String>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AllChannels {
+    #[serde(rename = "all")]
+    All,
+}
+
+impl PipelineChannels {
+    /// Get the channel indices, for a step that receives `num_channels` channels.
+    pub fn indices(&self, num_channels: usize) -> Vec<usize> {
+        match self {
+            PipelineChannels::Single(channel) => vec![*channel],
+            PipelineChannels::List(channels) => channels.clone(),
+            PipelineChannels::All(_) => (0..num_channels).collect(),
+            // Labels have already been resolved when the config was validated
+            PipelineChannels::Label(_) | PipelineChannels::Labels(_) => Vec::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Configuration {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
     pub devices: Devices,
     #[serde(default)]
     pub mixers: HashMap<String, Mixer>,
@@ -789,6 +1544,8 @@ pub struct Configuration {
     pub filters: HashMap<String, Filter>,
     #[serde(default)]
     pub pipeline: Vec<PipelineStep>,
+    #[serde(default)]
+    pub master_gain_db: PrcFmt,
 }
 
 fn validate_nonzero_usize<'de, D>(d: D) -> Result<usize, D::Error>
@@ -805,7 +1562,30 @@ where
     Ok(value)
 }
 
-pub fn load_config(filename: &str) -> Res<Configuration> {
+/// Log the title and descriptions of a config. These are only for the user, and not used for processing.
+fn log_descriptions(conf: &Configuration) {
+    if let Some(title) = &conf.title {
+        info!("Config title: {}", title);
+    }
+    if let Some(description) = &conf.description {
+        info!("Config description: {}", description);
+    }
+    for (name, mixer) in conf.mixers.iter() {
+        if let Some(description) = &mixer.description {
+            debug!("Mixer '{}': {}", name, description);
+        }
+    }
+    for (name, filter) in conf.filters.iter() {
+        if let Some(description) = filter.description() {
+            debug!("Filter '{}': {}", name, description);
+        }
+    }
+}
+
+/// Config file name meaning that the config should be read from stdin.
+pub const STDIN_CONFIG: &str = "-";
+
+fn read_config_file(filename: &str) -> Res<String> {
     let file = match File::open(filename) {
         Ok(f) => f,
         Err(err) => {
@@ -822,13 +1602,55 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
             return Err(ConfigError::new(&msg).into());
         }
     };
-    let configuration: Configuration = match serde_yaml::from_str(&contents) {
-        Ok(config) => config,
+    Ok(contents)
+}
+
+fn read_config_stdin() -> Res<String> {
+    let mut contents = String::new();
+    if let Err(err) = std::io::stdin().read_to_string(&mut contents) {
+        let msg = format!("Could not read config from stdin. Error: {}", err);
+        return Err(ConfigError::new(&msg).into());
+    }
+    if contents.trim().is_empty() {
+        return Err(ConfigError::new("No config was received on stdin").into());
+    }
+    Ok(contents)
+}
+
+/// Parse a config as YAML, or as JSON if `is_json` is set.
+/// The `source` describes where the config came from, for the error message.
+fn parse_config(contents: &str, is_json: bool, source: &str) -> Res<Configuration> {
+    let parse_result = if is_json {
+        serde_json::from_str::<Configuration>(contents).map_err(|err| err.to_string())
+    } else {
+        serde_yaml::from_str::<Configuration>(contents).map_err(|err| err.to_string())
+    };
+    match parse_result {
+        Ok(config) => Ok(config),
         Err(err) => {
-            let msg = format!("Invalid config file!\n{}", err);
-            return Err(ConfigError::new(&msg).into());
+            let msg = format!("Invalid {}!\n{}", source, err);
+            Err(ConfigError::new(&msg).into())
         }
+    }
+}
+
+/// Load a config from a file, or from stdin if the filename is `-`.
+/// Files with a `.json` extension are parsed as JSON, and a config on stdin
+/// is parsed as JSON if it starts with a `{`.
+pub fn load_config(filename: &str) -> Res<Configuration> {
+    let configuration = if filename == STDIN_CONFIG {
+        let contents = read_config_stdin()?;
+        let is_json = contents.trim_start().starts_with('{');
+        parse_config(&contents, is_json, "config from stdin")?
+    } else {
+        let contents = read_config_file(filename)?;
+        let is_json = Path::new(filename)
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("json"))
+            .unwrap_or(false);
+        parse_config(&contents, is_json, "config file")?
     };
+    log_descriptions(&configuration);
     //Ok(configuration)
     //apply_overrides(&mut configuration);
     //replace_tokens_in_config(&mut configuration);
@@ -836,46 +1658,71 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
     Ok(configuration)
 }
 
-fn apply_overrides(configuration: &mut Configuration) {
-    if let Some(rate) = OVERRIDES.read().unwrap().samplerate {
-        let cfg_rate = configuration.devices.samplerate;
-        let cfg_chunksize = configuration.devices.chunksize;
-
-        if !configuration.devices.enable_resampling {
-            debug!("Apply override for samplerate: {}", rate);
-            configuration.devices.samplerate = rate;
-            let scaled_chunksize = if rate > cfg_rate {
-                cfg_chunksize * (rate as f32 / cfg_rate as f32).round() as usize
-            } else {
-                cfg_chunksize / (cfg_rate as f32 / rate as f32).round() as usize
-            };
-            debug!(
-                "Samplerate changed, adjusting chunksize: {} -> {}",
-                cfg_chunksize, scaled_chunksize
-            );
-            configuration.devices.chunksize = scaled_chunksize;
-            #[allow(unreachable_patterns)]
-            match &mut configuration.devices.capture {
-                CaptureDevice::File { extra_samples, .. } => {
-                    let new_extra = *extra_samples * rate / cfg_rate;
-                    debug!("Scale extra samples: {} -> {}", *extra_samples, new_extra);
-                    *extra_samples = new_extra;
-                }
-                CaptureDevice::Stdin { extra_samples, .. } => {
-                    let new_extra = *extra_samples * rate / cfg_rate;
-                    debug!("Scale extra samples: {} -> {}", *extra_samples, new_extra);
-                    *extra_samples = new_extra;
-                }
-                _ => {}
-            }
+/// Change the sample rate of a config, in the same way as the samplerate override.
+/// With resampling enabled only the capture sample rate is changed,
+/// otherwise the whole pipeline runs at the new rate.
+pub fn set_samplerate(configuration: &mut Configuration, rate: usize) {
+    let cfg_rate = configuration.devices.samplerate;
+    let cfg_chunksize = configuration.devices.chunksize;
+
+    if !configuration.devices.enable_resampling {
+        debug!("Set samplerate: {}", rate);
+        configuration.devices.samplerate = rate;
+        let scaled_chunksize = if rate > cfg_rate {
+            cfg_chunksize * (rate as f32 / cfg_rate as f32).round() as usize
         } else {
-            debug!("Apply override for capture_samplerate: {}", rate);
-            configuration.devices.capture_samplerate = rate;
-            if rate == cfg_rate && !configuration.devices.enable_rate_adjust {
-                debug!("Disabling unneccesary 1:1 resampling");
-                configuration.devices.enable_resampling = false;
+            cfg_chunksize / (cfg_rate as f32 / rate as f32).round() as usize
+        };
+        debug!(
+            "Samplerate changed, adjusting chunksize: {} -> {}",
+            cfg_chunksize, scaled_chunksize
+        );
+        configuration.devices.chunksize = scaled_chunksize;
+        #[allow(unreachable_patterns)]
+        match &mut configuration.devices.capture {
+            CaptureDevice::File { extra_samples, .. } => {
+                let new_extra = *extra_samples * rate / cfg_rate;
+                debug!("Scale extra samples: {} -> {}", *extra_samples, new_extra);
+                *extra_samples = new_extra;
+            }
+            CaptureDevice::Stdin { extra_samples, .. } => {
+                let new_extra = *extra_samples * rate / cfg_rate;
+                debug!("Scale extra samples: {} -> {}", *extra_samples, new_extra);
+                *extra_samples = new_extra;
             }
+            _ => {}
         }
+    } else {
+        debug!("Set capture_samplerate: {}", rate);
+        configuration.devices.capture_samplerate = rate;
+        if rate == cfg_rate && !configuration.devices.enable_rate_adjust {
+            debug!("Disabling unneccesary 1:1 resampling");
+            configuration.devices.enable_resampling = false;
+        }
+    }
+}
+
+/// Sample rates that a measured capture rate can be rounded to.
+const STANDARD_SAMPLERATES: [usize; 15] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000, 352800, 384000,
+    705600, 768000,
+];
+
+/// Get the standard sample rate closest to a measured rate,
+/// if it is within the tolerance used when detecting rate changes.
+pub fn nearest_standard_samplerate(measured_rate: usize) -> Option<usize> {
+    STANDARD_SAMPLERATES
+        .iter()
+        .copied()
+        .min_by_key(|rate| rate.abs_diff(measured_rate))
+        .filter(|rate| {
+            (rate.abs_diff(measured_rate) as f32 / *rate as f32) < RATE_CHANGE_THRESHOLD_VALUE
+        })
+}
+
+fn apply_overrides(configuration: &mut Configuration) {
+    if let Some(rate) = OVERRIDES.read().unwrap().samplerate {
+        set_samplerate(configuration, rate);
     }
     if let Some(extra) = OVERRIDES.read().unwrap().extra_samples {
         debug!("Apply override for extra_samples: {}", extra);
@@ -899,6 +1746,9 @@ fn apply_overrides(configuration: &mut Configuration) {
             CaptureDevice::Stdin { channels, .. } => {
                 *channels = chans;
             }
+            CaptureDevice::Signal { channels, .. } => {
+                *channels = chans;
+            }
             #[cfg(target_os = "linux")]
             CaptureDevice::Alsa { channels, .. } => {
                 *channels = chans;
@@ -930,6 +1780,9 @@ fn apply_overrides(configuration: &mut Configuration) {
             CaptureDevice::Stdin { format, .. } => {
                 *format = fmt;
             }
+            CaptureDevice::Signal { .. } => {
+                error!("Not possible to override capture format for Signal, ignoring");
+            }
             #[cfg(target_os = "linux")]
             CaptureDevice::Alsa { format, .. } => {
                 *format = fmt;
@@ -966,28 +1819,27 @@ fn replace_tokens_in_config(config: &mut Configuration) {
     let samplerate = config.devices.samplerate;
     let num_channels = config.devices.capture.channels();
     for (_name, filter) in config.filters.iter_mut() {
-        match filter {
-            Filter::Conv {
-                parameters: ConvParameters::Raw { filename, .. },
-            }
-            | Filter::Conv {
-                parameters: ConvParameters::Wav { filename, .. },
-            } => {
-                *filename = replace_tokens(filename, samplerate, num_channels);
+        if let Filter::Conv { parameters, .. } = filter {
+            for filename in parameters.coeff_filenames_mut() {
+                for file in filename.files_mut() {
+                    *file = replace_tokens(file, samplerate, num_channels);
+                }
             }
-            _ => {}
         }
     }
     for mut step in config.pipeline.iter_mut() {
         match &mut step {
-            PipelineStep::Filter { names, .. } => {
+            PipelineStep::Filter { names, .. } | PipelineStep::Sum { names, .. } => {
                 for name in names.iter_mut() {
                     *name = replace_tokens(name, samplerate, num_channels);
                 }
             }
-            PipelineStep::Mixer { name } => {
+            PipelineStep::Mixer { name, .. } => {
                 *name = replace_tokens(name, samplerate, num_channels);
             }
+            PipelineStep::StereoWidth { .. }
+            | PipelineStep::Crossfeed { .. }
+            | PipelineStep::Reorder { .. } => {}
         }
     }
 }
@@ -997,13 +1849,15 @@ fn replace_relative_paths_in_config(config: &mut Configuration, configname: &str
     if let Ok(config_file) = PathBuf::from(configname.to_owned()).canonicalize() {
         if let Some(config_dir) = config_file.parent() {
             for (_name, filter) in config.filters.iter_mut() {
-                if let Filter::Conv {
-                    parameters: ConvParameters::Raw { filename, .. },
-                } = filter
-                {
-                    check_and_replace_relative_path(filename, config_dir);
-                } else if let Filter::Conv {
-                    parameters: ConvParameters::Wav { filename, .. },
+                if let Filter::Conv { parameters, .. } = filter {
+                    for filename in parameters.coeff_filenames_mut() {
+                        for file in filename.files_mut() {
+                            check_and_replace_relative_path(file, config_dir);
+                        }
+                    }
+                } else if let Filter::BiquadCombo {
+                    parameters: BiquadComboParameters::File { filename },
+                    ..
                 } = filter
                 {
                     check_and_replace_relative_path(filename, config_dir);
@@ -1049,6 +1903,245 @@ pub enum ConfigChange {
     None,
 }
 
+/// Expand the `swap` shortcut of mixers into the full list of mappings.
+fn expand_mixer_swaps(conf: &mut Configuration) -> Res<()> {
+    for (name, mixer) in conf.mixers.iter_mut() {
+        if let Some(swap) = mixer.swap.take() {
+            if !mixer.mapping.is_empty() {
+                let msg = format!("Mixer '{}' can't have both a swap and a mapping", name);
+                return Err(ConfigError::new(&msg).into());
+            }
+            if swap.len() != mixer.channels.out {
+                let msg = format!(
+                    "The swap of mixer '{}' must give {} channels, one for each output channel",
+                    name, mixer.channels.out
+                );
+                return Err(ConfigError::new(&msg).into());
+            }
+            if let Some(channel) = swap.iter().find(|ch| **ch >= mixer.channels.r#in) {
+                let msg = format!(
+                    "Invalid source channel {} in the swap of mixer '{}', max is {}",
+                    channel,
+                    name,
+                    mixer.channels.r#in - 1
+                );
+                return Err(ConfigError::new(&msg).into());
+            }
+            mixer.mapping = swap
+                .iter()
+                .enumerate()
+                .map(|(dest, channel)| MixerMapping {
+                    dest,
+                    sources: vec![MixerSource {
+                        channel: Some(*channel),
+                        label: None,
+                        gain: 0.0,
+                        inverted: false,
+                        mute: false,
+                    }],
+                    mute: false,
+                })
+                .collect();
+        }
+    }
+    Ok(())
+}
+
+/// Check the channel labels of a device.
+fn validate_channel_labels(name: &str, labels: &[String], channels: usize) -> Res<()> {
+    if !labels.is_empty() && labels.len() != channels {
+        let msg = format!(
+            "{} must have one label per channel, expected {} but got {}",
+            name,
+            channels,
+            labels.len()
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    for (idx, label) in labels.iter().enumerate() {
+        if labels[..idx].contains(label) {
+            let msg = format!("Channel label '{}' is used twice in {}", label, name);
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
+/// Get the index of the channel with a label.
+/// Labels can only be used where the channels are the ones of the capture or playback device.
+fn channel_index(label: &str, labels: &Option<Vec<String>>) -> Res<usize> {
+    match labels {
+        Some(labels) => match labels.iter().position(|l| l == label) {
+            Some(idx) => Ok(idx),
+            None => {
+                let msg = format!("Unknown channel label '{}'", label);
+                Err(ConfigError::new(&msg).into())
+            }
+        },
+        None => {
+            let msg = format!(
+                "Channel label '{}' is used where the channels have no labels",
+                label
+            );
+            Err(ConfigError::new(&msg).into())
+        }
+    }
+}
+
+/// Get the labels of the channels after a step, from the labels of the channels it receives.
+fn labels_after_step(step: &PipelineStep, labels: Option<Vec<String>>) -> Option<Vec<String>> {
+    let mut labels = labels?;
+    match step {
+        // An invalid order gives an error when the pipeline is validated
+        PipelineStep::Reorder { order, .. } if order.iter().all(|idx| *idx < labels.len()) => {
+            labels = order.iter().map(|idx| labels[*idx].clone()).collect();
+        }
+        PipelineStep::Sum { label, .. } => {
+            // The new channel can only be referred to by label if it is given one
+            labels.push(label.clone().unwrap_or_default());
+        }
+        _ => {}
+    }
+    Some(labels)
+}
+
+/// Get the labels of the channels a step receives, from the labels of the channels after it.
+/// This is the inverse of `labels_after_step`, used when working backwards from the playback labels.
+fn labels_before_step(
+    step: &PipelineStep,
+    labels: Option<Vec<String>>,
+) -> Res<Option<Vec<String>>> {
+    let mut labels = match labels {
+        Some(labels) => labels,
+        None => return Ok(None),
+    };
+    match step {
+        PipelineStep::Reorder { order, .. } => {
+            // Only a valid order can be inverted, an invalid one gives an error when the pipeline is validated
+            let mut before = vec![None; labels.len()];
+            if order.len() == labels.len() {
+                for (label, idx) in labels.iter().zip(order.iter()) {
+                    if let Some(slot) = before.get_mut(*idx) {
+                        *slot = Some(label.clone());
+                    }
+                }
+            }
+            if let Some(before) = before.into_iter().collect::<Option<Vec<String>>>() {
+                labels = before;
+            }
+        }
+        PipelineStep::Sum { label, .. } => {
+            // The new channel is the last one, and its playback label is the one of the output it ends up in
+            let playback_label = labels.pop();
+            if let (Some(label), Some(playback_label)) = (label, playback_label) {
+                if *label != playback_label {
+                    let msg = format!(
+                        "Sum step label '{}' does not match the playback label '{}' of its channel",
+                        label, playback_label
+                    );
+                    return Err(ConfigError::new(&msg).into());
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(Some(labels))
+}
+
+/// Replace the channel labels in mixer sources and filter steps by channel indices.
+/// Before the first mixer, the channels are the ones of the capture device,
+/// and after the last mixer they are the ones of the playback device.
+/// A pipeline without mixers uses the capture labels, or the playback labels if there are no capture labels.
+/// The capture labels are followed forwards through the steps that change the channels,
+/// while the playback labels describe the final channels and are followed backwards from the output.
+fn resolve_channel_labels(conf: &mut Configuration) -> Res<()> {
+    validate_channel_labels(
+        "capture_labels",
+        &conf.devices.capture_labels,
+        conf.devices.capture.channels(),
+    )?;
+    validate_channel_labels(
+        "playback_labels",
+        &conf.devices.playback_labels,
+        conf.devices.playback.channels(),
+    )?;
+    let non_empty = |labels: &Vec<String>| {
+        if labels.is_empty() {
+            None
+        } else {
+            Some(labels.clone())
+        }
+    };
+    let capture_labels = non_empty(&conf.devices.capture_labels);
+    let playback_labels = non_empty(&conf.devices.playback_labels);
+    let enabled: Vec<usize> = (0..conf.pipeline.len())
+        .filter(|idx| conf.pipeline[*idx].is_enabled())
+        .collect();
+    let mixers: Vec<usize> = enabled
+        .iter()
+        .copied()
+        .filter(|idx| matches!(conf.pipeline[*idx], PipelineStep::Mixer { .. }))
+        .collect();
+    // The labels of the channels received by each step
+    let mut step_labels: Vec<Option<Vec<String>>> = vec![None; conf.pipeline.len()];
+    // Without mixers, the capture labels are used if there are any
+    let use_capture = !mixers.is_empty() || capture_labels.is_some();
+    if use_capture {
+        let mut labels = capture_labels;
+        for idx in enabled.iter().copied() {
+            step_labels[idx] = labels.clone();
+            if mixers.first() == Some(&idx) {
+                break;
+            }
+            labels = labels_after_step(&conf.pipeline[idx], labels);
+        }
+    }
+    let after_last_mixer: Vec<usize> = match mixers.last() {
+        Some(last) => enabled.iter().copied().filter(|idx| idx > last).collect(),
+        None if !use_capture => enabled.clone(),
+        None => Vec::new(),
+    };
+    let mut labels = playback_labels;
+    for idx in after_last_mixer.into_iter().rev() {
+        labels = labels_before_step(&conf.pipeline[idx], labels)?;
+        step_labels[idx] = labels.clone();
+    }
+    for (step, labels) in conf.pipeline.iter_mut().zip(step_labels.iter()) {
+        if !step.is_enabled() {
+            continue;
+        }
+        match step {
+            PipelineStep::Mixer { name, .. } => {
+                if let Some(mixer) = conf.mixers.get_mut(name) {
+                    for source in mixer.mapping.iter_mut().flat_map(|m| m.sources.iter_mut()) {
+                        if let Some(label) = source.label.take() {
+                            source.channel = Some(channel_index(&label, labels)?);
+                        }
+                    }
+                }
+            }
+            PipelineStep::Filter { channel, .. } => match channel {
+                PipelineChannels::Label(label) => {
+                    *channel = PipelineChannels::Single(channel_index(label, labels)?);
+                }
+                PipelineChannels::Labels(list) => {
+                    let indices = list
+                        .iter()
+                        .map(|label| channel_index(label, labels))
+                        .collect::<Res<Vec<usize>>>()?;
+                    *channel = PipelineChannels::List(indices);
+                }
+                _ => {}
+            },
+            PipelineStep::Reorder { .. }
+            | PipelineStep::Sum { .. }
+            | PipelineStep::StereoWidth { .. }
+            | PipelineStep::Crossfeed { .. } => {}
+        }
+    }
+    Ok(())
+}
+
 pub fn load_validate_config(configname: &str) -> Res<Configuration> {
     let mut configuration = load_config(configname)?;
     validate_config(&mut configuration, Some(configname))?;
@@ -1057,12 +2150,26 @@ pub fn load_validate_config(configname: &str) -> Res<Configuration> {
 
 pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> ConfigChange {
     if currentconf == newconf {
-        return ConfigChange::None;
+        let filters: Vec<String> = newconf
+            .filters
+            .iter()
+            .filter(|(_, params)| reads_coefficient_file(params))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        if filters.is_empty() {
+            return ConfigChange::None;
+        }
+        return ConfigChange::FilterParameters {
+            filters,
+            mixers: Vec::new(),
+        };
     }
     if currentconf.devices != newconf.devices {
         return ConfigChange::Devices;
     }
-    if currentconf.pipeline != newconf.pipeline {
+    if currentconf.pipeline != newconf.pipeline
+        || currentconf.master_gain_db != newconf.master_gain_db
+    {
         return ConfigChange::Pipeline;
     }
     if currentconf.mixers != newconf.mixers {
@@ -1083,14 +2190,22 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
                 | (Filter::Dither { .. }, Filter::Dither { .. })
                 | (Filter::DiffEq { .. }, Filter::DiffEq { .. })
                 | (Filter::Volume { .. }, Filter::Volume { .. })
-                | (Filter::Loudness { .. }, Filter::Loudness { .. }) => {}
+                | (Filter::Loudness { .. }, Filter::Loudness { .. })
+                | (Filter::Limiter { .. }, Filter::Limiter { .. })
+                | (Filter::Compressor { .. }, Filter::Compressor { .. })
+                | (Filter::Saturation { .. }, Filter::Saturation { .. })
+                | (Filter::DcBlock { .. }, Filter::DcBlock { .. })
+                | (Filter::ShelfGain { .. }, Filter::ShelfGain { .. })
+                | (Filter::Deemphasis { .. }, Filter::Deemphasis { .. })
+                | (Filter::Preemphasis { .. }, Filter::Preemphasis { .. })
+                | (Filter::Invert { .. }, Filter::Invert { .. }) => {}
                 _ => {
                     // A filter changed type, need to rebuild the pipeline
                     return ConfigChange::Pipeline;
                 }
             };
             // Only parameters changed, ok to update
-            if params != current_filter {
+            if params != current_filter || reads_coefficient_file(params) {
                 filters.push(filter.to_string());
             }
         }
@@ -1106,15 +2221,130 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
     ConfigChange::FilterParameters { filters, mixers }
 }
 
-/// Validate the loaded configuration, stop on errors and print a helpful message.
-pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<()> {
-    // pre-process by applying overrides and replacing tokens
-    apply_overrides(conf);
-    replace_tokens_in_config(conf);
-    if let Some(fname) = filename {
-        replace_relative_paths_in_config(conf, fname);
+/// Check if a filter reads coefficients from a file.
+/// The file may have been modified even if the config is unchanged, so these filters are always updated.
+fn reads_coefficient_file(filter: &Filter) -> bool {
+    match filter {
+        Filter::Conv { parameters, .. } => !parameters.coeff_filenames().is_empty(),
+        Filter::BiquadCombo {
+            parameters: BiquadComboParameters::File { .. },
+            ..
+        } => true,
+        _ => false,
     }
+}
 
+/// Get a suggested chunksize for a sample rate, the power of two giving a chunk of about 20 ms.
+fn suggested_chunksize(samplerate: usize) -> usize {
+    (samplerate / 48).max(1).next_power_of_two()
+}
+
+/// Hint for a pipeline that outputs `num_channels` channels to a playback device with `num_channels_out`,
+/// naming the steps that decide the number of channels.
+fn channel_count_hint(
+    last_mixer: Option<&str>,
+    summed_channels: usize,
+    num_channels: usize,
+    num_channels_out: usize,
+) -> String {
+    let mixer = format!(
+        "a mixer from {} to {} channels",
+        num_channels, num_channels_out
+    );
+    if summed_channels > 0 {
+        let source = match last_mixer {
+            Some(name) => format!("the last mixer '{}'", name),
+            None => "the capture device".to_string(),
+        };
+        let added = match summed_channels {
+            1 => "1 channel".to_string(),
+            count => format!("{} channels", count),
+        };
+        let base_channels = num_channels - summed_channels;
+        return format!(
+            "Sum steps add {} to the {} channels of {}. Change the number of Sum steps, or add {}.",
+            added, base_channels, source, mixer
+        );
+    }
+    match last_mixer {
+        Some(name) => {
+            let change = format!("Change it to output {} channels", num_channels_out);
+            format!(
+                "The last mixer '{}' outputs {} channels. {}, or add {} after it.",
+                name, num_channels, change, mixer
+            )
+        }
+        None => format!(
+            "There is no mixer in the pipeline, so it outputs the {} channels of the capture device. Add {}.",
+            num_channels, mixer
+        ),
+    }
+}
+
+/// Extra hint for channel count errors, when a disabled mixer would have changed the number of channels.
+fn disabled_mixer_hint(disabled_mixer: Option<&str>) -> String {
+    match disabled_mixer {
+        Some(name) => format!(
+            " Note that the disabled mixer '{}' would change the number of channels.",
+            name
+        ),
+        None => String::new(),
+    }
+}
+
+/// Validate the loaded configuration, stop on errors and print a helpful message.
+pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<()> {
+    // pre-process by applying overrides and replacing tokens
+    apply_overrides(conf);
+    replace_tokens_in_config(conf);
+    if let Some(fname) = filename {
+        if fname == STDIN_CONFIG {
+            if let CaptureDevice::Stdin { .. } = conf.devices.capture {
+                return Err(ConfigError::new(
+                    "The capture device can't be Stdin when the config is read from stdin",
+                )
+                .into());
+            }
+        } else {
+            replace_relative_paths_in_config(conf, fname);
+        }
+    }
+    expand_mixer_swaps(conf)?;
+    resolve_channel_labels(conf)?;
+
+    if conf.devices.samplerate == 0 {
+        return Err(ConfigError::new("samplerate must be larger than zero").into());
+    }
+    if conf.devices.chunksize == 0 {
+        let msg = format!(
+            "chunksize must be larger than zero, a good value at {} Hz is {}",
+            conf.devices.samplerate,
+            suggested_chunksize(conf.devices.samplerate)
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    // The duration of one chunk, that limits the fade-out and the startup silence
+    let chunk_ms = 1000 * conf.devices.chunksize / conf.devices.samplerate;
+    if conf.devices.target_latency_ms < 0.0 {
+        return Err(ConfigError::new("target_latency_ms cannot be negative").into());
+    }
+    if conf.devices.target_latency_ms > 0.0 {
+        if conf.devices.target_level > 0 {
+            return Err(ConfigError::new(
+                "target_level and target_latency_ms can't both be given, use one of them",
+            )
+            .into());
+        }
+        let max_latency =
+            1000.0 * (2 * conf.devices.chunksize - 1) as f32 / conf.devices.samplerate as f32;
+        if conf.devices.effective_target_level() >= 2 * conf.devices.chunksize {
+            let msg = format!(
+                "target_latency_ms can't be larger than {:.1} ms with a chunksize of {}",
+                max_latency, conf.devices.chunksize
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
     if conf.devices.target_level >= 2 * conf.devices.chunksize {
         let msg = format!(
             "target_level can't be larger than {}",
@@ -1131,13 +2361,85 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
     if conf.devices.silence_timeout < 0.0 {
         return Err(ConfigError::new("silence_timeout cannot be negative").into());
     }
+    if conf.devices.open_retries > MAX_OPEN_RETRIES {
+        let msg = format!("open_retries can't be larger than {}", MAX_OPEN_RETRIES);
+        return Err(ConfigError::new(&msg).into());
+    }
+    if conf.devices.retry_delay_ms > MAX_RETRY_DELAY_MS {
+        let msg = format!("retry_delay_ms can't be larger than {}", MAX_RETRY_DELAY_MS);
+        return Err(ConfigError::new(&msg).into());
+    }
+    // The fade-out is applied to the last chunk only
+    if conf.devices.fadeout_ms > chunk_ms {
+        let msg = format!(
+            "fadeout_ms can't be larger than {} ms, the duration of one chunk",
+            chunk_ms
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    filters::validate_channel_gains(
+        "input_gains",
+        conf.devices.capture.channels(),
+        &conf.devices.input_gains,
+    )?;
+    filters::validate_channel_gains(
+        "output_gains",
+        conf.devices.playback.channels(),
+        &conf.devices.output_gains,
+    )?;
+    if let Some(probe) = &conf.devices.probe {
+        let channels = match probe.point {
+            ProbePoint::Capture => conf.devices.capture.channels(),
+            ProbePoint::Playback => conf.devices.playback.channels(),
+        };
+        if probe.channel >= channels {
+            let msg = format!(
+                "Probe channel {} is out of range, the {:?} device has {} channels",
+                probe.channel, probe.point, channels
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+        if !probe.fft_size.is_power_of_two() || !(64..=65536).contains(&probe.fft_size) {
+            let msg = "Probe fft_size must be a power of two, from 64 to 65536";
+            return Err(ConfigError::new(msg).into());
+        }
+    }
+    if conf.master_gain_db.is_nan() || conf.master_gain_db.abs() > 150.0 {
+        let msg = "master_gain_db must be in the range -150 to +150 dB";
+        return Err(ConfigError::new(msg).into());
+    }
+    if !(0.0..=100.0).contains(&conf.devices.dither_amplitude) {
+        let msg = "dither_amplitude must be in the range 0 to 100";
+        return Err(ConfigError::new(msg).into());
+    }
+    if let Some(level) = conf.devices.output_clip_db {
+        if !(-100.0..=50.0).contains(&level) {
+            let msg = "output_clip_db must be in the range -100 to +50 dB";
+            return Err(ConfigError::new(msg).into());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let PlaybackDevice::Alsa {
+        startup_silence_ms, ..
+    } = &conf.devices.playback
+    {
+        // The silence and the first chunk must fit together in the playback buffer of two chunks
+        if *startup_silence_ms > chunk_ms {
+            let msg = format!(
+                "startup_silence_ms can't be larger than {} ms, the duration of one chunk",
+                chunk_ms
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
     #[cfg(target_os = "windows")]
     if let CaptureDevice::Wasapi { format, .. } = &conf.devices.capture {
-        if *format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
-                "The Wasapi capture backend does not support FLOAT64LE sample format",
-            )
-            .into());
+        if *format == SampleFormat::FLOAT64LE || format.is_big_endian() {
+            let msg = format!(
+                "The Wasapi capture backend does not support {} sample format",
+                format
+            );
+            return Err(ConfigError::new(&msg).into());
         }
     }
     #[cfg(target_os = "windows")]
@@ -1168,11 +2470,12 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
     }
     #[cfg(target_os = "windows")]
     if let PlaybackDevice::Wasapi { format, .. } = &conf.devices.playback {
-        if *format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
-                "The Wasapi playback backend does not support FLOAT64LE sample format",
-            )
-            .into());
+        if *format == SampleFormat::FLOAT64LE || format.is_big_endian() {
+            let msg = format!(
+                "The Wasapi playback backend does not support {} sample format",
+                format
+            );
+            return Err(ConfigError::new(&msg).into());
         }
     }
     #[cfg(target_os = "windows")]
@@ -1207,27 +2510,116 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
     }
     #[cfg(target_os = "macos")]
     if let CaptureDevice::CoreAudio { format, .. } = &conf.devices.capture {
-        if *format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
-                "The CoreAudio capture backend does not support FLOAT64LE sample format",
-            )
-            .into());
+        if *format == SampleFormat::FLOAT64LE || format.is_big_endian() {
+            let msg = format!(
+                "The CoreAudio capture backend does not support {} sample format",
+                format
+            );
+            return Err(ConfigError::new(&msg).into());
         }
     }
     #[cfg(target_os = "macos")]
     if let PlaybackDevice::CoreAudio { format, .. } = &conf.devices.playback {
-        if *format == SampleFormat::FLOAT64LE {
+        if *format == SampleFormat::FLOAT64LE || format.is_big_endian() {
+            let msg = format!(
+                "The CoreAudio playback backend does not support {} sample format",
+                format
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    if let PlaybackDevice::File {
+        filename,
+        format,
+        wav_levels,
+        ..
+    } = &conf.devices.playback
+    {
+        if filedevice::is_wav(filename) && format.is_big_endian() {
+            let msg = format!(
+                "Wav files are little-endian, the {} sample format can't be used for '{}'",
+                format, filename
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+        if *wav_levels && !filedevice::is_wav(filename) {
+            let msg = format!(
+                "wav_levels can only be used when writing a wav file, not for '{}'",
+                filename
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    if let CaptureDevice::File {
+        filename,
+        channels,
+        format,
+        channel_adjust,
+        skip_bytes,
+        read_bytes,
+        start_frame,
+        ..
+    } = &conf.devices.capture
+    {
+        let capture_samplerate =
+            if conf.devices.capture_samplerate > 0 && conf.devices.enable_resampling {
+                conf.devices.capture_samplerate
+            } else {
+                conf.devices.samplerate
+            };
+        if filedevice::is_wav(filename) {
+            filedevice::read_wav_header(filename, *channels, channel_adjust, capture_samplerate)?;
+        } else if *channel_adjust != ChannelAdjust::Strict {
             return Err(ConfigError::new(
-                "The CoreAudio playback backend does not support FLOAT64LE sample format",
+                "channel_adjust can only be used with wav files, since raw files don't store the number of channels",
             )
             .into());
         }
+        if *start_frame > 0 {
+            let frames = filedevice::capture_file_frames(
+                filename,
+                *channels,
+                channel_adjust,
+                format,
+                *skip_bytes,
+                *read_bytes,
+                capture_samplerate,
+            )?;
+            if *start_frame >= frames {
+                let msg = format!(
+                    "start_frame {} is beyond the end of '{}', which has {} frames to capture",
+                    start_frame, filename, frames
+                );
+                return Err(ConfigError::new(&msg).into());
+            }
+        }
+    }
+    if let CaptureDevice::Signal {
+        signal, duration, ..
+    } = &conf.devices.capture
+    {
+        generatordevice::validate_signal(signal, *duration, conf.devices.samplerate)?;
     }
     let mut num_channels = conf.devices.capture.channels();
-    let fs = conf.devices.samplerate;
+    // The last mixer of the pipeline, that decides the number of channels sent to playback
+    let mut last_mixer: Option<&str> = None;
+    // A disabled mixer that would have changed the number of channels
+    let mut disabled_mixer: Option<&str> = None;
+    // The number of channels added by Sum steps after the last mixer
+    let mut summed_channels = 0;
     for step in &conf.pipeline {
+        if !step.is_enabled() {
+            if let PipelineStep::Mixer { name, .. } = step {
+                if let Some(mixer) = conf.mixers.get(name) {
+                    if mixer.channels.r#in != mixer.channels.out {
+                        disabled_mixer = Some(name);
+                    }
+                }
+            }
+            continue;
+        }
         match step {
-            PipelineStep::Mixer { name } => {
+            PipelineStep::Mixer { name, .. } => {
                 if !conf.mixers.contains_key(name) {
                     let msg = format!("Use of missing mixer '{}'", name);
                     return Err(ConfigError::new(&msg).into());
@@ -1235,12 +2627,17 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
                     let chan_in = conf.mixers.get(name).unwrap().channels.r#in;
                     if chan_in != num_channels {
                         let msg = format!(
-                            "Mixer '{}' has wrong number of input channels. Expected {}, found {}.",
-                            name, num_channels, chan_in
+                            "Mixer '{}' has wrong number of input channels. Expected {}, found {}.{}",
+                            name,
+                            num_channels,
+                            chan_in,
+                            disabled_mixer_hint(disabled_mixer)
                         );
                         return Err(ConfigError::new(&msg).into());
                     }
                     num_channels = conf.mixers.get(name).unwrap().channels.out;
+                    last_mixer = Some(name);
+                    summed_channels = 0;
                     match mixer::validate_mixer(conf.mixers.get(name).unwrap()) {
                         Ok(_) => {}
                         Err(err) => {
@@ -1250,46 +2647,1534 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
                     }
                 }
             }
-            PipelineStep::Filter { channel, names } => {
-                if *channel >= num_channels {
-                    let msg = format!("Use of non existing channel {}", channel);
-                    return Err(ConfigError::new(&msg).into());
-                }
-                for name in names {
-                    if !conf.filters.contains_key(name) {
-                        let msg = format!("Use of missing filter '{}'", name);
+            PipelineStep::Filter { channel, names, .. } => {
+                for idx in channel.indices(num_channels) {
+                    if idx >= num_channels {
+                        let msg = format!(
+                            "Use of non existing channel {}, the filter step receives {} channels",
+                            idx, num_channels
+                        );
                         return Err(ConfigError::new(&msg).into());
                     }
-                    match filters::validate_filter(fs, conf.filters.get(name).unwrap()) {
-                        Ok(_) => {}
-                        Err(err) => {
-                            let msg = format!("Invalid filter '{}'. Reason: {}", name, err);
-                            return Err(ConfigError::new(&msg).into());
-                        }
-                    }
                 }
+                validate_step_filters(conf, names, channel.indices(num_channels).len())?;
+            }
+            PipelineStep::StereoWidth {
+                channels, width, ..
+            } => {
+                filters::validate_stereo_width(num_channels, channels, *width)?;
+            }
+            PipelineStep::Crossfeed {
+                channels,
+                delay_ms,
+                attenuation_db,
+                cutoff_freq,
+                ..
+            } => {
+                filters::validate_crossfeed(
+                    num_channels,
+                    conf.devices.samplerate,
+                    channels,
+                    *delay_ms,
+                    *attenuation_db,
+                    *cutoff_freq,
+                )?;
+            }
+            PipelineStep::Reorder { order, .. } => {
+                filters::validate_reorder(num_channels, order)?;
+            }
+            PipelineStep::Sum {
+                channels,
+                gain,
+                names,
+                ..
+            } => {
+                filters::validate_sum(num_channels, channels, *gain)?;
+                validate_step_filters(conf, names, 1)?;
+                num_channels += 1;
+                summed_channels += 1;
             }
         }
     }
     let num_channels_out = conf.devices.playback.channels();
     if num_channels != num_channels_out {
+        let hint = channel_count_hint(last_mixer, summed_channels, num_channels, num_channels_out);
         let msg = format!(
-            "Pipeline outputs {} channels, playback device has {}.",
-            num_channels, num_channels_out
+            "Pipeline outputs {} channels, playback device has {}. {}{}",
+            num_channels,
+            num_channels_out,
+            hint,
+            disabled_mixer_hint(disabled_mixer)
         );
         return Err(ConfigError::new(&msg).into());
     }
+    for (channel, gain) in estimate_output_gains(conf).iter().enumerate() {
+        if *gain > 1.0 {
+            warn!(
+                "The estimated worst case gain to playback channel {} is {:.1} dB, the output may clip",
+                channel,
+                20.0 * gain.log10()
+            );
+        }
+    }
     Ok(())
 }
 
+/// Get the boost in dB of a peaking or shelving Biquad, zero for other types.
+fn biquad_boost_db(parameters: &BiquadParameters) -> PrcFmt {
+    match parameters {
+        BiquadParameters::Peaking(PeakingWidth::Q { gain, .. })
+        | BiquadParameters::Peaking(PeakingWidth::Bandwidth { gain, .. })
+        | BiquadParameters::Highshelf(ShelfSteepness::Q { gain, .. })
+        | BiquadParameters::Highshelf(ShelfSteepness::Slope { gain, .. })
+        | BiquadParameters::Lowshelf(ShelfSteepness::Q { gain, .. })
+        | BiquadParameters::Lowshelf(ShelfSteepness::Slope { gain, .. })
+        | BiquadParameters::HighshelfFO { gain, .. }
+        | BiquadParameters::LowshelfFO { gain, .. } => gain.max(0.0),
+        _ => 0.0,
+    }
+}
+
+/// Get the largest gain of a filter as a linear factor, for estimating the gain staging.
+/// Only Gain filters and the boosts of peaking and shelving Biquads are included.
+/// For the BiquadCombos built from such Biquads, the boosts of the stages are added up,
+/// as if they all peaked at the same frequency.
+/// All other filters are assumed to have unity gain.
+fn filter_peak_gain(filter: &Filter, samplerate: usize) -> PrcFmt {
+    let boost_db = match filter {
+        Filter::Gain { parameters, .. } => {
+            if parameters.mute {
+                return 0.0;
+            }
+            return match parameters.scale {
+                GainScale::Decibel => (10.0 as PrcFmt).powf(parameters.gain / 20.0),
+                GainScale::Linear => parameters.gain.abs(),
+            };
+        }
+        Filter::Biquad { parameters, .. } => biquad_boost_db(parameters),
+        Filter::BiquadCombo { parameters, .. } => match parameters {
+            BiquadComboParameters::FivePointPeq {
+                gls,
+                gp1,
+                gp2,
+                gp3,
+                ghs,
+                ..
+            } => [gls, gp1, gp2, gp3, ghs]
+                .iter()
+                .map(|gain| gain.max(0.0))
+                .sum(),
+            BiquadComboParameters::PeakingBank { bands } => {
+                bands.iter().map(|band| band.gain.max(0.0)).sum()
+            }
+            BiquadComboParameters::Tilt {
+                pivot_freq,
+                slope_db_oct,
+            } => biquadcombo::tilt_stages(samplerate, *pivot_freq, *slope_db_oct)
+                .iter()
+                .map(biquad_boost_db)
+                .sum(),
+            BiquadComboParameters::Cascade { stages } => stages.iter().map(biquad_boost_db).sum(),
+            _ => 0.0,
+        },
+        Filter::ShelfGain { parameters, .. } => parameters.gain_db.max(0.0),
+        _ => 0.0,
+    };
+    (10.0 as PrcFmt).powf(boost_db / 20.0)
+}
+
+/// Check that the filters of a pipeline step exist and are valid,
+/// for a step that applies them to `nbr_channels` channels.
+fn validate_step_filters(conf: &Configuration, names: &[String], nbr_channels: usize) -> Res<()> {
+    for name in names {
+        if !conf.filters.contains_key(name) {
+            let msg = format!("Use of missing filter '{}'", name);
+            return Err(ConfigError::new(&msg).into());
+        }
+        if let Filter::Conv { parameters, .. } = &conf.filters[name] {
+            for filename in parameters.coeff_filenames() {
+                if let CoeffFilename::PerChannel(files) = filename {
+                    if files.len() != nbr_channels {
+                        let msg = format!(
+                            "Filter '{}' has {} coefficient files but is used for {} channels",
+                            name,
+                            files.len(),
+                            nbr_channels
+                        );
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                }
+            }
+        }
+        match filters::validate_filter(
+            conf.devices.samplerate,
+            conf.devices.chunksize,
+            conf.filters.get(name).unwrap(),
+        ) {
+            Ok(_) => {}
+            Err(err) => {
+                let msg = format!("Invalid filter '{}'. Reason: {}", name, err);
+                return Err(ConfigError::new(&msg).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Estimate the worst case gain from the capture device to each playback channel, as linear factors.
+/// The gains along each channel path are multiplied, and the mixers add up their sources.
+/// The phase is ignored, so this is only an estimate meant to catch large mistakes.
+fn estimate_output_gains(conf: &Configuration) -> Vec<PrcFmt> {
+    let mut gains = vec![1.0; conf.devices.capture.channels()];
+    for (gain, trim) in gains.iter_mut().zip(conf.devices.input_gains.iter()) {
+        *gain *= (10.0 as PrcFmt).powf(trim / 20.0);
+    }
+    for step in conf.pipeline.iter().filter(|step| step.is_enabled()) {
+        match step {
+            PipelineStep::Mixer { name, .. } => {
+                let mixer = &conf.mixers[name];
+                let mut new_gains = vec![0.0; mixer.channels.out];
+                for mapping in mixer.mapping.iter().filter(|m| !m.mute) {
+                    for source in mapping.sources.iter().filter(|s| !s.mute) {
+                        if let Some(channel) = source.channel {
+                            new_gains[mapping.dest] +=
+                                (10.0 as PrcFmt).powf(source.gain / 20.0) * gains[channel];
+                        }
+                    }
+                }
+                gains = new_gains;
+            }
+            PipelineStep::Filter { channel, names, .. } => {
+                for idx in channel.indices(gains.len()) {
+                    for name in names.iter() {
+                        let filter = &conf.filters[name];
+                        if !filter.is_bypassed() {
+                            gains[idx] *= filter_peak_gain(filter, conf.devices.samplerate);
+                        }
+                    }
+                }
+            }
+            PipelineStep::StereoWidth {
+                channels, width, ..
+            } => {
+                // Each output is a mix of both inputs, with a gain of at most max(1, width)
+                let gain = width.max(1.0) * gains[channels[0]].max(gains[channels[1]]);
+                gains[channels[0]] = gain;
+                gains[channels[1]] = gain;
+            }
+            PipelineStep::Crossfeed {
+                channels,
+                attenuation_db,
+                ..
+            } => {
+                // Each output gets an attenuated copy of the other channel added
+                let feed = (10.0 as PrcFmt).powf(-attenuation_db / 20.0);
+                let gain = (1.0 + feed) * gains[channels[0]].max(gains[channels[1]]);
+                gains[channels[0]] = gain;
+                gains[channels[1]] = gain;
+            }
+            PipelineStep::Reorder { order, .. } => {
+                gains = order.iter().map(|idx| gains[*idx]).collect();
+            }
+            PipelineStep::Sum {
+                channels,
+                gain,
+                names,
+                ..
+            } => {
+                let mut sum_gain = (10.0 as PrcFmt).powf(gain / 20.0)
+                    * channels.iter().map(|idx| gains[*idx]).sum::<PrcFmt>();
+                for name in names.iter() {
+                    let filter = &conf.filters[name];
+                    if !filter.is_bypassed() {
+                        sum_gain *= filter_peak_gain(filter, conf.devices.samplerate);
+                    }
+                }
+                gains.push(sum_gain);
+            }
+        }
+    }
+    for (gain, trim) in gains.iter_mut().zip(conf.devices.output_gains.iter()) {
+        *gain *= (10.0 as PrcFmt).powf(trim / 20.0);
+    }
+    let master_gain = (10.0 as PrcFmt).powf(conf.master_gain_db / 20.0);
+    for gain in gains.iter_mut() {
+        *gain *= master_gain;
+    }
+    gains
+}
+
 /// Get a vector telling which channels are actually used in the pipeline
 pub fn get_used_capture_channels(conf: &Configuration) -> Vec<bool> {
-    for step in conf.pipeline.iter() {
-        if let PipelineStep::Mixer { name } = step {
-            let mixerconf = conf.mixers.get(name).unwrap();
-            return mixer::get_used_input_channels(mixerconf);
+    let capture_channels = conf.devices.capture.channels();
+    // The capture channels that each channel comes from, changed by Reorder and Sum steps before the first mixer
+    let mut sources: Vec<Vec<usize>> = (0..capture_channels).map(|idx| vec![idx]).collect();
+    for step in conf.pipeline.iter().filter(|step| step.is_enabled()) {
+        match step {
+            PipelineStep::Mixer { name, .. } => {
+                let mixerconf = conf.mixers.get(name).unwrap();
+                let mut used = vec![false; capture_channels];
+                for (channel, is_used) in mixer::get_used_input_channels(mixerconf)
+                    .into_iter()
+                    .enumerate()
+                {
+                    if is_used {
+                        for source in sources[channel].iter() {
+                            used[*source] = true;
+                        }
+                    }
+                }
+                return used;
+            }
+            PipelineStep::Reorder { order, .. } => {
+                sources = order.iter().map(|idx| sources[*idx].clone()).collect();
+            }
+            PipelineStep::Sum { channels, .. } => {
+                let summed = channels
+                    .iter()
+                    .flat_map(|idx| sources[*idx].iter().copied())
+                    .collect();
+                sources.push(summed);
+            }
+            _ => {}
         }
     }
-    let capture_channels = conf.devices.capture.channels();
     vec![true; capture_channels]
 }
+
+/// A filter used in the pipeline, as listed in the startup summary.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct SummaryFilter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub filter_type: String,
+}
+
+/// Summary of a validated config, printed as JSON at startup by `--describe`.
+/// The values are the ones the devices are opened with, after any command line overrides.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct ConfigSummary {
+    pub samplerate: usize,
+    pub capture_samplerate: usize,
+    pub chunksize: usize,
+    pub target_level: usize,
+    pub capture_channels: usize,
+    pub playback_channels: usize,
+    pub capture_type: String,
+    pub playback_type: String,
+    pub filters: Vec<SummaryFilter>,
+    pub mixers: Vec<String>,
+}
+
+/// Get the value of the "type" tag that a config item is serialized with.
+fn type_tag<T: Serialize>(item: &T) -> String {
+    serde_json::to_value(item)
+        .ok()
+        .and_then(|value| value.get("type").and_then(|t| t.as_str().map(String::from)))
+        .unwrap_or_default()
+}
+
+/// Summarize a validated config.
+/// Only the filters and mixers used in the pipeline are listed, in the order they are first used.
+pub fn config_summary(conf: &Configuration) -> ConfigSummary {
+    let capture_samplerate =
+        if conf.devices.capture_samplerate > 0 && conf.devices.enable_resampling {
+            conf.devices.capture_samplerate
+        } else {
+            conf.devices.samplerate
+        };
+    // The playback devices use one chunk when no target level is given
+    let target_level = match conf.devices.effective_target_level() {
+        0 => conf.devices.chunksize,
+        level => level,
+    };
+    let mut filters: Vec<SummaryFilter> = Vec::new();
+    let mut mixers: Vec<String> = Vec::new();
+    for step in conf.pipeline.iter().filter(|step| step.is_enabled()) {
+        match step {
+            PipelineStep::Mixer { name, .. } => {
+                if !mixers.contains(name) {
+                    mixers.push(name.clone());
+                }
+            }
+            PipelineStep::Filter { names, .. } | PipelineStep::Sum { names, .. } => {
+                for name in names.iter() {
+                    if !filters.iter().any(|f| &f.name == name) {
+                        filters.push(SummaryFilter {
+                            name: name.clone(),
+                            filter_type: type_tag(&conf.filters[name]),
+                        });
+                    }
+                }
+            }
+            PipelineStep::StereoWidth { .. }
+            | PipelineStep::Crossfeed { .. }
+            | PipelineStep::Reorder { .. } => {}
+        }
+    }
+    ConfigSummary {
+        samplerate: conf.devices.samplerate,
+        capture_samplerate,
+        chunksize: conf.devices.chunksize,
+        target_level,
+        capture_channels: conf.devices.capture.channels(),
+        playback_channels: conf.devices.playback.channels(),
+        capture_type: type_tag(&conf.devices.capture),
+        playback_type: type_tag(&conf.devices.playback),
+        filters,
+        mixers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        config_diff, config_summary, estimate_output_gains, get_used_capture_channels, load_config,
+        nearest_standard_samplerate, parse_config, set_samplerate, suggested_chunksize,
+        validate_config, AllChannels, ConfigChange, Configuration, Filter, PipelineChannels,
+        PipelineStep, STDIN_CONFIG,
+    };
+    use crate::filters;
+    use crate::PrcFmt;
+
+    fn make_config(filters: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+{}
+",
+            filters
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn load_yaml_and_json() {
+        let from_yaml = load_config("testdata/config.yml").unwrap();
+        let from_json = load_config("testdata/config.json").unwrap();
+        assert_eq!(from_yaml, from_json);
+    }
+
+    #[test]
+    fn config_from_stdin() {
+        let from_yaml = load_config("testdata/config.yml").unwrap();
+        let json = std::fs::read_to_string("testdata/config.json").unwrap();
+        let from_json = parse_config(&json, true, "config from stdin").unwrap();
+        assert_eq!(from_yaml, from_json);
+        let err = parse_config("devices: 1", false, "config from stdin").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid config from stdin!"));
+        // Stdin can't deliver both the config and the audio
+        let mut conf = make_config("  {}");
+        assert!(validate_config(&mut conf.clone(), None).is_ok());
+        assert!(validate_config(&mut conf, Some(STDIN_CONFIG)).is_err());
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let conf = make_config(
+            "
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+",
+        );
+        assert!(matches!(config_diff(&conf, &conf), ConfigChange::None));
+    }
+
+    #[test]
+    fn diff_unchanged_coefficient_file() {
+        let conf = make_config(
+            "
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+  fir:
+    type: Conv
+    parameters:
+      type: Wav
+      filename: testdata/int32.wav
+",
+        );
+        match config_diff(&conf, &conf) {
+            ConfigChange::FilterParameters { filters, mixers } => {
+                assert_eq!(filters, vec!["fir".to_string()]);
+                assert!(mixers.is_empty());
+            }
+            other => panic!("Unexpected change {:?}", other),
+        }
+    }
+
+    #[test]
+    fn estimate_combo_gain() {
+        let conf = make_config(
+            "
+  tilt:
+    type: BiquadCombo
+    parameters:
+      type: Tilt
+      pivot_freq: 1000
+      slope_db_oct: 2.0
+  bank:
+    type: BiquadCombo
+    parameters:
+      type: PeakingBank
+      bands:
+        - freq: 100
+          gain: 3.0
+          q: 1.0
+        - freq: 1000
+          gain: -6.0
+          q: 1.0
+        - freq: 5000
+          gain: 2.0
+          q: 1.0
+pipeline:
+  - type: Filter
+    channels: [0]
+    names:
+      - tilt
+  - type: Filter
+    channels: [1]
+    names:
+      - bank
+",
+        );
+        let gains: Vec<PrcFmt> = estimate_output_gains(&conf)
+            .iter()
+            .map(|gain| 20.0 * gain.log10())
+            .collect();
+        // Four highshelves of +2 dB between the pivot and 40% of 44.1 kHz
+        assert!((gains[0] - 8.0).abs() < 1.0e-6);
+        // The two boosting bands add up
+        assert!((gains[1] - 5.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn estimate_gain_staging() {
+        let mut conf = make_config(
+            "
+  boost:
+    type: Gain
+    parameters:
+      gain: 6.0
+  peak:
+    type: Biquad
+    parameters:
+      type: Peaking
+      freq: 1000
+      q: 2.0
+      gain: 3.0
+  cut:
+    type: Biquad
+    parameters:
+      type: Peaking
+      freq: 100
+      q: 2.0
+      gain: -10.0
+mixers:
+  sum:
+    channels:
+      in: 2
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+            gain: 0
+          - channel: 1
+            gain: -6
+      - dest: 1
+        sources:
+          - channel: 1
+            gain: -3
+pipeline:
+  - type: Mixer
+    name: sum
+  - type: Filter
+    channels: [0]
+    names:
+      - boost
+      - cut
+  - type: Filter
+    channels: [1]
+    names:
+      - peak
+",
+        );
+        assert!(validate_config(&mut conf, None).is_ok());
+        let gains: Vec<PrcFmt> = estimate_output_gains(&conf)
+            .iter()
+            .map(|gain| 20.0 * gain.log10())
+            .collect();
+        // Channel 0 sums 0 dB and -6 dB, then gets +6 dB
+        let expected_0 = 20.0 * (1.0 + (10.0 as PrcFmt).powf(-6.0 / 20.0)).log10() + 6.0;
+        assert!((gains[0] - expected_0).abs() < 1.0e-6);
+        assert!(gains[1].abs() < 1.0e-6);
+        // The master gain shifts all channels
+        conf.master_gain_db = -4.0;
+        let gains: Vec<PrcFmt> = estimate_output_gains(&conf)
+            .iter()
+            .map(|gain| 20.0 * gain.log10())
+            .collect();
+        assert!((gains[0] - expected_0 + 4.0).abs() < 1.0e-6);
+        assert!((gains[1] + 4.0).abs() < 1.0e-6);
+        conf.master_gain_db = PrcFmt::NAN;
+        assert!(validate_config(&mut conf, None).is_err());
+        conf.master_gain_db = 200.0;
+        assert!(validate_config(&mut conf, None).is_err());
+        conf.master_gain_db = -150.0;
+        assert!(validate_config(&mut conf, None).is_ok());
+    }
+
+    #[test]
+    fn summarize_config() {
+        let mut conf = make_config(
+            "
+  boost:
+    type: Gain
+    parameters:
+      gain: 6.0
+  unused:
+    type: Gain
+    parameters:
+      gain: 3.0
+  peak:
+    type: Biquad
+    parameters:
+      type: Peaking
+      freq: 1000
+      q: 2.0
+      gain: 3.0
+mixers:
+  sum:
+    channels:
+      in: 2
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+            gain: 0
+      - dest: 1
+        sources:
+          - channel: 1
+            gain: 0
+pipeline:
+  - type: Filter
+    channels: [0]
+    names:
+      - boost
+      - peak
+  - type: Mixer
+    name: sum
+  - type: Filter
+    channels: [1]
+    names:
+      - boost
+",
+        );
+        assert!(validate_config(&mut conf, None).is_ok());
+        let summary = config_summary(&conf);
+        assert_eq!(summary.samplerate, 44100);
+        assert_eq!(summary.capture_samplerate, 44100);
+        assert_eq!(summary.chunksize, 1024);
+        assert_eq!(summary.target_level, 1024);
+        assert_eq!(summary.capture_channels, 2);
+        assert_eq!(summary.playback_channels, 2);
+        assert_eq!(summary.capture_type, "Stdin");
+        assert_eq!(summary.playback_type, "Stdout");
+        assert_eq!(summary.mixers, vec!["sum".to_string()]);
+        let filters: Vec<(&str, &str)> = summary
+            .filters
+            .iter()
+            .map(|f| (f.name.as_str(), f.filter_type.as_str()))
+            .collect();
+        assert_eq!(filters, vec![("boost", "Gain"), ("peak", "Biquad")]);
+    }
+
+    #[test]
+    fn summarize_resolved_values() {
+        let mut conf = make_config("  {}");
+        conf.devices.target_latency_ms = 10.0;
+        conf.devices.enable_resampling = true;
+        // The same change as the samplerate override
+        set_samplerate(&mut conf, 48000);
+        assert!(validate_config(&mut conf, None).is_ok());
+        let summary = config_summary(&conf);
+        assert_eq!(summary.samplerate, 44100);
+        assert_eq!(summary.capture_samplerate, 48000);
+        assert_eq!(summary.target_level, 441);
+    }
+
+    fn make_upmix_config(pipeline: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 6
+    format: S16LE
+filters:
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+mixers:
+  stereo:
+    channels:
+      in: 2
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+      - dest: 1
+        sources:
+          - channel: 1
+  upmix:
+    channels:
+      in: 2
+      out: 6
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+      - dest: 1
+        sources:
+          - channel: 1
+      - dest: 2
+        sources:
+          - channel: 0
+            gain: -6
+          - channel: 1
+            gain: -6
+      - dest: 3
+        sources:
+          - channel: 0
+            gain: -6
+          - channel: 1
+            gain: -6
+      - dest: 4
+        sources:
+          - channel: 0
+      - dest: 5
+        sources:
+          - channel: 1
+pipeline:
+{}",
+            pipeline
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn validate_upmix() {
+        let mut conf = make_upmix_config(
+            "
+  - type: Filter
+    channels: [0, 1]
+    names:
+      - gain",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Pipeline outputs 2 channels, playback device has 6. There is no mixer in the pipeline, so it outputs the 2 channels of the capture device. Add a mixer from 2 to 6 channels."
+        );
+        let mut conf = make_upmix_config(
+            "
+  - type: Mixer
+    name: stereo",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Pipeline outputs 2 channels, playback device has 6. The last mixer 'stereo' outputs 2 channels. Change it to output 6 channels, or add a mixer from 2 to 6 channels after it."
+        );
+        let mut conf = make_upmix_config(
+            "
+  - type: Mixer
+    name: stereo
+  - type: Mixer
+    name: upmix
+  - type: Filter
+    channels: [2, 3, 4, 5]
+    names:
+      - gain",
+        );
+        assert!(validate_config(&mut conf, None).is_ok());
+    }
+
+    #[test]
+    fn validate_sum_channels() {
+        let mut conf = make_upmix_config(
+            "
+  - type: Sum
+    channels: [0, 1]
+    gain: -6.0
+    names:
+      - gain
+  - type: Filter
+    channels: [2]
+    names:
+      - gain",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Pipeline outputs 3 channels, playback device has 6. Sum steps add 1 channel to the 2 channels of the capture device. Change the number of Sum steps, or add a mixer from 3 to 6 channels."
+        );
+        let mut conf = make_upmix_config(
+            "
+  - type: Filter
+    channels: [3]
+    names:
+      - gain
+  - type: Sum
+    channels: [0, 1]",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Use of non existing channel 3, the filter step receives 2 channels"
+        );
+        let pipeline = (0..4)
+            .map(|_| "\n  - type: Sum\n    channels: [0, 1]")
+            .collect::<String>();
+        let mut conf = make_upmix_config(&pipeline);
+        assert!(validate_config(&mut conf, None).is_ok());
+        assert_eq!(get_used_capture_channels(&conf), vec![true, true]);
+    }
+
+    #[test]
+    fn resolve_channel_labels_with_sum() {
+        // Sum steps after the mixer get the playback label of the output they end up in
+        let mut conf = make_label_config(
+            "
+  - type: Mixer
+    name: split
+  - type: Filter
+    channels: [woofer_L, tweeter_R]
+    names:
+      - gain
+  - type: Sum
+    channels: [0, 1]
+",
+        );
+        conf.devices.playback_labels = vec![
+            "woofer_L".to_string(),
+            "woofer_R".to_string(),
+            "tweeter_L".to_string(),
+            "tweeter_R".to_string(),
+            "sub".to_string(),
+        ];
+        conf.devices.playback = super::PlaybackDevice::Stdout {
+            channels: 5,
+            format: super::SampleFormat::S16LE,
+        };
+        validate_config(&mut conf, None).unwrap();
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[1] {
+            assert_eq!(channel, &PipelineChannels::List(vec![0, 3]));
+        } else {
+            panic!("Expected a filter step");
+        }
+        // A label on the Sum step must agree with the playback label
+        if let PipelineStep::Sum { label, .. } = &mut conf.pipeline[2] {
+            *label = Some("mono".to_string());
+        }
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Sum step label 'mono' does not match the playback label 'sub' of its channel"
+        );
+    }
+
+    fn make_label_config(pipeline: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture_labels: [L, R]
+  playback_labels: [woofer_L, woofer_R, tweeter_L, tweeter_R]
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 4
+    format: S16LE
+filters:
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+mixers:
+  split:
+    channels:
+      in: 2
+      out: 4
+    mapping:
+      - dest: 0
+        sources:
+          - label: L
+      - dest: 1
+        sources:
+          - label: R
+      - dest: 2
+        sources:
+          - label: L
+      - dest: 3
+        sources:
+          - label: R
+pipeline:
+{}
+",
+            pipeline
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn resolve_channel_labels() {
+        let mut conf = make_label_config(
+            "
+  - type: Filter
+    channels: R
+    names:
+      - gain
+  - type: Mixer
+    name: split
+  - type: Filter
+    channels: [tweeter_L, tweeter_R]
+    names:
+      - gain
+",
+        );
+        validate_config(&mut conf, None).unwrap();
+        let sources: Vec<usize> = conf.mixers["split"]
+            .mapping
+            .iter()
+            .map(|m| m.sources[0].channel.unwrap())
+            .collect();
+        assert_eq!(sources, vec![0, 1, 0, 1]);
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[0] {
+            assert_eq!(channel, &PipelineChannels::Single(1));
+        } else {
+            panic!("Expected a filter step");
+        }
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[2] {
+            assert_eq!(channel, &PipelineChannels::List(vec![2, 3]));
+        } else {
+            panic!("Expected a filter step");
+        }
+        // Playback labels can't be used before the mixer
+        let mut conf = make_label_config(
+            "
+  - type: Filter
+    channels: tweeter_L
+    names:
+      - gain
+  - type: Mixer
+    name: split
+",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unknown channel label 'tweeter_L'"));
+    }
+
+    #[test]
+    fn resolve_channel_labels_with_reorder() {
+        // Capture labels follow the channels forwards through a reorder
+        let mut conf = make_label_config(
+            "
+  - type: Reorder
+    order: [1, 0]
+  - type: Filter
+    channels: L
+    names:
+      - gain
+  - type: Mixer
+    name: split
+",
+        );
+        validate_config(&mut conf, None).unwrap();
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[1] {
+            assert_eq!(channel, &PipelineChannels::Single(1));
+        } else {
+            panic!("Expected a filter step");
+        }
+        // Playback labels name the output channels, so they are followed backwards from the output
+        let mut conf = make_label_config(
+            "
+  - type: Mixer
+    name: split
+  - type: Filter
+    channels: tweeter_L
+    names:
+      - gain
+  - type: Reorder
+    order: [2, 3, 0, 1]
+",
+        );
+        validate_config(&mut conf, None).unwrap();
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[1] {
+            assert_eq!(channel, &PipelineChannels::Single(0));
+        } else {
+            panic!("Expected a filter step");
+        }
+        // Without mixers and capture labels, the playback labels are followed backwards
+        let mut conf = make_label_config(
+            "
+  - type: Filter
+    channels: woofer_R
+    names:
+      - gain
+  - type: Reorder
+    order: [1, 0]
+",
+        );
+        conf.devices.capture_labels = Vec::new();
+        conf.devices.playback_labels = vec!["woofer_L".to_string(), "woofer_R".to_string()];
+        conf.devices.playback = super::PlaybackDevice::Stdout {
+            channels: 2,
+            format: super::SampleFormat::S16LE,
+        };
+        validate_config(&mut conf, None).unwrap();
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[0] {
+            assert_eq!(channel, &PipelineChannels::Single(0));
+        } else {
+            panic!("Expected a filter step");
+        }
+    }
+
+    #[test]
+    fn coefficient_file_per_channel() {
+        let make_conf = |channels: &str| {
+            make_config(&format!(
+                "
+  fir:
+    type: Conv
+    parameters:
+      type: Raw
+      filename:
+        - testdata/text.txt
+        - testdata/text.txt
+pipeline:
+  - type: Filter
+    channels: {}
+    names:
+      - fir
+",
+                channels
+            ))
+        };
+        let mut conf = make_conf("[0, 1]");
+        assert!(validate_config(&mut conf, None).is_ok());
+        if let Filter::Conv { parameters, .. } = &conf.filters["fir"] {
+            let coeffs = filters::load_conv_coeffs(parameters, 1).unwrap();
+            assert_eq!(coeffs.len(), 5);
+        } else {
+            panic!("Expected a Conv filter");
+        }
+        let err = validate_config(&mut make_conf("0"), None).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("has 2 coefficient files but is used for 1 channels"));
+    }
+
+    /// A config with a mixer from 2 to `channels` channels, followed by a gain on `filter_channel`.
+    fn make_mixer_config(channels: usize, filter_channel: &str) -> Configuration {
+        let mapping: String = (0..channels)
+            .map(|dest| {
+                format!(
+                    "      - dest: {}\n        sources:\n          - channel: {}\n",
+                    dest,
+                    dest % 2
+                )
+            })
+            .collect();
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: {channels}
+    format: S16LE
+mixers:
+  mix:
+    channels:
+      in: 2
+      out: {channels}
+    mapping:
+{mapping}
+filters:
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+pipeline:
+  - type: Mixer
+    name: mix
+  - type: Filter
+    channel: {filter_channel}
+    names:
+      - gain
+",
+            channels = channels,
+            mapping = mapping,
+            filter_channel = filter_channel
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn filter_channel_after_narrowing_mixer() {
+        assert!(validate_config(&mut make_mixer_config(1, "0"), None).is_ok());
+        assert!(validate_config(&mut make_mixer_config(1, "1"), None).is_err());
+    }
+
+    #[test]
+    fn filter_channel_after_widening_mixer() {
+        assert!(validate_config(&mut make_mixer_config(4, "3"), None).is_ok());
+        assert!(validate_config(&mut make_mixer_config(4, "4"), None).is_err());
+    }
+
+    #[test]
+    fn filter_channel_list() {
+        assert!(validate_config(&mut make_mixer_config(4, "[0, 3]"), None).is_ok());
+        assert!(validate_config(&mut make_mixer_config(4, "[0, 4]"), None).is_err());
+        let conf = make_mixer_config(4, "[1, 2]");
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[1] {
+            assert_eq!(channel.indices(4), vec![1, 2]);
+        } else {
+            panic!("expected a filter step");
+        }
+    }
+
+    #[test]
+    fn filter_channel_all() {
+        let mut conf = make_mixer_config(4, "all");
+        assert!(validate_config(&mut conf, None).is_ok());
+        if let PipelineStep::Filter { channel, .. } = &conf.pipeline[1] {
+            assert_eq!(channel, &PipelineChannels::All(AllChannels::All));
+            assert_eq!(channel.indices(4), vec![0, 1, 2, 3]);
+        } else {
+            panic!("expected a filter step");
+        }
+    }
+
+    /// A config with a stereo mixer, given as `mixer` yaml after the channels.
+    fn make_swap_config(mixer: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+mixers:
+  swapper:
+    channels:
+      in: 2
+      out: 2
+{}
+pipeline:
+  - type: Mixer
+    name: swapper
+",
+            mixer
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn expand_mixer_swap() {
+        let mut conf = make_swap_config("    swap: [1, 0]");
+        assert!(validate_config(&mut conf, None).is_ok());
+        let mixer = &conf.mixers["swapper"];
+        assert_eq!(mixer.swap, None);
+        assert_eq!(mixer.mapping.len(), 2);
+        assert_eq!(mixer.mapping[0].dest, 0);
+        assert_eq!(mixer.mapping[0].sources[0].channel, Some(1));
+        assert_eq!(mixer.mapping[1].dest, 1);
+        assert_eq!(mixer.mapping[1].sources[0].channel, Some(0));
+    }
+
+    #[test]
+    fn invalid_mixer_swap() {
+        let mut conf = make_swap_config("    swap: [0, 2]");
+        assert!(validate_config(&mut conf, None).is_err());
+        let mut conf = make_swap_config("    swap: [1]");
+        assert!(validate_config(&mut conf, None).is_err());
+        let mut conf = make_swap_config(
+            "    swap: [1, 0]\n    mapping:\n      - dest: 0\n        sources:\n          - channel: 0",
+        );
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    #[test]
+    fn invalid_filter_error_has_name() {
+        let mut conf = make_config(
+            "
+  myeq:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      freq: 30000.0
+      q: 0.7
+pipeline:
+  - type: Filter
+    channel: 0
+    names:
+      - myeq
+",
+        );
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Invalid filter 'myeq'. Reason: Frequency must be < samplerate/2"
+        );
+    }
+
+    #[test]
+    fn read_descriptions() {
+        let yaml = "
+title: Living room
+description: Crossover and room correction
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+mixers:
+  mono:
+    description: Sum to mono
+    channels:
+      in: 2
+      out: 1
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+          - channel: 1
+filters:
+  gain:
+    type: Gain
+    description: Reduce level
+    parameters:
+      gain: -6.0
+  plain:
+    type: Gain
+    parameters:
+      gain: -6.0
+";
+        let conf: Configuration = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(conf.title.as_deref(), Some("Living room"));
+        assert_eq!(
+            conf.description.as_deref(),
+            Some("Crossover and room correction")
+        );
+        assert_eq!(
+            conf.mixers["mono"].description.as_deref(),
+            Some("Sum to mono")
+        );
+        assert_eq!(conf.filters["gain"].description(), Some("Reduce level"));
+        assert_eq!(conf.filters["plain"].description(), None);
+        // A config without any descriptions must still be valid
+        let conf = make_config("  gain:\n    type: Gain\n    parameters:\n      gain: -6.0\n");
+        assert_eq!(conf.title, None);
+        assert_eq!(conf.description, None);
+    }
+
+    fn make_wav_capture_config(resampling: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+{}
+  capture:
+    type: File
+    channels: 1
+    filename: testdata/int32.wav
+    format: S32LE
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+",
+            resampling
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn file_capture_start_frame() {
+        let make_conf = |start_frame: usize| -> Configuration {
+            let yaml = format!(
+                "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: File
+    channels: 1
+    filename: testdata/int16.raw
+    format: S16LE
+    start_frame: {}
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+",
+                start_frame
+            );
+            serde_yaml::from_str(&yaml).unwrap()
+        };
+        // The file holds 5 frames
+        assert!(validate_config(&mut make_conf(4), None).is_ok());
+        let err = validate_config(&mut make_conf(5), None).unwrap_err();
+        assert!(err.to_string().contains("which has 5 frames"));
+    }
+
+    #[test]
+    fn wav_capture_samplerate_mismatch() {
+        // The wav file is 44.1 kHz
+        let err = validate_config(&mut make_wav_capture_config(""), None).unwrap_err();
+        assert!(err.to_string().contains("capture_samplerate to 44100"));
+        let mut conf =
+            make_wav_capture_config("  enable_resampling: true\n  capture_samplerate: 44100\n");
+        assert!(validate_config(&mut conf, None).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn alsa_startup_silence_limit() {
+        let make_conf = |silence_ms: usize| -> Configuration {
+            let yaml = format!(
+                "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Alsa
+    channels: 2
+    device: hw:0
+    format: S16LE
+    startup_silence_ms: {}
+",
+                silence_ms
+            );
+            serde_yaml::from_str(&yaml).unwrap()
+        };
+        // One chunk of 1024 frames at 44.1 kHz is 23 ms
+        assert!(validate_config(&mut make_conf(0), None).is_ok());
+        assert!(validate_config(&mut make_conf(23), None).is_ok());
+        assert!(validate_config(&mut make_conf(24), None).is_err());
+        // A zero samplerate gives an error instead of a division by zero
+        let mut conf = make_conf(10);
+        conf.devices.samplerate = 0;
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    #[test]
+    fn adapt_to_new_samplerate() {
+        assert_eq!(nearest_standard_samplerate(47993), Some(48000));
+        assert_eq!(nearest_standard_samplerate(44150), Some(44100));
+        assert_eq!(nearest_standard_samplerate(60000), None);
+        assert_eq!(nearest_standard_samplerate(0), None);
+        // Without resampling the whole pipeline changes rate
+        let mut conf = make_config("  {}");
+        set_samplerate(&mut conf, 88200);
+        assert_eq!(conf.devices.samplerate, 88200);
+        assert_eq!(conf.devices.chunksize, 2048);
+        // With resampling only the capture rate changes
+        let mut conf = make_config("  {}");
+        conf.devices.enable_resampling = true;
+        conf.devices.capture_samplerate = 44100;
+        conf.devices.samplerate = 48000;
+        set_samplerate(&mut conf, 96000);
+        assert_eq!(conf.devices.samplerate, 48000);
+        assert_eq!(conf.devices.capture_samplerate, 96000);
+        assert!(validate_config(&mut conf, None).is_ok());
+    }
+
+    #[test]
+    fn zero_samplerate() {
+        let mut conf = make_config("  {}");
+        conf.devices.samplerate = 0;
+        conf.devices.fadeout_ms = 10;
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(err, "samplerate must be larger than zero");
+    }
+
+    #[test]
+    fn retry_limits() {
+        let mut conf = make_config("  {}");
+        conf.devices.open_retries = 100;
+        conf.devices.retry_delay_ms = 5000;
+        assert!(validate_config(&mut conf, None).is_ok());
+        conf.devices.open_retries = 101;
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(err, "open_retries can't be larger than 100");
+        conf.devices.open_retries = 3;
+        conf.devices.retry_delay_ms = 5001;
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(err, "retry_delay_ms can't be larger than 5000");
+    }
+
+    #[test]
+    fn zero_chunksize() {
+        let mut conf = make_config("  {}");
+        conf.devices.chunksize = 0;
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "chunksize must be larger than zero, a good value at 44100 Hz is 1024"
+        );
+        // The old name buffersize is still accepted
+        let conf: Configuration = serde_yaml::from_str(
+            "
+devices:
+  samplerate: 96000
+  buffersize: 2048
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+",
+        )
+        .unwrap();
+        assert_eq!(conf.devices.chunksize, 2048);
+        assert_eq!(suggested_chunksize(96000), 2048);
+        assert_eq!(suggested_chunksize(192000), 4096);
+    }
+
+    #[test]
+    fn target_latency() {
+        let mut conf = make_config("  {}");
+        conf.devices.target_latency_ms = 10.0;
+        assert!(validate_config(&mut conf, None).is_ok());
+        assert_eq!(conf.devices.effective_target_level(), 441);
+        conf.devices.target_level = 500;
+        assert!(validate_config(&mut conf, None).is_err());
+        // Two chunks of 1024 frames at 44.1 kHz is 46.4 ms
+        let mut conf = make_config("  {}");
+        conf.devices.target_latency_ms = 50.0;
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "target_latency_ms can't be larger than 46.4 ms with a chunksize of 1024"
+        );
+    }
+
+    fn make_disabled_step_config(upmix_enabled: bool, downmix_enabled: bool) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+mixers:
+  upmix:
+    channels:
+      in: 2
+      out: 4
+  downmix:
+    channels:
+      in: 4
+      out: 2
+pipeline:
+  - type: Mixer
+    name: upmix
+    enabled: {}
+  - type: Filter
+    channel: 3
+    names: [missing]
+    enabled: false
+  - type: Mixer
+    name: downmix
+    enabled: {}
+",
+            upmix_enabled, downmix_enabled
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn disabled_steps() {
+        // Steps are enabled by default
+        let conf = make_swap_config("    swap: [1, 0]");
+        assert!(conf.pipeline[0].is_enabled());
+        // The disabled filter step is not validated
+        let mut conf = make_disabled_step_config(true, true);
+        assert!(validate_config(&mut conf, None).is_ok());
+        // Disabling both mixers keeps the channel counts lined up
+        let mut conf = make_disabled_step_config(false, false);
+        assert!(validate_config(&mut conf, None).is_ok());
+        assert!(config_summary(&conf).mixers.is_empty());
+        // Disabling only one of them does not
+        let mut conf = make_disabled_step_config(false, true);
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert!(err.contains("disabled mixer 'upmix'"), "{}", err);
+        let mut conf = make_disabled_step_config(true, false);
+        let err = validate_config(&mut conf, None).unwrap_err().to_string();
+        assert!(err.contains("disabled mixer 'downmix'"), "{}", err);
+    }
+}
@@ -9,6 +9,87 @@ use rawsample::{SampleReader, SampleWriter};
 use std::collections::VecDeque;
 use std::io::Cursor;
 
+/// Counter for the number of clipped samples and their peak value, per channel.
+pub struct ClipCounter {
+    pub(crate) clipped: Vec<usize>,
+    pub(crate) peak: Vec<PrcFmt>,
+}
+
+impl ClipCounter {
+    fn new(channels: usize) -> Self {
+        ClipCounter {
+            clipped: vec![0; channels],
+            peak: vec![0.0; channels],
+        }
+    }
+
+    /// Register a clipped sample value on the given channel.
+    fn add(&mut self, channel: usize, value: PrcFmt) {
+        self.clipped[channel] += 1;
+        if value.abs() > self.peak[channel] {
+            self.peak[channel] = value.abs();
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.clipped.iter().sum()
+    }
+
+    /// Describe the clipping of each channel, returns None if nothing was clipped.
+    fn message(&self) -> Option<String> {
+        if self.total() == 0 {
+            return None;
+        }
+        let channels: Vec<String> = self
+            .clipped
+            .iter()
+            .zip(self.peak.iter())
+            .enumerate()
+            .filter(|(_, (clipped, _))| **clipped > 0)
+            .map(|(chan, (clipped, peak))| {
+                format!(
+                    "channel {}: {} samples, peak +{:.2} dB ({:.1}%)",
+                    chan,
+                    clipped,
+                    20.0 * peak.log10(),
+                    peak * 100.0
+                )
+            })
+            .collect();
+        Some(format!(
+            "Clipping detected, {} samples clipped, {}",
+            self.total(),
+            channels.join(", ")
+        ))
+    }
+
+    /// Log a warning if any samples were clipped.
+    fn warn(self) -> Self {
+        if let Some(msg) = self.message() {
+            warn!("{}", msg);
+        }
+        self
+    }
+}
+
+/// Clamp the valid frames of a chunk to the range -limit to +limit.
+/// Logs a warning if any samples were clamped, and returns the clamped samples per channel.
+pub fn clamp_chunk(chunk: &mut AudioChunk, limit: PrcFmt) -> ClipCounter {
+    let mut clipped = ClipCounter::new(chunk.channels);
+    for (chan, waveform) in chunk.waveforms.iter_mut().enumerate() {
+        for value in waveform.iter_mut().take(chunk.valid_frames) {
+            if *value > limit {
+                clipped.add(chan, *value);
+                *value = limit;
+            } else if *value < -limit {
+                clipped.add(chan, *value);
+                *value = -limit;
+            }
+        }
+    }
+    clipped.warn()
+}
+
 pub fn map_formats(sampleformat: &SampleFormat) -> rawsample::SampleFormat {
     match sampleformat {
         SampleFormat::S16LE => rawsample::SampleFormat::S16LE,
@@ -17,6 +98,9 @@ pub fn map_formats(sampleformat: &SampleFormat) -> rawsample::SampleFormat {
         SampleFormat::S32LE => rawsample::SampleFormat::S32LE,
         SampleFormat::FLOAT32LE => rawsample::SampleFormat::F32LE,
         SampleFormat::FLOAT64LE => rawsample::SampleFormat::F64LE,
+        SampleFormat::S16BE => rawsample::SampleFormat::S16BE,
+        SampleFormat::S24BE => rawsample::SampleFormat::S24BE4,
+        SampleFormat::S32BE => rawsample::SampleFormat::S32BE,
     }
 }
 
@@ -32,44 +116,48 @@ pub fn map_file_formats(fileformat: &FileFormat) -> rawsample::SampleFormat {
     }
 }
 
+/// The largest value that can be written in a sample format without clipping.
+fn max_sample_value(sampleformat: &SampleFormat) -> PrcFmt {
+    match sampleformat {
+        SampleFormat::FLOAT32LE | SampleFormat::FLOAT64LE => 1.0,
+        _ => {
+            let scalefactor = (2.0 as PrcFmt).powi(sampleformat.bits_per_sample() as i32 - 1);
+            (scalefactor - 1.0) / scalefactor
+        }
+    }
+}
+
 /// Convert an AudioChunk to an interleaved buffer of u8.
+/// Returns the number of valid bytes, and the clipped samples per channel.
 pub fn chunk_to_buffer_rawbytes(
     chunk: &AudioChunk,
     buf: &mut [u8],
     sampleformat: &SampleFormat,
-) -> (usize, usize) {
+) -> (usize, ClipCounter) {
     //let _num_samples = chunk.channels * chunk.frames;
     //let data_bytes_per_sample = bits as usize / 8;
     let rawformat = map_formats(sampleformat);
     let mut cursor = Cursor::new(buf);
-    let mut clipped = 0;
-    let mut peak = 0.0;
+    let mut clipped = ClipCounter::new(chunk.channels);
+    let maxval = max_sample_value(sampleformat);
     let num_valid_bytes = chunk.valid_frames * chunk.channels * sampleformat.bytes_per_sample();
     let mut nextframe = vec![0.0; chunk.channels];
     for frame in 0..chunk.frames {
-        //for chan in 0..chunk.channels {
         for (chan, value) in nextframe.iter_mut().enumerate() {
             let float_val = if chunk.waveforms[chan].is_empty() {
                 0.0
             } else {
                 chunk.waveforms[chan][frame]
             };
-            if float_val.abs() > peak {
-                peak = float_val.abs();
+            // The values are clamped when written, check them here to know which channel clipped
+            if float_val > maxval || float_val < -1.0 {
+                clipped.add(chan, float_val);
             }
             *value = float_val;
         }
-        clipped += PrcFmt::write_samples(&nextframe, &mut cursor, &rawformat).unwrap();
-    }
-    if clipped > 0 {
-        warn!(
-            "Clipping detected, {} samples clipped, peak +{:.2} dB ({:.1}%)",
-            clipped,
-            20.0 * peak.log10(),
-            peak * 100.0
-        );
+        PrcFmt::write_samples(&nextframe, &mut cursor, &rawformat).unwrap();
     }
-    (num_valid_bytes, clipped)
+    (num_valid_bytes, clipped.warn())
 }
 
 /// Convert a buffer of interleaved u8 to an AudioChunk.
@@ -117,11 +205,10 @@ pub fn chunk_to_queue_int<T: num_traits::cast::NumCast>(
     chunk: &AudioChunk,
     queue: &mut VecDeque<T>,
     scalefactor: PrcFmt,
-) -> usize {
+) -> ClipCounter {
     let _num_samples = chunk.channels * chunk.frames;
     let mut value: T;
-    let mut clipped = 0;
-    let mut peak = 0.0;
+    let mut clipped = ClipCounter::new(chunk.channels);
     let maxval = if (scalefactor >= 2_147_483_648.0) && cfg!(feature = "32bit") {
         (scalefactor - 128.0) / scalefactor
     } else {
@@ -136,16 +223,10 @@ pub fn chunk_to_queue_int<T: num_traits::cast::NumCast>(
                 chunk.waveforms[chan][frame]
             };
             if float_val > maxval {
-                clipped += 1;
-                if float_val > peak {
-                    peak = float_val;
-                }
+                clipped.add(chan, float_val);
                 float_val = maxval;
             } else if float_val < minval {
-                clipped += 1;
-                if -float_val > peak {
-                    peak = -float_val;
-                }
+                clipped.add(chan, float_val);
                 float_val = minval;
             }
             value = match num_traits::cast(float_val * scalefactor) {
@@ -158,15 +239,7 @@ pub fn chunk_to_queue_int<T: num_traits::cast::NumCast>(
             queue.push_back(value);
         }
     }
-    if clipped > 0 {
-        warn!(
-            "Clipping detected, {} samples clipped, peak +{:.2} dB ({:.1}%)",
-            clipped,
-            20.0 * peak.log10(),
-            peak * 100.0
-        );
-    }
-    clipped
+    clipped.warn()
 }
 
 /// Convert a buffer of interleaved ints to an AudioChunk, only used by CPAL backend.
@@ -205,12 +278,11 @@ pub fn queue_to_chunk_int<T: num_traits::cast::AsPrimitive<PrcFmt>>(
 pub fn chunk_to_queue_float<T: num_traits::cast::NumCast>(
     chunk: &AudioChunk,
     queue: &mut VecDeque<T>,
-) -> usize {
+) -> ClipCounter {
     let _num_samples = chunk.channels * chunk.frames;
     //let mut buf = Vec::with_capacity(num_samples);
     let mut value: T;
-    let mut clipped = 0;
-    let mut peak = 0.0;
+    let mut clipped = ClipCounter::new(chunk.channels);
     let maxval = 1.0;
     let minval = -1.0;
     for frame in 0..chunk.frames {
@@ -221,16 +293,10 @@ pub fn chunk_to_queue_float<T: num_traits::cast::NumCast>(
                 chunk.waveforms[chan][frame]
             };
             if float_val > maxval {
-                clipped += 1;
-                if float_val > peak {
-                    peak = float_val;
-                }
+                clipped.add(chan, float_val);
                 float_val = maxval;
             } else if float_val < minval {
-                clipped += 1;
-                if -float_val > peak {
-                    peak = -float_val;
-                }
+                clipped.add(chan, float_val);
                 float_val = minval;
             }
             value = match num_traits::cast(float_val) {
@@ -243,15 +309,7 @@ pub fn chunk_to_queue_float<T: num_traits::cast::NumCast>(
             queue.push_back(value);
         }
     }
-    if clipped > 0 {
-        warn!(
-            "Clipping detected, {} samples clipped, peak +{:.2} dB ({:.1}%)",
-            clipped,
-            20.0 * peak.log10(),
-            peak * 100.0
-        );
-    }
-    clipped
+    clipped.warn()
 }
 
 /// Convert a buffer of interleaved floats to an AudioChunk, only used by CPAL backend.
@@ -287,13 +345,14 @@ pub fn queue_to_chunk_float<T: num_traits::cast::AsPrimitive<PrcFmt>>(
 mod tests {
     use crate::audiodevice::AudioChunk;
     use crate::config::SampleFormat;
-    use crate::conversions::{buffer_to_chunk_rawbytes, chunk_to_buffer_rawbytes};
-    #[cfg(feature = "cpal-backend")]
-    use crate::PrcFmt;
+    use crate::conversions::{
+        buffer_to_chunk_rawbytes, chunk_to_buffer_rawbytes, clamp_chunk, ClipCounter,
+    };
     #[cfg(feature = "cpal-backend")]
-    use conversions::{
+    use crate::conversions::{
         chunk_to_queue_float, chunk_to_queue_int, queue_to_chunk_float, queue_to_chunk_int,
     };
+    use crate::PrcFmt;
     #[cfg(feature = "cpal-backend")]
     use std::collections::VecDeque;
 
@@ -330,6 +389,28 @@ mod tests {
         assert_eq!(buffer, expected);
     }
 
+    #[test]
+    fn to_buffer_int16_be() {
+        let sampleformat = SampleFormat::S16BE;
+        let waveforms = vec![vec![0.1]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 1, 1);
+        let mut buffer = vec![0u8; 2];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &sampleformat);
+        let expected = vec![0x0C, 0xCC];
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn to_buffer_int24_be() {
+        let sampleformat = SampleFormat::S24BE;
+        let waveforms = vec![vec![0.1, -0.1]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 2, 2);
+        let mut buffer = vec![0u8; 8];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &sampleformat);
+        let expected = vec![0x00, 0x0C, 0xCC, 0xCC, 0x00, 0xF3, 0x33, 0x33];
+        assert_eq!(buffer, expected);
+    }
+
     #[test]
     fn from_buffer_int24_3() {
         let waveforms = vec![vec![0.1, -0.1]; 1];
@@ -497,6 +578,94 @@ mod tests {
         assert_eq!(waveforms[0], chunk2.waveforms[0]);
     }
 
+    #[test]
+    fn to_from_buffer_16_be() {
+        let waveforms = vec![vec![-0.5, 0.0, 0.5]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 2];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S16BE);
+        let chunk2 =
+            buffer_to_chunk_rawbytes(&buffer, 1, &SampleFormat::S16BE, buffer.len(), &[true; 1]);
+        assert_eq!(waveforms[0], chunk2.waveforms[0]);
+    }
+
+    #[test]
+    fn to_from_buffer_24_be() {
+        let waveforms = vec![vec![-0.5, 0.0, 0.5]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 4];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S24BE);
+        let chunk2 =
+            buffer_to_chunk_rawbytes(&buffer, 1, &SampleFormat::S24BE, buffer.len(), &[true; 1]);
+        assert_eq!(waveforms[0], chunk2.waveforms[0]);
+    }
+
+    #[test]
+    fn to_from_buffer_32_be() {
+        let waveforms = vec![vec![-0.5, 0.0, 0.5]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 4];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S32BE);
+        let chunk2 =
+            buffer_to_chunk_rawbytes(&buffer, 1, &SampleFormat::S32BE, buffer.len(), &[true; 1]);
+        assert_eq!(waveforms[0], chunk2.waveforms[0]);
+    }
+
+    #[test]
+    fn clipping_per_channel() {
+        let chunk = AudioChunk::new(
+            vec![vec![0.5, -0.5, 0.0], vec![2.0, 0.0, -1.5], vec![]],
+            2.0,
+            -1.5,
+            3,
+            3,
+        );
+        let mut buffer = vec![0u8; 3 * 3 * 2];
+        let (_, clipped) = chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S16LE);
+        assert_eq!(clipped.total(), 2);
+        assert_eq!(clipped.clipped, vec![0, 2, 0]);
+        assert_eq!(clipped.peak, vec![0.0, 2.0, 0.0]);
+        // Full scale is not clipped in float formats
+        let chunk = AudioChunk::new(vec![vec![1.0, -1.0, 1.25]], 1.25, -1.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 4];
+        let (_, clipped) = chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::FLOAT32LE);
+        assert_eq!(clipped.clipped, vec![1]);
+
+        let mut counter = ClipCounter::new(3);
+        assert_eq!(counter.message(), None);
+        counter.add(1, 2.0);
+        counter.add(1, -1.5);
+        assert_eq!(counter.total(), 2);
+        assert_eq!(
+            counter.message().unwrap(),
+            "Clipping detected, 2 samples clipped, channel 1: 2 samples, peak +6.02 dB (200.0%)"
+        );
+    }
+
+    #[test]
+    fn clamp_to_limit() {
+        let limit = 0.5;
+        let mut chunk = AudioChunk::new(
+            vec![vec![-2.0, 0.25, 3.0], vec![0.5, -0.5, -0.75]],
+            0.0,
+            0.0,
+            3,
+            3,
+        );
+        assert_eq!(clamp_chunk(&mut chunk, limit).clipped, vec![2, 1]);
+        assert_eq!(chunk.waveforms[0], vec![-0.5, 0.25, 0.5]);
+        assert_eq!(chunk.waveforms[1], vec![0.5, -0.5, -0.5]);
+        // The clamped values give bounded integers
+        let mut buffer = vec![0u8; 3 * 2 * 2];
+        let (_, clipped) = chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S16LE);
+        assert_eq!(clipped.total(), 0);
+        let values: Vec<i16> = buffer
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(values, vec![-16384, 16384, 8192, -16384, 16384, -16384]);
+    }
+
     #[test]
     fn clipping_16() {
         let waveforms = vec![vec![-1.0, 0.0, 32767.0 / 32768.0]; 1];
@@ -529,6 +698,27 @@ mod tests {
         assert_eq!(waveforms[0], chunk2.waveforms[0]);
     }
 
+    #[test]
+    fn clipping_24_3() {
+        let waveforms = vec![vec![-1.0, 0.0, 8388607.0 / 8388608.0]; 1];
+        let chunk = AudioChunk::new(vec![vec![-2.0, 0.0, 2.0]; 1], 0.0, 0.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 3];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S24LE3);
+        let expected = vec![0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x7F];
+        assert_eq!(buffer, expected);
+        let chunk2 =
+            buffer_to_chunk_rawbytes(&buffer, 1, &SampleFormat::S24LE3, buffer.len(), &[true; 1]);
+        assert_eq!(waveforms[0], chunk2.waveforms[0]);
+    }
+
+    #[test]
+    fn from_buffer_int24_3_sign() {
+        let buffer = vec![0xFF, 0xFF, 0xFF, 0x01, 0x00, 0x00];
+        let chunk =
+            buffer_to_chunk_rawbytes(&buffer, 1, &SampleFormat::S24LE3, buffer.len(), &[true; 1]);
+        assert_eq!(chunk.waveforms[0], vec![-1.0 / 8388608.0, 1.0 / 8388608.0]);
+    }
+
     #[test]
     fn clipping_32() {
         #[cfg(feature = "32bit")]
@@ -564,6 +754,23 @@ mod tests {
         assert_eq!(waveforms[0], chunk2.waveforms[0]);
     }
 
+    #[test]
+    fn to_from_buffer_float32_rounding() {
+        let waveforms = vec![vec![0.1, -0.3]; 1];
+        let chunk = AudioChunk::new(waveforms, 0.0, 0.0, 2, 2);
+        let mut buffer = vec![0u8; 2 * 4];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::FLOAT32LE);
+        let chunk2 = buffer_to_chunk_rawbytes(
+            &buffer,
+            1,
+            &SampleFormat::FLOAT32LE,
+            buffer.len(),
+            &[true; 1],
+        );
+        let expected = vec![0.1_f32 as PrcFmt, -0.3_f32 as PrcFmt];
+        assert_eq!(expected, chunk2.waveforms[0]);
+    }
+
     #[test]
     fn to_from_buffer_float64() {
         let waveforms = vec![vec![-0.5, 0.0, 0.5]; 1];
@@ -580,6 +787,25 @@ mod tests {
         assert_eq!(waveforms[0], chunk2.waveforms[0]);
     }
 
+    #[cfg(not(feature = "32bit"))]
+    #[test]
+    fn to_from_buffer_float64_bitexact() {
+        let waveforms = vec![vec![0.123456789012345, -0.987654321098765, 1.0e-300]; 1];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 3, 3);
+        let mut buffer = vec![0u8; 3 * 8];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::FLOAT64LE);
+        let expected: Vec<u8> = waveforms[0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(buffer, expected);
+        let chunk2 = buffer_to_chunk_rawbytes(
+            &buffer,
+            1,
+            &SampleFormat::FLOAT64LE,
+            buffer.len(),
+            &[true; 1],
+        );
+        assert_eq!(waveforms[0], chunk2.waveforms[0]);
+    }
+
     #[cfg(feature = "cpal-backend")]
     #[test]
     fn to_from_queue_i16() {
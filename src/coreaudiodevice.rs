@@ -103,6 +103,7 @@ pub struct CoreaudioCaptureDevice {
     pub change_format: bool,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
 }
@@ -488,9 +489,11 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
                                     break;
                                 }
                             }
-                            if conversion_result.1 > 0 {
-                                playback_status.write().unwrap().clipped_samples +=
-                                    conversion_result.1;
+                            if conversion_result.1.total() > 0 {
+                                playback_status
+                                    .write()
+                                    .unwrap()
+                                    .add_clipped(&conversion_result.1);
                             }
                         }
                         Ok(AudioMessage::Pause) => {
@@ -551,6 +554,7 @@ impl CaptureDevice for CoreaudioCaptureDevice {
         let resampler_conf = self.resampler_conf.clone();
         let async_src = resampler_is_async(&resampler_conf);
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = (1000.0 * self.rate_measure_interval) as u64;
@@ -671,7 +675,7 @@ impl CaptureDevice for CoreaudioCaptureDevice {
                 let mut value_range = 0.0;
                 let mut chunk_stats;
                 let mut rate_adjust = 0.0;
-                let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
+                let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, &silence_mode, capture_samplerate, chunksize);
                 let mut state = ProcessingState::Running;
                 let blockalign = 4*channels;
                 let mut data_queue: VecDeque<u8> = VecDeque::with_capacity(4 * blockalign * chunksize_samples );
@@ -1,3 +1,4 @@
+use crate::config::SilenceMode;
 use crate::NewValue;
 use crate::PrcFmt;
 use crate::ProcessingState;
@@ -21,13 +22,17 @@ pub struct TimeAverage {
 pub struct SilenceCounter {
     silence_threshold: PrcFmt,
     silence_limit_nbr: usize,
+    pause: bool,
     silent_nbr: usize,
+    silent: bool,
+    changed: bool,
 }
 
 impl SilenceCounter {
     pub fn new(
         silence_threshold_db: PrcFmt,
         silence_timeout: PrcFmt,
+        silence_mode: &SilenceMode,
         samplerate: usize,
         chunksize: usize,
     ) -> SilenceCounter {
@@ -37,31 +42,52 @@ impl SilenceCounter {
         SilenceCounter {
             silence_threshold,
             silence_limit_nbr,
+            pause: *silence_mode == SilenceMode::Pause,
             silent_nbr: 0,
+            silent: false,
+            changed: false,
         }
     }
 
+    /// Update with the value range of a new chunk, and get the processing state.
+    /// In `Continue` mode the silence is only detected, and the state stays `Running`.
     pub fn update(&mut self, value_range: PrcFmt) -> ProcessingState {
-        let mut state = ProcessingState::Running;
+        let mut silent = false;
         if self.silence_limit_nbr > 0 {
             if value_range > self.silence_threshold {
-                if self.silent_nbr > self.silence_limit_nbr {
+                if self.pause && self.silent_nbr > self.silence_limit_nbr {
                     debug!("Resuming processing");
                 }
                 self.silent_nbr = 0;
             } else {
-                if self.silent_nbr == self.silence_limit_nbr {
+                if self.pause && self.silent_nbr == self.silence_limit_nbr {
                     debug!("Pausing processing");
                 }
                 if self.silent_nbr >= self.silence_limit_nbr {
-                    trace!("Pausing processing");
-                    state = ProcessingState::Paused;
+                    silent = true;
                 }
                 self.silent_nbr += 1;
             }
         }
+        let state = if silent && self.pause {
+            trace!("Pausing processing");
+            ProcessingState::Paused
+        } else {
+            ProcessingState::Running
+        };
+        self.changed = silent != self.silent;
+        self.silent = silent;
         state
     }
+
+    /// Get the new silence state, if it changed at the last update.
+    pub fn transition(&self) -> Option<bool> {
+        if self.changed {
+            Some(self.silent)
+        } else {
+            None
+        }
+    }
 }
 
 impl Stopwatch {
@@ -203,6 +229,7 @@ impl ValueWatcher {
 
 #[cfg(test)]
 mod tests {
+    use crate::config::SilenceMode;
     use crate::countertimer::{Averager, SilenceCounter, Stopwatch, TimeAverage, ValueWatcher};
     use crate::ProcessingState;
     use std::time::Instant;
@@ -271,7 +298,7 @@ mod tests {
 
     #[test]
     fn silencecounter() {
-        let mut counter = SilenceCounter::new(-40.0, 3.0, 48000, 1024);
+        let mut counter = SilenceCounter::new(-40.0, 3.0, &SilenceMode::Pause, 48000, 1024);
         let limit_nbr = (3.0f64 * 48000.0 / 1024.0).round() as usize;
         assert_eq!(counter.silence_limit_nbr, limit_nbr);
         assert_eq!(counter.silence_threshold, 0.01);
@@ -293,9 +320,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn silencecounter_transitions() {
+        let mut counter = SilenceCounter::new(-40.0, 1.0, &SilenceMode::Pause, 48000, 24000);
+        counter.update(0.1);
+        assert_eq!(counter.transition(), None);
+        counter.update(0.001);
+        counter.update(0.001);
+        assert_eq!(counter.transition(), None);
+        counter.update(0.001);
+        assert_eq!(counter.transition(), Some(true));
+        counter.update(0.001);
+        assert_eq!(counter.transition(), None);
+        counter.update(0.1);
+        assert_eq!(counter.transition(), Some(false));
+        counter.update(0.1);
+        assert_eq!(counter.transition(), None);
+    }
+
+    #[test]
+    fn silencecounter_continue() {
+        let mut counter = SilenceCounter::new(-40.0, 1.0, &SilenceMode::Continue, 48000, 24000);
+        assert_eq!(counter.update(0.1), ProcessingState::Running);
+        for _ in 0..2 {
+            assert_eq!(counter.update(0.001), ProcessingState::Running);
+        }
+        assert_eq!(counter.transition(), None);
+        // The silence is still detected, but processing keeps running
+        assert_eq!(counter.update(0.001), ProcessingState::Running);
+        assert_eq!(counter.transition(), Some(true));
+        assert_eq!(counter.update(0.001), ProcessingState::Running);
+        assert_eq!(counter.update(0.1), ProcessingState::Running);
+        assert_eq!(counter.transition(), Some(false));
+    }
+
     #[test]
     fn silencecounter_largechunksize() {
-        let mut counter = SilenceCounter::new(-40.0, 1.0, 48000, 23000);
+        let mut counter = SilenceCounter::new(-40.0, 1.0, &SilenceMode::Pause, 48000, 23000);
         let limit_nbr = 2;
         assert_eq!(counter.silence_limit_nbr, limit_nbr);
         assert_eq!(counter.silence_threshold, 0.01);
@@ -59,10 +59,24 @@ pub struct CpalCaptureDevice {
     pub sample_format: SampleFormat,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
 }
 
+/// Jack can't resample, the configured samplerate must match the one of the Jack server.
+#[cfg(feature = "jack-backend")]
+fn check_jack_samplerate(server_rate: u32, samplerate: usize) -> Res<()> {
+    if server_rate as usize != samplerate {
+        let msg = format!(
+            "Samplerate {} does not match the Jack server samplerate {}",
+            samplerate, server_rate
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
 fn open_cpal_playback(
     host_cfg: CpalHost,
     devname: &str,
@@ -103,6 +117,10 @@ fn open_cpal_playback(
         SampleFormat::FLOAT32LE => cpal::SampleFormat::F32,
         _ => panic!("Unsupported sample format"),
     };
+    #[cfg(feature = "jack-backend")]
+    if matches!(host_cfg, CpalHost::Jack) {
+        check_jack_samplerate(device.default_output_config()?.sample_rate().0, samplerate)?;
+    }
     let stream_config = StreamConfig {
         channels: channels as ChannelCount,
         sample_rate: SampleRate(samplerate as u32),
@@ -153,6 +171,10 @@ fn open_cpal_capture(
         SampleFormat::FLOAT32LE => cpal::SampleFormat::F32,
         _ => panic!("Unsupported sample format"),
     };
+    #[cfg(feature = "jack-backend")]
+    if matches!(host_cfg, CpalHost::Jack) {
+        check_jack_samplerate(device.default_input_config()?.sample_rate().0, samplerate)?;
+    }
     let stream_config = StreamConfig {
         channels: channels as ChannelCount,
         sample_rate: SampleRate(samplerate as u32),
@@ -219,7 +241,7 @@ impl PlaybackDevice for CpalPlaybackDevice {
                         let stream = match sample_format {
                             SampleFormat::S16LE => {
                                 trace!("Build i16 output stream");
-                                let mut clipped = 0;
+                                let mut clipped = None;
                                 let mut running = true;
                                 let mut sample_queue: VecDeque<i16> =
                                     VecDeque::with_capacity(4 * chunksize_clone * channels_clone);
@@ -240,11 +262,11 @@ impl PlaybackDevice for CpalPlaybackDevice {
                                                         running = true;
                                                         info!("Restarting playback after buffer underrun");
                                                     }
-                                                    clipped = chunk_to_queue_int(
+                                                    clipped = Some(chunk_to_queue_int(
                                                         &chunk,
                                                         &mut sample_queue,
                                                         scalefactor,
-                                                    )
+                                                    ))
                                                 }
                                                 Err(_) => {
                                                     if running {
@@ -262,11 +284,13 @@ impl PlaybackDevice for CpalPlaybackDevice {
                                         write_data_to_device(&mut buffer, &mut sample_queue);
                                         buffer_fill_clone
                                             .store(sample_queue.len(), Ordering::Relaxed);
-                                        if clipped > 0 {
-                                            playback_status_clone
-                                                .write()
-                                                .unwrap()
-                                                .clipped_samples += clipped;
+                                        if let Some(clipped) = clipped.take() {
+                                            if clipped.total() > 0 {
+                                                playback_status_clone
+                                                    .write()
+                                                    .unwrap()
+                                                    .add_clipped(&clipped);
+                                            }
                                         }
                                     },
                                     move |err| error!("an error occurred on stream: {}", err),
@@ -276,7 +300,7 @@ impl PlaybackDevice for CpalPlaybackDevice {
                             }
                             SampleFormat::FLOAT32LE => {
                                 trace!("Build f32 output stream");
-                                let mut clipped = 0;
+                                let mut clipped = None;
                                 let mut running = true;
                                 let mut sample_queue: VecDeque<f32> =
                                     VecDeque::with_capacity(4 * chunksize_clone * channels_clone);
@@ -297,10 +321,10 @@ impl PlaybackDevice for CpalPlaybackDevice {
                                                         running = true;
                                                         info!("Restarting playback after buffer underrun");
                                                     }
-                                                    clipped = chunk_to_queue_float(
+                                                    clipped = Some(chunk_to_queue_float(
                                                         &chunk,
                                                         &mut sample_queue,
-                                                    )
+                                                    ))
                                                 }
                                                 Err(_) => {
                                                     if running {
@@ -318,11 +342,13 @@ impl PlaybackDevice for CpalPlaybackDevice {
                                         write_data_to_device(&mut buffer, &mut sample_queue);
                                         buffer_fill_clone
                                             .store(sample_queue.len(), Ordering::Relaxed);
-                                        if clipped > 0 {
-                                            playback_status_clone
-                                                .write()
-                                                .unwrap()
-                                                .clipped_samples += clipped;
+                                        if let Some(clipped) = clipped.take() {
+                                            if clipped.total() > 0 {
+                                                playback_status_clone
+                                                    .write()
+                                                    .unwrap()
+                                                    .add_clipped(&clipped);
+                                            }
                                         }
                                     },
                                     move |err| error!("an error occurred on stream: {}", err),
@@ -463,6 +489,7 @@ impl CaptureDevice for CpalCaptureDevice {
         let resampler_conf = self.resampler_conf.clone();
         let async_src = resampler_is_async(&resampler_conf);
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = self.rate_measure_interval;
@@ -551,7 +578,7 @@ impl CaptureDevice for CpalCaptureDevice {
                         let mut value_range = 0.0;
                         let mut chunk_stats;
                         let mut rate_adjust = 0.0;
-                        let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
+                        let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, &silence_mode, capture_samplerate, chunksize);
                         let mut state = ProcessingState::Running;
                         loop {
                             match command_channel.try_recv() {
@@ -676,6 +703,9 @@ impl CaptureDevice for CpalCaptureDevice {
                             capture_status.write().unwrap().signal_peak = chunk_stats.peak_db();
                             value_range = chunk.maxval - chunk.minval;
                             state = silence_counter.update(value_range);
+                            if let Some(silent) = silence_counter.transition() {
+                                status_channel.send(StatusMessage::CaptureSilence { silent, time: time::SystemTime::now() }).unwrap_or(());
+                            }
                             if state == ProcessingState::Running {
                                 if let Some(resampl) = &mut resampler {
                                     let new_waves = resampl.process(&chunk.waveforms, None).unwrap();
@@ -718,3 +748,19 @@ impl CaptureDevice for CpalCaptureDevice {
         Ok(Box::new(handle))
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "jack-backend")]
+mod tests {
+    use crate::cpaldevice::check_jack_samplerate;
+
+    #[test]
+    fn jack_samplerate() {
+        assert!(check_jack_samplerate(48000, 48000).is_ok());
+        let err = check_jack_samplerate(48000, 44100).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Samplerate 44100 does not match the Jack server samplerate 48000"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config;
 use crate::filters::Filter;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand_distr::{Distribution, Triangular};
 
 use crate::NewValue;
@@ -16,6 +17,7 @@ pub struct Dither {
     filter: Vec<PrcFmt>,
     idx: usize,
     filterlen: usize,
+    rng: StdRng,
 }
 
 impl Dither {
@@ -32,9 +34,16 @@ impl Dither {
             filter,
             idx,
             filterlen,
+            rng: StdRng::from_entropy(),
         }
     }
 
+    /// Seed the random number generator, to get the same dither noise on every run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
     // Some filters borrowed from SOX: http://sox.sourceforge.net/SoX/NoiseShaping
     pub fn from_config(name: String, conf: config::DitherParameters) -> Self {
         match conf {
@@ -159,6 +168,29 @@ impl Dither {
     }
 }
 
+/// Create the dither applied when quantizing to the sample format of the playback device.
+/// The amplitude is given in LSB.
+/// Returns None if dither is disabled, or if the format doesn't need it.
+pub fn for_playback(
+    name: String,
+    dither: &config::DeviceDither,
+    amplitude: PrcFmt,
+    format: &config::SampleFormat,
+) -> Option<Dither> {
+    let bits = match format {
+        config::SampleFormat::S16LE | config::SampleFormat::S16BE => 16,
+        config::SampleFormat::S24LE
+        | config::SampleFormat::S24LE3
+        | config::SampleFormat::S24BE => 24,
+        _ => return None,
+    };
+    match dither {
+        config::DeviceDither::None => None,
+        config::DeviceDither::Flat => Some(Dither::new(name, bits, Vec::new(), amplitude)),
+        config::DeviceDither::Shaped => Some(Dither::new(name, bits, vec![0.8], amplitude)),
+    }
+}
+
 impl Filter for Dither {
     fn name(&self) -> String {
         self.name.clone()
@@ -168,10 +200,15 @@ impl Filter for Dither {
         //rand_nbrs = np.random.triangular(-1, 0, 1, len(wave_in))
 
         if self.filterlen > 0 {
-            let rng = thread_rng();
-            let dith_rng = Triangular::new(-1.0, 1.0, 0.0).unwrap();
-            let dith_iter = dith_rng.sample_iter(rng);
-            for (item, dith) in waveform.iter_mut().zip(dith_iter) {
+            let dith_rng = if self.amplitude > 0.0 {
+                Some(Triangular::new(-self.amplitude, self.amplitude, 0.0).unwrap())
+            } else {
+                None
+            };
+            for item in waveform.iter_mut() {
+                let dith = dith_rng
+                    .as_ref()
+                    .map_or(0.0, |dist| dist.sample(&mut self.rng));
                 let scaled = *item * self.scalefact;
                 let mut filt_buf = 0.0;
                 for (n, coeff) in self.filter.iter().enumerate() {
@@ -192,9 +229,8 @@ impl Filter for Dither {
                 *item = result_r / self.scalefact;
             }
         } else if self.amplitude > 0.0 {
-            let rng = thread_rng();
             let dith_rng = Triangular::new(-self.amplitude, self.amplitude, 0.0).unwrap();
-            let dith_iter = dith_rng.sample_iter(rng);
+            let dith_iter = dith_rng.sample_iter(&mut self.rng);
             for (item, dith) in waveform.iter_mut().zip(dith_iter) {
                 let scaled = *item * self.scalefact + dith;
                 *item = scaled.round() / self.scalefact;
@@ -209,7 +245,10 @@ impl Filter for Dither {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Dither { parameters: conf } = conf {
+        if let config::Filter::Dither {
+            parameters: conf, ..
+        } = conf
+        {
             let name = self.name.clone();
             *self = Dither::from_config(name, conf);
         } else {
@@ -248,8 +287,8 @@ pub fn validate_config(conf: &config::DitherParameters) -> Res<()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::DitherParameters;
-    use crate::dither::Dither;
+    use crate::config::{DeviceDither, DitherParameters, SampleFormat};
+    use crate::dither::{for_playback, Dither};
     use crate::filters::Filter;
     use crate::PrcFmt;
 
@@ -340,4 +379,94 @@ mod tests {
             1e-9
         ));
     }
+
+    fn dithered_mean(dither: &DeviceDither, value: PrcFmt) -> PrcFmt {
+        let mut dith = for_playback("test".to_string(), dither, 1.0, &SampleFormat::S16LE).unwrap();
+        let mut sum = 0.0;
+        let nbr_chunks = 100;
+        for _ in 0..nbr_chunks {
+            let mut waveform = vec![value; 1000];
+            dith.process_waveform(&mut waveform).unwrap();
+            sum += waveform.iter().sum::<PrcFmt>();
+        }
+        sum / (1000 * nbr_chunks) as PrcFmt
+    }
+
+    #[test]
+    fn test_playback_dither_mean() {
+        let lsb = 1.0 / 32768.0;
+        let value = 0.3 * lsb;
+        let flat_mean = dithered_mean(&DeviceDither::Flat, value);
+        assert!(is_close(flat_mean / lsb, 0.3, 0.02));
+        let shaped_mean = dithered_mean(&DeviceDither::Shaped, value);
+        assert!(is_close(shaped_mean / lsb, 0.3, 0.02));
+    }
+
+    fn dithered_chunk(seed: u64) -> Vec<PrcFmt> {
+        let mut dith = for_playback(
+            "test".to_string(),
+            &DeviceDither::Shaped,
+            1.0,
+            &SampleFormat::S16LE,
+        )
+        .unwrap()
+        .with_seed(seed);
+        let mut waveform: Vec<PrcFmt> = (0..1000).map(|n| 0.001 * n as PrcFmt).collect();
+        dith.process_waveform(&mut waveform).unwrap();
+        waveform
+    }
+
+    #[test]
+    fn test_playback_dither_seed() {
+        assert_eq!(dithered_chunk(1234), dithered_chunk(1234));
+        assert_ne!(dithered_chunk(1234), dithered_chunk(4321));
+    }
+
+    #[test]
+    fn test_playback_dither_amplitude() {
+        let lsb = 1.0 / 32768.0;
+        let mut dith = for_playback(
+            "test".to_string(),
+            &DeviceDither::Flat,
+            0.25,
+            &SampleFormat::S16LE,
+        )
+        .unwrap();
+        // A quarter LSB of dither can never move a value at an LSB step to the next one
+        let mut waveform = vec![2.0 * lsb; 1000];
+        dith.process_waveform(&mut waveform).unwrap();
+        assert!(waveform.iter().all(|val| *val == 2.0 * lsb));
+    }
+
+    #[test]
+    fn test_playback_dither_formats() {
+        assert!(for_playback(
+            "test".to_string(),
+            &DeviceDither::Flat,
+            1.0,
+            &SampleFormat::S24LE3
+        )
+        .is_some());
+        assert!(for_playback(
+            "test".to_string(),
+            &DeviceDither::Flat,
+            1.0,
+            &SampleFormat::S32LE
+        )
+        .is_none());
+        assert!(for_playback(
+            "test".to_string(),
+            &DeviceDither::Flat,
+            1.0,
+            &SampleFormat::FLOAT32LE
+        )
+        .is_none());
+        assert!(for_playback(
+            "test".to_string(),
+            &DeviceDither::None,
+            1.0,
+            &SampleFormat::S16LE
+        )
+        .is_none());
+    }
 }
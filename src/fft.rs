@@ -0,0 +1,237 @@
+use num_complex::Complex;
+use num_traits::Zero;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+#[cfg(feature = "FFTW")]
+use fftw::array::AlignedVec;
+#[cfg(feature = "FFTW")]
+use fftw::plan::*;
+#[cfg(feature = "FFTW")]
+use fftw::types::*;
+
+use crate::PrcFmt;
+use crate::Res;
+
+#[cfg(all(feature = "FFTW", feature = "32bit"))]
+type FftwComplex = c32;
+#[cfg(all(feature = "FFTW", not(feature = "32bit")))]
+type FftwComplex = c64;
+
+/// The FFT used by the filters and the spectrum analysis.
+/// This is the realfft crate by default, and FFTW when the `FFTW` feature is enabled.
+#[cfg(not(feature = "FFTW"))]
+pub type DefaultFft = RealFftBackend;
+#[cfg(feature = "FFTW")]
+pub type DefaultFft = FftwBackend;
+
+/// Forward and inverse FFT of real valued data of a fixed length.
+/// The spectrum has `fft_len / 2 + 1` values, and the inverse transform is not normalized,
+/// so a forward and inverse transform scales the data by `fft_len`.
+pub trait RealFft: Sized {
+    fn new(len: usize) -> Self;
+
+    fn fft_len(&self) -> usize;
+
+    /// Transform `input`, that is used as scratch space and is modified.
+    fn forward(&mut self, input: &mut [PrcFmt], output: &mut [Complex<PrcFmt>]) -> Res<()>;
+
+    /// Transform `input` back, it is used as scratch space and is modified.
+    fn inverse(&mut self, input: &mut [Complex<PrcFmt>], output: &mut [PrcFmt]) -> Res<()>;
+
+    /// Allocate a zeroed input vector for the forward transform.
+    fn make_input_vec(&self) -> Vec<PrcFmt> {
+        vec![0.0; self.fft_len()]
+    }
+
+    /// Allocate a zeroed output vector for the forward transform.
+    fn make_output_vec(&self) -> Vec<Complex<PrcFmt>> {
+        vec![Complex::zero(); self.fft_len() / 2 + 1]
+    }
+}
+
+/// FFT using the realfft crate.
+pub struct RealFftBackend {
+    len: usize,
+    fft: Arc<dyn RealToComplex<PrcFmt>>,
+    ifft: Arc<dyn ComplexToReal<PrcFmt>>,
+    scratch_fw: Vec<Complex<PrcFmt>>,
+    scratch_inv: Vec<Complex<PrcFmt>>,
+}
+
+impl RealFft for RealFftBackend {
+    fn new(len: usize) -> Self {
+        let mut planner = RealFftPlanner::<PrcFmt>::new();
+        let fft = planner.plan_fft_forward(len);
+        let ifft = planner.plan_fft_inverse(len);
+        let scratch_fw = fft.make_scratch_vec();
+        let scratch_inv = ifft.make_scratch_vec();
+        RealFftBackend {
+            len,
+            fft,
+            ifft,
+            scratch_fw,
+            scratch_inv,
+        }
+    }
+
+    fn fft_len(&self) -> usize {
+        self.len
+    }
+
+    fn forward(&mut self, input: &mut [PrcFmt], output: &mut [Complex<PrcFmt>]) -> Res<()> {
+        self.fft
+            .process_with_scratch(input, output, &mut self.scratch_fw)?;
+        Ok(())
+    }
+
+    fn inverse(&mut self, input: &mut [Complex<PrcFmt>], output: &mut [PrcFmt]) -> Res<()> {
+        // The imaginary parts of the first and last values must be zero for a real result,
+        // realfft rejects values that are only zero within rounding errors.
+        if let Some(first) = input.first_mut() {
+            first.im = 0.0;
+        }
+        if self.len % 2 == 0 {
+            if let Some(last) = input.last_mut() {
+                last.im = 0.0;
+            }
+        }
+        self.ifft
+            .process_with_scratch(input, output, &mut self.scratch_inv)?;
+        Ok(())
+    }
+}
+
+/// FFT using FFTW. The data is copied to aligned buffers,
+/// since FFTW uses a different version of num-complex.
+/// The FFT convolution avoids these copies by using FFTW directly, see `fftconv_fftw`.
+#[cfg(feature = "FFTW")]
+pub struct FftwBackend {
+    len: usize,
+    #[cfg(feature = "32bit")]
+    fft: R2CPlan32,
+    #[cfg(not(feature = "32bit"))]
+    fft: R2CPlan64,
+    #[cfg(feature = "32bit")]
+    ifft: C2RPlan32,
+    #[cfg(not(feature = "32bit"))]
+    ifft: C2RPlan64,
+    real_buf: AlignedVec<PrcFmt>,
+    complex_buf: AlignedVec<FftwComplex>,
+}
+
+#[cfg(feature = "FFTW")]
+impl RealFft for FftwBackend {
+    fn new(len: usize) -> Self {
+        // Measuring takes longer than estimating, but gives faster plans for the convolution
+        let fft = R2CPlan::aligned(&[len], Flag::MEASURE).unwrap();
+        let ifft = C2RPlan::aligned(&[len], Flag::MEASURE).unwrap();
+        FftwBackend {
+            len,
+            fft,
+            ifft,
+            real_buf: AlignedVec::new(len),
+            complex_buf: AlignedVec::new(len / 2 + 1),
+        }
+    }
+
+    fn fft_len(&self) -> usize {
+        self.len
+    }
+
+    fn forward(&mut self, input: &mut [PrcFmt], output: &mut [Complex<PrcFmt>]) -> Res<()> {
+        self.real_buf.copy_from_slice(input);
+        self.fft.r2c(&mut self.real_buf, &mut self.complex_buf)?;
+        for (out, value) in output.iter_mut().zip(self.complex_buf.iter()) {
+            *out = Complex::new(value.re, value.im);
+        }
+        Ok(())
+    }
+
+    fn inverse(&mut self, input: &mut [Complex<PrcFmt>], output: &mut [PrcFmt]) -> Res<()> {
+        for (buf, value) in self.complex_buf.iter_mut().zip(input.iter()) {
+            *buf = FftwComplex::new(value.re, value.im);
+        }
+        self.ifft.c2r(&mut self.complex_buf, &mut self.real_buf)?;
+        output.copy_from_slice(&self.real_buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fft::{RealFft, RealFftBackend};
+    use crate::PrcFmt;
+    use num_complex::Complex;
+
+    fn test_signal(len: usize) -> Vec<PrcFmt> {
+        (0..len)
+            .map(|n| (0.3 * n as PrcFmt).sin() + 0.5 * (1.7 * n as PrcFmt).cos() + 0.1)
+            .collect()
+    }
+
+    /// Transform by direct evaluation of the DFT sums.
+    fn naive_dft(input: &[PrcFmt]) -> Vec<Complex<PrcFmt>> {
+        let len = input.len();
+        let pi = std::f64::consts::PI as PrcFmt;
+        (0..len / 2 + 1)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .map(|(n, value)| {
+                        let phase = -2.0 * pi * (k * n) as PrcFmt / len as PrcFmt;
+                        Complex::new(value * phase.cos(), value * phase.sin())
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn check_backend<F: RealFft>(len: usize) {
+        let signal = test_signal(len);
+        let mut fft = F::new(len);
+        let mut input = signal.clone();
+        let mut spectrum = fft.make_output_vec();
+        fft.forward(&mut input, &mut spectrum).unwrap();
+        for (value, expected) in spectrum.iter().zip(naive_dft(&signal).iter()) {
+            assert!(
+                (value - expected).norm() < 1e-3,
+                "{} != {}",
+                value,
+                expected
+            );
+        }
+        let mut output = fft.make_input_vec();
+        fft.inverse(&mut spectrum, &mut output).unwrap();
+        for (value, expected) in output.iter().zip(signal.iter()) {
+            assert!((value / len as PrcFmt - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn realfft_transforms() {
+        check_backend::<RealFftBackend>(64);
+        check_backend::<RealFftBackend>(90);
+    }
+
+    #[test]
+    #[cfg(feature = "FFTW")]
+    fn fftw_matches_realfft() {
+        use crate::fft::FftwBackend;
+        check_backend::<FftwBackend>(64);
+        check_backend::<FftwBackend>(90);
+        let len = 256;
+        let mut realfft = RealFftBackend::new(len);
+        let mut fftw = FftwBackend::new(len);
+        let mut input = test_signal(len);
+        let mut expected = realfft.make_output_vec();
+        realfft.forward(&mut input, &mut expected).unwrap();
+        let mut input = test_signal(len);
+        let mut spectrum = fftw.make_output_vec();
+        fftw.forward(&mut input, &mut spectrum).unwrap();
+        for (value, expected) in spectrum.iter().zip(expected.iter()) {
+            assert!((value - expected).norm() < 1e-6);
+        }
+    }
+}
@@ -1,11 +1,10 @@
 use crate::config;
+use crate::fft::{DefaultFft, RealFft};
 use crate::filters;
 use crate::filters::Filter;
 use crate::helpers::{multiply_add_elements, multiply_elements};
 use num_complex::Complex;
 use num_traits::Zero;
-use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
-use std::sync::Arc;
 
 // Sample format
 use crate::PrcFmt;
@@ -14,84 +13,76 @@ use crate::Res;
 pub struct FftConv {
     name: String,
     npoints: usize,
+    fft_len: usize,
     nsegments: usize,
     overlap: Vec<PrcFmt>,
     coeffs_f: Vec<Vec<Complex<PrcFmt>>>,
-    fft: Arc<dyn RealToComplex<PrcFmt>>,
-    ifft: Arc<dyn ComplexToReal<PrcFmt>>,
-    scratch_fw: Vec<Complex<PrcFmt>>,
-    scratch_inv: Vec<Complex<PrcFmt>>,
+    fft: DefaultFft,
     input_buf: Vec<PrcFmt>,
     input_f: Vec<Vec<Complex<PrcFmt>>>,
     temp_buf: Vec<Complex<PrcFmt>>,
     output_buf: Vec<PrcFmt>,
     index: usize,
+    position: usize,
 }
 
 impl FftConv {
     /// Create a new FFT colvolution filter.
     pub fn new(name: String, data_length: usize, coeffs: &[PrcFmt]) -> Self {
-        let input_buf: Vec<PrcFmt> = vec![0.0; 2 * data_length];
-        let temp_buf: Vec<Complex<PrcFmt>> = vec![Complex::zero(); data_length + 1];
-        let output_buf: Vec<PrcFmt> = vec![0.0; 2 * data_length];
-        let mut planner = RealFftPlanner::<PrcFmt>::new();
-        let fft = planner.plan_fft_forward(2 * data_length);
-        let ifft = planner.plan_fft_inverse(2 * data_length);
-        let mut scratch_fw = fft.make_scratch_vec();
-        let scratch_inv = ifft.make_scratch_vec();
+        let fft_len = filters::fft_len_for_chunksize(data_length);
+        let input_buf: Vec<PrcFmt> = vec![0.0; fft_len];
+        let temp_buf: Vec<Complex<PrcFmt>> = vec![Complex::zero(); fft_len / 2 + 1];
+        let output_buf: Vec<PrcFmt> = vec![0.0; fft_len];
+        let mut fft = DefaultFft::new(fft_len);
 
         let nsegments = ((coeffs.len() as PrcFmt) / (data_length as PrcFmt)).ceil() as usize;
 
-        let input_f = vec![vec![Complex::zero(); data_length + 1]; nsegments];
-        let mut coeffs_padded = vec![vec![0.0; 2 * data_length]; nsegments];
-        let mut coeffs_f = vec![vec![Complex::zero(); data_length + 1]; nsegments];
+        let input_f = vec![vec![Complex::zero(); fft_len / 2 + 1]; nsegments];
+        let mut coeffs_padded = vec![vec![0.0; fft_len]; nsegments];
+        let mut coeffs_f = vec![vec![Complex::zero(); fft_len / 2 + 1]; nsegments];
 
-        debug!("Conv {} is using {} segments", name, nsegments);
+        debug!(
+            "Conv {} is using {} segments, with FFT length {}",
+            name, nsegments, fft_len
+        );
 
         for (n, coeff) in coeffs.iter().enumerate() {
-            coeffs_padded[n / data_length][n % data_length] = coeff / (2 * data_length) as PrcFmt;
+            coeffs_padded[n / data_length][n % data_length] = coeff / fft_len as PrcFmt;
         }
 
         for (segment, segment_f) in coeffs_padded.iter_mut().zip(coeffs_f.iter_mut()) {
-            fft.process_with_scratch(segment, segment_f, &mut scratch_fw)
-                .unwrap();
+            fft.forward(segment, segment_f).unwrap();
         }
 
         FftConv {
             name,
             npoints: data_length,
+            fft_len,
             nsegments,
             overlap: vec![0.0; data_length],
             coeffs_f,
             fft,
-            ifft,
-            scratch_fw,
-            scratch_inv,
             input_f,
             input_buf,
             output_buf,
             temp_buf,
             index: 0,
+            position: 0,
         }
     }
 
-    pub fn from_config(name: String, data_length: usize, conf: config::ConvParameters) -> Self {
-        let values = match conf {
-            config::ConvParameters::Values { values, length } => {
-                filters::pad_vector(&values, length)
-            }
-            config::ConvParameters::Raw {
-                filename,
-                format,
-                read_bytes_lines,
-                skip_bytes_lines,
-            } => filters::read_coeff_file(&filename, &format, read_bytes_lines, skip_bytes_lines)
-                .unwrap(),
-            config::ConvParameters::Wav { filename, channel } => {
-                filters::read_wav(&filename, channel).unwrap()
-            }
-        };
-        FftConv::new(name, data_length, &values)
+    /// Create a Conv filter from a config struct. The `position` is the place of the channel
+    /// among the channels of the filter step, and selects the file when there is one per channel.
+    pub fn from_config(
+        name: String,
+        data_length: usize,
+        position: usize,
+        conf: config::ConvParameters,
+    ) -> Self {
+        let values = filters::load_conv_coeffs(&conf, position).unwrap();
+        let mut conv = FftConv::new(name, data_length, &values);
+        conv.position = position;
+        conv
     }
 }
 
@@ -102,25 +93,16 @@ impl Filter for FftConv {
 
     /// Process a waveform by FT, then multiply transform with transform of filter, and then transform back.
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
-        // Copy to inut buffer and clear overlap area
+        // Copy to inut buffer and clear overlap and padding area
         self.input_buf[0..self.npoints].copy_from_slice(waveform);
-        for item in self
-            .input_buf
-            .iter_mut()
-            .skip(self.npoints)
-            .take(self.npoints)
-        {
+        for item in self.input_buf.iter_mut().skip(self.npoints) {
             *item = 0.0;
         }
 
         // FFT and store result in history, update index
         self.index = (self.index + 1) % self.nsegments;
         self.fft
-            .process_with_scratch(
-                &mut self.input_buf,
-                &mut self.input_f[self.index],
-                &mut self.scratch_fw,
-            )
+            .forward(&mut self.input_buf, &mut self.input_f[self.index])
             .unwrap();
 
         // Loop through history of input FTs, multiply with filter FTs, accumulate result
@@ -141,40 +123,23 @@ impl Filter for FftConv {
         }
 
         // IFFT result, store result and overlap
-        self.ifft
-            .process_with_scratch(
-                &mut self.temp_buf,
-                &mut self.output_buf,
-                &mut self.scratch_inv,
-            )
+        self.fft
+            .inverse(&mut self.temp_buf, &mut self.output_buf)
             .unwrap();
         for (n, item) in waveform.iter_mut().enumerate().take(self.npoints) {
             *item = self.output_buf[n] + self.overlap[n];
         }
         self.overlap
-            .copy_from_slice(&self.output_buf[self.npoints..]);
+            .copy_from_slice(&self.output_buf[self.npoints..2 * self.npoints]);
         Ok(())
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Conv { parameters: conf } = conf {
-            let coeffs = match conf {
-                config::ConvParameters::Values { values, length } => {
-                    filters::pad_vector(&values, length)
-                }
-                config::ConvParameters::Raw {
-                    filename,
-                    format,
-                    read_bytes_lines,
-                    skip_bytes_lines,
-                } => {
-                    filters::read_coeff_file(&filename, &format, read_bytes_lines, skip_bytes_lines)
-                        .unwrap()
-                }
-                config::ConvParameters::Wav { filename, channel } => {
-                    filters::read_wav(&filename, channel).unwrap()
-                }
-            };
+        if let config::Filter::Conv {
+            parameters: conf, ..
+        } = conf
+        {
+            let coeffs = filters::load_conv_coeffs(&conf, self.position).unwrap();
 
             let nsegments = ((coeffs.len() as PrcFmt) / (self.npoints as PrcFmt)).ceil() as usize;
 
@@ -183,24 +148,21 @@ impl Filter for FftConv {
             } else {
                 // length changed, clearing history
                 self.nsegments = nsegments;
-                let input_f = vec![vec![Complex::zero(); self.npoints + 1]; nsegments];
+                let input_f = vec![vec![Complex::zero(); self.fft_len / 2 + 1]; nsegments];
                 self.input_f = input_f;
             }
 
-            let mut coeffs_f = vec![vec![Complex::zero(); self.npoints + 1]; nsegments];
-            let mut coeffs_padded = vec![vec![0.0; 2 * self.npoints]; nsegments];
+            let mut coeffs_f = vec![vec![Complex::zero(); self.fft_len / 2 + 1]; nsegments];
+            let mut coeffs_padded = vec![vec![0.0; self.fft_len]; nsegments];
 
             debug!("conv using {} segments", nsegments);
 
             for (n, coeff) in coeffs.iter().enumerate() {
-                coeffs_padded[n / self.npoints][n % self.npoints] =
-                    coeff / (2 * self.npoints) as PrcFmt;
+                coeffs_padded[n / self.npoints][n % self.npoints] = coeff / self.fft_len as PrcFmt;
             }
 
             for (segment, segment_f) in coeffs_padded.iter_mut().zip(coeffs_f.iter_mut()) {
-                self.fft
-                    .process_with_scratch(segment, segment_f, &mut self.scratch_fw)
-                    .unwrap();
+                self.fft.forward(segment, segment_f).unwrap();
             }
             self.coeffs_f = coeffs_f;
         } else {
@@ -210,37 +172,66 @@ impl Filter for FftConv {
     }
 }
 
-/// Validate a FFT convolution config.
-pub fn validate_config(conf: &config::ConvParameters) -> Res<()> {
-    match conf {
-        config::ConvParameters::Values { .. } => Ok(()),
-        config::ConvParameters::Raw {
-            filename,
-            format,
-            read_bytes_lines,
-            skip_bytes_lines,
+/// Validate the coefficients of a FFT convolution config, and get their length.
+fn validate_coeffs(conf: &config::ConvParameters) -> Res<usize> {
+    let coeffs_len = match conf {
+        config::ConvParameters::Values {
+            values,
+            length,
+            normalize,
         } => {
-            let coeffs =
-                filters::read_coeff_file(filename, format, *read_bytes_lines, *skip_bytes_lines)?;
-            if coeffs.is_empty() {
+            if values.is_empty() {
                 return Err(config::ConfigError::new("Conv coefficients are empty").into());
             }
-            Ok(())
+            if let Some(normalize) = normalize {
+                // Both gains are at most the sum of the magnitudes of the coefficients.
+                // A gain that is zero apart from rounding errors is much smaller than that.
+                let max_gain: PrcFmt = values.iter().map(|value| value.abs()).sum();
+                if filters::normalization_gain(values, normalize) <= 1.0e-6 * max_gain {
+                    let msg = format!(
+                        "Conv coefficients with zero gain can't be normalized by {:?}",
+                        normalize
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
+            values.len().max(*length)
         }
-        config::ConvParameters::Wav { filename, channel } => {
-            let coeffs = filters::read_wav(filename, *channel)?;
-            if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
+        config::ConvParameters::Raw { filename, .. }
+        | config::ConvParameters::Wav { filename, .. } => {
+            if filename.is_empty() {
+                return Err(config::ConfigError::new("Conv filename list is empty").into());
             }
-            Ok(())
+            let mut coeffs_len = 0;
+            for position in 0..filename.len() {
+                let coeffs = filters::load_conv_coeffs(conf, position)?;
+                if coeffs.is_empty() {
+                    return Err(config::ConfigError::new("Conv coefficients are empty").into());
+                }
+                coeffs_len = coeffs_len.max(coeffs.len());
+            }
+            coeffs_len
         }
-    }
+        config::ConvParameters::Cascade { parts } => {
+            filters::validate_conv_cascade(parts, validate_coeffs)?
+        }
+    };
+    Ok(coeffs_len)
+}
+
+/// Validate a FFT convolution config.
+pub fn validate_config(conf: &config::ConvParameters, chunksize: usize) -> Res<()> {
+    let coeffs_len = validate_coeffs(conf)?;
+    filters::check_conv_length(coeffs_len, chunksize);
+    filters::check_fft_size(chunksize);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::ConvParameters;
-    use crate::fftconv::FftConv;
+    use crate::config::{ConvNormalization, ConvParameters};
+    use crate::fftconv::{validate_config, FftConv};
+    use crate::filters::fft_len_for_chunksize;
     use crate::filters::Filter;
     use crate::PrcFmt;
 
@@ -264,8 +255,9 @@ mod tests {
         let conf = ConvParameters::Values {
             values: coeffs,
             length: 0,
+            normalize: None,
         };
-        let mut filter = FftConv::from_config("test".to_string(), 8, conf);
+        let mut filter = FftConv::from_config("test".to_string(), 8, 0, conf);
         let mut wave1 = vec![1.0, 1.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0];
         let expected = vec![0.5, 1.0, 1.0, 0.5, 0.0, -0.5, -0.5, 0.0];
         filter.process_waveform(&mut wave1).unwrap();
@@ -304,4 +296,122 @@ mod tests {
         assert!(compare_waveforms(wave4, exp4, 1e-5));
         assert!(compare_waveforms(wave5, exp5, 1e-5));
     }
+
+    /// Straightforward direct convolution, to compare against
+    fn direct_convolution(signal: &[PrcFmt], coeffs: &[PrcFmt]) -> Vec<PrcFmt> {
+        (0..signal.len())
+            .map(|n| {
+                coeffs
+                    .iter()
+                    .take(n + 1)
+                    .enumerate()
+                    .map(|(k, c)| c * signal[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_against_direct() {
+        let chunksize = 16;
+        // A filter spanning several segments, with a length that isn't a multiple of the chunksize
+        let coeffs: Vec<PrcFmt> = (0..100)
+            .map(|n| (0.3 * n as PrcFmt).sin() * (-0.02 * n as PrcFmt).exp())
+            .collect();
+        let signal: Vec<PrcFmt> = (0..20 * chunksize)
+            .map(|n| (1.7 * n as PrcFmt).sin() + 0.5 * (0.11 * n as PrcFmt).cos())
+            .collect();
+        let expected = direct_convolution(&signal, &coeffs);
+        let mut filter = FftConv::new("test".to_owned(), chunksize, &coeffs);
+        let mut output = Vec::with_capacity(signal.len());
+        for chunk in signal.chunks(chunksize) {
+            let mut waveform = chunk.to_vec();
+            filter.process_waveform(&mut waveform).unwrap();
+            output.extend(waveform);
+        }
+        assert!(compare_waveforms(output, expected, 1e-5));
+    }
+
+    #[test]
+    fn padded_fft_against_direct() {
+        // 2 * 13 is not an efficient FFT size, the FFT is padded to 28 points
+        let chunksize = 13;
+        assert_eq!(fft_len_for_chunksize(chunksize), 28);
+        let coeffs: Vec<PrcFmt> = (0..50)
+            .map(|n| (0.3 * n as PrcFmt).sin() * (-0.05 * n as PrcFmt).exp())
+            .collect();
+        let signal: Vec<PrcFmt> = (0..10 * chunksize)
+            .map(|n| (1.7 * n as PrcFmt).sin())
+            .collect();
+        let expected = direct_convolution(&signal, &coeffs);
+        let mut filter = FftConv::new("test".to_owned(), chunksize, &coeffs);
+        let mut output = Vec::with_capacity(signal.len());
+        for chunk in signal.chunks(chunksize) {
+            let mut waveform = chunk.to_vec();
+            filter.process_waveform(&mut waveform).unwrap();
+            output.extend(waveform);
+        }
+        assert!(compare_waveforms(output, expected, 1e-5));
+    }
+
+    #[test]
+    fn normalize_values() {
+        // A kernel with a DC gain of 0.5, and a peak gain of 1.5 at the Nyquist frequency
+        let values = vec![1.0, -0.5];
+        let conf = ConvParameters::Values {
+            values: values.clone(),
+            length: 0,
+            normalize: Some(ConvNormalization::Dc),
+        };
+        let mut filter = FftConv::from_config("test".to_string(), 8, 0, conf);
+        let mut dc = vec![1.0; 8];
+        filter.process_waveform(&mut dc).unwrap();
+        assert!(is_close(dc[7], 1.0, 1e-7));
+
+        let conf = ConvParameters::Values {
+            values,
+            length: 0,
+            normalize: Some(ConvNormalization::Peak),
+        };
+        let mut filter = FftConv::from_config("test".to_string(), 8, 0, conf);
+        let mut nyquist = vec![1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        filter.process_waveform(&mut nyquist).unwrap();
+        assert!(is_close(nyquist[7], -1.0, 1e-7));
+    }
+
+    #[test]
+    fn check_config() {
+        let conf = ConvParameters::Values {
+            values: vec![1.0, 0.5],
+            length: 0,
+            normalize: None,
+        };
+        assert!(validate_config(&conf, 1024).is_ok());
+        let conf = ConvParameters::Values {
+            values: Vec::new(),
+            length: 1024,
+            normalize: None,
+        };
+        assert!(validate_config(&conf, 1024).is_err());
+        // A very long filter only gives a warning
+        let conf = ConvParameters::Values {
+            values: vec![1.0],
+            length: 100000,
+            normalize: None,
+        };
+        assert!(validate_config(&conf, 64).is_ok());
+        let conf = ConvParameters::Values {
+            values: vec![1.0, -1.0],
+            length: 0,
+            normalize: Some(ConvNormalization::Dc),
+        };
+        assert!(validate_config(&conf, 64).is_err());
+        // The sum is not exactly zero due to rounding, but should still be rejected
+        let conf = ConvParameters::Values {
+            values: vec![0.1, 0.2, -0.3],
+            length: 0,
+            normalize: Some(ConvNormalization::Dc),
+        };
+        assert!(validate_config(&conf, 64).is_err());
+    }
 }
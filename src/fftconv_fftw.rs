@@ -82,6 +82,7 @@ pub fn multiply_add_elements(
 pub struct FftConv {
     name: String,
     npoints: usize,
+    fft_len: usize,
     nsegments: usize,
     overlap: Vec<PrcFmt>,
     coeffs_f: Vec<AlignedVec<ComplexFmt>>,
@@ -98,30 +99,35 @@ pub struct FftConv {
     temp_buf: AlignedVec<ComplexFmt>,
     output_buf: AlignedVec<PrcFmt>,
     index: usize,
+    position: usize,
 }
 
 impl FftConv {
     /// Create a new FFT colvolution filter.
     pub fn new(name: String, data_length: usize, coeffs: &[PrcFmt]) -> Self {
-        let input_buf = AlignedVec::<PrcFmt>::new(2 * data_length);
-        let temp_buf = AlignedVec::<ComplexFmt>::new(data_length + 1);
-        let output_buf = AlignedVec::<PrcFmt>::new(2 * data_length);
+        let fft_len = filters::fft_len_for_chunksize(data_length);
+        let input_buf = AlignedVec::<PrcFmt>::new(fft_len);
+        let temp_buf = AlignedVec::<ComplexFmt>::new(fft_len / 2 + 1);
+        let output_buf = AlignedVec::<PrcFmt>::new(fft_len);
         #[cfg(feature = "32bit")]
-        let mut fft: R2CPlan32 = R2CPlan::aligned(&[2 * data_length], Flag::MEASURE).unwrap();
+        let mut fft: R2CPlan32 = R2CPlan::aligned(&[fft_len], Flag::MEASURE).unwrap();
         #[cfg(not(feature = "32bit"))]
-        let mut fft: R2CPlan64 = R2CPlan::aligned(&[2 * data_length], Flag::MEASURE).unwrap();
-        let ifft = C2RPlan::aligned(&[2 * data_length], Flag::MEASURE).unwrap();
+        let mut fft: R2CPlan64 = R2CPlan::aligned(&[fft_len], Flag::MEASURE).unwrap();
+        let ifft = C2RPlan::aligned(&[fft_len], Flag::MEASURE).unwrap();
 
         let nsegments = ((coeffs.len() as PrcFmt) / (data_length as PrcFmt)).ceil() as usize;
 
-        let input_f = vec![AlignedVec::<ComplexFmt>::new(data_length + 1); nsegments];
-        let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(data_length + 1); nsegments];
-        let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(2 * data_length); nsegments];
+        let input_f = vec![AlignedVec::<ComplexFmt>::new(fft_len / 2 + 1); nsegments];
+        let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(fft_len / 2 + 1); nsegments];
+        let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(fft_len); nsegments];
 
-        debug!("Conv {} is using {} segments", name, nsegments);
+        debug!(
+            "Conv {} is using {} segments, with FFT length {}",
+            name, nsegments, fft_len
+        );
 
         for (n, coeff) in coeffs.iter().enumerate() {
-            coeffs_al[n / data_length][n % data_length] = coeff / (2.0 * data_length as PrcFmt);
+            coeffs_al[n / data_length][n % data_length] = coeff / fft_len as PrcFmt;
         }
 
         for (segment, segment_f) in coeffs_al.iter_mut().zip(coeffs_f.iter_mut()) {
@@ -131,6 +137,7 @@ impl FftConv {
         FftConv {
             name,
             npoints: data_length,
+            fft_len,
             nsegments,
             overlap: vec![0.0; data_length],
             coeffs_f,
@@ -141,26 +148,22 @@ impl FftConv {
             output_buf,
             temp_buf,
             index: 0,
+            position: 0,
         }
     }
 
-    pub fn from_config(name: String, data_length: usize, conf: config::ConvParameters) -> Self {
-        let values = match conf {
-            config::ConvParameters::Values { values, length } => {
-                filters::pad_vector(&values, length)
-            }
-            config::ConvParameters::Raw {
-                filename,
-                format,
-                read_bytes_lines,
-                skip_bytes_lines,
-            } => filters::read_coeff_file(&filename, &format, read_bytes_lines, skip_bytes_lines)
-                .unwrap(),
-            config::ConvParameters::Wav { filename, channel } => {
-                filters::read_wav(&filename, channel).unwrap()
-            }
-        };
-        FftConv::new(name, data_length, &values)
+    /// Create a Conv filter from a config struct. The `position` is the place of the channel
+    /// among the channels of the filter step, and selects the file when there is one per channel.
+    pub fn from_config(
+        name: String,
+        data_length: usize,
+        position: usize,
+        conf: config::ConvParameters,
+    ) -> Self {
+        let values = filters::load_conv_coeffs(&conf, position).unwrap();
+        let mut conv = FftConv::new(name, data_length, &values);
+        conv.position = position;
+        conv
     }
 }
 
@@ -205,29 +208,16 @@ impl Filter for FftConv {
             *item = self.output_buf[n] + self.overlap[n];
         }
         self.overlap
-            .copy_from_slice(&self.output_buf[self.npoints..]);
+            .copy_from_slice(&self.output_buf[self.npoints..2 * self.npoints]);
         Ok(())
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Conv { parameters: conf } = conf {
-            let coeffs = match conf {
-                config::ConvParameters::Values { values, length } => {
-                    filters::pad_vector(&values, length)
-                }
-                config::ConvParameters::Raw {
-                    filename,
-                    format,
-                    read_bytes_lines,
-                    skip_bytes_lines,
-                } => {
-                    filters::read_coeff_file(&filename, &format, read_bytes_lines, skip_bytes_lines)
-                        .unwrap()
-                }
-                config::ConvParameters::Wav { filename, channel } => {
-                    filters::read_wav(&filename, channel).unwrap()
-                }
-            };
+        if let config::Filter::Conv {
+            parameters: conf, ..
+        } = conf
+        {
+            let coeffs = filters::load_conv_coeffs(&conf, self.position).unwrap();
 
             let nsegments = ((coeffs.len() as PrcFmt) / (self.npoints as PrcFmt)).ceil() as usize;
 
@@ -236,18 +226,17 @@ impl Filter for FftConv {
             } else {
                 // length changed, clearing history
                 self.nsegments = nsegments;
-                let input_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
+                let input_f = vec![AlignedVec::<ComplexFmt>::new(self.fft_len / 2 + 1); nsegments];
                 self.input_f = input_f;
             }
 
-            let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
-            let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(2 * self.npoints); nsegments];
+            let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(self.fft_len / 2 + 1); nsegments];
+            let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(self.fft_len); nsegments];
 
             debug!("conv using {} segments", nsegments);
 
             for (n, coeff) in coeffs.iter().enumerate() {
-                coeffs_al[n / self.npoints][n % self.npoints] =
-                    coeff / (2.0 * self.npoints as PrcFmt);
+                coeffs_al[n / self.npoints][n % self.npoints] = coeff / self.fft_len as PrcFmt;
             }
 
             for (segment, segment_f) in coeffs_al.iter_mut().zip(coeffs_f.iter_mut()) {
@@ -261,39 +250,16 @@ impl Filter for FftConv {
     }
 }
 
-/// Validate a FFT convolution config.
-pub fn validate_config(conf: &config::ConvParameters) -> Res<()> {
-    match conf {
-        config::ConvParameters::Values { .. } => Ok(()),
-        config::ConvParameters::Raw {
-            filename,
-            format,
-            read_bytes_lines,
-            skip_bytes_lines,
-        } => {
-            let coeffs =
-                filters::read_coeff_file(&filename, &format, *read_bytes_lines, *skip_bytes_lines)?;
-            if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
-            }
-            Ok(())
-        }
-        config::ConvParameters::Wav { filename, channel } => {
-            let coeffs = filters::read_wav(&filename, *channel)?;
-            if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
-            }
-            Ok(())
-        }
-    }
-}
+/// The validation doesn't depend on the FFT library, and is shared with the default convolution.
+pub use crate::fftconv::validate_config;
 
 #[cfg(test)]
 mod tests {
+    use crate::config::ConvParameters;
+    use crate::fftconv;
+    use crate::fftconv_fftw::FftConv;
+    use crate::filters::Filter;
     use crate::PrcFmt;
-    use config::ConvParameters;
-    use fftconv_fftw::FftConv;
-    use filters::Filter;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {}", left, right);
@@ -315,8 +281,9 @@ mod tests {
         let conf = ConvParameters::Values {
             values: coeffs,
             length: 0,
+            normalize: None,
         };
-        let mut filter = FftConv::from_config("test".to_string(), 8, conf);
+        let mut filter = FftConv::from_config("test".to_string(), 8, 0, conf);
         let mut wave1 = vec![1.0, 1.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0];
         let expected = vec![0.5, 1.0, 1.0, 0.5, 0.0, -0.5, -0.5, 0.0];
         filter.process_waveform(&mut wave1).unwrap();
@@ -355,4 +322,23 @@ mod tests {
         assert!(compare_waveforms(wave4, exp4, 1e-5));
         assert!(compare_waveforms(wave5, exp5, 1e-5));
     }
+
+    #[test]
+    fn matches_default_convolution() {
+        let coeffs: Vec<PrcFmt> = (0..50)
+            .map(|n| (0.37 * n as PrcFmt).sin() / (n + 1) as PrcFmt)
+            .collect();
+        let mut fftw_filter = FftConv::new("fftw".to_owned(), 16, &coeffs);
+        let mut default_filter = fftconv::FftConv::new("default".to_owned(), 16, &coeffs);
+        for chunk in 0..6 {
+            let wave: Vec<PrcFmt> = (0..16)
+                .map(|n| ((16 * chunk + n) as PrcFmt * 0.21).cos())
+                .collect();
+            let mut fftw_wave = wave.clone();
+            let mut default_wave = wave;
+            fftw_filter.process_waveform(&mut fftw_wave).unwrap();
+            default_filter.process_waveform(&mut default_wave).unwrap();
+            assert!(compare_waveforms(fftw_wave, default_wave, 1e-6));
+        }
+    }
 }
@@ -3,18 +3,20 @@ use crate::config;
 use crate::config::SampleFormat;
 use crate::conversions::{buffer_to_chunk_rawbytes, chunk_to_buffer_rawbytes};
 use crate::countertimer;
+use crate::filters;
 
 use std::error::Error;
 use std::fs::File;
 #[cfg(target_os = "linux")]
 use std::fs::OpenOptions;
-use std::io::{stdin, stdout, Write};
+use std::io::{stdin, stdout, Seek, SeekFrom, Write};
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use rubato::VecResampler;
 
@@ -35,6 +37,7 @@ pub struct FilePlaybackDevice {
     pub samplerate: usize,
     pub channels: usize,
     pub sample_format: SampleFormat,
+    pub wav_levels: bool,
 }
 
 #[derive(Clone)]
@@ -57,12 +60,16 @@ pub struct FileCaptureDevice {
     pub capture_samplerate: usize,
     pub resampler_conf: config::Resampler,
     pub channels: usize,
+    pub channel_adjust: config::ChannelAdjust,
     pub sample_format: SampleFormat,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
     pub extra_samples: usize,
     pub skip_bytes: usize,
     pub read_bytes: usize,
+    pub start_frame: usize,
+    pub length_frames: usize,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
 }
@@ -74,13 +81,17 @@ struct CaptureChannels {
 }
 
 struct CaptureParams {
+    /// The number of channels in the file
     channels: usize,
+    /// The number of channels sent to the pipeline
+    pipeline_channels: usize,
     sample_format: SampleFormat,
     store_bytes_per_sample: usize,
     extra_bytes: usize,
     buffer_bytes: usize,
     capture_samplerate: usize,
     silence_timeout: PrcFmt,
+    silence_mode: config::SilenceMode,
     silence_threshold: PrcFmt,
     chunksize: usize,
     resampling_ratio: f32,
@@ -101,6 +112,320 @@ pub trait Reader {
     fn read(&mut self, data: &mut [u8]) -> Result<ReadResult, Box<dyn Error>>;
 }
 
+/// Check if a file should be treated as wav, based on its extension.
+pub fn is_wav(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("wav"))
+        .unwrap_or(false)
+}
+
+/// Seek a capture file to the position where reading starts.
+fn seek_to(
+    mut file: File,
+    skip_bytes: usize,
+    skip_by_reading: &mut usize,
+) -> std::io::Result<File> {
+    if skip_bytes > 0 {
+        debug!("seeking past the first {} bytes", skip_bytes);
+        file.seek(SeekFrom::Start(skip_bytes as u64))?;
+    }
+    *skip_by_reading = 0;
+    Ok(file)
+}
+
+/// Get the number of frames that can be captured from a file,
+/// after skipping `skip_bytes` and reading at most `read_bytes` if it's non-zero.
+pub fn capture_file_frames(
+    filename: &str,
+    channels: usize,
+    channel_adjust: &config::ChannelAdjust,
+    sample_format: &SampleFormat,
+    skip_bytes: usize,
+    read_bytes: usize,
+    samplerate: usize,
+) -> Res<usize> {
+    let (format, data_bytes, file_channels) = if is_wav(filename) {
+        let (format, params) = read_wav_header(filename, channels, channel_adjust, samplerate)?;
+        (format, params.data_length, params.channels)
+    } else {
+        (
+            sample_format.clone(),
+            std::fs::metadata(filename)?.len() as usize,
+            channels,
+        )
+    };
+    let mut bytes = data_bytes.saturating_sub(skip_bytes);
+    if read_bytes > 0 && read_bytes < bytes {
+        bytes = read_bytes;
+    }
+    Ok(bytes / (file_channels * format.bytes_per_sample()))
+}
+
+/// Read the header of a wav file for capture, and check that it agrees with the configured
+/// number of channels and sample rate.
+/// A different number of channels is accepted if the channel adjust policy allows it.
+pub fn read_wav_header(
+    filename: &str,
+    channels: usize,
+    channel_adjust: &config::ChannelAdjust,
+    samplerate: usize,
+) -> Res<(SampleFormat, filters::WavParams)> {
+    let params = filters::find_data_in_wav(filename)?;
+    if !channel_adjust.accepts(params.channels, channels) {
+        let msg = match channel_adjust {
+            config::ChannelAdjust::Strict => format!(
+                "Wav file '{}' has {} channels, but the capture device is configured for {}",
+                filename, params.channels, channels
+            ),
+            config::ChannelAdjust::Truncate => format!(
+                "Wav file '{}' has {} channels, too few to be truncated to the {} channels of the capture device",
+                filename, params.channels, channels
+            ),
+            config::ChannelAdjust::Pad => format!(
+                "Wav file '{}' has {} channels, too many to be padded to the {} channels of the capture device",
+                filename, params.channels, channels
+            ),
+        };
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if params.sample_rate != samplerate {
+        let msg = format!(
+            "Wav file '{}' has a sample rate of {} Hz, but the capture device is configured for {} Hz. Enable resampling and set capture_samplerate to {} to play it at the right speed.",
+            filename, params.sample_rate, samplerate, params.sample_rate
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    let sample_format = match params.sample_format {
+        config::FileFormat::S16LE => SampleFormat::S16LE,
+        config::FileFormat::S24LE => SampleFormat::S24LE,
+        config::FileFormat::S24LE3 => SampleFormat::S24LE3,
+        config::FileFormat::S32LE => SampleFormat::S32LE,
+        config::FileFormat::FLOAT32LE => SampleFormat::FLOAT32LE,
+        config::FileFormat::FLOAT64LE => SampleFormat::FLOAT64LE,
+        config::FileFormat::TEXT => {
+            let msg = format!("Unsupported sample format in wav file '{}'", filename);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    };
+    Ok((sample_format, params))
+}
+
+/// The last 12 bytes of the KSDATAFORMAT_SUBTYPE guids.
+/// The first four bytes are the format code, 1 for PCM and 3 for float.
+const SUBTYPE_GUID_TAIL: [u8; 12] = [
+    0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Get the speaker positions for the WAVEFORMATEXTENSIBLE channel mask.
+/// Channel counts without a common layout get no speaker assignment.
+fn wav_channel_mask(channels: usize) -> u32 {
+    match channels {
+        // FL FR BL BR
+        4 => 0x33,
+        // FL FR FC LFE BL BR
+        6 => 0x3F,
+        // FL FR FC LFE BL BR SL SR
+        8 => 0x63F,
+        _ => 0,
+    }
+}
+
+/// Build a wav header.
+/// Mono and stereo files get the canonical 44 byte header,
+/// files with more channels get a 68 byte WAVEFORMATEXTENSIBLE header with a channel mask.
+/// S24LE is always written with the extended header, as 24 valid bits in a 32 bit container.
+/// The samples must then be left-justified, see `left_justify_s24`.
+pub fn wav_header(
+    channels: usize,
+    samplerate: usize,
+    sample_format: &SampleFormat,
+    data_bytes: u32,
+) -> Vec<u8> {
+    let formatcode: u16 = match sample_format {
+        SampleFormat::FLOAT32LE | SampleFormat::FLOAT64LE => 3,
+        _ => 1,
+    };
+    let extensible = channels > 2 || *sample_format == SampleFormat::S24LE;
+    let fmt_length: u32 = if extensible { 40 } else { 16 };
+    let bytes_per_frame = channels * sample_format.bytes_per_sample();
+    let mut header = Vec::with_capacity(28 + fmt_length as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(20 + fmt_length + data_bytes).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&fmt_length.to_le_bytes());
+    if extensible {
+        header.extend_from_slice(&0xFFFEu16.to_le_bytes());
+    } else {
+        header.extend_from_slice(&formatcode.to_le_bytes());
+    }
+    header.extend_from_slice(&(channels as u16).to_le_bytes());
+    header.extend_from_slice(&(samplerate as u32).to_le_bytes());
+    header.extend_from_slice(&((samplerate * bytes_per_frame) as u32).to_le_bytes());
+    header.extend_from_slice(&(bytes_per_frame as u16).to_le_bytes());
+    if extensible {
+        // The extended header gives the container size and the number of valid bits separately
+        header.extend_from_slice(&(8 * sample_format.bytes_per_sample() as u16).to_le_bytes());
+        header.extend_from_slice(&22u16.to_le_bytes());
+        header.extend_from_slice(&(sample_format.bits_per_sample() as u16).to_le_bytes());
+        header.extend_from_slice(&wav_channel_mask(channels).to_le_bytes());
+        header.extend_from_slice(&(formatcode as u32).to_le_bytes());
+        header.extend_from_slice(&SUBTYPE_GUID_TAIL);
+    } else {
+        header.extend_from_slice(&(sample_format.bits_per_sample() as u16).to_le_bytes());
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_bytes.to_le_bytes());
+    header
+}
+
+/// Shift S24LE samples to the top 24 bits of their 32 bit containers, as required in wav files.
+pub fn left_justify_s24(buffer: &mut [u8]) {
+    for sample in buffer.chunks_exact_mut(4) {
+        let value = i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) << 8;
+        sample.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Write the final chunk lengths to a wav header of the given length written by `wav_header`.
+pub fn update_wav_header(
+    file: &mut File,
+    header_length: u64,
+    data_bytes: u64,
+) -> std::io::Result<()> {
+    let riff_extra = header_length as u32 - 8;
+    let data_bytes = data_bytes.min((u32::MAX - riff_extra) as u64) as u32;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&(riff_extra + data_bytes).to_le_bytes())?;
+    file.seek(SeekFrom::Start(header_length - 4))?;
+    file.write_all(&data_bytes.to_le_bytes())?;
+    Ok(())
+}
+
+/// Append a LIST chunk with an INFO comment (ICMT) after the data of a wav file,
+/// and update the RIFF length to include it.
+pub fn append_wav_comment(file: &mut File, comment: &str) -> std::io::Result<()> {
+    let end = file.seek(SeekFrom::End(0))?;
+    let mut text = comment.as_bytes().to_vec();
+    text.push(0);
+    let text_length = text.len() as u32;
+    // Chunks must start at even positions, so odd lengths get a pad byte
+    if text.len() % 2 == 1 {
+        text.push(0);
+    }
+    let mut chunk = Vec::with_capacity(text.len() + 21);
+    if end % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk.extend_from_slice(b"LIST");
+    chunk.extend_from_slice(&(12 + text.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(b"INFO");
+    chunk.extend_from_slice(b"ICMT");
+    chunk.extend_from_slice(&text_length.to_le_bytes());
+    chunk.extend_from_slice(&text);
+    file.write_all(&chunk)?;
+    let riff_length = (end + chunk.len() as u64 - 8).min(u32::MAX as u64) as u32;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_length.to_le_bytes())?;
+    Ok(())
+}
+
+/// Peak and RMS levels of each channel, accumulated over everything written to a file.
+pub struct LevelMeter {
+    sum_squares: Vec<PrcFmt>,
+    peak: Vec<PrcFmt>,
+    frames: usize,
+}
+
+impl LevelMeter {
+    pub fn new(channels: usize) -> Self {
+        LevelMeter {
+            sum_squares: vec![0.0; channels],
+            peak: vec![0.0; channels],
+            frames: 0,
+        }
+    }
+
+    /// Add the valid frames of a chunk. Unused channels, with empty waveforms, count as silent.
+    /// The samples are clamped to the range of the file, the same way as when they are written.
+    pub fn add(&mut self, chunk: &AudioChunk) {
+        for ((waveform, sum), peak) in chunk
+            .waveforms
+            .iter()
+            .zip(self.sum_squares.iter_mut())
+            .zip(self.peak.iter_mut())
+        {
+            for value in waveform.iter().take(chunk.valid_frames) {
+                let value = value.clamp(-1.0, 1.0);
+                *sum += value * value;
+                *peak = peak.max(value.abs());
+            }
+        }
+        self.frames += chunk.valid_frames;
+    }
+
+    pub fn stats(&self) -> ChunkStats {
+        let rms = self
+            .sum_squares
+            .iter()
+            .map(|sum| {
+                if self.frames > 0 {
+                    (sum / self.frames as PrcFmt).sqrt()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        ChunkStats {
+            rms,
+            peak: self.peak.clone(),
+        }
+    }
+
+    /// Describe the levels in a comment for the wav metadata.
+    pub fn comment(&self) -> String {
+        let stats = self.stats();
+        let join = |levels: Vec<f32>| {
+            levels
+                .iter()
+                .map(|level| format!("{:.2}", level))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        format!(
+            "Levels per channel, peak dBFS: {}; RMS dBFS: {}",
+            join(stats.peak_db()),
+            join(stats.rms_db())
+        )
+    }
+}
+
+/// Flush everything written to the output, and finalize the wav header if there is one.
+/// With a level meter, the levels are added to the metadata of the wav file.
+fn finish_output(
+    output: &mut dyn Write,
+    wav_file: &mut Option<(File, u64)>,
+    data_bytes: u64,
+    levels: &Option<LevelMeter>,
+) {
+    if let Err(err) = output.flush() {
+        error!("Unable to flush playback output: {}", err);
+    }
+    if let Some((file, header_length)) = wav_file {
+        if let Err(err) = update_wav_header(file, *header_length, data_bytes) {
+            error!("Unable to update wav header: {}", err);
+        }
+        if let Some(levels) = levels {
+            let comment = levels.comment();
+            info!("Output file {}", comment);
+            if let Err(err) = append_wav_comment(file, &comment) {
+                error!("Unable to write levels to wav file: {}", err);
+            }
+        }
+    }
+}
+
 /// Start a playback thread listening for AudioMessages via a channel.
 impl PlaybackDevice for FilePlaybackDevice {
     fn start(
@@ -112,15 +437,31 @@ impl PlaybackDevice for FilePlaybackDevice {
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let destination = self.destination.clone();
         let chunksize = self.chunksize;
+        let samplerate = self.samplerate;
         let channels = self.channels;
         let store_bytes_per_sample = self.sample_format.bytes_per_sample();
         let sample_format = self.sample_format.clone();
+        let mut levels = if self.wav_levels {
+            Some(LevelMeter::new(channels))
+        } else {
+            None
+        };
         let handle = thread::Builder::new()
             .name("FilePlayback".to_string())
             .spawn(move || {
+                // A separate handle to the file, used to update the wav header at the end
+                let mut wav_file = None;
                 let file_res: Result<Box<dyn Write>, std::io::Error> = match destination {
                     PlaybackDest::Filename(filename) => {
-                        File::create(filename).map(|f| Box::new(f) as Box<dyn Write>)
+                        File::create(&filename).and_then(|mut f| {
+                            if is_wav(&filename) {
+                                debug!("Writing wav header to '{}'", filename);
+                                let header = wav_header(channels, samplerate, &sample_format, 0);
+                                f.write_all(&header)?;
+                                wav_file = Some((f.try_clone()?, header.len() as u64));
+                            }
+                            Ok(Box::new(f) as Box<dyn Write>)
+                        })
                     }
                     PlaybackDest::Stdout => Ok(Box::new(stdout())),
                 };
@@ -134,6 +475,7 @@ impl PlaybackDevice for FilePlaybackDevice {
                         barrier.wait();
                         debug!("starting playback loop");
                         let mut buffer = vec![0u8; chunksize * channels * store_bytes_per_sample];
+                        let mut written_bytes: u64 = 0;
                         loop {
                             match channel.recv() {
                                 Ok(AudioMessage::Audio(chunk)) => {
@@ -142,18 +484,25 @@ impl PlaybackDevice for FilePlaybackDevice {
                                         &mut buffer,
                                         &sample_format,
                                     );
+                                    if wav_file.is_some() && sample_format == SampleFormat::S24LE {
+                                        left_justify_s24(&mut buffer[0..valid_bytes]);
+                                    }
                                     let write_res = file.write_all(&buffer[0..valid_bytes]);
                                     match write_res {
-                                        Ok(_) => {}
+                                        Ok(_) => {
+                                            written_bytes += valid_bytes as u64;
+                                        }
                                         Err(err) => {
                                             status_channel
                                                 .send(StatusMessage::PlaybackError(err.to_string()))
                                                 .unwrap_or(());
                                         }
                                     };
-                                    if nbr_clipped > 0 {
-                                        playback_status.write().unwrap().clipped_samples +=
-                                            nbr_clipped;
+                                    if nbr_clipped.total() > 0 {
+                                        playback_status.write().unwrap().add_clipped(&nbr_clipped);
+                                    }
+                                    if let Some(levels) = levels.as_mut() {
+                                        levels.add(&chunk);
                                     }
                                     chunk_stats = chunk.get_stats();
                                     playback_status.write().unwrap().signal_rms =
@@ -170,6 +519,7 @@ impl PlaybackDevice for FilePlaybackDevice {
                                     trace!("Pause message received");
                                 }
                                 Ok(AudioMessage::EndOfStream) => {
+                                    finish_output(&mut file, &mut wav_file, written_bytes, &levels);
                                     status_channel
                                         .send(StatusMessage::PlaybackDone)
                                         .unwrap_or(());
@@ -177,6 +527,7 @@ impl PlaybackDevice for FilePlaybackDevice {
                                 }
                                 Err(err) => {
                                     error!("Message channel error: {}", err);
+                                    finish_output(&mut file, &mut wav_file, written_bytes, &levels);
                                     status_channel
                                         .send(StatusMessage::PlaybackError(err.to_string()))
                                         .unwrap_or(());
@@ -221,13 +572,21 @@ fn get_nbr_capture_bytes(
 }
 
 fn build_chunk(buf: &[u8], params: &CaptureParams, bytes_read: usize) -> AudioChunk {
-    buffer_to_chunk_rawbytes(
+    let used_channels = device_used_channels(
+        &params.capture_status.read().unwrap().used_channels,
+        params.channels,
+    );
+    let mut chunk = buffer_to_chunk_rawbytes(
         buf,
         params.channels,
         &params.sample_format,
         bytes_read,
-        &params.capture_status.read().unwrap().used_channels,
-    )
+        &used_channels,
+    );
+    if params.pipeline_channels != params.channels {
+        chunk.adjust_channels(params.pipeline_channels);
+    }
+    chunk
 }
 
 fn get_capture_bytes(
@@ -277,6 +636,7 @@ fn capture_loop(
     let mut silence_counter = countertimer::SilenceCounter::new(
         params.silence_threshold,
         params.silence_timeout,
+        &params.silence_mode,
         params.capture_samplerate,
         params.chunksize,
     );
@@ -354,7 +714,7 @@ fn capture_loop(
                         extra_bytes_left / params.store_bytes_per_sample / params.channels;
                     send_silence(
                         extra_samples,
-                        params.channels,
+                        params.pipeline_channels,
                         params.chunksize,
                         &msg_channels.audio,
                     );
@@ -474,6 +834,15 @@ fn capture_loop(
         params.capture_status.write().unwrap().signal_rms = chunk_stats.rms_db();
         params.capture_status.write().unwrap().signal_peak = chunk_stats.peak_db();
         state = silence_counter.update(value_range);
+        if let Some(silent) = silence_counter.transition() {
+            msg_channels
+                .status
+                .send(StatusMessage::CaptureSilence {
+                    silent,
+                    time: SystemTime::now(),
+                })
+                .unwrap_or(());
+        }
         if state == ProcessingState::Running {
             if let Some(resampl) = &mut resampler {
                 let new_waves = resampl.process(&chunk.waveforms, None).unwrap();
@@ -520,8 +889,54 @@ impl CaptureDevice for FileCaptureDevice {
         let samplerate = self.samplerate;
         let chunksize = self.chunksize;
         let capture_samplerate = self.capture_samplerate;
-        let channels = self.channels;
-        let store_bytes_per_sample = self.sample_format.bytes_per_sample();
+        let pipeline_channels = self.channels;
+        // The number of channels in the file, that can differ from the pipeline for wav files
+        let mut channels = self.channels;
+        let mut sample_format = self.sample_format.clone();
+        let mut skip_bytes = self.skip_bytes;
+        let mut read_bytes = self.read_bytes;
+        if let CaptureSource::Filename(filename) = &source {
+            if is_wav(filename) {
+                // Skip and read limits are given relative to the start of the wav data
+                let (wav_format, params) = read_wav_header(
+                    filename,
+                    pipeline_channels,
+                    &self.channel_adjust,
+                    capture_samplerate,
+                )?;
+                debug!(
+                    "Capturing from wav file '{}', format: {:?}, data length: {} bytes",
+                    filename, wav_format, params.data_length
+                );
+                if params.channels != pipeline_channels {
+                    debug!(
+                        "Adjusting the {} channels of the file to {} channels",
+                        params.channels, pipeline_channels
+                    );
+                    channels = params.channels;
+                }
+                let data_left = params.data_length.saturating_sub(skip_bytes);
+                if read_bytes == 0 || read_bytes > data_left {
+                    read_bytes = data_left;
+                }
+                skip_bytes += params.data_offset;
+                sample_format = wav_format;
+            }
+        }
+        let store_bytes_per_sample = sample_format.bytes_per_sample();
+        // The start frame and length are counted from where reading would otherwise start
+        let start_bytes = self.start_frame * channels * store_bytes_per_sample;
+        if start_bytes > 0 {
+            debug!("Starting capture at frame {}", self.start_frame);
+            skip_bytes += start_bytes;
+            if read_bytes > 0 {
+                read_bytes = read_bytes.saturating_sub(start_bytes);
+            }
+        }
+        let length_bytes = self.length_frames * channels * store_bytes_per_sample;
+        if length_bytes > 0 && (read_bytes == 0 || length_bytes < read_bytes) {
+            read_bytes = length_bytes;
+        }
         let buffer_bytes = 2.0f32.powf(
             (capture_samplerate as f32 / samplerate as f32 * chunksize as f32)
                 .log2()
@@ -530,14 +945,12 @@ impl CaptureDevice for FileCaptureDevice {
             * 2
             * channels
             * store_bytes_per_sample;
-        let sample_format = self.sample_format.clone();
         let enable_resampling = self.enable_resampling;
         let resampler_conf = self.resampler_conf.clone();
         let async_src = resampler_is_async(&resampler_conf);
         let extra_bytes = self.extra_samples * store_bytes_per_sample * channels;
-        let skip_bytes = self.skip_bytes;
-        let read_bytes = self.read_bytes;
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = self.rate_measure_interval;
@@ -548,7 +961,7 @@ impl CaptureDevice for FileCaptureDevice {
                     debug!("Creating resampler");
                     get_resampler(
                         &resampler_conf,
-                        channels,
+                        pipeline_channels,
                         samplerate,
                         capture_samplerate,
                         chunksize,
@@ -558,12 +971,14 @@ impl CaptureDevice for FileCaptureDevice {
                 };
                 let params = CaptureParams {
                     channels,
+                    pipeline_channels,
                     sample_format,
                     store_bytes_per_sample,
                     extra_bytes,
                     buffer_bytes,
                     silence_threshold,
                     silence_timeout,
+                    silence_mode,
                     chunksize,
                     resampling_ratio: samplerate as f32 / capture_samplerate as f32,
                     read_bytes,
@@ -573,9 +988,12 @@ impl CaptureDevice for FileCaptureDevice {
                     stop_on_rate_change,
                     rate_measure_interval,
                 };
+                // Files are positioned by seeking, stdin can only skip by reading
+                let mut skip_by_reading = skip_bytes;
                 #[cfg(not(target_os = "linux"))]
                 let file_res: Result<Box<dyn Reader>, std::io::Error> = match source {
                     CaptureSource::Filename(filename) => File::open(filename)
+                        .and_then(|f| seek_to(f, skip_bytes, &mut skip_by_reading))
                         .map(|f| Box::new(BlockingReader::new(f)) as Box<dyn Reader>),
                     CaptureSource::Stdin => Ok(Box::new(BlockingReader::new(stdin()))),
                 };
@@ -585,6 +1003,7 @@ impl CaptureDevice for FileCaptureDevice {
                         .read(true)
                         .custom_flags(nix::libc::O_NONBLOCK)
                         .open(filename)
+                        .and_then(|f| seek_to(f, skip_bytes, &mut skip_by_reading))
                         .map(|f| {
                             Box::new(NonBlockingReader::new(
                                 f,
@@ -608,9 +1027,9 @@ impl CaptureDevice for FileCaptureDevice {
                             status: status_channel,
                             command: command_channel,
                         };
-                        if skip_bytes > 0 {
-                            debug!("skipping the first {} bytes", skip_bytes);
-                            let mut tempbuf = vec![0u8; skip_bytes];
+                        if skip_by_reading > 0 {
+                            debug!("skipping the first {} bytes", skip_by_reading);
+                            let mut tempbuf = vec![0u8; skip_by_reading];
                             let _ = file.read(&mut tempbuf);
                         }
 
@@ -661,3 +1080,548 @@ fn sleep_until_next(bytes_per_frame: usize, samplerate: usize, nbr_bytes: usize)
         thread::sleep(io_duration - Duration::from_millis(2));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::{
+        AudioChunk, AudioMessage, CaptureDevice, PlaybackDevice, PlaybackRamp,
+    };
+    use crate::config::{ChannelAdjust, FileFormat, Resampler, SampleFormat, SilenceMode};
+    use crate::conversions::chunk_to_buffer_rawbytes;
+    use crate::filedevice::{
+        capture_file_frames, is_wav, left_justify_s24, read_wav_header, update_wav_header,
+        wav_header, CaptureSource, FileCaptureDevice, FilePlaybackDevice, LevelMeter, PlaybackDest,
+    };
+    use crate::filters::{find_data_in_wav, read_wav};
+    use crate::PrcFmt;
+    use crate::{CaptureStatus, ClippedChannels, PlaybackStatus, ProcessingState, StatusMessage};
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Barrier, RwLock};
+    use std::thread;
+
+    /// Capture a mono S16LE file until the end of the stream, returning the number of valid frames.
+    fn capture_frames(
+        filename: &str,
+        samplerate: usize,
+        capture_samplerate: usize,
+        resampler_conf: Resampler,
+    ) -> usize {
+        capture_samples(
+            filename,
+            samplerate,
+            capture_samplerate,
+            resampler_conf,
+            0,
+            0,
+        )
+        .len()
+    }
+
+    /// Capture a mono S16LE file until the end of the stream, returning the valid samples.
+    fn capture_samples(
+        filename: &str,
+        samplerate: usize,
+        capture_samplerate: usize,
+        resampler_conf: Resampler,
+        start_frame: usize,
+        length_frames: usize,
+    ) -> Vec<PrcFmt> {
+        let device = FileCaptureDevice {
+            source: CaptureSource::Filename(filename.to_string()),
+            chunksize: 1024,
+            samplerate,
+            enable_resampling: samplerate != capture_samplerate,
+            capture_samplerate,
+            resampler_conf,
+            channels: 1,
+            channel_adjust: ChannelAdjust::Strict,
+            sample_format: SampleFormat::S16LE,
+            silence_threshold: 0.0,
+            silence_timeout: 0.0,
+            silence_mode: SilenceMode::Pause,
+            extra_samples: 0,
+            skip_bytes: 0,
+            read_bytes: 0,
+            start_frame,
+            length_frames,
+            stop_on_rate_change: false,
+            rate_measure_interval: 1.0,
+        };
+        let mut samples = Vec::new();
+        for chunk in capture_chunks(device) {
+            samples.extend_from_slice(&chunk.waveforms[0][0..chunk.valid_frames]);
+        }
+        samples
+    }
+
+    /// Run a capture device until the end of the stream, returning the captured chunks.
+    fn capture_chunks(mut device: FileCaptureDevice) -> Vec<AudioChunk> {
+        let (tx_audio, rx_audio) = mpsc::sync_channel(10);
+        let (tx_status, rx_status) = mpsc::channel();
+        let (_tx_command, rx_command) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let status = Arc::new(RwLock::new(CaptureStatus {
+            update_interval: 1000,
+            measured_samplerate: 0,
+            signal_range: 0.0,
+            signal_rms: Vec::new(),
+            signal_peak: Vec::new(),
+            state: ProcessingState::Running,
+            rate_adjust: 0.0,
+            used_channels: vec![true; device.channels],
+        }));
+        let handle = device
+            .start(tx_audio, barrier.clone(), tx_status, rx_command, status)
+            .unwrap();
+        barrier.wait();
+        let mut chunks = Vec::new();
+        loop {
+            match rx_audio.recv().unwrap() {
+                AudioMessage::Audio(chunk) => chunks.push(chunk),
+                AudioMessage::Pause => {}
+                AudioMessage::EndOfStream => break,
+            }
+        }
+        handle.join().unwrap();
+        assert!(matches!(
+            rx_status.recv().unwrap(),
+            StatusMessage::CaptureReady
+        ));
+        assert!(matches!(
+            rx_status.recv().unwrap(),
+            StatusMessage::CaptureDone
+        ));
+        chunks
+    }
+
+    #[test]
+    fn capture_until_end_of_file() {
+        // The file holds 5 samples, these should all arrive before the end of stream
+        let frames = capture_frames("testdata/int16.raw", 44100, 44100, Resampler::BalancedAsync);
+        assert_eq!(frames, 5);
+    }
+
+    #[test]
+    fn capture_from_start_frame() {
+        let path = std::env::temp_dir().join("camilladsp_test_start_frame.raw");
+        let data: Vec<u8> = (0..100i16).flat_map(|n| (256 * n).to_le_bytes()).collect();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let filename = path.to_str().unwrap();
+        assert_eq!(
+            capture_file_frames(
+                filename,
+                1,
+                &ChannelAdjust::Strict,
+                &SampleFormat::S16LE,
+                0,
+                0,
+                44100
+            )
+            .unwrap(),
+            100
+        );
+        let samples = capture_samples(filename, 44100, 44100, Resampler::BalancedAsync, 10, 30);
+        let expected = (10..40)
+            .map(|n| n as PrcFmt / 128.0)
+            .collect::<Vec<PrcFmt>>();
+        let all = capture_samples(filename, 44100, 44100, Resampler::BalancedAsync, 90, 0);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, expected);
+        assert_eq!(all.len(), 10);
+    }
+
+    /// Capture a number of seconds of audio at 44.1 kHz, resampled to 48 kHz.
+    /// Returns the number of frames missing compared to the ideal length.
+    fn resampled_shortfall(seconds: usize, resampler_conf: Resampler) -> isize {
+        let path = std::env::temp_dir().join(format!("camilladsp_test_resampled_{}.raw", seconds));
+        File::create(&path)
+            .unwrap()
+            .write_all(&vec![0x10u8; 2 * 44100 * seconds])
+            .unwrap();
+        let frames = capture_frames(path.to_str().unwrap(), 48000, 44100, resampler_conf);
+        std::fs::remove_file(&path).unwrap();
+        (48000 * seconds) as isize - frames as isize
+    }
+
+    #[test]
+    fn capture_resampled_length() {
+        // The only frames missing at the end should be the ones still in the resampler delay line,
+        // this must not grow with the length of the recording.
+        let short = resampled_shortfall(1, Resampler::BalancedAsync);
+        let long = resampled_shortfall(10, Resampler::BalancedAsync);
+        assert!(
+            (1..100).contains(&short),
+            "unexpected shortfall for 1 s: {}",
+            short
+        );
+        assert!(
+            (long - short).abs() <= 1,
+            "shortfall grew from {} for 1 s to {} for 10 s",
+            short,
+            long
+        );
+    }
+
+    #[test]
+    fn check_is_wav() {
+        assert!(is_wav("some/path/file.wav"));
+        assert!(is_wav("FILE.WAV"));
+        assert!(!is_wav("file.raw"));
+        assert!(!is_wav("wav"));
+    }
+
+    #[test]
+    fn check_wav_header() {
+        let strict = ChannelAdjust::Strict;
+        let (format, params) = read_wav_header("testdata/int32.wav", 1, &strict, 44100).unwrap();
+        assert_eq!(format, SampleFormat::S32LE);
+        assert_eq!(params.data_offset, 44);
+        assert_eq!(params.data_length, 20);
+        assert!(read_wav_header("testdata/int32.wav", 2, &strict, 44100).is_err());
+        assert!(read_wav_header("testdata/int32.wav", 1, &strict, 48000).is_err());
+        assert!(read_wav_header("testdata/int32.wav", 2, &ChannelAdjust::Pad, 44100).is_ok());
+        assert!(read_wav_header("testdata/int32.wav", 2, &ChannelAdjust::Truncate, 44100).is_err());
+    }
+
+    fn wav_capture_device(
+        filename: &str,
+        channels: usize,
+        channel_adjust: ChannelAdjust,
+    ) -> FileCaptureDevice {
+        FileCaptureDevice {
+            source: CaptureSource::Filename(filename.to_string()),
+            chunksize: 1024,
+            samplerate: 44100,
+            enable_resampling: false,
+            capture_samplerate: 44100,
+            resampler_conf: Resampler::BalancedAsync,
+            channels,
+            channel_adjust,
+            sample_format: SampleFormat::S16LE,
+            silence_threshold: 0.0,
+            silence_timeout: 0.0,
+            silence_mode: SilenceMode::Pause,
+            extra_samples: 0,
+            skip_bytes: 0,
+            read_bytes: 0,
+            start_frame: 0,
+            length_frames: 0,
+            stop_on_rate_change: false,
+            rate_measure_interval: 1.0,
+        }
+    }
+
+    #[test]
+    fn capture_wav_channel_adjust() {
+        // A stereo wav file with 4 frames, with a negated copy of the left channel on the right
+        let path = std::env::temp_dir().join("camilladsp_test_channel_adjust.wav");
+        let mut data = wav_header(2, 44100, &SampleFormat::S16LE, 16);
+        for n in 1..5i16 {
+            data.extend_from_slice(&(256 * n).to_le_bytes());
+            data.extend_from_slice(&(-256 * n).to_le_bytes());
+        }
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let filename = path.to_str().unwrap();
+        let left: Vec<PrcFmt> = (1..5).map(|n| n as PrcFmt / 128.0).collect();
+
+        let chunks = capture_chunks(wav_capture_device(filename, 1, ChannelAdjust::Truncate));
+        assert_eq!(chunks[0].channels, 1);
+        assert_eq!(chunks[0].waveforms[0][0..4], left[..]);
+
+        let chunks = capture_chunks(wav_capture_device(filename, 3, ChannelAdjust::Pad));
+        assert_eq!(chunks[0].channels, 3);
+        assert_eq!(chunks[0].waveforms.len(), 3);
+        assert_eq!(chunks[0].waveforms[0][0..4], left[..]);
+        assert_eq!(chunks[0].waveforms[1][0], -1.0 / 128.0);
+        assert!(chunks[0].waveforms[2].iter().all(|value| *value == 0.0));
+
+        let mut device = wav_capture_device(filename, 3, ChannelAdjust::Truncate);
+        let (tx_audio, _rx_audio) = mpsc::sync_channel(10);
+        let (tx_status, _rx_status) = mpsc::channel();
+        let (_tx_command, rx_command) = mpsc::channel();
+        let status = Arc::new(RwLock::new(CaptureStatus {
+            update_interval: 1000,
+            measured_samplerate: 0,
+            signal_range: 0.0,
+            signal_rms: Vec::new(),
+            signal_peak: Vec::new(),
+            state: ProcessingState::Running,
+            rate_adjust: 0.0,
+            used_channels: vec![true; 3],
+        }));
+        let barrier = Arc::new(Barrier::new(1));
+        assert!(device
+            .start(tx_audio, barrier, tx_status, rx_command, status)
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn left_justified_24_bit_wav() {
+        // A spec-conformant file, with 24 valid bits in the top of each 32 bit container
+        let path = std::env::temp_dir().join("camilladsp_test_24in32.wav");
+        let values: [i32; 4] = [0x400000, -0x200000, 0x7FFFFF, -0x800000];
+        let mut data = Vec::new();
+        for value in values.iter() {
+            data.extend_from_slice(&(value << 8).to_le_bytes());
+        }
+        let header = wav_header(2, 48000, &SampleFormat::S24LE, data.len() as u32);
+        assert_eq!(header.len(), 68);
+        assert_eq!(header[34..36], 32u16.to_le_bytes());
+        assert_eq!(header[38..40], 24u16.to_le_bytes());
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&header).unwrap();
+        file.write_all(&data).unwrap();
+        drop(file);
+        let filename = path.to_str().unwrap();
+        let params = find_data_in_wav(filename).unwrap();
+        assert_eq!(params.sample_format, FileFormat::S32LE);
+        let left = read_wav(filename, 0).unwrap();
+        let right = read_wav(filename, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((left[0] - 0.5).abs() < 1e-6);
+        assert!((right[0] + 0.25).abs() < 1e-6);
+        assert!((left[1] - 1.0).abs() < 1e-6);
+        assert!((right[1] + 1.0).abs() < 1e-6);
+
+        // Samples converted to S24LE give the same data after justifying
+        let chunk = AudioChunk::new(vec![vec![0.5], vec![-0.25]], 0.5, -0.25, 1, 1);
+        let mut buffer = vec![0u8; 8];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S24LE);
+        left_justify_s24(&mut buffer);
+        assert_eq!(buffer, data[0..8]);
+    }
+
+    #[test]
+    fn write_wav_header() {
+        let path = std::env::temp_dir().join("camilladsp_test_write_header.wav");
+        let mut file = File::create(&path).unwrap();
+        let header = wav_header(2, 96000, &SampleFormat::FLOAT32LE, 0);
+        assert_eq!(header.len(), 44);
+        file.write_all(&header).unwrap();
+        file.write_all(&[0u8; 64]).unwrap();
+        update_wav_header(&mut file, 44, 64).unwrap();
+        drop(file);
+        let params = find_data_in_wav(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(params.sample_format, FileFormat::FLOAT32LE);
+        assert_eq!(params.sample_rate, 96000);
+        assert_eq!(params.channels, 2);
+        assert_eq!(params.data_offset, 44);
+        assert_eq!(params.data_length, 64);
+    }
+
+    /// Start playback to a wav file at 48 kHz,
+    /// returns the sender for the audio messages and the handle of the playback thread.
+    fn start_file_playback(
+        filename: &str,
+        chunksize: usize,
+        channels: usize,
+        sample_format: SampleFormat,
+        wav_levels: bool,
+    ) -> (mpsc::SyncSender<AudioMessage>, Box<thread::JoinHandle<()>>) {
+        let mut device = FilePlaybackDevice {
+            destination: PlaybackDest::Filename(filename.to_string()),
+            chunksize,
+            samplerate: 48000,
+            channels,
+            sample_format,
+            wav_levels,
+        };
+        let (tx_audio, rx_audio) = mpsc::sync_channel(10);
+        let (tx_status, _rx_status) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let status = Arc::new(RwLock::new(PlaybackStatus {
+            update_interval: 1000,
+            clipped_samples: 0,
+            clipped_channels: ClippedChannels::default(),
+            buffer_level: 0,
+            signal_rms: Vec::new(),
+            signal_peak: Vec::new(),
+        }));
+        let handle = device
+            .start(rx_audio, barrier.clone(), tx_status, status)
+            .unwrap();
+        barrier.wait();
+        (tx_audio, handle)
+    }
+
+    #[test]
+    fn write_multichannel_wav() {
+        let path = std::env::temp_dir().join("camilladsp_test_multichannel.wav");
+        let filename = path.to_str().unwrap().to_string();
+        let channels = 6;
+        let (tx_audio, handle) =
+            start_file_playback(&filename, 256, channels, SampleFormat::FLOAT32LE, false);
+        // A different tone in each channel
+        let tones: Vec<Vec<PrcFmt>> = (0..channels)
+            .map(|ch| {
+                (0..512)
+                    .map(|n| {
+                        0.1 * (ch + 1) as PrcFmt * (0.01 * (ch + 1) as PrcFmt * n as PrcFmt).sin()
+                    })
+                    .collect()
+            })
+            .collect();
+        for start in [0, 256] {
+            let waveforms = tones
+                .iter()
+                .map(|tone| tone[start..start + 256].to_vec())
+                .collect();
+            let chunk = AudioChunk::new(waveforms, 1.0, -1.0, 256, 256);
+            tx_audio.send(AudioMessage::Audio(chunk)).unwrap();
+        }
+        tx_audio.send(AudioMessage::EndOfStream).unwrap();
+        handle.join().unwrap();
+
+        let params = find_data_in_wav(&filename).unwrap();
+        assert_eq!(params.sample_format, FileFormat::FLOAT32LE);
+        assert_eq!(params.channels, channels);
+        assert_eq!(params.data_offset, 68);
+        assert_eq!(params.data_length, 512 * channels * 4);
+        let header = std::fs::read(&path).unwrap();
+        assert_eq!(header[40..44], 0x3Fu32.to_le_bytes());
+        for (ch, tone) in tones.iter().enumerate() {
+            let data = read_wav(&filename, ch).unwrap();
+            assert_eq!(data.len(), tone.len());
+            for (read, expected) in data.iter().zip(tone.iter()) {
+                assert!((read - expected).abs() < 1e-6);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_wav_levels() {
+        let path = std::env::temp_dir().join("camilladsp_test_wav_levels.wav");
+        let filename = path.to_str().unwrap().to_string();
+        let (tx_audio, handle) = start_file_playback(&filename, 256, 2, SampleFormat::S16LE, true);
+        let chunk = AudioChunk::new(vec![vec![0.5; 256], vec![-0.25; 256]], 0.5, -0.25, 256, 256);
+        tx_audio.send(AudioMessage::Audio(chunk)).unwrap();
+        tx_audio.send(AudioMessage::EndOfStream).unwrap();
+        handle.join().unwrap();
+
+        let params = find_data_in_wav(&filename).unwrap();
+        assert_eq!(params.data_length, 256 * 2 * 2);
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let riff_length = u32::from_le_bytes(contents[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_length, contents.len() - 8);
+        let list = &contents[params.data_offset + params.data_length..];
+        assert_eq!(&list[0..4], b"LIST");
+        assert_eq!(&list[8..16], b"INFOICMT");
+        let comment = String::from_utf8_lossy(&list[20..]);
+        assert!(comment.contains("peak dBFS: -6.02, -12.04;"));
+        assert!(comment.contains("RMS dBFS: -6.02, -12.04"));
+    }
+
+    #[test]
+    fn levels_of_clipped_samples() {
+        // The file can't hold samples outside +-1.0, the levels must not exceed 0 dBFS
+        let mut levels = LevelMeter::new(2);
+        let chunk = AudioChunk::new(vec![vec![2.0, -2.0], vec![0.5, -4.0]], 2.0, -4.0, 2, 2);
+        levels.add(&chunk);
+        let stats = levels.stats();
+        assert_eq!(stats.peak, vec![1.0, 1.0]);
+        assert_eq!(stats.rms[0], 1.0);
+        assert!((stats.rms[1] - (1.25 as PrcFmt / 2.0).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fade_out_at_end_of_stream() {
+        let path = std::env::temp_dir().join("camilladsp_test_fadeout.wav");
+        let filename = path.to_str().unwrap().to_string();
+        let (tx_audio, handle) =
+            start_file_playback(&filename, 480, 2, SampleFormat::FLOAT32LE, false);
+        // Fade out over the last 5 ms, 240 frames
+        let mut ramp = PlaybackRamp::new(48000, 0, 5);
+        for _ in 0..3 {
+            let chunk = AudioChunk::new(vec![vec![0.5; 480]; 2], 1.0, -1.0, 480, 480);
+            if let Some(chunk) = ramp.process(chunk) {
+                tx_audio.send(AudioMessage::Audio(chunk)).unwrap();
+            }
+        }
+        tx_audio
+            .send(AudioMessage::Audio(ramp.finish().unwrap()))
+            .unwrap();
+        tx_audio.send(AudioMessage::EndOfStream).unwrap();
+        handle.join().unwrap();
+
+        for ch in 0..2 {
+            let data = read_wav(&filename, ch).unwrap();
+            assert_eq!(data.len(), 3 * 480);
+            assert!((data[1199] - 0.5).abs() < 1e-6);
+            for pair in data[1200..].windows(2) {
+                assert!(pair[1] < pair[0]);
+            }
+            assert!((data[1320] - 0.25).abs() < 0.01);
+            assert_eq!(data[3 * 480 - 1], 0.0);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fade_out_holds_back_one_chunk() {
+        let path = std::env::temp_dir().join("camilladsp_test_fadeout_holdback.wav");
+        let filename = path.to_str().unwrap().to_string();
+        let (tx_audio, handle) =
+            start_file_playback(&filename, 480, 1, SampleFormat::FLOAT32LE, false);
+        let mut ramp = PlaybackRamp::new(48000, 0, 5);
+        let make_chunk = |value| AudioChunk::new(vec![vec![value; 480]], 1.0, -1.0, 480, 480);
+        // The first chunk is held back until the second one arrives
+        assert!(ramp.process(make_chunk(0.1)).is_none());
+        let chunk = ramp.process(make_chunk(0.2)).unwrap();
+        assert_eq!(chunk.waveforms[0][0], 0.1);
+        tx_audio.send(AudioMessage::Audio(chunk)).unwrap();
+        // A pause sends the held back chunk without a fade
+        tx_audio
+            .send(AudioMessage::Audio(ramp.flush().unwrap()))
+            .unwrap();
+        tx_audio.send(AudioMessage::Pause).unwrap();
+        assert!(ramp.process(make_chunk(0.3)).is_none());
+        tx_audio
+            .send(AudioMessage::Audio(ramp.finish().unwrap()))
+            .unwrap();
+        tx_audio.send(AudioMessage::EndOfStream).unwrap();
+        handle.join().unwrap();
+
+        let data = read_wav(&filename, 0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 3 * 480);
+        assert!((data[479] - 0.1).abs() < 1e-6);
+        assert!((data[959] - 0.2).abs() < 1e-6);
+        assert!((data[960] - 0.3).abs() < 1e-6);
+        assert_eq!(data[3 * 480 - 1], 0.0);
+    }
+
+    #[test]
+    fn file_length_matches_frames() {
+        // End with an EndOfStream message, and with the channel closing without one
+        for (name, send_end) in [("eos", true), ("closed", false)] {
+            let path = std::env::temp_dir().join(format!("camilladsp_test_length_{}.wav", name));
+            let filename = path.to_str().unwrap().to_string();
+            let (tx_audio, handle) =
+                start_file_playback(&filename, 256, 2, SampleFormat::S16LE, false);
+            // Two full chunks and a partially filled last one
+            for valid_frames in [256, 256, 100] {
+                let chunk = AudioChunk::new(vec![vec![0.25; 256]; 2], 1.0, -1.0, 256, valid_frames);
+                tx_audio.send(AudioMessage::Audio(chunk)).unwrap();
+            }
+            if send_end {
+                tx_audio.send(AudioMessage::EndOfStream).unwrap();
+            }
+            drop(tx_audio);
+            handle.join().unwrap();
+
+            let frames = 256 + 256 + 100;
+            let params = find_data_in_wav(&filename).unwrap();
+            assert_eq!(params.data_length, frames * 2 * 2);
+            let file_length = std::fs::metadata(&path).unwrap().len() as usize;
+            assert_eq!(file_length, params.data_offset + frames * 2 * 2);
+            assert_eq!(read_wav(&filename, 1).unwrap().len(), frames);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
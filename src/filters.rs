@@ -2,28 +2,41 @@ use crate::audiodevice::AudioChunk;
 use crate::basicfilters;
 use crate::biquad;
 use crate::biquadcombo;
+use crate::compressor;
 use crate::config;
 use crate::conversions;
 use crate::diffeq;
 use crate::dither;
+use crate::fft::{DefaultFft, RealFft};
 #[cfg(not(feature = "FFTW"))]
 use crate::fftconv;
 #[cfg(feature = "FFTW")]
 use crate::fftconv_fftw as fftconv;
+use crate::limiter;
 use crate::loudness;
 use crate::mixer;
+use crate::saturation;
 use rawsample::SampleReader;
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::{BufRead, Read, Seek, SeekFrom};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::PrcFmt;
 use crate::ProcessingParameters;
 use crate::Res;
 
+/// Conv filters needing more segments than this per chunk give a warning.
+const MAX_CONV_SEGMENTS: usize = 1000;
+
+/// Largest number of coefficients of the combined kernel of a cascaded Conv filter,
+/// about 22 seconds at 48 kHz.
+pub const MAX_CASCADE_LENGTH: usize = 1 << 20;
+
 /// Windows Guid
 /// Used to give sample format in the extended WAVEFORMATEXTENSIBLE wav header
 #[derive(Debug, PartialEq, Eq)]
@@ -67,14 +80,14 @@ const SUBTYPE_PCM: Guid = Guid {
 
 #[derive(Debug)]
 pub struct WavParams {
-    sample_format: config::FileFormat,
-    sample_rate: usize,
-    data_offset: usize,
-    data_length: usize,
-    channels: usize,
+    pub sample_format: config::FileFormat,
+    pub sample_rate: usize,
+    pub data_offset: usize,
+    pub data_length: usize,
+    pub channels: usize,
 }
 
-pub trait Filter {
+pub trait Filter: Send {
     // Filter a Vec
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()>;
 
@@ -83,6 +96,187 @@ pub trait Filter {
     fn name(&self) -> String;
 }
 
+/// Get the gain of a filter kernel in the way used by the given normalization.
+/// For Dc this is the sum of the coefficients, and for Peak the largest magnitude
+/// of the frequency response, evaluated with zero padding to a fine frequency grid.
+pub fn normalization_gain(values: &[PrcFmt], normalize: &config::ConvNormalization) -> PrcFmt {
+    match normalize {
+        config::ConvNormalization::Dc => values.iter().sum::<PrcFmt>().abs(),
+        config::ConvNormalization::Peak => {
+            let fft_len = (8 * values.len()).max(1024).next_power_of_two();
+            let mut fft = DefaultFft::new(fft_len);
+            let mut input = fft.make_input_vec();
+            input[0..values.len()].copy_from_slice(values);
+            let mut spectrum = fft.make_output_vec();
+            fft.forward(&mut input, &mut spectrum).unwrap();
+            spectrum
+                .iter()
+                .fold(0.0, |max: PrcFmt, val| max.max(val.norm()))
+        }
+    }
+}
+
+/// Get the coefficients of a Conv filter given as values in the config,
+/// padded with zeros to `length` and normalized if requested.
+pub fn values_coeffs(
+    values: &[PrcFmt],
+    length: usize,
+    normalize: &Option<config::ConvNormalization>,
+) -> Vec<PrcFmt> {
+    let mut coeffs = pad_vector(values, length);
+    if let Some(normalize) = normalize {
+        let gain = normalization_gain(&coeffs, normalize);
+        debug!(
+            "Normalizing Conv coefficients by {:?}, gain: {}",
+            normalize, gain
+        );
+        for coeff in coeffs.iter_mut() {
+            *coeff /= gain;
+        }
+    }
+    coeffs
+}
+
+/// Load the coefficients of a Conv filter.
+/// The `position` is the place of the channel among the channels of the filter step,
+/// and selects the file when the filter has one file per channel.
+pub fn load_conv_coeffs(conf: &config::ConvParameters, position: usize) -> Res<Vec<PrcFmt>> {
+    match conf {
+        config::ConvParameters::Values {
+            values,
+            length,
+            normalize,
+        } => Ok(values_coeffs(values, *length, normalize)),
+        config::ConvParameters::Raw {
+            filename,
+            format,
+            read_bytes_lines,
+            skip_bytes_lines,
+        } => read_coeff_file(
+            filename.for_position(position),
+            format,
+            *read_bytes_lines,
+            *skip_bytes_lines,
+        ),
+        config::ConvParameters::Wav { filename, channel } => {
+            read_wav(filename.for_position(position), *channel)
+        }
+        config::ConvParameters::Cascade { parts } => {
+            let mut combined: Option<Vec<PrcFmt>> = None;
+            for part in parts.iter() {
+                let coeffs = load_conv_coeffs(part, position)?;
+                combined = Some(match combined {
+                    Some(previous) => convolve_kernels(&previous, &coeffs),
+                    None => coeffs,
+                });
+            }
+            let combined = combined.unwrap_or_default();
+            debug!(
+                "Convolved {} Conv kernels into one with {} coefficients",
+                parts.len(),
+                combined.len()
+            );
+            Ok(combined)
+        }
+    }
+}
+
+/// Convolve two filter kernels, giving a single kernel with the same effect as applying them in series.
+/// The result has `first.len() + second.len() - 1` coefficients.
+pub fn convolve_kernels(first: &[PrcFmt], second: &[PrcFmt]) -> Vec<PrcFmt> {
+    if first.is_empty() || second.is_empty() {
+        return Vec::new();
+    }
+    let len = first.len() + second.len() - 1;
+    let fft_len = len.next_power_of_two().max(2);
+    let mut fft = DefaultFft::new(fft_len);
+    let mut spectrums = [first, second].map(|kernel| {
+        let mut input = fft.make_input_vec();
+        input[0..kernel.len()].copy_from_slice(kernel);
+        let mut spectrum = fft.make_output_vec();
+        fft.forward(&mut input, &mut spectrum).unwrap();
+        spectrum
+    });
+    let [product, other] = &mut spectrums;
+    for (value, other_value) in product.iter_mut().zip(other.iter()) {
+        *value *= other_value;
+    }
+    let mut output = fft.make_input_vec();
+    fft.inverse(product, &mut output).unwrap();
+    output.truncate(len);
+    let scale = 1.0 / fft_len as PrcFmt;
+    for value in output.iter_mut() {
+        *value *= scale;
+    }
+    output
+}
+
+/// Validate the parts of a cascaded Conv filter, and get the length of the combined kernel.
+/// Each part is validated on its own by `validate_part`, that returns the length of the part.
+/// The combined length is calculated from these, without loading and convolving the parts.
+/// Wav files of the parts must have the same sample rate,
+/// and the parts with one file per channel must have the same number of files.
+pub fn validate_conv_cascade(
+    parts: &[config::ConvParameters],
+    validate_part: impl Fn(&config::ConvParameters) -> Res<usize>,
+) -> Res<usize> {
+    if parts.is_empty() {
+        return Err(config::ConfigError::new("Conv cascade has no parts").into());
+    }
+    let mut wav_samplerate: Option<(usize, &str)> = None;
+    let mut nbr_files: Option<usize> = None;
+    let mut parts_len = 0;
+    for part in parts.iter() {
+        if let config::ConvParameters::Cascade { .. } = part {
+            return Err(config::ConfigError::new("Conv cascades can't be nested").into());
+        }
+        parts_len += validate_part(part)?;
+        if let config::ConvParameters::Wav { filename, .. } = part {
+            for position in 0..filename.len() {
+                let file = filename.for_position(position);
+                let samplerate = find_data_in_wav(file)?.sample_rate;
+                match wav_samplerate {
+                    Some((first_rate, first_file)) if first_rate != samplerate => {
+                        let msg = format!(
+                            "The parts of a Conv cascade must have the same sample rate, '{}' is {} Hz but '{}' is {} Hz",
+                            first_file, first_rate, file, samplerate
+                        );
+                        return Err(config::ConfigError::new(&msg).into());
+                    }
+                    Some(_) => {}
+                    None => wav_samplerate = Some((samplerate, file)),
+                }
+            }
+        }
+        for filename in part.coeff_filenames() {
+            if let config::CoeffFilename::PerChannel(files) = filename {
+                match nbr_files {
+                    Some(nbr) if nbr != files.len() => {
+                        let msg = format!(
+                            "The parts of a Conv cascade have different numbers of coefficient files, {} and {}",
+                            nbr,
+                            files.len()
+                        );
+                        return Err(config::ConfigError::new(&msg).into());
+                    }
+                    Some(_) => {}
+                    None => nbr_files = Some(files.len()),
+                }
+            }
+        }
+    }
+    // Convolving kernels of lengths a and b gives a kernel of length a + b - 1
+    let coeffs_len = parts_len + 1 - parts.len();
+    if coeffs_len > MAX_CASCADE_LENGTH {
+        let msg = format!(
+            "The combined kernel of a Conv cascade has {} coefficients, max is {}",
+            coeffs_len, MAX_CASCADE_LENGTH
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(coeffs_len)
+}
+
 pub fn pad_vector(values: &[PrcFmt], length: usize) -> Vec<PrcFmt> {
     let new_len = if values.len() > length {
         values.len()
@@ -94,6 +288,165 @@ pub fn pad_vector(values: &[PrcFmt], length: usize) -> Vec<PrcFmt> {
     new_values
 }
 
+/// Warn if a Conv filter is so long compared to the chunksize
+/// that each chunk needs a very large number of filter segments.
+pub fn check_conv_length(coeffs_len: usize, chunksize: usize) {
+    let nbr_segments = (coeffs_len + chunksize - 1) / chunksize;
+    if nbr_segments > MAX_CONV_SEGMENTS {
+        warn!(
+            "Conv filter has {} coefficients, this needs {} segments at chunksize {}. Consider a larger chunksize.",
+            coeffs_len, nbr_segments, chunksize
+        );
+    }
+}
+
+/// Check if a length only has the prime factors 2, 3, 5 and 7, that the FFT handles efficiently.
+pub fn is_good_fft_size(len: usize) -> bool {
+    if len == 0 {
+        return false;
+    }
+    let mut rest = len;
+    for factor in [2, 3, 5, 7] {
+        while rest % factor == 0 {
+            rest /= factor;
+        }
+    }
+    rest == 1
+}
+
+/// Find the efficient FFT size closest to a length, picking the smaller one on a tie.
+pub fn nearest_good_fft_size(len: usize) -> usize {
+    (0..)
+        .find_map(|diff| {
+            if len > diff && is_good_fft_size(len - diff) {
+                Some(len - diff)
+            } else if is_good_fft_size(len + diff) {
+                Some(len + diff)
+            } else {
+                None
+            }
+        })
+        .unwrap()
+}
+
+/// Get the FFT length used for a chunksize.
+/// This is twice the chunksize, zero padded to the next even efficient size if needed.
+pub fn fft_len_for_chunksize(chunksize: usize) -> usize {
+    (2 * chunksize..)
+        .step_by(2)
+        .find(|len| is_good_fft_size(*len))
+        .unwrap()
+}
+
+/// Warn if the chunksize is a poor FFT size, that would make Conv filters much slower.
+pub fn check_fft_size(chunksize: usize) {
+    if !is_good_fft_size(chunksize) {
+        warn!(
+            "Chunksize {} is not an efficient FFT size, Conv filters will pad the FFT to {} points. Consider chunksize {} instead.",
+            chunksize,
+            fft_len_for_chunksize(chunksize),
+            nearest_good_fft_size(chunksize)
+        );
+    }
+}
+
+/// Get the shelving Biquad of a ShelfGain filter, with the steepest slope that
+/// doesn't overshoot, 12 dB/oct.
+pub fn shelf_gain_biquad(conf: &config::ShelfGainParameters) -> config::BiquadParameters {
+    let steepness = config::ShelfSteepness::Slope {
+        freq: conf.freq,
+        slope: 12.0,
+        gain: conf.gain_db,
+    };
+    match conf.kind {
+        config::ShelfKind::Low => config::BiquadParameters::Lowshelf(steepness),
+        config::ShelfKind::High => config::BiquadParameters::Highshelf(steepness),
+    }
+}
+
+/// Read a biquad cascade from a text file in the format exported by REW and Rephase.
+/// Each biquad starts with a `biquadN,` line, followed by `b0=`, `b1=`, `b2=`, `a1=` and `a2=` lines.
+/// The files use the opposite sign for `a1` and `a2` compared to the Biquad filter,
+/// these are converted to Free biquad parameters.
+pub fn read_biquad_file(filename: &str) -> Res<Vec<config::BiquadParameters>> {
+    let text = match std::fs::read_to_string(filename) {
+        Ok(text) => text,
+        Err(err) => {
+            let msg = format!("Could not open biquad file '{}'. Error: {}", filename, err);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    };
+    parse_biquad_text(&text).map_err(|err| {
+        let msg = format!("Biquad file '{}': {}", filename, err);
+        config::ConfigError::new(&msg).into()
+    })
+}
+
+fn parse_biquad_text(text: &str) -> Result<Vec<config::BiquadParameters>, String> {
+    let mut stages: Vec<[Option<PrcFmt>; 5]> = Vec::new();
+    let tokens = text
+        .split([',', '\n'])
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        if token.to_lowercase().starts_with("biquad") {
+            stages.push([None; 5]);
+            continue;
+        }
+        let (key, value) = match token.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(format!("Can't parse '{}'", token)),
+        };
+        let idx = match key {
+            "b0" => 0,
+            "b1" => 1,
+            "b2" => 2,
+            "a1" => 3,
+            "a2" => 4,
+            _ => return Err(format!("Unknown coefficient '{}'", key)),
+        };
+        let stage = match stages.last_mut() {
+            Some(stage) => stage,
+            None => {
+                return Err(format!(
+                    "Coefficient '{}' found before the first biquad",
+                    key
+                ))
+            }
+        };
+        let value: PrcFmt = match value.parse() {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Can't parse value '{}' of {}: {}", value, key, err)),
+        };
+        if !value.is_finite() {
+            return Err(format!("Coefficient {} must be a finite number", key));
+        }
+        stage[idx] = Some(value);
+    }
+    if stages.is_empty() {
+        return Err("No biquads found".to_string());
+    }
+    stages
+        .iter()
+        .enumerate()
+        .map(|(n, coeffs)| match coeffs {
+            [Some(b0), Some(b1), Some(b2), Some(a1), Some(a2)] => {
+                Ok(config::BiquadParameters::Free {
+                    a1: -a1,
+                    a2: -a2,
+                    b0: *b0,
+                    b1: *b1,
+                    b2: *b2,
+                })
+            }
+            _ => Err(format!(
+                "Biquad {} must have all five coefficients b0, b1, b2, a1 and a2",
+                n + 1
+            )),
+        })
+        .collect()
+}
+
 pub fn read_coeff_file(
     filename: &str,
     format: &config::FileFormat,
@@ -154,6 +507,18 @@ pub fn read_coeff_file(
         }
         // All other formats
         _ => {
+            let data_bytes = (f.metadata()?.len() as usize)
+                .saturating_sub(skip_bytes_lines)
+                .min(read_bytes_lines);
+            if data_bytes % format.bytes_per_sample() > 0 {
+                let msg = format!(
+                    "Coefficient file '{}' is truncated, the data length of {} bytes is not a multiple of the {} byte sample size",
+                    filename,
+                    data_bytes,
+                    format.bytes_per_sample()
+                );
+                return Err(config::ConfigError::new(&msg).into());
+            }
             file.seek(SeekFrom::Start(skip_bytes_lines as u64))?;
             let rawformat = conversions::map_file_formats(format);
             let mut nextvalue = vec![0.0; 1];
@@ -257,6 +622,8 @@ pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
                         (SUBTYPE_PCM, 16, 2, 16) => config::FileFormat::S16LE,
                         (SUBTYPE_PCM, 24, 3, 24) => config::FileFormat::S24LE3,
                         (SUBTYPE_PCM, 24, 4, 24) => config::FileFormat::S24LE,
+                        // The valid bits are left-justified, so this reads as 32 bit samples
+                        (SUBTYPE_PCM, 32, 4, 24) => config::FileFormat::S32LE,
                         (SUBTYPE_PCM, 32, 4, 32) => config::FileFormat::S32LE,
                         (SUBTYPE_FLOAT, 32, 4, 32) => config::FileFormat::FLOAT32LE,
                         (SUBTYPE_FLOAT, 64, 8, 64) => config::FileFormat::FLOAT64LE,
@@ -340,12 +707,23 @@ pub fn read_wav(filename: &str, channel: usize) -> Res<Vec<PrcFmt>> {
 pub struct FilterGroup {
     channel: usize,
     filters: Vec<Box<dyn Filter>>,
+    names: Vec<String>,
+    bypassed: Vec<bool>,
+    /// The bypass of each filter, with the runtime bypasses applied.
+    active_bypass: Vec<bool>,
+    /// The value of the bypass change counter when `active_bypass` was updated,
+    /// None if it must be updated before the next chunk.
+    bypass_generation: Option<usize>,
+    bypass_changes: Arc<AtomicUsize>,
+    processing_status: Arc<RwLock<ProcessingParameters>>,
 }
 
 impl FilterGroup {
     /// Creates a group of filters to process a chunk.
+    /// The `position` is the place of the channel among the channels of the filter step.
     pub fn from_config(
         channel: usize,
+        position: usize,
         names: Vec<String>,
         filter_configs: HashMap<String, config::Filter>,
         waveform_length: usize,
@@ -354,30 +732,32 @@ impl FilterGroup {
     ) -> Self {
         debug!("Build from config");
         let mut filters = Vec::<Box<dyn Filter>>::new();
-        for name in names {
+        let bypassed: Vec<bool> = names
+            .iter()
+            .map(|name| filter_configs[name].is_bypassed())
+            .collect();
+        for name in names.iter().cloned() {
             let filter_cfg = filter_configs[&name].clone();
             let filter: Box<dyn Filter> =
                 match filter_cfg {
-                    config::Filter::Conv { parameters } => Box::new(fftconv::FftConv::from_config(
-                        name,
-                        waveform_length,
-                        parameters,
-                    )),
-                    config::Filter::Biquad { parameters } => Box::new(biquad::Biquad::new(
+                    config::Filter::Conv { parameters, .. } => Box::new(
+                        fftconv::FftConv::from_config(name, waveform_length, position, parameters),
+                    ),
+                    config::Filter::Biquad { parameters, .. } => Box::new(biquad::Biquad::new(
                         name,
                         sample_freq,
                         biquad::BiquadCoefficients::from_config(sample_freq, parameters),
                     )),
-                    config::Filter::BiquadCombo { parameters } => Box::new(
+                    config::Filter::BiquadCombo { parameters, .. } => Box::new(
                         biquadcombo::BiquadCombo::from_config(name, sample_freq, parameters),
                     ),
-                    config::Filter::Delay { parameters } => Box::new(
+                    config::Filter::Delay { parameters, .. } => Box::new(
                         basicfilters::Delay::from_config(name, sample_freq, parameters),
                     ),
-                    config::Filter::Gain { parameters } => {
+                    config::Filter::Gain { parameters, .. } => {
                         Box::new(basicfilters::Gain::from_config(name, parameters))
                     }
-                    config::Filter::Volume { parameters } => {
+                    config::Filter::Volume { parameters, .. } => {
                         Box::new(basicfilters::Volume::from_config(
                             name,
                             parameters,
@@ -386,7 +766,7 @@ impl FilterGroup {
                             processing_status.clone(),
                         ))
                     }
-                    config::Filter::Loudness { parameters } => {
+                    config::Filter::Loudness { parameters, .. } => {
                         Box::new(loudness::Loudness::from_config(
                             name,
                             parameters,
@@ -395,16 +775,53 @@ impl FilterGroup {
                             processing_status.clone(),
                         ))
                     }
-                    config::Filter::Dither { parameters } => {
+                    config::Filter::Dither { parameters, .. } => {
                         Box::new(dither::Dither::from_config(name, parameters))
                     }
-                    config::Filter::DiffEq { parameters } => {
+                    config::Filter::DiffEq { parameters, .. } => {
                         Box::new(diffeq::DiffEq::from_config(name, parameters))
                     }
+                    config::Filter::Limiter { parameters, .. } => {
+                        Box::new(limiter::Limiter::from_config(name, sample_freq, parameters))
+                    }
+                    config::Filter::Compressor { parameters, .. } => Box::new(
+                        compressor::Compressor::from_config(name, sample_freq, parameters),
+                    ),
+                    config::Filter::Saturation { parameters, .. } => {
+                        Box::new(saturation::Saturation::from_config(name, parameters))
+                    }
+                    config::Filter::DcBlock { parameters, .. } => Box::new(
+                        basicfilters::DcBlock::from_config(name, sample_freq, parameters),
+                    ),
+                    config::Filter::ShelfGain { parameters, .. } => Box::new(biquad::Biquad::new(
+                        name,
+                        sample_freq,
+                        biquad::BiquadCoefficients::from_config(
+                            sample_freq,
+                            shelf_gain_biquad(&parameters),
+                        ),
+                    )),
+                    config::Filter::Deemphasis { parameters, .. } => Box::new(
+                        basicfilters::Emphasis::from_config(name, sample_freq, parameters, true),
+                    ),
+                    config::Filter::Preemphasis { parameters, .. } => Box::new(
+                        basicfilters::Emphasis::from_config(name, sample_freq, parameters, false),
+                    ),
+                    config::Filter::Invert { .. } => Box::new(basicfilters::Invert::new(name)),
                 };
             filters.push(filter);
         }
-        FilterGroup { channel, filters }
+        let bypass_changes = processing_status.read().unwrap().bypass_changes.clone();
+        FilterGroup {
+            channel,
+            filters,
+            names,
+            active_bypass: bypassed.clone(),
+            bypassed,
+            bypass_generation: None,
+            bypass_changes,
+            processing_status,
+        }
     }
 
     pub fn update_parameters(
@@ -412,33 +829,289 @@ impl FilterGroup {
         filterconfigs: HashMap<String, config::Filter>,
         changed: Vec<String>,
     ) {
-        for filter in &mut self.filters {
+        for (filter, bypassed) in self.filters.iter_mut().zip(self.bypassed.iter_mut()) {
             if changed.iter().any(|n| n == &filter.name()) {
-                filter.update_parameters(filterconfigs[&filter.name()].clone());
+                let filterconfig = filterconfigs[&filter.name()].clone();
+                *bypassed = filterconfig.is_bypassed();
+                filter.update_parameters(filterconfig);
             }
         }
+        self.bypass_generation = None;
+    }
+
+    /// Update the bypass of the filters if the runtime bypasses have changed.
+    fn update_bypass(&mut self) {
+        let generation = self.bypass_changes.load(Ordering::Acquire);
+        if self.bypass_generation == Some(generation) {
+            return;
+        }
+        let overrides = &self.processing_status.read().unwrap().bypassed_filters;
+        for ((active, name), bypassed) in self
+            .active_bypass
+            .iter_mut()
+            .zip(self.names.iter())
+            .zip(self.bypassed.iter())
+        {
+            *active = *overrides.get(name).unwrap_or(bypassed);
+        }
+        self.bypass_generation = Some(generation);
     }
 
-    /// Apply all the filters to an AudioChunk.
+    /// Apply all the filters to an AudioChunk, skipping the bypassed ones.
     fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
-        if !input.waveforms[self.channel].is_empty() {
-            for filter in &mut self.filters {
-                filter.process_waveform(&mut input.waveforms[self.channel])?;
+        self.process_waveform(&mut input.waveforms[self.channel])
+    }
+
+    /// Apply all the filters to the waveform of the channel of this group.
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        if !waveform.is_empty() {
+            self.update_bypass();
+            for (filter, bypassed) in self.filters.iter_mut().zip(self.active_bypass.iter()) {
+                if !bypassed {
+                    filter.process_waveform(waveform)?;
+                }
             }
         }
         Ok(())
     }
 }
 
+/// Stereo width control of a pair of channels, using mid-side processing.
+/// The side part is scaled by the width, 0 gives mono, 1 leaves the channels unchanged,
+/// and larger values widen the stereo image.
+pub struct StereoWidth {
+    left: usize,
+    right: usize,
+    width: PrcFmt,
+}
+
+impl StereoWidth {
+    pub fn new(channels: [usize; 2], width: PrcFmt) -> Self {
+        debug!(
+            "Creating stereo width for channels {:?}, width: {}",
+            channels, width
+        );
+        StereoWidth {
+            left: channels[0],
+            right: channels[1],
+            width,
+        }
+    }
+
+    /// Apply the width to the channel pair of an AudioChunk.
+    fn process_chunk(&mut self, input: &mut AudioChunk) {
+        if input.waveforms[self.left].is_empty() && input.waveforms[self.right].is_empty() {
+            return;
+        }
+        // An unused channel is silent, it still receives the side part of the other one
+        for chan in [self.left, self.right] {
+            if input.waveforms[chan].is_empty() {
+                input.waveforms[chan] = vec![0.0; input.frames];
+            }
+        }
+        for n in 0..input.frames {
+            let left = input.waveforms[self.left][n];
+            let right = input.waveforms[self.right][n];
+            let mid = 0.5 * (left + right);
+            let side = 0.5 * (left - right) * self.width;
+            input.waveforms[self.left][n] = mid + side;
+            input.waveforms[self.right][n] = mid - side;
+        }
+    }
+}
+
+/// Crossfeed for headphone listening, between a pair of channels.
+/// A delayed and lowpass filtered copy of each channel is attenuated and added to the other one,
+/// similar to how the sound from a loudspeaker reaches both ears.
+pub struct Crossfeed {
+    left: usize,
+    right: usize,
+    gain: PrcFmt,
+    delays: Vec<basicfilters::Delay>,
+    lowpasses: Vec<biquad::Biquad>,
+    feeds: [Vec<PrcFmt>; 2],
+}
+
+impl Crossfeed {
+    pub fn new(
+        channels: [usize; 2],
+        samplerate: usize,
+        chunksize: usize,
+        delay_ms: PrcFmt,
+        attenuation_db: PrcFmt,
+        cutoff_freq: PrcFmt,
+    ) -> Self {
+        debug!(
+            "Creating crossfeed for channels {:?}, delay: {} ms, attenuation: {} dB, cutoff: {} Hz",
+            channels, delay_ms, attenuation_db, cutoff_freq
+        );
+        let delay_samples = delay_ms * samplerate as PrcFmt / 1000.0;
+        let make_delay =
+            || basicfilters::Delay::new("crossfeed".to_string(), samplerate, delay_samples, false);
+        let make_lowpass = || {
+            let coeffs = biquad::BiquadCoefficients::from_config(
+                samplerate,
+                config::BiquadParameters::LowpassFO { freq: cutoff_freq },
+            );
+            biquad::Biquad::new("crossfeed".to_string(), samplerate, coeffs)
+        };
+        Crossfeed {
+            left: channels[0],
+            right: channels[1],
+            gain: (10.0 as PrcFmt).powf(-attenuation_db / 20.0),
+            delays: vec![make_delay(), make_delay()],
+            lowpasses: vec![make_lowpass(), make_lowpass()],
+            feeds: [vec![0.0; chunksize], vec![0.0; chunksize]],
+        }
+    }
+
+    /// Mix the filtered copies into the channel pair of an AudioChunk.
+    fn process_chunk(&mut self, input: &mut AudioChunk) {
+        if input.waveforms[self.left].is_empty() && input.waveforms[self.right].is_empty() {
+            return;
+        }
+        // An unused channel is silent, it still receives the crossfeed from the other one
+        for chan in [self.left, self.right] {
+            if input.waveforms[chan].is_empty() {
+                input.waveforms[chan] = vec![0.0; input.frames];
+            }
+        }
+        for (((feed, chan), delay), lowpass) in self
+            .feeds
+            .iter_mut()
+            .zip([self.left, self.right])
+            .zip(self.delays.iter_mut())
+            .zip(self.lowpasses.iter_mut())
+        {
+            feed.clear();
+            feed.extend_from_slice(&input.waveforms[chan]);
+            delay.process_waveform(feed).unwrap();
+            lowpass.process_waveform(feed).unwrap();
+        }
+        let feeds = &self.feeds;
+        for (chan, feed) in [(self.left, &feeds[1]), (self.right, &feeds[0])] {
+            for (item, value) in input.waveforms[chan].iter_mut().zip(feed.iter()) {
+                *item += self.gain * value;
+            }
+        }
+    }
+}
+
+/// Reordering of the channels, without any mixing.
+/// Channel n of the output is channel `order[n]` of the input.
+pub struct ChannelReorder {
+    order: Vec<usize>,
+}
+
+impl ChannelReorder {
+    pub fn new(order: Vec<usize>) -> Self {
+        debug!("Creating channel reorder: {:?}", order);
+        ChannelReorder { order }
+    }
+
+    /// Reorder the waveforms of an AudioChunk.
+    /// The order is a permutation, so each waveform is moved once without copying.
+    fn process_chunk(&mut self, input: &mut AudioChunk) {
+        let mut waveforms = std::mem::take(&mut input.waveforms);
+        input.waveforms = self
+            .order
+            .iter()
+            .map(|idx| std::mem::take(&mut waveforms[*idx]))
+            .collect();
+    }
+}
+
+/// Sum of some channels, added as a new channel after the existing ones.
+pub struct ChannelSum {
+    channels: Vec<usize>,
+    gain: PrcFmt,
+}
+
+impl ChannelSum {
+    pub fn new(channels: Vec<usize>, gain_db: PrcFmt) -> Self {
+        debug!("Creating channel sum: {:?}, gain: {} dB", channels, gain_db);
+        let gain = (10.0 as PrcFmt).powf(gain_db / 20.0);
+        ChannelSum { channels, gain }
+    }
+
+    /// Add the sum channel to an AudioChunk.
+    /// Unused channels, with empty waveforms, are skipped. If all are unused the sum is unused too.
+    fn process_chunk(&mut self, input: &mut AudioChunk) {
+        let mut sum: Vec<PrcFmt> = Vec::new();
+        for waveform in self
+            .channels
+            .iter()
+            .map(|idx| &input.waveforms[*idx])
+            .filter(|waveform| !waveform.is_empty())
+        {
+            if sum.is_empty() {
+                sum = vec![0.0; waveform.len()];
+            }
+            for (total, value) in sum.iter_mut().zip(waveform.iter()) {
+                *total += value;
+            }
+        }
+        if self.gain != 1.0 {
+            for item in sum.iter_mut() {
+                *item *= self.gain;
+            }
+        }
+        input.waveforms.push(sum);
+        input.channels += 1;
+    }
+}
+
+/// Fixed gain trim of each channel, from the `input_gains` and `output_gains` of the devices.
+pub struct ChannelGains {
+    gains: Vec<PrcFmt>,
+}
+
+impl ChannelGains {
+    /// Create from a list of gains in dB, one per channel.
+    pub fn new(gains_db: &[PrcFmt]) -> Self {
+        debug!("Creating channel gains: {:?} dB", gains_db);
+        let gains = gains_db
+            .iter()
+            .map(|gain| (10.0 as PrcFmt).powf(gain / 20.0))
+            .collect();
+        ChannelGains { gains }
+    }
+
+    /// Scale each channel of an AudioChunk by its gain.
+    fn process_chunk(&mut self, input: &mut AudioChunk) {
+        for (waveform, gain) in input.waveforms.iter_mut().zip(self.gains.iter()) {
+            if *gain != 1.0 {
+                for item in waveform.iter_mut() {
+                    *item *= gain;
+                }
+            }
+        }
+    }
+}
+
 /// A Pipeline is made up of a series of PipelineSteps,
-/// each one can be a single Mixer of a group of Filters
+/// each one can be a single Mixer, a group of Filters, a stereo width control,
+/// a crossfeed, a channel reordering, a channel sum, or the gain trims of the devices.
 pub enum PipelineStep {
     MixerStep(mixer::Mixer),
     FilterStep(FilterGroup),
+    StereoWidthStep(StereoWidth),
+    CrossfeedStep(Crossfeed),
+    ReorderStep(ChannelReorder),
+    SumStep(ChannelSum),
+    ChannelGainsStep(ChannelGains),
+}
+
+lazy_static! {
+    /// The thread pool shared by all pipelines, together with its number of threads.
+    /// During a crossfade or an A/B comparison two pipelines are processed in turn,
+    /// so they can both use the same threads.
+    static ref THREAD_POOL: Mutex<Option<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(None);
 }
 
 pub struct Pipeline {
     steps: Vec<PipelineStep>,
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
 }
 
 impl Pipeline {
@@ -449,27 +1122,125 @@ impl Pipeline {
     ) -> Self {
         debug!("Build new pipeline");
         let mut steps = Vec::<PipelineStep>::new();
-        for step in conf.pipeline {
+        let mut num_channels = conf.devices.capture.channels();
+        if !conf.devices.input_gains.is_empty() {
+            steps.push(PipelineStep::ChannelGainsStep(ChannelGains::new(
+                &conf.devices.input_gains,
+            )));
+        }
+        for step in conf.pipeline.into_iter().filter(|step| step.is_enabled()) {
             match step {
-                config::PipelineStep::Mixer { name } => {
+                config::PipelineStep::Mixer { name, .. } => {
                     let mixconf = conf.mixers[&name].clone();
+                    num_channels = mixconf.channels.out;
                     let mixer = mixer::Mixer::from_config(name, mixconf);
                     steps.push(PipelineStep::MixerStep(mixer));
                 }
-                config::PipelineStep::Filter { channel, names } => {
-                    let fltgrp = FilterGroup::from_config(
-                        channel,
-                        names,
-                        conf.filters.clone(),
-                        conf.devices.chunksize,
+                config::PipelineStep::Filter { channel, names, .. } => {
+                    for (position, idx) in channel.indices(num_channels).into_iter().enumerate() {
+                        let fltgrp = FilterGroup::from_config(
+                            idx,
+                            position,
+                            names.clone(),
+                            conf.filters.clone(),
+                            conf.devices.chunksize,
+                            conf.devices.samplerate,
+                            processing_status.clone(),
+                        );
+                        steps.push(PipelineStep::FilterStep(fltgrp));
+                    }
+                }
+                config::PipelineStep::StereoWidth {
+                    channels, width, ..
+                } => {
+                    steps.push(PipelineStep::StereoWidthStep(StereoWidth::new(
+                        channels, width,
+                    )));
+                }
+                config::PipelineStep::Crossfeed {
+                    channels,
+                    delay_ms,
+                    attenuation_db,
+                    cutoff_freq,
+                    ..
+                } => {
+                    steps.push(PipelineStep::CrossfeedStep(Crossfeed::new(
+                        channels,
                         conf.devices.samplerate,
-                        processing_status.clone(),
-                    );
-                    steps.push(PipelineStep::FilterStep(fltgrp));
+                        conf.devices.chunksize,
+                        delay_ms,
+                        attenuation_db,
+                        cutoff_freq,
+                    )));
+                }
+                config::PipelineStep::Reorder { order, .. } => {
+                    steps.push(PipelineStep::ReorderStep(ChannelReorder::new(order)));
+                }
+                config::PipelineStep::Sum {
+                    channels,
+                    gain,
+                    names,
+                    ..
+                } => {
+                    steps.push(PipelineStep::SumStep(ChannelSum::new(channels, gain)));
+                    // The filters of the sum are an ordinary filter step for the new channel
+                    if !names.is_empty() {
+                        let fltgrp = FilterGroup::from_config(
+                            num_channels,
+                            0,
+                            names,
+                            conf.filters.clone(),
+                            conf.devices.chunksize,
+                            conf.devices.samplerate,
+                            processing_status.clone(),
+                        );
+                        steps.push(PipelineStep::FilterStep(fltgrp));
+                    }
+                    num_channels += 1;
                 }
             }
         }
-        Pipeline { steps }
+        if !conf.devices.output_gains.is_empty() {
+            steps.push(PipelineStep::ChannelGainsStep(ChannelGains::new(
+                &conf.devices.output_gains,
+            )));
+        }
+        if conf.master_gain_db != 0.0 {
+            let gains = vec![conf.master_gain_db; conf.devices.playback.channels()];
+            steps.push(PipelineStep::ChannelGainsStep(ChannelGains::new(&gains)));
+        }
+        let playback_format = conf.devices.playback.sampleformat();
+        for channel in 0..conf.devices.playback.channels() {
+            let name = format!("playback_dither_{}", channel);
+            if let Some(mut dith) = dither::for_playback(
+                name,
+                &conf.devices.dither,
+                conf.devices.dither_amplitude,
+                &playback_format,
+            ) {
+                if let Some(seed) = conf.devices.dither_seed {
+                    // Each channel gets its own seed, to avoid identical noise in all channels
+                    dith = dith.with_seed(seed.wrapping_add(channel as u64));
+                }
+                debug!(
+                    "Adding {:?} dither to playback channel {}",
+                    conf.devices.dither, channel
+                );
+                let fltgrp = FilterGroup {
+                    channel,
+                    names: vec![dith.name()],
+                    filters: vec![Box::new(dith)],
+                    bypassed: vec![false],
+                    active_bypass: vec![false],
+                    bypass_generation: None,
+                    bypass_changes: processing_status.read().unwrap().bypass_changes.clone(),
+                    processing_status: processing_status.clone(),
+                };
+                steps.push(PipelineStep::FilterStep(fltgrp));
+            }
+        }
+        let thread_pool = make_thread_pool(conf.devices.worker_threads);
+        Pipeline { steps, thread_pool }
     }
 
     pub fn update_parameters(
@@ -489,47 +1260,501 @@ impl Pipeline {
                 PipelineStep::FilterStep(flt) => {
                     flt.update_parameters(conf.filters.clone(), filters.clone());
                 }
+                PipelineStep::StereoWidthStep(_) => {}
+                PipelineStep::CrossfeedStep(_) => {}
+                PipelineStep::ReorderStep(_) => {}
+                PipelineStep::SumStep(_) => {}
+                PipelineStep::ChannelGainsStep(_) => {}
             }
         }
     }
 
-    /// Process an AudioChunk by calling either a MixerStep or a FilterStep
+    /// Process an AudioChunk by calling either a MixerStep or a FilterStep.
+    /// With a thread pool, each run of consecutive filter steps is processed in parallel,
+    /// one channel per task. All other steps may combine channels and are processed serially.
     pub fn process_chunk(&mut self, mut chunk: AudioChunk) -> AudioChunk {
-        for mut step in &mut self.steps {
-            match &mut step {
+        let mut index = 0;
+        while index < self.steps.len() {
+            if let Some(pool) = &self.thread_pool {
+                let run_length = self.steps[index..]
+                    .iter()
+                    .take_while(|step| matches!(step, PipelineStep::FilterStep(_)))
+                    .count();
+                if run_length > 1 {
+                    let run = &mut self.steps[index..index + run_length];
+                    process_filter_steps_parallel(pool, run, &mut chunk);
+                    index += run_length;
+                    continue;
+                }
+            }
+            match &mut self.steps[index] {
                 PipelineStep::MixerStep(mix) => {
                     chunk = mix.process_chunk(&chunk);
                 }
                 PipelineStep::FilterStep(flt) => {
                     flt.process_chunk(&mut chunk).unwrap();
                 }
+                PipelineStep::StereoWidthStep(sw) => {
+                    sw.process_chunk(&mut chunk);
+                }
+                PipelineStep::CrossfeedStep(cf) => {
+                    cf.process_chunk(&mut chunk);
+                }
+                PipelineStep::ReorderStep(ro) => {
+                    ro.process_chunk(&mut chunk);
+                }
+                PipelineStep::SumStep(sum) => {
+                    sum.process_chunk(&mut chunk);
+                }
+                PipelineStep::ChannelGainsStep(cg) => {
+                    cg.process_chunk(&mut chunk);
+                }
             }
+            index += 1;
+        }
+        chunk
+    }
+}
+
+/// Get the thread pool for processing the channels in parallel.
+/// A single worker thread means serial processing, and zero uses one thread per cpu core.
+/// The pool is reused by all pipelines, and is only replaced when the number of threads changes.
+fn make_thread_pool(worker_threads: usize) -> Option<Arc<rayon::ThreadPool>> {
+    if worker_threads == 1 {
+        return None;
+    }
+    let mut shared = THREAD_POOL.lock().unwrap();
+    if let Some((threads, pool)) = shared.as_ref() {
+        if *threads == worker_threads {
+            return Some(pool.clone());
+        }
+    }
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(worker_threads)
+        .thread_name(|n| format!("filter_worker_{}", n))
+        .build()
+    {
+        Ok(pool) => {
+            debug!(
+                "Processing filters in parallel with {} worker threads",
+                pool.current_num_threads()
+            );
+            let pool = Arc::new(pool);
+            *shared = Some((worker_threads, pool.clone()));
+            Some(pool)
+        }
+        Err(err) => {
+            warn!(
+                "Could not start worker threads, processing filters serially. Error: {}",
+                err
+            );
+            None
         }
+    }
+}
+
+/// Process a run of filter steps, with the filters of each channel as one task in the pool.
+/// The steps only touch their own channel, so the channels are independent,
+/// and the steps of each channel are still applied in the order of the pipeline.
+fn process_filter_steps_parallel(
+    pool: &rayon::ThreadPool,
+    steps: &mut [PipelineStep],
+    chunk: &mut AudioChunk,
+) {
+    let mut groups: Vec<Vec<&mut FilterGroup>> =
+        chunk.waveforms.iter().map(|_| Vec::new()).collect();
+    for step in steps.iter_mut() {
+        if let PipelineStep::FilterStep(flt) = step {
+            groups[flt.channel].push(flt);
+        }
+    }
+    pool.install(|| {
         chunk
+            .waveforms
+            .par_iter_mut()
+            .zip(groups.into_par_iter())
+            .for_each(|(waveform, groups)| {
+                for group in groups {
+                    group.process_waveform(waveform).unwrap();
+                }
+            });
+    });
+}
+
+/// Linear crossfade from the output of an old pipeline to the one of a new pipeline,
+/// used to avoid clicks when a reloaded config replaces the pipeline.
+/// Both pipelines process every chunk until the crossfade is done.
+/// A reload during a crossfade starts from the current mix, by keeping the unfinished
+/// crossfade as the old side until it is done too.
+pub struct Crossfade {
+    old: Pipeline,
+    previous: Option<Box<Crossfade>>,
+    frames: usize,
+    position: usize,
+}
+
+impl Crossfade {
+    pub fn new(old: Pipeline, previous: Option<Crossfade>, frames: usize) -> Self {
+        debug!("Crossfading to the new pipeline over {} frames", frames);
+        Crossfade {
+            old,
+            previous: previous.map(Box::new),
+            frames,
+            position: 0,
+        }
+    }
+
+    /// True when the output comes only from the new pipeline.
+    pub fn is_done(&self) -> bool {
+        self.position >= self.frames
+    }
+
+    /// Process a chunk with both pipelines and blend the outputs.
+    pub fn process_chunk(&mut self, new: &mut Pipeline, chunk: AudioChunk) -> AudioChunk {
+        let old_chunk = match self.previous.as_mut() {
+            Some(previous) => previous.process_chunk(&mut self.old, copy_chunk(&chunk)),
+            None => self.old.process_chunk(copy_chunk(&chunk)),
+        };
+        if self.previous.as_ref().map(|previous| previous.is_done()) == Some(true) {
+            self.previous = None;
+        }
+        let mut new_chunk = new.process_chunk(chunk);
+        blend_chunks(&old_chunk, &mut new_chunk, self.position, self.frames);
+        self.position += new_chunk.frames;
+        new_chunk
+    }
+}
+
+/// Make a copy of a chunk, for processing the same input with two pipelines.
+fn copy_chunk(chunk: &AudioChunk) -> AudioChunk {
+    let mut copy = AudioChunk::new(
+        chunk.waveforms.clone(),
+        chunk.maxval,
+        chunk.minval,
+        chunk.frames,
+        chunk.valid_frames,
+    );
+    copy.timestamp = chunk.timestamp;
+    copy
+}
+
+/// Blend linearly from the old to the new chunk, `position` frames into a crossfade of `frames` frames.
+fn blend_chunks(
+    old_chunk: &AudioChunk,
+    new_chunk: &mut AudioChunk,
+    position: usize,
+    frames: usize,
+) {
+    let empty = Vec::new();
+    for (chan, waveform) in new_chunk.waveforms.iter_mut().enumerate() {
+        let old_waveform = old_chunk.waveforms.get(chan).unwrap_or(&empty);
+        if old_waveform.is_empty() && waveform.is_empty() {
+            continue;
+        }
+        if waveform.is_empty() {
+            *waveform = vec![0.0; new_chunk.frames];
+        }
+        for (n, item) in waveform.iter_mut().enumerate() {
+            let gain = ((position + n) as PrcFmt / frames as PrcFmt).min(1.0);
+            let old_value = old_waveform.get(n).copied().unwrap_or(0.0);
+            *item = (1.0 - gain) * old_value + gain * *item;
+        }
     }
 }
 
+/// Pipeline of config B, for A/B comparisons with the active pipeline.
+/// Both pipelines process every chunk, so that their filters are always up to date,
+/// and the output can switch between them at any chunk boundary without glitches.
+pub struct AbSwitch {
+    pipeline_b: Pipeline,
+    use_b: bool,
+    fade_frames: usize,
+    position: usize,
+}
+
+impl AbSwitch {
+    pub fn new(pipeline_b: Pipeline, use_b: bool, fade_frames: usize) -> Self {
+        AbSwitch {
+            pipeline_b,
+            use_b,
+            fade_frames,
+            position: fade_frames,
+        }
+    }
+
+    /// Select the output of pipeline B or of the active pipeline, starting a crossfade if it changes.
+    pub fn select(&mut self, use_b: bool) {
+        if use_b != self.use_b {
+            debug!("Switching to config {}", if use_b { "B" } else { "A" });
+            self.use_b = use_b;
+            self.position = 0;
+        }
+    }
+
+    /// Process a copy of a chunk with pipeline B.
+    pub fn process_b(&mut self, chunk: &AudioChunk) -> AudioChunk {
+        self.pipeline_b.process_chunk(copy_chunk(chunk))
+    }
+
+    /// Get the selected output from the outputs of the two pipelines for the same chunk.
+    pub fn select_output(&mut self, chunk_a: AudioChunk, chunk_b: AudioChunk) -> AudioChunk {
+        let (old_chunk, mut new_chunk) = if self.use_b {
+            (chunk_a, chunk_b)
+        } else {
+            (chunk_b, chunk_a)
+        };
+        if self.position < self.fade_frames {
+            blend_chunks(&old_chunk, &mut new_chunk, self.position, self.fade_frames);
+            self.position += new_chunk.frames;
+        }
+        new_chunk
+    }
+}
+
+/// Validate a stereo width step, for a pipeline with `num_channels` channels at this step.
+pub fn validate_stereo_width(num_channels: usize, channels: &[usize; 2], width: PrcFmt) -> Res<()> {
+    for channel in channels {
+        if *channel >= num_channels {
+            let msg = format!("Use of non existing channel {} in StereoWidth", channel);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if channels[0] == channels[1] {
+        return Err(config::ConfigError::new("StereoWidth needs two different channels").into());
+    }
+    if !width.is_finite() || width < 0.0 {
+        return Err(
+            config::ConfigError::new("StereoWidth width must be a finite number >= 0").into(),
+        );
+    }
+    Ok(())
+}
+
+/// Validate a reorder step, for a pipeline with `num_channels` channels at this step.
+/// The order must be a permutation of the channels.
+pub fn validate_reorder(num_channels: usize, order: &[usize]) -> Res<()> {
+    if order.len() != num_channels {
+        let msg = format!(
+            "Reorder must give {} channels, one for each channel, but gives {}",
+            num_channels,
+            order.len()
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    let mut used = vec![false; num_channels];
+    for channel in order {
+        if *channel >= num_channels {
+            let msg = format!("Use of non existing channel {} in Reorder", channel);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        if used[*channel] {
+            let msg = format!("Channel {} is used more than once in Reorder", channel);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        used[*channel] = true;
+    }
+    Ok(())
+}
+
+/// Validate a sum step, for a pipeline with `num_channels` channels at this step.
+pub fn validate_sum(num_channels: usize, channels: &[usize], gain: PrcFmt) -> Res<()> {
+    if !gain.is_finite() || gain.abs() > 150.0 {
+        return Err(
+            config::ConfigError::new("Sum gain must be in the range -150 to +150 dB").into(),
+        );
+    }
+    if channels.is_empty() {
+        return Err(config::ConfigError::new("Sum must use at least one channel").into());
+    }
+    for (n, channel) in channels.iter().enumerate() {
+        if *channel >= num_channels {
+            let msg = format!(
+                "Use of non existing channel {} in Sum, the step receives {} channels",
+                channel, num_channels
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        if channels[..n].contains(channel) {
+            let msg = format!("Channel {} is used more than once in Sum", channel);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
+/// Validate a crossfeed step, for a pipeline with `num_channels` channels at this step.
+pub fn validate_crossfeed(
+    num_channels: usize,
+    samplerate: usize,
+    channels: &[usize; 2],
+    delay_ms: PrcFmt,
+    attenuation_db: PrcFmt,
+    cutoff_freq: PrcFmt,
+) -> Res<()> {
+    for channel in channels {
+        if *channel >= num_channels {
+            let msg = format!(
+                "Use of non existing channel {} in Crossfeed, the step receives {} channels",
+                channel, num_channels
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if channels[0] == channels[1] {
+        return Err(config::ConfigError::new("Crossfeed needs two different channels").into());
+    }
+    if !(0.0..=10.0).contains(&delay_ms) {
+        return Err(
+            config::ConfigError::new("Crossfeed delay must be in the range 0 to 10 ms").into(),
+        );
+    }
+    if !(0.0..=100.0).contains(&attenuation_db) {
+        return Err(config::ConfigError::new(
+            "Crossfeed attenuation must be in the range 0 to 100 dB",
+        )
+        .into());
+    }
+    if cutoff_freq.is_nan() || cutoff_freq <= 0.0 {
+        return Err(config::ConfigError::new("Crossfeed cutoff frequency must be > 0").into());
+    } else if cutoff_freq >= samplerate as PrcFmt / 2.0 {
+        return Err(
+            config::ConfigError::new("Crossfeed cutoff frequency must be < samplerate/2").into(),
+        );
+    }
+    Ok(())
+}
+
+/// Validate the gain trims of a device with `num_channels` channels.
+pub fn validate_channel_gains(name: &str, num_channels: usize, gains: &[PrcFmt]) -> Res<()> {
+    if !gains.is_empty() && gains.len() != num_channels {
+        let msg = format!(
+            "{} must have one value per channel, expected {} but got {}",
+            name,
+            num_channels,
+            gains.len()
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if gains
+        .iter()
+        .any(|gain| !gain.is_finite() || gain.abs() > 150.0)
+    {
+        let msg = format!("{} must be in the range -150 to +150 dB", name);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
+/// Find the first parameter of a filter config that is NaN or infinite, and return its name.
+/// Nested parameters are named by their path, for example `bands[1].gain`.
+fn find_non_finite(value: &serde_yaml::Value, path: &str) -> Option<String> {
+    match value {
+        serde_yaml::Value::Number(number) => match number.as_f64() {
+            Some(val) if !val.is_finite() => Some(path.to_string()),
+            _ => None,
+        },
+        serde_yaml::Value::Sequence(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(n, item)| find_non_finite(item, &format!("{}[{}]", path, n))),
+        serde_yaml::Value::Mapping(map) => map.iter().find_map(|(key, item)| {
+            let key = key.as_str().unwrap_or_default();
+            if path.is_empty() {
+                find_non_finite(item, key)
+            } else {
+                find_non_finite(item, &format!("{}.{}", path, key))
+            }
+        }),
+        _ => None,
+    }
+}
+
+/// Check that no numerical parameter of a filter is NaN or infinite.
+/// These would otherwise pass the range checks, since any comparison with NaN is false.
+pub fn validate_finite_parameters(filter_config: &config::Filter) -> Res<()> {
+    let value = match filter_config {
+        config::Filter::Conv { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Biquad { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Delay { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Gain { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Dither { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::DiffEq { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Volume { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Loudness { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::BiquadCombo { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Limiter { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Compressor { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Saturation { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::DcBlock { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::ShelfGain { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Deemphasis { parameters, .. }
+        | config::Filter::Preemphasis { parameters, .. } => serde_yaml::to_value(parameters),
+        config::Filter::Invert { .. } => return Ok(()),
+    }?;
+    if let Some(name) = find_non_finite(&value, "") {
+        let msg = format!("Parameter '{}' must be a finite number", name);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
 /// Validate the filter config, to give a helpful message intead of a panic.
-pub fn validate_filter(fs: usize, filter_config: &config::Filter) -> Res<()> {
+pub fn validate_filter(fs: usize, chunksize: usize, filter_config: &config::Filter) -> Res<()> {
+    validate_finite_parameters(filter_config)?;
     match filter_config {
-        config::Filter::Conv { parameters } => fftconv::validate_config(parameters),
-        config::Filter::Biquad { parameters } => biquad::validate_config(fs, parameters),
-        config::Filter::Delay { parameters } => basicfilters::validate_delay_config(parameters),
-        config::Filter::Gain { parameters } => basicfilters::validate_gain_config(parameters),
-        config::Filter::Dither { parameters } => dither::validate_config(parameters),
-        config::Filter::DiffEq { parameters } => diffeq::validate_config(parameters),
-        config::Filter::Volume { parameters } => basicfilters::validate_volume_config(parameters),
-        config::Filter::Loudness { parameters } => loudness::validate_config(parameters),
-        config::Filter::BiquadCombo { parameters } => biquadcombo::validate_config(fs, parameters),
+        config::Filter::Conv { parameters, .. } => fftconv::validate_config(parameters, chunksize),
+        config::Filter::Biquad { parameters, .. } => biquad::validate_config(fs, parameters),
+        config::Filter::Delay { parameters, .. } => {
+            basicfilters::validate_delay_config(fs, parameters)
+        }
+        config::Filter::Gain { parameters, .. } => basicfilters::validate_gain_config(parameters),
+        config::Filter::Dither { parameters, .. } => dither::validate_config(parameters),
+        config::Filter::DiffEq { parameters, .. } => diffeq::validate_config(parameters),
+        config::Filter::Volume { parameters, .. } => {
+            basicfilters::validate_volume_config(parameters)
+        }
+        config::Filter::Loudness { parameters, .. } => loudness::validate_config(parameters),
+        config::Filter::BiquadCombo { parameters, .. } => {
+            biquadcombo::validate_config(fs, parameters)
+        }
+        config::Filter::Limiter { parameters, .. } => limiter::validate_config(parameters),
+        config::Filter::Compressor { parameters, .. } => compressor::validate_config(parameters),
+        config::Filter::Saturation { parameters, .. } => saturation::validate_config(parameters),
+        config::Filter::DcBlock { parameters, .. } => {
+            basicfilters::validate_dcblock_config(fs, parameters)
+        }
+        config::Filter::ShelfGain { parameters, .. } => {
+            biquad::validate_config(fs, &shelf_gain_biquad(parameters))
+        }
+        config::Filter::Deemphasis { parameters, .. }
+        | config::Filter::Preemphasis { parameters, .. } => {
+            basicfilters::validate_emphasis_config(fs, parameters)
+        }
+        // Invert has no parameters
+        config::Filter::Invert { .. } => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config;
     use crate::config::FileFormat;
+    use crate::filters::{fft_len_for_chunksize, is_good_fft_size, nearest_good_fft_size};
     use crate::filters::{find_data_in_wav, read_wav};
-    use crate::filters::{pad_vector, read_coeff_file};
+    use crate::filters::{load_conv_coeffs, validate_conv_cascade, MAX_CASCADE_LENGTH};
+    use crate::filters::{pad_vector, parse_biquad_text, read_biquad_file, read_coeff_file};
+    use crate::filters::{
+        validate_channel_gains, AbSwitch, ChannelGains, Crossfade, Pipeline, PipelineStep,
+    };
+    use crate::filters::{
+        validate_crossfeed, validate_filter, validate_reorder, validate_stereo_width, validate_sum,
+        ChannelReorder, ChannelSum, Crossfeed, FilterGroup, StereoWidth,
+    };
     use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {} = {}", left, right, left - right);
@@ -690,6 +1915,76 @@ mod tests {
         );
     }
 
+    #[test]
+    fn read_malformed() {
+        let dir = std::env::temp_dir();
+        let text_path = dir.join("camilladsp_test_malformed.txt");
+        std::fs::write(&text_path, "1.0\nabc\n0.5\n").unwrap();
+        let res = read_coeff_file(text_path.to_str().unwrap(), &FileFormat::TEXT, 0, 0);
+        std::fs::remove_file(&text_path).unwrap();
+        let err = res.unwrap_err().to_string();
+        assert!(err.contains("line 2"), "{}", err);
+
+        // Two and a half float32 values
+        let raw_path = dir.join("camilladsp_test_malformed.raw");
+        std::fs::write(&raw_path, [0u8; 10]).unwrap();
+        let res = read_coeff_file(raw_path.to_str().unwrap(), &FileFormat::FLOAT32LE, 0, 0);
+        let res_limited = read_coeff_file(raw_path.to_str().unwrap(), &FileFormat::FLOAT32LE, 8, 0);
+        std::fs::remove_file(&raw_path).unwrap();
+        assert!(res.is_err());
+        assert_eq!(res_limited.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn read_rew_biquads() {
+        let stages = read_biquad_file("testdata/biquads_rew.txt").unwrap();
+        assert_eq!(stages.len(), 2);
+        // The signs of a1 and a2 are flipped
+        assert_eq!(
+            stages[1],
+            config::BiquadParameters::Free {
+                a1: -1.9683094099486,
+                a2: 0.9696879667279,
+                b0: 1.0012639549448,
+                b1: -1.9683094099486,
+                b2: 0.9684240117831,
+            }
+        );
+        let conf = config::Filter::BiquadCombo {
+            description: None,
+            bypassed: false,
+            parameters: config::BiquadComboParameters::File {
+                filename: "testdata/biquads_rew.txt".to_string(),
+            },
+        };
+        assert!(validate_filter(48000, 1024, &conf).is_ok());
+    }
+
+    #[test]
+    fn good_fft_sizes() {
+        assert!(is_good_fft_size(1024));
+        assert!(is_good_fft_size(1000));
+        assert!(is_good_fft_size(882));
+        assert!(!is_good_fft_size(1021));
+        assert!(!is_good_fft_size(0));
+        assert_eq!(nearest_good_fft_size(1021), 1024);
+        assert_eq!(nearest_good_fft_size(1013), 1008);
+        assert_eq!(fft_len_for_chunksize(1024), 2048);
+        assert_eq!(fft_len_for_chunksize(1021), 2048);
+    }
+
+    #[test]
+    fn read_malformed_biquads() {
+        assert!(parse_biquad_text("").is_err());
+        assert!(parse_biquad_text("b0=1.0,\n").is_err());
+        let missing = parse_biquad_text("biquad1,\nb0=1.0,\nb1=0.0,\nb2=0.0,\na1=0.0,\n");
+        assert!(missing.unwrap_err().contains("Biquad 1"));
+        let bad_value = parse_biquad_text("biquad1,\nb0=abc,\n");
+        assert!(bad_value.unwrap_err().contains("b0"));
+        let non_finite = parse_biquad_text("biquad1,\nb0=inf,\n");
+        assert!(non_finite.is_err());
+    }
+
     #[test]
     fn test_padding() {
         let values: Vec<PrcFmt> = vec![1.0, 0.5];
@@ -700,6 +1995,56 @@ mod tests {
         assert!(compare_waveforms(&values_padded, &values_5, 1e-15));
     }
 
+    fn values_part(values: Vec<PrcFmt>) -> config::ConvParameters {
+        config::ConvParameters::Values {
+            values,
+            length: 0,
+            normalize: None,
+        }
+    }
+
+    #[test]
+    fn cascade_unit_kernels() {
+        let unit = config::ConvParameters::Cascade {
+            parts: vec![values_part(vec![1.0]), values_part(vec![1.0])],
+        };
+        assert!(compare_waveforms(
+            &load_conv_coeffs(&unit, 0).unwrap(),
+            &[1.0],
+            1e-12
+        ));
+        // Delays of one and two samples add up to three
+        let delays = config::ConvParameters::Cascade {
+            parts: vec![
+                values_part(vec![0.0, 1.0]),
+                values_part(vec![0.0, 0.0, 1.0]),
+                values_part(vec![0.5]),
+            ],
+        };
+        assert!(compare_waveforms(
+            &load_conv_coeffs(&delays, 0).unwrap(),
+            &[0.0, 0.0, 0.0, 0.5],
+            1e-12
+        ));
+    }
+
+    #[test]
+    fn check_conv_cascade() {
+        let parts = vec![values_part(vec![1.0, 0.5]), values_part(vec![0.0, 1.0])];
+        assert_eq!(validate_conv_cascade(&parts, |_| Ok(2)).unwrap(), 3);
+        assert!(validate_conv_cascade(&[], |_| Ok(1)).is_err());
+        let nested = vec![config::ConvParameters::Cascade {
+            parts: parts.clone(),
+        }];
+        assert!(validate_conv_cascade(&nested, |_| Ok(1)).is_err());
+        let half = MAX_CASCADE_LENGTH / 2;
+        assert_eq!(
+            validate_conv_cascade(&parts, |_| Ok(half)).unwrap(),
+            MAX_CASCADE_LENGTH - 1
+        );
+        assert!(validate_conv_cascade(&parts, |_| Ok(half + 1)).is_err());
+    }
+
     #[test]
     pub fn test_analyze_wav() {
         let info = find_data_in_wav("testdata/int32.wav").unwrap();
@@ -719,4 +2064,425 @@ mod tests {
         let bad = read_wav("testdata/int32.wav", 1);
         assert!(bad.is_err());
     }
+
+    fn apply_width(width: PrcFmt) -> Vec<Vec<PrcFmt>> {
+        let waveforms = vec![vec![1.0, 0.5], vec![0.0, 0.5], vec![0.3, 0.3]];
+        let mut chunk = AudioChunk::new(waveforms, 1.0, 0.0, 2, 2);
+        let mut sw = StereoWidth::new([0, 1], width);
+        sw.process_chunk(&mut chunk);
+        chunk.waveforms
+    }
+
+    #[test]
+    fn stereo_width() {
+        assert_eq!(
+            apply_width(0.0),
+            vec![vec![0.5, 0.5], vec![0.5, 0.5], vec![0.3, 0.3]]
+        );
+        assert_eq!(
+            apply_width(1.0),
+            vec![vec![1.0, 0.5], vec![0.0, 0.5], vec![0.3, 0.3]]
+        );
+        assert_eq!(
+            apply_width(2.0),
+            vec![vec![1.5, 0.5], vec![-0.5, 0.5], vec![0.3, 0.3]]
+        );
+    }
+
+    #[test]
+    fn check_stereo_width() {
+        assert!(validate_stereo_width(2, &[0, 1], 1.5).is_ok());
+        assert!(validate_stereo_width(2, &[0, 2], 1.5).is_err());
+        assert!(validate_stereo_width(2, &[1, 1], 1.5).is_err());
+        assert!(validate_stereo_width(2, &[0, 1], -1.0).is_err());
+        assert!(validate_stereo_width(2, &[0, 1], PrcFmt::NAN).is_err());
+        assert!(validate_stereo_width(2, &[0, 1], PrcFmt::INFINITY).is_err());
+    }
+
+    #[test]
+    fn reorder_channels() {
+        let waveforms = vec![vec![1.0, 1.0], Vec::new(), vec![3.0, 3.0]];
+        let mut chunk = AudioChunk::new(waveforms, 3.0, 0.0, 2, 2);
+        let mut reorder = ChannelReorder::new(vec![2, 0, 1]);
+        reorder.process_chunk(&mut chunk);
+        assert_eq!(
+            chunk.waveforms,
+            vec![vec![3.0, 3.0], vec![1.0, 1.0], Vec::new()]
+        );
+    }
+
+    #[test]
+    fn check_reorder() {
+        assert!(validate_reorder(2, &[1, 0]).is_ok());
+        assert!(validate_reorder(3, &[1, 0]).is_err());
+        assert!(validate_reorder(2, &[0, 2]).is_err());
+        assert!(validate_reorder(2, &[1, 1]).is_err());
+    }
+
+    #[test]
+    fn sum_channels() {
+        let waveforms = vec![vec![1.0, 2.0], Vec::new(), vec![3.0, -1.0]];
+        let mut chunk = AudioChunk::new(waveforms, 3.0, -1.0, 2, 2);
+        let mut sum = ChannelSum::new(vec![0, 1, 2], -6.0206);
+        sum.process_chunk(&mut chunk);
+        assert_eq!(chunk.channels, 4);
+        assert!((chunk.waveforms[3][0] - 2.0).abs() < 1e-4);
+        assert!((chunk.waveforms[3][1] - 0.5).abs() < 1e-4);
+        let mut unused = ChannelSum::new(vec![1], 0.0);
+        unused.process_chunk(&mut chunk);
+        assert!(chunk.waveforms[4].is_empty());
+    }
+
+    #[test]
+    fn check_sum() {
+        assert!(validate_sum(2, &[0, 1], -6.0).is_ok());
+        assert!(validate_sum(2, &[], 0.0).is_err());
+        assert!(validate_sum(2, &[0, 2], 0.0).is_err());
+        assert!(validate_sum(2, &[1, 1], 0.0).is_err());
+        assert!(validate_sum(2, &[0, 1], 1.0e6).is_err());
+        assert!(validate_sum(2, &[0, 1], PrcFmt::NAN).is_err());
+    }
+
+    #[test]
+    fn crossfeed_impulse() {
+        let mut impulse = vec![0.0; 4800];
+        impulse[0] = 1.0;
+        let mut chunk = AudioChunk::new(vec![impulse.clone(), Vec::new()], 1.0, 0.0, 4800, 4800);
+        let mut cf = Crossfeed::new([0, 1], 48000, 4800, 0.5, 6.0, 700.0);
+        cf.process_chunk(&mut chunk);
+        // The left channel is unchanged, the right gets a delayed, filtered and attenuated copy
+        assert_eq!(chunk.waveforms[0], impulse);
+        let feed = &chunk.waveforms[1];
+        assert!(feed[..24].iter().all(|val| *val == 0.0));
+        assert!(feed[24] > 0.0);
+        let dc_gain: PrcFmt = feed.iter().sum();
+        assert!(is_close(
+            dc_gain,
+            (10.0 as PrcFmt).powf(-6.0 / 20.0),
+            1.0e-3
+        ));
+        // A lowpassed copy has a lower peak than the attenuation alone would give
+        assert!(feed[24] < 0.5 * dc_gain);
+    }
+
+    #[test]
+    fn check_crossfeed() {
+        assert!(validate_crossfeed(2, 48000, &[0, 1], 0.3, 6.0, 700.0).is_ok());
+        assert!(validate_crossfeed(2, 48000, &[0, 2], 0.3, 6.0, 700.0).is_err());
+        assert!(validate_crossfeed(2, 48000, &[1, 1], 0.3, 6.0, 700.0).is_err());
+        assert!(validate_crossfeed(2, 48000, &[0, 1], -0.3, 6.0, 700.0).is_err());
+        assert!(validate_crossfeed(2, 48000, &[0, 1], 0.3, -6.0, 700.0).is_err());
+        assert!(validate_crossfeed(2, 48000, &[0, 1], 0.3, 6.0, 30000.0).is_err());
+        assert!(validate_crossfeed(2, 48000, &[0, 1], 0.3, 6.0, PrcFmt::NAN).is_err());
+    }
+
+    #[test]
+    fn channel_gains() {
+        let waveforms = vec![vec![1.0, -0.5], Vec::new(), vec![0.2, 0.4]];
+        let mut chunk = AudioChunk::new(waveforms, 1.0, -0.5, 2, 2);
+        let mut gains = ChannelGains::new(&[-6.0, 3.0, 0.0]);
+        gains.process_chunk(&mut chunk);
+        let factor = (10.0 as PrcFmt).powf(-6.0 / 20.0);
+        assert!(is_close(chunk.waveforms[0][0], factor, 1.0e-9));
+        assert!(is_close(chunk.waveforms[0][1], -0.5 * factor, 1.0e-9));
+        assert!(chunk.waveforms[1].is_empty());
+        assert_eq!(chunk.waveforms[2], vec![0.2, 0.4]);
+    }
+
+    #[test]
+    fn crossfade_pipelines() {
+        let old = Pipeline {
+            steps: Vec::new(),
+            thread_pool: None,
+        };
+        let mut new = Pipeline {
+            steps: vec![PipelineStep::ChannelGainsStep(ChannelGains {
+                gains: vec![0.0, 0.0],
+            })],
+            thread_pool: None,
+        };
+        let mut fade = Crossfade::new(old, None, 4);
+        let mut process = |fade: &mut Crossfade| {
+            let chunk = AudioChunk::new(vec![vec![1.0, 1.0], Vec::new()], 1.0, 1.0, 2, 2);
+            fade.process_chunk(&mut new, chunk).waveforms
+        };
+        assert_eq!(process(&mut fade), vec![vec![1.0, 0.75], Vec::new()]);
+        assert!(!fade.is_done());
+        assert_eq!(process(&mut fade), vec![vec![0.5, 0.25], Vec::new()]);
+        assert!(fade.is_done());
+    }
+
+    #[test]
+    fn crossfade_during_crossfade() {
+        let old = Pipeline {
+            steps: Vec::new(),
+            thread_pool: None,
+        };
+        let mut middle = Pipeline {
+            steps: vec![PipelineStep::ChannelGainsStep(ChannelGains {
+                gains: vec![0.0, 0.0],
+            })],
+            thread_pool: None,
+        };
+        let mut new = Pipeline {
+            steps: vec![PipelineStep::ChannelGainsStep(ChannelGains {
+                gains: vec![0.5, 0.5],
+            })],
+            thread_pool: None,
+        };
+        let make_chunk = || AudioChunk::new(vec![vec![1.0, 1.0], Vec::new()], 1.0, 1.0, 2, 2);
+        let mut fade = Crossfade::new(old, None, 4);
+        let output = fade.process_chunk(&mut middle, make_chunk()).waveforms;
+        assert_eq!(output, vec![vec![1.0, 0.75], Vec::new()]);
+        // Reload halfway, the new crossfade starts from the mix of old and middle
+        let mut fade = Crossfade::new(middle, Some(fade), 4);
+        let output = fade.process_chunk(&mut new, make_chunk()).waveforms;
+        assert_eq!(output, vec![vec![0.5, 0.3125], Vec::new()]);
+        assert!(fade.previous.is_none());
+        let output = fade.process_chunk(&mut new, make_chunk()).waveforms;
+        assert_eq!(output, vec![vec![0.25, 0.375], Vec::new()]);
+        assert!(fade.is_done());
+    }
+
+    #[test]
+    fn ab_switch() {
+        let mut pipeline_a = Pipeline {
+            steps: Vec::new(),
+            thread_pool: None,
+        };
+        let pipeline_b = Pipeline {
+            steps: vec![PipelineStep::ChannelGainsStep(ChannelGains {
+                gains: vec![0.0, 0.0],
+            })],
+            thread_pool: None,
+        };
+        let mut ab = AbSwitch::new(pipeline_b, false, 4);
+        let mut process = |ab: &mut AbSwitch| {
+            let chunk = AudioChunk::new(vec![vec![1.0, 1.0], Vec::new()], 1.0, 1.0, 2, 2);
+            let chunk_b = ab.process_b(&chunk);
+            let chunk_a = pipeline_a.process_chunk(chunk);
+            ab.select_output(chunk_a, chunk_b).waveforms
+        };
+        assert_eq!(process(&mut ab), vec![vec![1.0, 1.0], Vec::new()]);
+        ab.select(true);
+        assert_eq!(process(&mut ab), vec![vec![1.0, 0.75], Vec::new()]);
+        assert_eq!(process(&mut ab), vec![vec![0.5, 0.25], Vec::new()]);
+        assert_eq!(process(&mut ab), vec![vec![0.0, 0.0], Vec::new()]);
+        ab.select(false);
+        assert_eq!(process(&mut ab), vec![vec![0.0, 0.25], Vec::new()]);
+    }
+
+    fn make_parallel_pipeline(worker_threads: usize) -> Pipeline {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 44100
+  chunksize: 16
+  worker_threads: {}
+  capture:
+    type: Stdin
+    channels: 4
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 4
+    format: S16LE
+filters:
+  lowpass:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      freq: 5000
+      q: 0.7
+  fir:
+    type: Conv
+    parameters:
+      type: Values
+      values: [0.5, 0.3, -0.2, 0.1]
+pipeline:
+  - type: Filter
+    channels: all
+    names: [lowpass]
+  - type: Filter
+    channel: 1
+    names: [fir]
+  - type: StereoWidth
+    channels: [0, 1]
+    width: 0.5
+  - type: Filter
+    channels: [0, 1, 3]
+    names: [fir, lowpass]
+",
+            worker_threads
+        );
+        let conf: config::Configuration = serde_yaml::from_str(&yaml).unwrap();
+        let status = Arc::new(RwLock::new(ProcessingParameters::default()));
+        Pipeline::from_config(conf, status)
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        let mut serial = make_parallel_pipeline(1);
+        let mut parallel = make_parallel_pipeline(3);
+        assert!(serial.thread_pool.is_none());
+        assert!(parallel.thread_pool.is_some());
+        // A second pipeline with the same number of threads shares the pool
+        let other = make_parallel_pipeline(3);
+        assert!(Arc::ptr_eq(
+            parallel.thread_pool.as_ref().unwrap(),
+            other.thread_pool.as_ref().unwrap()
+        ));
+        for n in 0..4 {
+            let waveforms: Vec<Vec<PrcFmt>> = (0..4)
+                .map(|ch| {
+                    (0..16)
+                        .map(|i| ((7 * i + 3 * ch + n) % 11) as PrcFmt / 11.0 - 0.5)
+                        .collect()
+                })
+                .collect();
+            let chunk = AudioChunk::new(waveforms.clone(), 0.5, -0.5, 16, 16);
+            let expected = serial.process_chunk(chunk);
+            let chunk = AudioChunk::new(waveforms, 0.5, -0.5, 16, 16);
+            let output = parallel.process_chunk(chunk);
+            assert_eq!(output.waveforms, expected.waveforms);
+        }
+    }
+
+    #[test]
+    fn check_channel_gains() {
+        assert!(validate_channel_gains("input_gains", 2, &[]).is_ok());
+        assert!(validate_channel_gains("input_gains", 2, &[-3.0, 1.5]).is_ok());
+        assert!(validate_channel_gains("input_gains", 2, &[-3.0]).is_err());
+        assert!(validate_channel_gains("input_gains", 2, &[-3.0, 200.0]).is_err());
+        assert!(validate_channel_gains("input_gains", 2, &[-3.0, PrcFmt::NAN]).is_err());
+    }
+
+    #[test]
+    fn filter_bypass() {
+        let filters: HashMap<String, config::Filter> = serde_yaml::from_str(
+            "
+gain:
+  type: Gain
+  parameters:
+    gain: -6.0
+invert:
+  type: Gain
+  bypassed: true
+  parameters:
+    gain: 0.0
+    inverted: true
+",
+        )
+        .unwrap();
+        let status = Arc::new(RwLock::new(ProcessingParameters::default()));
+        let mut group = FilterGroup::from_config(
+            0,
+            0,
+            vec!["gain".to_string(), "invert".to_string()],
+            filters,
+            2,
+            44100,
+            status.clone(),
+        );
+        let mut process = || {
+            let mut chunk = AudioChunk::new(vec![vec![1.0, 1.0]], 1.0, -1.0, 2, 2);
+            group.process_chunk(&mut chunk).unwrap();
+            chunk.waveforms[0][0]
+        };
+        // Only the gain is active from the config
+        assert!(is_close(process(), 0.501, 0.001));
+        // The runtime bypass overrides the config in both directions
+        status
+            .write()
+            .unwrap()
+            .set_filter_bypass("gain".to_string(), true);
+        status
+            .write()
+            .unwrap()
+            .set_filter_bypass("invert".to_string(), false);
+        assert_eq!(process(), -1.0);
+        // Clearing the runtime bypasses goes back to the config
+        status.write().unwrap().clear_filter_bypasses();
+        assert!(is_close(process(), 0.501, 0.001));
+    }
+
+    #[test]
+    fn shelf_gain() {
+        let make_filters = |kind: &str, freq: PrcFmt| -> HashMap<String, config::Filter> {
+            let yaml = format!(
+                "shelf: {{type: ShelfGain, parameters: {{freq: {}, gain_db: 6.0, kind: {}}}}}",
+                freq, kind
+            );
+            serde_yaml::from_str(&yaml).unwrap()
+        };
+        let status = Arc::new(RwLock::new(ProcessingParameters::default()));
+        let mut group = FilterGroup::from_config(
+            0,
+            0,
+            vec!["shelf".to_string()],
+            make_filters("Low", 200.0),
+            4096,
+            44100,
+            status,
+        );
+        let process_dc = |group: &mut FilterGroup| {
+            let mut chunk = AudioChunk::new(vec![vec![1.0; 4096]], 1.0, 1.0, 4096, 4096);
+            group.process_chunk(&mut chunk).unwrap();
+            chunk.waveforms[0][4095]
+        };
+        // A low shelf boosts DC by the full gain, a high shelf leaves it unchanged
+        assert!(is_close(process_dc(&mut group), 1.995, 0.001));
+        group.update_parameters(make_filters("High", 200.0), vec!["shelf".to_string()]);
+        assert!(is_close(process_dc(&mut group), 1.0, 0.001));
+
+        let shelf = &make_filters("Low", 200.0)["shelf"];
+        assert!(validate_filter(44100, 1024, shelf).is_ok());
+        let shelf = &make_filters("High", 30000.0)["shelf"];
+        assert!(validate_filter(44100, 1024, shelf).is_err());
+        let shelf: config::Filter = serde_yaml::from_str(
+            "{type: ShelfGain, parameters: {freq: 200, gain_db: .nan, kind: High}}",
+        )
+        .unwrap();
+        assert!(validate_filter(44100, 1024, &shelf).is_err());
+    }
+
+    #[test]
+    fn reject_non_finite_parameters() {
+        let check = |yaml: &str| {
+            let conf: config::Filter = serde_yaml::from_str(yaml).unwrap();
+            validate_filter(44100, 1024, &conf).map_err(|err| err.to_string())
+        };
+        assert!(check(
+            "{type: Biquad, parameters: {type: Peaking, freq: 1000, q: 1.0, gain: 3.0}}"
+        )
+        .is_ok());
+        assert_eq!(
+            check("{type: Biquad, parameters: {type: Peaking, freq: 1000, q: 1.0, gain: .inf}}"),
+            Err("Parameter 'gain' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: Biquad, parameters: {type: Highpass, freq: .nan, q: 0.7}}"),
+            Err("Parameter 'freq' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: Biquad, parameters: {type: Lowpass, freq: 1000, q: .nan}}"),
+            Err("Parameter 'q' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: Biquad, parameters: {type: Lowshelf, freq: 100, slope: .nan, gain: 3}}"),
+            Err("Parameter 'slope' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: Gain, parameters: {gain: -.inf}}"),
+            Err("Parameter 'gain' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: BiquadCombo, parameters: {type: PeakingBank, bands: [{freq: 100, q: 1, gain: 1}, {freq: 200, q: 1, gain: .nan}]}}"),
+            Err("Parameter 'bands[1].gain' must be a finite number".to_string())
+        );
+        assert_eq!(
+            check("{type: Conv, parameters: {type: Values, values: [1.0, .nan]}}"),
+            Err("Parameter 'values[1]' must be a finite number".to_string())
+        );
+    }
 }
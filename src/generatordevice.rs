@@ -0,0 +1,397 @@
+use crate::audiodevice::*;
+use crate::config;
+use crate::config::ConfigError;
+use rand::{rngs::ThreadRng, thread_rng, Rng};
+use std::sync::mpsc;
+use std::sync::{Arc, Barrier, RwLock};
+use std::thread;
+
+use crate::CommandMessage;
+use crate::PrcFmt;
+use crate::Res;
+use crate::StatusMessage;
+use crate::{CaptureStatus, ProcessingState};
+
+pub struct GeneratorCaptureDevice {
+    pub chunksize: usize,
+    pub samplerate: usize,
+    pub channels: usize,
+    pub signal: config::Signal,
+    pub duration: PrcFmt,
+}
+
+/// State of the Paul Kellet pink noise filter.
+#[derive(Default)]
+struct PinkFilter {
+    b: [PrcFmt; 7],
+}
+
+impl PinkFilter {
+    fn process(&mut self, white: PrcFmt) -> PrcFmt {
+        let b = &mut self.b;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        // Scale to roughly the same peak level as the white noise
+        0.11 * pink
+    }
+}
+
+/// Generator of a mono test signal.
+pub struct SignalGenerator {
+    signal: config::Signal,
+    samplerate: PrcFmt,
+    amplitude: PrcFmt,
+    sweep_length: PrcFmt,
+    position: usize,
+    rng: ThreadRng,
+    pink: PinkFilter,
+}
+
+impl SignalGenerator {
+    /// Create a generator, the duration in seconds is the length of a sweep.
+    pub fn new(signal: config::Signal, samplerate: usize, duration: PrcFmt) -> Self {
+        let level = match signal {
+            config::Signal::Sine { level, .. }
+            | config::Signal::Sweep { level, .. }
+            | config::Signal::WhiteNoise { level }
+            | config::Signal::PinkNoise { level } => level,
+        };
+        SignalGenerator {
+            signal,
+            samplerate: samplerate as PrcFmt,
+            amplitude: PrcFmt::powf(10.0, level / 20.0),
+            sweep_length: duration,
+            position: 0,
+            rng: thread_rng(),
+            pink: PinkFilter::default(),
+        }
+    }
+
+    /// Phase in radians at time t, for a logarithmic sweep.
+    /// This is the exponential sine sweep, with the instantaneous frequency
+    /// f(t) = start_freq * (end_freq / start_freq)^(t / sweep_length).
+    fn sweep_phase(&self, start_freq: PrcFmt, end_freq: PrcFmt, t: PrcFmt) -> PrcFmt {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let ratio = end_freq / start_freq;
+        if (ratio - 1.0).abs() < 1.0e-9 {
+            2.0 * pi * start_freq * t
+        } else {
+            let k = self.sweep_length / ratio.ln();
+            2.0 * pi * start_freq * k * (ratio.powf(t / self.sweep_length) - 1.0)
+        }
+    }
+
+    /// Generate the next value.
+    fn next_value(&mut self) -> PrcFmt {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let t = self.position as PrcFmt / self.samplerate;
+        self.position += 1;
+        let value = match self.signal {
+            config::Signal::Sine { freq, .. } => (2.0 * pi * freq * t).sin(),
+            config::Signal::Sweep {
+                start_freq,
+                end_freq,
+                ..
+            } => self.sweep_phase(start_freq, end_freq, t).sin(),
+            config::Signal::WhiteNoise { .. } => self.rng.gen_range(-1.0..1.0),
+            config::Signal::PinkNoise { .. } => {
+                let white = self.rng.gen_range(-1.0..1.0);
+                self.pink.process(white).clamp(-1.0, 1.0)
+            }
+        };
+        self.amplitude * value
+    }
+
+    /// Generate a waveform of the given number of frames.
+    pub fn generate(&mut self, frames: usize) -> Vec<PrcFmt> {
+        (0..frames).map(|_| self.next_value()).collect()
+    }
+}
+
+/// Validate the parameters of a test signal.
+pub fn validate_signal(signal: &config::Signal, duration: PrcFmt, samplerate: usize) -> Res<()> {
+    if duration < 0.0 {
+        return Err(ConfigError::new("Signal duration cannot be negative").into());
+    }
+    let max_freq = samplerate as PrcFmt / 2.0;
+    let (freqs, level) = match signal {
+        config::Signal::Sine { freq, level } => (vec![*freq], *level),
+        config::Signal::Sweep {
+            start_freq,
+            end_freq,
+            level,
+        } => {
+            if duration == 0.0 {
+                return Err(ConfigError::new("A sweep needs a duration larger than zero").into());
+            }
+            (vec![*start_freq, *end_freq], *level)
+        }
+        config::Signal::WhiteNoise { level } | config::Signal::PinkNoise { level } => {
+            (Vec::new(), *level)
+        }
+    };
+    for freq in freqs {
+        if freq <= 0.0 {
+            return Err(ConfigError::new("Signal frequency must be > 0").into());
+        } else if freq >= max_freq {
+            return Err(ConfigError::new("Signal frequency must be < samplerate/2").into());
+        }
+    }
+    if level > 0.0 {
+        return Err(ConfigError::new("Signal level cannot be larger than 0 dB").into());
+    } else if level < -150.0 {
+        return Err(ConfigError::new("Signal level must be larger than -150 dB").into());
+    }
+    Ok(())
+}
+
+/// Start a capture thread providing AudioMessages with a generated signal via a channel.
+impl CaptureDevice for GeneratorCaptureDevice {
+    fn start(
+        &mut self,
+        channel: mpsc::SyncSender<AudioMessage>,
+        barrier: Arc<Barrier>,
+        status_channel: mpsc::Sender<StatusMessage>,
+        command_channel: mpsc::Receiver<CommandMessage>,
+        capture_status: Arc<RwLock<CaptureStatus>>,
+    ) -> Res<Box<thread::JoinHandle<()>>> {
+        let chunksize = self.chunksize;
+        let samplerate = self.samplerate;
+        let channels = self.channels;
+        let signal = self.signal.clone();
+        let duration = self.duration;
+        let handle = thread::Builder::new()
+            .name("SignalCapture".to_string())
+            .spawn(move || {
+                let mut generator = SignalGenerator::new(signal, samplerate, duration);
+                // A duration of zero means that the signal never ends
+                let mut frames_left = if duration > 0.0 {
+                    Some((duration * samplerate as PrcFmt).round() as usize)
+                } else {
+                    None
+                };
+                status_channel
+                    .send(StatusMessage::CaptureReady)
+                    .unwrap_or(());
+                barrier.wait();
+                debug!("starting signal generator loop");
+                {
+                    let mut capt_stat = capture_status.write().unwrap();
+                    capt_stat.measured_samplerate = samplerate;
+                    capt_stat.state = ProcessingState::Running;
+                }
+                loop {
+                    match command_channel.try_recv() {
+                        Ok(CommandMessage::Exit) => {
+                            debug!("Exit message received, sending EndOfStream");
+                            channel.send(AudioMessage::EndOfStream).unwrap_or(());
+                            status_channel
+                                .send(StatusMessage::CaptureDone)
+                                .unwrap_or(());
+                            break;
+                        }
+                        Ok(CommandMessage::SetSpeed { .. }) => {
+                            trace!("The signal generator does not support rate adjust, ignoring");
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {}
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            error!("Command channel was closed");
+                            break;
+                        }
+                    }
+                    let frames = match frames_left {
+                        Some(left) => left.min(chunksize),
+                        None => chunksize,
+                    };
+                    if frames == 0 {
+                        debug!("Signal generator reached the end of the duration");
+                        channel.send(AudioMessage::EndOfStream).unwrap_or(());
+                        status_channel
+                            .send(StatusMessage::CaptureDone)
+                            .unwrap_or(());
+                        break;
+                    }
+                    if let Some(left) = &mut frames_left {
+                        *left -= frames;
+                    }
+                    let mut waveform = generator.generate(frames);
+                    waveform.resize(chunksize, 0.0);
+                    let maxval = waveform.iter().fold(0.0, |max: PrcFmt, val| max.max(*val));
+                    let minval = waveform.iter().fold(0.0, |min: PrcFmt, val| min.min(*val));
+                    let used_channels = capture_status.read().unwrap().used_channels.clone();
+                    let waveforms = (0..channels)
+                        .map(|ch| {
+                            if used_channels.get(ch).copied().unwrap_or(true) {
+                                waveform.clone()
+                            } else {
+                                Vec::new()
+                            }
+                        })
+                        .collect();
+                    let chunk = AudioChunk::new(waveforms, maxval, minval, chunksize, frames);
+                    {
+                        let chunk_stats = chunk.get_stats();
+                        let mut capt_stat = capture_status.write().unwrap();
+                        capt_stat.signal_range = (maxval - minval) as f32;
+                        capt_stat.signal_rms = chunk_stats.rms_db();
+                        capt_stat.signal_peak = chunk_stats.peak_db();
+                    }
+                    if channel.send(AudioMessage::Audio(chunk)).is_err() {
+                        info!("Processing thread has already stopped.");
+                        break;
+                    }
+                }
+                capture_status.write().unwrap().state = ProcessingState::Inactive;
+            })
+            .unwrap();
+        Ok(Box::new(handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::{AudioMessage, CaptureDevice};
+    use crate::config::Signal;
+    use crate::generatordevice::{validate_signal, GeneratorCaptureDevice, SignalGenerator};
+    use crate::PrcFmt;
+    use crate::{CaptureStatus, ProcessingState};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Barrier, RwLock};
+
+    fn peak(waveform: &[PrcFmt]) -> PrcFmt {
+        waveform
+            .iter()
+            .fold(0.0, |max: PrcFmt, val| max.max(val.abs()))
+    }
+
+    #[test]
+    fn sine_level_and_frequency() {
+        let signal = Signal::Sine {
+            freq: 1000.0,
+            level: -6.0,
+        };
+        let mut generator = SignalGenerator::new(signal, 48000, 0.0);
+        let waveform = generator.generate(4800);
+        assert!((peak(&waveform) - 0.501).abs() < 0.001);
+        // 10 periods in the first 10 ms, starting at zero, give 9 more upwards zero crossings
+        let crossings = waveform[0..480]
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert_eq!(crossings, 9);
+    }
+
+    #[test]
+    fn noise_level() {
+        for signal in [
+            Signal::WhiteNoise { level: -20.0 },
+            Signal::PinkNoise { level: -20.0 },
+        ] {
+            let mut generator = SignalGenerator::new(signal, 48000, 0.0);
+            let waveform = generator.generate(48000);
+            assert!(peak(&waveform) <= 0.1);
+            assert!(peak(&waveform) > 0.05);
+        }
+    }
+
+    #[test]
+    fn sweep_frequency() {
+        let signal = Signal::Sweep {
+            start_freq: 20.0,
+            end_freq: 20000.0,
+            level: 0.0,
+        };
+        let generator = SignalGenerator::new(signal, 48000, 10.0);
+        let pi = std::f64::consts::PI as PrcFmt;
+        let dt = 1.0e-4;
+        for (t, expected) in [(0.0, 20.0), (5.0, 632.456), (10.0, 20000.0)] {
+            let phase_diff = generator.sweep_phase(20.0, 20000.0, t + dt)
+                - generator.sweep_phase(20.0, 20000.0, t);
+            let freq = phase_diff / (2.0 * pi * dt);
+            assert!(
+                (freq / expected - 1.0).abs() < 0.01,
+                "frequency {} at {} s, expected {}",
+                freq,
+                t,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn capture_for_duration() {
+        let mut device = GeneratorCaptureDevice {
+            chunksize: 1024,
+            samplerate: 48000,
+            channels: 2,
+            signal: Signal::Sweep {
+                start_freq: 20.0,
+                end_freq: 20000.0,
+                level: -3.0,
+            },
+            duration: 0.5,
+        };
+        let (tx_audio, rx_audio) = mpsc::sync_channel(10);
+        let (tx_status, _rx_status) = mpsc::channel();
+        let (_tx_command, rx_command) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let status = Arc::new(RwLock::new(CaptureStatus {
+            update_interval: 1000,
+            measured_samplerate: 0,
+            signal_range: 0.0,
+            signal_rms: Vec::new(),
+            signal_peak: Vec::new(),
+            state: ProcessingState::Running,
+            rate_adjust: 0.0,
+            used_channels: vec![true, false],
+        }));
+        let handle = device
+            .start(tx_audio, barrier.clone(), tx_status, rx_command, status)
+            .unwrap();
+        barrier.wait();
+        let mut frames = 0;
+        loop {
+            match rx_audio.recv().unwrap() {
+                AudioMessage::Audio(chunk) => {
+                    assert_eq!(chunk.waveforms[0].len(), 1024);
+                    assert!(chunk.waveforms[1].is_empty());
+                    frames += chunk.valid_frames;
+                }
+                AudioMessage::Pause => {}
+                AudioMessage::EndOfStream => break,
+            }
+        }
+        handle.join().unwrap();
+        assert_eq!(frames, 24000);
+    }
+
+    #[test]
+    fn check_signal() {
+        let sine = Signal::Sine {
+            freq: 1000.0,
+            level: -6.0,
+        };
+        assert!(validate_signal(&sine, 0.0, 48000).is_ok());
+        assert!(validate_signal(&sine, -1.0, 48000).is_err());
+        let high = Signal::Sine {
+            freq: 30000.0,
+            level: -6.0,
+        };
+        assert!(validate_signal(&high, 0.0, 48000).is_err());
+        let loud = Signal::WhiteNoise { level: 3.0 };
+        assert!(validate_signal(&loud, 0.0, 48000).is_err());
+        let sweep = Signal::Sweep {
+            start_freq: 20.0,
+            end_freq: 20000.0,
+            level: -6.0,
+        };
+        assert!(validate_signal(&sweep, 10.0, 48000).is_ok());
+        assert!(validate_signal(&sweep, 0.0, 48000).is_err());
+    }
+}
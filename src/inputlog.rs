@@ -0,0 +1,192 @@
+use crate::audiodevice::AudioChunk;
+use crate::config::SampleFormat;
+use crate::filedevice::{is_wav, update_wav_header, wav_header};
+use crate::PrcFmt;
+use crate::Res;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+use std::thread;
+
+/// Number of chunks that can wait for the writer before new ones are dropped.
+pub const INPUT_LOG_QUEUE_CHUNKS: usize = 64;
+
+/// Sample format of the log, the same as the floats used for processing.
+#[cfg(not(feature = "32bit"))]
+const LOG_FORMAT: SampleFormat = SampleFormat::FLOAT64LE;
+#[cfg(feature = "32bit")]
+const LOG_FORMAT: SampleFormat = SampleFormat::FLOAT32LE;
+
+/// Log of the audio entering the pipeline, written to a file by a separate thread.
+/// This is a tap at the start of the processing, not in the capture device,
+/// so channels that the pipeline does not use are already dropped and are logged as silence.
+/// The file is a wav file if the name ends with ".wav", and raw samples otherwise.
+///
+/// The chunks are interleaved into byte buffers that the writer sends back when done,
+/// so that the processing thread reuses them instead of allocating for every chunk.
+pub struct InputLog {
+    sender: mpsc::SyncSender<Vec<u8>>,
+    returned: mpsc::Receiver<Vec<u8>>,
+    spare: Option<Vec<u8>>,
+    dropping: bool,
+    dropped_chunks: usize,
+}
+
+impl InputLog {
+    /// Create the file and start the writer thread.
+    pub fn new(filename: &str, channels: usize, samplerate: usize) -> Res<Self> {
+        let mut file = File::create(filename)?;
+        let wav_file = if is_wav(filename) {
+            let header = wav_header(channels, samplerate, &LOG_FORMAT, 0);
+            file.write_all(&header)?;
+            Some((file.try_clone()?, header.len() as u64))
+        } else {
+            None
+        };
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(INPUT_LOG_QUEUE_CHUNKS);
+        // One more than the queue, so that returning a buffer never has to wait
+        let (return_sender, returned) = mpsc::sync_channel::<Vec<u8>>(INPUT_LOG_QUEUE_CHUNKS + 1);
+        let name = filename.to_string();
+        thread::Builder::new()
+            .name("InputLog".to_string())
+            .spawn(move || {
+                write_log(
+                    receiver,
+                    return_sender,
+                    BufWriter::new(file),
+                    wav_file,
+                    &name,
+                )
+            })?;
+        debug!("Logging pipeline input to '{}'", filename);
+        Ok(InputLog {
+            sender,
+            returned,
+            spare: None,
+            dropping: false,
+            dropped_chunks: 0,
+        })
+    }
+
+    /// Queue the samples of a chunk for writing. This never blocks,
+    /// the chunk is dropped with a warning if the writer is falling behind.
+    pub fn push(&mut self, chunk: &AudioChunk) {
+        let mut data = self
+            .spare
+            .take()
+            .or_else(|| self.returned.try_recv().ok())
+            .unwrap_or_default();
+        interleave(chunk, &mut data);
+        match self.sender.try_send(data) {
+            Ok(()) => {
+                if self.dropping {
+                    warn!(
+                        "Input log caught up, {} chunks were dropped",
+                        self.dropped_chunks
+                    );
+                    self.dropping = false;
+                    self.dropped_chunks = 0;
+                }
+            }
+            Err(mpsc::TrySendError::Full(data)) => {
+                if !self.dropping {
+                    warn!("Input log is falling behind, dropping chunks");
+                    self.dropping = true;
+                }
+                self.dropped_chunks += 1;
+                self.spare = Some(data);
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// Write buffers until the sending side is dropped, then update the wav header.
+/// Each written buffer is sent back for reuse.
+fn write_log<W: Write>(
+    receiver: mpsc::Receiver<Vec<u8>>,
+    return_sender: mpsc::SyncSender<Vec<u8>>,
+    mut writer: W,
+    mut wav_file: Option<(File, u64)>,
+    name: &str,
+) {
+    let mut data_bytes: u64 = 0;
+    while let Ok(data) = receiver.recv() {
+        if let Err(err) = writer.write_all(&data) {
+            error!("Unable to write input log '{}', error: {}", name, err);
+            return;
+        }
+        data_bytes += data.len() as u64;
+        let _ = return_sender.try_send(data);
+    }
+    if let Err(err) = writer.flush() {
+        error!("Unable to write input log '{}', error: {}", name, err);
+    }
+    if let Some((file, header_length)) = wav_file.as_mut() {
+        if let Err(err) = update_wav_header(file, *header_length, data_bytes) {
+            error!("Unable to update wav header of '{}': {}", name, err);
+        }
+    }
+    debug!("Input log '{}' closed", name);
+}
+
+/// Interleave the valid frames of a chunk as little-endian floats, replacing the contents of `data`.
+/// Unused channels, that have empty waveforms, are filled with zeros.
+fn interleave(chunk: &AudioChunk, data: &mut Vec<u8>) {
+    data.clear();
+    data.reserve(std::mem::size_of::<PrcFmt>() * chunk.valid_frames * chunk.channels);
+    for frame in 0..chunk.valid_frames {
+        for waveform in chunk.waveforms.iter() {
+            let value = waveform.get(frame).copied().unwrap_or(0.0);
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::inputlog::{interleave, write_log};
+    use crate::PrcFmt;
+    use std::sync::mpsc;
+
+    fn to_values(data: &[u8]) -> Vec<PrcFmt> {
+        data.chunks(std::mem::size_of::<PrcFmt>())
+            .map(|b| {
+                let mut arr = [0u8; std::mem::size_of::<PrcFmt>()];
+                arr.copy_from_slice(b);
+                PrcFmt::from_le_bytes(arr)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_raw_log() {
+        let (sender, receiver) = mpsc::sync_channel(4);
+        let (return_sender, returned) = mpsc::sync_channel(5);
+        let chunk = AudioChunk::new(vec![vec![0.5, 0.25], Vec::new()], 0.5, 0.0, 2, 2);
+        let mut buffer = Vec::new();
+        interleave(&chunk, &mut buffer);
+        sender.send(buffer).unwrap();
+        drop(sender);
+        let mut data = Vec::new();
+        write_log(receiver, return_sender, &mut data, None, "test");
+        assert_eq!(to_values(&data), vec![0.5, 0.0, 0.25, 0.0]);
+        // The written buffer is handed back for reuse
+        assert_eq!(returned.try_recv().unwrap().len(), data.len());
+    }
+
+    #[test]
+    fn interleave_replaces_contents() {
+        let chunk = AudioChunk::new(
+            vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]],
+            6.0,
+            1.0,
+            3,
+            2,
+        );
+        let mut data = vec![0xff; 100];
+        interleave(&chunk, &mut data);
+        assert_eq!(to_values(&data), vec![1.0, 4.0, 2.0, 5.0]);
+    }
+}
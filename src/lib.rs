@@ -46,9 +46,12 @@ extern crate wasapi;
 extern crate log;
 
 use serde::Serialize;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 // Sample format
 #[cfg(feature = "32bit")]
@@ -56,6 +59,9 @@ pub type PrcFmt = f32;
 #[cfg(not(feature = "32bit"))]
 pub type PrcFmt = f64;
 
+/// Number of bits of the floats used for processing, selected at build time by the `32bit` feature.
+pub const PROCESSING_BITS: usize = 8 * std::mem::size_of::<PrcFmt>();
+
 pub trait NewValue<T> {
     fn new(val: T) -> Self;
 }
@@ -70,10 +76,12 @@ pub type Res<T> = Result<T, Box<dyn error::Error>>;
 
 #[cfg(target_os = "linux")]
 pub mod alsadevice;
+pub mod analysis;
 pub mod audiodevice;
 pub mod basicfilters;
 pub mod biquad;
 pub mod biquadcombo;
+pub mod compressor;
 pub mod config;
 pub mod conversions;
 #[cfg(target_os = "macos")]
@@ -83,7 +91,7 @@ pub mod countertimer;
 pub mod cpaldevice;
 pub mod diffeq;
 pub mod dither;
-#[cfg(not(feature = "FFTW"))]
+pub mod fft;
 pub mod fftconv;
 #[cfg(feature = "FFTW")]
 pub mod fftconv_fftw;
@@ -94,12 +102,17 @@ pub mod filereader;
 #[cfg(target_os = "linux")]
 pub mod filereader_nonblock;
 pub mod filters;
+pub mod generatordevice;
 pub mod helpers;
+pub mod inputlog;
+pub mod limiter;
 pub mod loudness;
 pub mod mixer;
+pub mod outputtap;
 pub mod processing;
 #[cfg(feature = "pulse-backend")]
 pub mod pulsedevice;
+pub mod saturation;
 #[cfg(feature = "websocket")]
 pub mod socketserver;
 #[cfg(target_os = "windows")]
@@ -115,6 +128,26 @@ pub enum StatusMessage {
     PlaybackDone,
     CaptureDone,
     SetSpeed(f64),
+    ProcessingTime(f32),
+    /// Average delay of the playback buffer in milliseconds.
+    PlaybackLatency(f32),
+    /// The playback device recovered from a buffer underrun,
+    /// `count` is the number of underruns since the device was opened.
+    PlaybackXrun {
+        count: usize,
+        time: SystemTime,
+    },
+    /// The capture device recovered from a buffer overrun,
+    /// `count` is the number of overruns since the device was opened.
+    CaptureXrun {
+        count: usize,
+        time: SystemTime,
+    },
+    Spectrum(Vec<f32>),
+    CaptureSilence {
+        silent: bool,
+        time: SystemTime,
+    },
 }
 
 pub enum CommandMessage {
@@ -165,20 +198,92 @@ pub struct CaptureStatus {
 pub struct PlaybackStatus {
     pub update_interval: usize,
     pub clipped_samples: usize,
+    pub clipped_channels: ClippedChannels,
     pub buffer_level: usize,
     pub signal_rms: Vec<f32>,
     pub signal_peak: Vec<f32>,
 }
 
-#[derive(Clone, Debug)]
+impl PlaybackStatus {
+    /// Add the samples that were clipped in a chunk to the counts.
+    pub fn add_clipped(&mut self, clipped: &conversions::ClipCounter) {
+        self.clipped_samples += clipped.total();
+        let counts = &mut self.clipped_channels;
+        if counts.samples.len() < clipped.clipped.len() {
+            counts.samples.resize(clipped.clipped.len(), 0);
+            counts.peak.resize(clipped.clipped.len(), 0.0);
+        }
+        for (chan, (nbr, peak)) in clipped.clipped.iter().zip(clipped.peak.iter()).enumerate() {
+            counts.samples[chan] += nbr;
+            counts.peak[chan] = counts.peak[chan].max(peak.abs() as f32);
+        }
+    }
+
+    /// Clear the clipping counts, both the total and the ones per channel.
+    pub fn reset_clipped(&mut self) {
+        self.clipped_samples = 0;
+        self.clipped_channels = ClippedChannels::default();
+    }
+}
+
+/// Number of clipped samples of each playback channel, and the largest absolute value of them.
+/// A peak of 1.0 is full scale, and the peak is zero for channels that have not clipped.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ClippedChannels {
+    pub samples: Vec<usize>,
+    pub peak: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct ProcessingParameters {
     pub volume: f32,
     pub mute: bool,
+    /// Bypass of filters set at runtime, overriding the `bypassed` flag in the config.
+    pub bypassed_filters: HashMap<String, bool>,
+    /// Counts the changes of `bypassed_filters`, so the filters only need to look them up after a change.
+    /// It is shared with the filters, that can read it without locking the parameters.
+    pub bypass_changes: Arc<AtomicUsize>,
+    /// Config B for A/B comparisons, loaded at runtime.
+    pub config_b: Option<config::Configuration>,
+    /// Counts the changes of `config_b`, so the processing thread knows when to rebuild it.
+    pub config_b_changes: usize,
+    /// Process with config B instead of the active config.
+    pub use_config_b: bool,
+}
+
+impl ProcessingParameters {
+    /// Bypass a filter at runtime, or enable it again.
+    pub fn set_filter_bypass(&mut self, name: String, bypassed: bool) {
+        self.bypassed_filters.insert(name, bypassed);
+        self.bypass_changes.fetch_add(1, Ordering::Release);
+    }
+
+    /// Remove all runtime bypasses, so the filters are bypassed as given by the config.
+    pub fn clear_filter_bypasses(&mut self) {
+        self.bypassed_filters.clear();
+        self.bypass_changes.fetch_add(1, Ordering::Release);
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ProcessingStatus {
     pub stop_reason: StopReason,
+    pub processing_time: f32,
+    /// The average delay of the playback buffer in milliseconds, zero if not measured.
+    pub playback_latency: f32,
+    /// The last magnitude spectrum from the spectrum probe, in dB.
+    pub spectrum: Vec<f32>,
+    pub xruns: XrunCounts,
+}
+
+/// Number of buffer underruns and overruns (xruns) of the devices since CamillaDSP was started.
+/// The times are in seconds since the Unix epoch, and zero until there has been an xrun.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct XrunCounts {
+    pub playback: usize,
+    pub capture: usize,
+    pub last_playback_time: f64,
+    pub last_capture_time: f64,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
@@ -198,6 +303,8 @@ pub struct StatusStructs {
     pub playback: Arc<RwLock<PlaybackStatus>>,
     pub processing: Arc<RwLock<ProcessingParameters>>,
     pub status: Arc<RwLock<ProcessingStatus>>,
+    /// Optional tap receiving a copy of every processed chunk.
+    pub tap: Option<Arc<outputtap::OutputTap>>,
 }
 
 impl fmt::Display for ProcessingState {
@@ -215,7 +322,7 @@ impl fmt::Display for ProcessingState {
 
 pub fn list_supported_devices() -> (Vec<String>, Vec<String>) {
     let mut playbacktypes = vec!["File".to_owned(), "Stdout".to_owned()];
-    let mut capturetypes = vec!["File".to_owned(), "Stdin".to_owned()];
+    let mut capturetypes = vec!["File".to_owned(), "Stdin".to_owned(), "Signal".to_owned()];
 
     if cfg!(target_os = "linux") {
         playbacktypes.push("Alsa".to_owned());
@@ -0,0 +1,192 @@
+use crate::config;
+use crate::filters::Filter;
+
+use crate::NewValue;
+use crate::PrcFmt;
+use crate::Res;
+
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    pub name: String,
+    samplerate: usize,
+    threshold: PrcFmt,
+    attack: PrcFmt,
+    release: PrcFmt,
+    envelope: PrcFmt,
+}
+
+/// Calculate the coefficient of a one-pole smoothing filter with the given time constant.
+pub fn time_constant_coeff(time_ms: PrcFmt, samplerate: usize) -> PrcFmt {
+    if time_ms > 0.0 {
+        (-1000.0 / (time_ms * samplerate as PrcFmt)).exp()
+    } else {
+        0.0
+    }
+}
+
+impl Limiter {
+    /// Creates a Limiter from a config struct
+    pub fn from_config(name: String, samplerate: usize, conf: config::LimiterParameters) -> Self {
+        let threshold = PrcFmt::new(10.0).powf(conf.threshold_db / 20.0);
+        let attack = time_constant_coeff(conf.attack_ms, samplerate);
+        let release = time_constant_coeff(conf.release_ms, samplerate);
+        debug!(
+            "Creating limiter '{}', threshold: {} dB, attack: {} ms, release: {} ms",
+            name, conf.threshold_db, conf.attack_ms, conf.release_ms
+        );
+        Limiter {
+            name,
+            samplerate,
+            threshold,
+            attack,
+            release,
+            envelope: 0.0,
+        }
+    }
+
+    /// Process a single sample
+    fn process_single(&mut self, input: PrcFmt) -> PrcFmt {
+        let level = input.abs();
+        let coeff = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + coeff * (self.envelope - level);
+        let gain = if self.envelope > self.threshold {
+            self.threshold / self.envelope
+        } else {
+            1.0
+        };
+        // The envelope lags behind fast transients during the attack,
+        // clamp what gets through so that the threshold is never exceeded.
+        let output = input * gain;
+        if output > self.threshold {
+            self.threshold
+        } else if output < -self.threshold {
+            -self.threshold
+        } else {
+            output
+        }
+    }
+}
+
+impl Filter for Limiter {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            *item = self.process_single(*item);
+        }
+        if self.envelope.is_subnormal() {
+            self.envelope = 0.0;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::Limiter {
+            parameters: conf, ..
+        } = conf
+        {
+            self.threshold = PrcFmt::new(10.0).powf(conf.threshold_db / 20.0);
+            self.attack = time_constant_coeff(conf.attack_ms, self.samplerate);
+            self.release = time_constant_coeff(conf.release_ms, self.samplerate);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a Limiter config.
+pub fn validate_config(conf: &config::LimiterParameters) -> Res<()> {
+    if conf.threshold_db < -100.0 {
+        return Err(config::ConfigError::new("Threshold must be larger than -100 dB").into());
+    } else if conf.threshold_db > 50.0 {
+        return Err(config::ConfigError::new("Threshold must be less than +50 dB").into());
+    } else if conf.attack_ms < 0.0 {
+        return Err(config::ConfigError::new("Attack time cannot be negative").into());
+    } else if conf.release_ms < 0.0 {
+        return Err(config::ConfigError::new("Release time cannot be negative").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::LimiterParameters;
+    use crate::filters::Filter;
+    use crate::limiter::{validate_config, Limiter};
+    use crate::PrcFmt;
+
+    fn make_sine(ampl: PrcFmt, freq: PrcFmt, fs: usize, len: usize) -> Vec<PrcFmt> {
+        let pi = std::f64::consts::PI as PrcFmt;
+        (0..len)
+            .map(|n| ampl * (2.0 * pi * freq * n as PrcFmt / fs as PrcFmt).sin())
+            .collect()
+    }
+
+    #[test]
+    fn limit_loud_sine() {
+        let conf = LimiterParameters {
+            threshold_db: -6.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+        };
+        let threshold = (10.0 as PrcFmt).powf(-6.0 / 20.0);
+        let mut limiter = Limiter::from_config("test".to_string(), 48000, conf);
+        let sine = make_sine(2.0, 997.0, 48000, 48000);
+        for chunk in sine.chunks(1024) {
+            let mut waveform = chunk.to_vec();
+            limiter.process_waveform(&mut waveform).unwrap();
+            let peak = waveform
+                .iter()
+                .fold(0.0, |max: PrcFmt, val| max.max(val.abs()));
+            assert!(
+                peak <= threshold,
+                "peak {} above threshold {}",
+                peak,
+                threshold
+            );
+        }
+    }
+
+    #[test]
+    fn pass_quiet_sine() {
+        let conf = LimiterParameters {
+            threshold_db: -6.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+        };
+        let mut limiter = Limiter::from_config("test".to_string(), 48000, conf);
+        let sine = make_sine(0.25, 997.0, 48000, 4800);
+        let mut waveform = sine.clone();
+        limiter.process_waveform(&mut waveform).unwrap();
+        assert_eq!(waveform, sine);
+    }
+
+    #[test]
+    fn check_config() {
+        let okconf = LimiterParameters {
+            threshold_db: -1.0,
+            attack_ms: 0.0,
+            release_ms: 50.0,
+        };
+        assert!(validate_config(&okconf).is_ok());
+        let badconf = LimiterParameters {
+            threshold_db: -1.0,
+            attack_ms: -1.0,
+            release_ms: 50.0,
+        };
+        assert!(validate_config(&badconf).is_err());
+        let badconf = LimiterParameters {
+            threshold_db: -1.0,
+            attack_ms: 1.0,
+            release_ms: -50.0,
+        };
+        assert!(validate_config(&badconf).is_err());
+    }
+}
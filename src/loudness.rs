@@ -109,6 +109,35 @@ impl Loudness {
             })
             .collect()
     }
+
+    /// Update the shelving filters for the boost needed at the given volume level.
+    fn update_biquads(&mut self, level: f32) {
+        let relboost = get_rel_boost(level, self.reference_level);
+        trace!(
+            "Updating loudness biquads, relative boost {}%",
+            100.0 * relboost
+        );
+        let highshelf_conf = config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope {
+            freq: 3500.0,
+            slope: 12.0,
+            gain: (relboost * self.high_boost) as PrcFmt,
+        });
+        let lowshelf_conf = config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope {
+            freq: 70.0,
+            slope: 12.0,
+            gain: (relboost * self.low_boost) as PrcFmt,
+        });
+        self.high_biquad.update_parameters(config::Filter::Biquad {
+            description: None,
+            bypassed: false,
+            parameters: highshelf_conf,
+        });
+        self.low_biquad.update_parameters(config::Filter::Biquad {
+            description: None,
+            bypassed: false,
+            parameters: lowshelf_conf,
+        });
+    }
 }
 
 impl Filter for Loudness {
@@ -144,6 +173,7 @@ impl Filter for Loudness {
                     shared_vol as PrcFmt
                 };
                 self.ramp_step = 0;
+                self.update_biquads(self.current_volume as f32);
             }
             self.target_volume = shared_vol;
             self.target_linear_gain = if shared_mute {
@@ -174,28 +204,7 @@ impl Filter for Loudness {
                 *item *= *stepgain;
             }
             self.current_volume = 20.0 * ramp.last().unwrap().log10();
-            let relboost = get_rel_boost(self.current_volume as f32, self.reference_level);
-            trace!(
-                "Updating loudness biquads, relative boost {}%",
-                100.0 * relboost
-            );
-            let highshelf_conf =
-                config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope {
-                    freq: 3500.0,
-                    slope: 12.0,
-                    gain: (relboost * self.high_boost) as PrcFmt,
-                });
-            let lowshelf_conf = config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope {
-                freq: 70.0,
-                slope: 12.0,
-                gain: (relboost * self.low_boost) as PrcFmt,
-            });
-            self.high_biquad.update_parameters(config::Filter::Biquad {
-                parameters: highshelf_conf,
-            });
-            self.low_biquad.update_parameters(config::Filter::Biquad {
-                parameters: lowshelf_conf,
-            });
+            self.update_biquads(self.current_volume as f32);
         }
         if get_rel_boost(self.current_volume as f32, self.reference_level) > 0.0 {
             trace!("Applying loudness biquads");
@@ -206,32 +215,18 @@ impl Filter for Loudness {
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
-        if let config::Filter::Loudness { parameters: conf } = conf {
+        if let config::Filter::Loudness {
+            parameters: conf, ..
+        } = conf
+        {
             self.ramptime_in_chunks = (conf.ramp_time
                 / (1000.0 * self.chunksize as f32 / self.samplerate as f32))
                 .round() as usize;
-            let current_volume = self.processing_status.read().unwrap().volume;
-            let relboost = get_rel_boost(current_volume, conf.reference_level);
-            let highshelf_conf =
-                config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope {
-                    freq: 3500.0,
-                    slope: 12.0,
-                    gain: (relboost * conf.high_boost) as PrcFmt,
-                });
-            let lowshelf_conf = config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope {
-                freq: 70.0,
-                slope: 12.0,
-                gain: (relboost * conf.low_boost) as PrcFmt,
-            });
-            self.high_biquad.update_parameters(config::Filter::Biquad {
-                parameters: highshelf_conf,
-            });
-            self.low_biquad.update_parameters(config::Filter::Biquad {
-                parameters: lowshelf_conf,
-            });
             self.reference_level = conf.reference_level;
             self.high_boost = conf.high_boost;
             self.low_boost = conf.low_boost;
+            let current_volume = self.processing_status.read().unwrap().volume;
+            self.update_biquads(current_volume);
         } else {
             // This should never happen unless there is a bug somewhere else
             panic!("Invalid config change!");
@@ -258,3 +253,100 @@ pub fn validate_config(conf: &config::LoudnessParameters) -> Res<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::LoudnessParameters;
+    use crate::filters::Filter;
+    use crate::loudness::{get_rel_boost, validate_config, Loudness};
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::sync::{Arc, RwLock};
+
+    fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
+        println!("{} - {}", left, right);
+        (left - right).abs() < maxdiff
+    }
+
+    fn make_conf(ramp_time: f32) -> LoudnessParameters {
+        LoudnessParameters {
+            ramp_time,
+            reference_level: -25.0,
+            high_boost: 10.0,
+            low_boost: 10.0,
+        }
+    }
+
+    // Run a sine through the filter and return the gain in dB, measured over the last half.
+    fn measure_gain(loudness: &mut Loudness, freq: PrcFmt) -> PrcFmt {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let mut peak: PrcFmt = 0.0;
+        for n in 0..96 {
+            let mut waveform: Vec<PrcFmt> = (0..1000)
+                .map(|i| (2.0 * pi * freq * (1000 * n + i) as PrcFmt / 48000.0).sin())
+                .collect();
+            loudness.process_waveform(&mut waveform).unwrap();
+            if n >= 48 {
+                peak = waveform.iter().fold(peak, |max, val| max.max(val.abs()));
+            }
+        }
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn check_rel_boost() {
+        assert_eq!(get_rel_boost(-20.0, -25.0), 0.0);
+        assert_eq!(get_rel_boost(-35.0, -25.0), 0.5);
+        assert_eq!(get_rel_boost(-60.0, -25.0), 1.0);
+    }
+
+    #[test]
+    fn boost_follows_volume() {
+        let status = Arc::new(RwLock::new(ProcessingParameters {
+            volume: -25.0,
+            ..Default::default()
+        }));
+        let mut loudness = Loudness::from_config(
+            "test".to_string(),
+            make_conf(0.0),
+            1000,
+            48000,
+            status.clone(),
+        );
+        // No boost at the reference level
+        assert!(is_close(measure_gain(&mut loudness, 20.0), -25.0, 0.01));
+        // Full boost of the low frequencies 20 dB below the reference level
+        status.write().unwrap().volume = -45.0;
+        assert!(is_close(measure_gain(&mut loudness, 20.0), -35.0, 0.5));
+        assert!(is_close(measure_gain(&mut loudness, 1000.0), -45.0, 1.0));
+    }
+
+    #[test]
+    fn boost_follows_volume_ramp() {
+        let status = Arc::new(RwLock::new(ProcessingParameters {
+            volume: -25.0,
+            ..Default::default()
+        }));
+        let mut loudness = Loudness::from_config(
+            "test".to_string(),
+            make_conf(200.0),
+            1000,
+            48000,
+            status.clone(),
+        );
+        status.write().unwrap().volume = -35.0;
+        assert!(is_close(measure_gain(&mut loudness, 20.0), -30.0, 0.5));
+    }
+
+    #[test]
+    fn check_config() {
+        assert!(validate_config(&make_conf(0.0)).is_ok());
+        let mut badconf = make_conf(0.0);
+        badconf.reference_level = 5.0;
+        assert!(validate_config(&badconf).is_err());
+        let mut badconf = make_conf(0.0);
+        badconf.low_boost = 25.0;
+        assert!(validate_config(&badconf).is_err());
+        assert!(validate_config(&make_conf(-1.0)).is_err());
+    }
+}
@@ -3,12 +3,16 @@ use crate::config;
 use crate::PrcFmt;
 use crate::Res;
 
+/// Format used for summing the mixer sources, the widest available float.
+type MixFmt = f64;
+
 #[derive(Clone)]
 pub struct Mixer {
     pub name: String,
     pub channels_in: usize,
     pub channels_out: usize,
     pub mapping: Vec<Vec<MixerSource>>,
+    sums: Vec<MixFmt>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -17,77 +21,69 @@ pub struct MixerSource {
     pub gain: PrcFmt,
 }
 
+/// Build the list of sources for each output channel, leaving out muted mappings and sources.
+fn make_mapping(config: config::Mixer) -> Vec<Vec<MixerSource>> {
+    let mut mapping = vec![Vec::<MixerSource>::new(); config.channels.out];
+    for cfg_mapping in config.mapping {
+        if !cfg_mapping.mute {
+            let dest = cfg_mapping.dest;
+            for cfg_src in cfg_mapping.sources {
+                if let (false, Some(channel)) = (cfg_src.mute, cfg_src.channel) {
+                    let mut gain: PrcFmt = 10.0;
+                    gain = gain.powf(cfg_src.gain / 20.0);
+                    if cfg_src.inverted {
+                        gain = -gain;
+                    }
+                    let src = MixerSource { channel, gain };
+                    mapping[dest].push(src);
+                }
+            }
+        }
+    }
+    mapping
+}
+
 impl Mixer {
     /// Creates a Mixer from a config struct
     pub fn from_config(name: String, config: config::Mixer) -> Self {
         let ch_in = config.channels.r#in;
         let ch_out = config.channels.out;
-        let mut mapping = vec![Vec::<MixerSource>::new(); ch_out];
-        for cfg_mapping in config.mapping {
-            if !cfg_mapping.mute {
-                let dest = cfg_mapping.dest;
-                for cfg_src in cfg_mapping.sources {
-                    if !cfg_src.mute {
-                        let mut gain: PrcFmt = 10.0;
-                        gain = gain.powf(cfg_src.gain / 20.0);
-                        if cfg_src.inverted {
-                            gain = -gain;
-                        }
-                        let src = MixerSource {
-                            channel: cfg_src.channel,
-                            gain,
-                        };
-                        mapping[dest].push(src);
-                    }
-                }
-            }
-        }
+        let mapping = make_mapping(config);
         Mixer {
             name,
             channels_in: ch_in,
             channels_out: ch_out,
             mapping,
+            sums: Vec::new(),
         }
     }
 
     pub fn update_parameters(&mut self, config: config::Mixer) {
-        let ch_in = config.channels.r#in;
-        let ch_out = config.channels.out;
-        let mut mapping = vec![Vec::<MixerSource>::new(); ch_out];
-        for cfg_mapping in config.mapping {
-            let dest = cfg_mapping.dest;
-            for cfg_src in cfg_mapping.sources {
-                let mut gain: PrcFmt = 10.0;
-                gain = gain.powf(cfg_src.gain / 20.0);
-                if cfg_src.inverted {
-                    gain = -gain;
-                }
-                let src = MixerSource {
-                    channel: cfg_src.channel,
-                    gain,
-                };
-                mapping[dest].push(src);
-            }
-        }
-        self.channels_in = ch_in;
-        self.channels_out = ch_out;
-        self.mapping = mapping;
+        self.channels_in = config.channels.r#in;
+        self.channels_out = config.channels.out;
+        self.mapping = make_mapping(config);
     }
 
     /// Apply a Mixer to an AudioChunk, yielding a new AudioChunk with a possibly different number of channels.
+    /// The sources of each output are summed in f64 also when processing is done in f32,
+    /// so that intermediate sums of many loud sources can't overflow or lose precision.
+    /// Only the final sum is converted back to the processing format.
     pub fn process_chunk(&mut self, input: &AudioChunk) -> AudioChunk {
         let mut waveforms = Vec::<Vec<PrcFmt>>::with_capacity(self.channels_out);
+        let sums = &mut self.sums;
         for out_chan in 0..self.channels_out {
-            waveforms.push(vec![0.0; input.frames]);
-            for source in 0..self.mapping[out_chan].len() {
-                let source_chan = self.mapping[out_chan][source].channel;
-                if !input.waveforms[source_chan].is_empty() {
-                    let gain = self.mapping[out_chan][source].gain;
-                    for n in 0..input.frames {
-                        waveforms[out_chan][n] += gain * input.waveforms[source_chan][n];
+            sums.clear();
+            sums.resize(input.frames, 0.0);
+            for source in self.mapping[out_chan].iter() {
+                let waveform = &input.waveforms[source.channel];
+                if !waveform.is_empty() {
+                    let gain = source.gain as MixFmt;
+                    for (sum, value) in sums.iter_mut().zip(waveform.iter()) {
+                        *sum += gain * *value as MixFmt;
                     }
                 }
             }
+            waveforms.push(sums.iter().map(|sum| *sum as PrcFmt).collect());
         }
 
         AudioChunk::from(input, waveforms)
@@ -108,19 +104,76 @@ pub fn validate_mixer(mixer_config: &config::Mixer) -> Res<()> {
             return Err(config::ConfigError::new(&msg).into());
         }
         for source in mapping.sources.iter() {
-            if source.channel >= chan_in {
+            let channel = match source.channel {
+                Some(channel) => channel,
+                None => {
+                    let msg = format!(
+                        "Mixer source for destination channel {} needs a channel or a label.",
+                        mapping.dest
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            };
+            if channel >= chan_in {
                 let msg = format!(
                     "Invalid source channel {}, max is {}.",
-                    source.channel,
+                    channel,
                     chan_in - 1
                 );
                 return Err(config::ConfigError::new(&msg).into());
             }
         }
     }
+    for dest in unmapped_destinations(mixer_config) {
+        warn!(
+            "Mixer destination channel {} has no mapping, it will be silent.",
+            dest
+        );
+    }
+    for (dest, source) in opposite_polarity_sources(mixer_config) {
+        warn!(
+            "Mixer destination channel {} gets source channel {} both inverted and not inverted, they may cancel out.",
+            dest, source
+        );
+    }
     Ok(())
 }
 
+/// Get the destination channels that no mapping writes to.
+fn unmapped_destinations(mixer_config: &config::Mixer) -> Vec<usize> {
+    (0..mixer_config.channels.out)
+        .filter(|dest| !mixer_config.mapping.iter().any(|m| m.dest == *dest))
+        .collect()
+}
+
+/// Get the pairs of destination and source channels,
+/// where the destination gets the same source both with and without inversion.
+/// Muted mappings and sources are ignored.
+fn opposite_polarity_sources(mixer_config: &config::Mixer) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    for dest in 0..mixer_config.channels.out {
+        let sources: Vec<&config::MixerSource> = mixer_config
+            .mapping
+            .iter()
+            .filter(|m| m.dest == dest && !m.mute)
+            .flat_map(|m| m.sources.iter())
+            .filter(|s| !s.mute)
+            .collect();
+        for source in 0..mixer_config.channels.r#in {
+            let inverted = sources
+                .iter()
+                .any(|s| s.channel == Some(source) && s.inverted);
+            let normal = sources
+                .iter()
+                .any(|s| s.channel == Some(source) && !s.inverted);
+            if inverted && normal {
+                found.push((dest, source));
+            }
+        }
+    }
+    found
+}
+
 /// Get a vector showing which input channels are used
 pub fn get_used_input_channels(mixer_config: &config::Mixer) -> Vec<bool> {
     let chan_in = mixer_config.channels.r#in;
@@ -128,8 +181,8 @@ pub fn get_used_input_channels(mixer_config: &config::Mixer) -> Vec<bool> {
     for mapping in mixer_config.mapping.iter() {
         if !mapping.mute {
             for source in mapping.sources.iter() {
-                if !source.mute {
-                    used_channels[source.channel] = true;
+                if let (false, Some(channel)) = (source.mute, source.channel) {
+                    used_channels[channel] = true;
                 }
             }
         }
@@ -139,33 +192,40 @@ pub fn get_used_input_channels(mixer_config: &config::Mixer) -> Vec<bool> {
 
 #[cfg(test)]
 mod tests {
+    use crate::audiodevice::AudioChunk;
     use crate::config::{Mixer, MixerChannels, MixerMapping, MixerSource};
     use crate::mixer;
-    use crate::mixer::get_used_input_channels;
+    use crate::mixer::{
+        get_used_input_channels, opposite_polarity_sources, unmapped_destinations, validate_mixer,
+    };
 
     #[test]
     fn check_all_used() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
@@ -191,6 +251,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -202,25 +264,29 @@ mod tests {
     fn check_not_mapped() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
@@ -246,6 +312,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -257,25 +325,29 @@ mod tests {
     fn check_mute_source() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: true,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: true,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
@@ -301,6 +373,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -312,25 +386,29 @@ mod tests {
     fn check_mute_mapping() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: -3.0,
             inverted: false,
             mute: false,
@@ -356,6 +434,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -367,25 +447,29 @@ mod tests {
     fn check_make_mixer() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
@@ -411,6 +495,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -449,25 +535,29 @@ mod tests {
     fn check_make_mixer_muted() {
         let chans = MixerChannels { r#in: 2, out: 4 };
         let src0 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src1 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src2 = MixerSource {
-            channel: 0,
+            channel: Some(0),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
         };
         let src3 = MixerSource {
-            channel: 1,
+            channel: Some(1),
+            label: None,
             gain: 0.0,
             inverted: false,
             mute: false,
@@ -493,6 +583,8 @@ mod tests {
             mute: false,
         };
         let conf = Mixer {
+            description: None,
+            swap: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
         };
@@ -515,4 +607,200 @@ mod tests {
 
         assert_eq!(mix.mapping, exp_map);
     }
+
+    fn make_sum_config(mute_second: bool) -> Mixer {
+        let chans = MixerChannels { r#in: 2, out: 1 };
+        let src0 = MixerSource {
+            channel: Some(0),
+            label: None,
+            gain: 0.0,
+            inverted: false,
+            mute: false,
+        };
+        let src1 = MixerSource {
+            channel: Some(1),
+            label: None,
+            gain: 0.0,
+            inverted: false,
+            mute: mute_second,
+        };
+        let map0 = MixerMapping {
+            dest: 0,
+            sources: vec![src0, src1],
+            mute: false,
+        };
+        Mixer {
+            description: None,
+            swap: None,
+            channels: chans,
+            mapping: vec![map0],
+        }
+    }
+
+    #[test]
+    fn check_muted_source_is_silent() {
+        let input = AudioChunk::new(vec![vec![1.0, 2.0], vec![10.0, 20.0]], 20.0, 1.0, 2, 2);
+        let mut mix = mixer::Mixer::from_config("dummy".to_string(), make_sum_config(true));
+        let output = mix.process_chunk(&input);
+        assert_eq!(output.waveforms, vec![vec![1.0, 2.0]]);
+
+        // Unmuting and muting again via update_parameters
+        mix.update_parameters(make_sum_config(false));
+        let output = mix.process_chunk(&input);
+        assert_eq!(output.waveforms, vec![vec![11.0, 22.0]]);
+        mix.update_parameters(make_sum_config(true));
+        let output = mix.process_chunk(&input);
+        assert_eq!(output.waveforms, vec![vec![1.0, 2.0]]);
+    }
+
+    #[test]
+    fn check_many_loud_sources() {
+        // 32 sources at +40 dB, where the partial sums are far above what an f32 can hold
+        // but the total is not.
+        let nbr_sources = 32;
+        let sources = (0..nbr_sources)
+            .map(|channel| MixerSource {
+                channel: Some(channel),
+                label: None,
+                gain: 40.0,
+                inverted: false,
+                mute: false,
+            })
+            .collect();
+        let conf = Mixer {
+            description: None,
+            swap: None,
+            channels: MixerChannels {
+                r#in: nbr_sources,
+                out: 1,
+            },
+            mapping: vec![MixerMapping {
+                dest: 0,
+                sources,
+                mute: false,
+            }],
+        };
+        let waveforms = (0..nbr_sources)
+            .map(|channel| match channel {
+                0..=15 => vec![1.0e36; 4],
+                16..=30 => vec![-1.0e36; 4],
+                _ => vec![0.0; 4],
+            })
+            .collect();
+        let input = AudioChunk::new(waveforms, 1.0e36, -1.0e36, 4, 4);
+        let mut mix = mixer::Mixer::from_config("dummy".to_string(), conf);
+        let output = mix.process_chunk(&input);
+        for value in output.waveforms[0].iter() {
+            assert!(value.is_finite(), "{} is not finite", value);
+            assert!((value / 1.0e38 - 1.0).abs() < 1.0e-4, "{} != 1e38", value);
+            // With 64-bit processing the sum is as exact as the gain
+            #[cfg(not(feature = "32bit"))]
+            assert!((value / 1.0e38 - 1.0).abs() < 1.0e-12, "{} != 1e38", value);
+        }
+        // The summing buffer is reused for the next chunk
+        let output = mix.process_chunk(&input);
+        assert_eq!(output.waveforms[0].len(), 4);
+        assert!((output.waveforms[0][0] / 1.0e38 - 1.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn check_source_without_channel() {
+        let conf: Mixer = serde_yaml::from_str(
+            "
+channels:
+  in: 2
+  out: 1
+mapping:
+  - dest: 0
+    sources:
+      - gain: -3.0
+",
+        )
+        .unwrap();
+        assert_eq!(conf.mapping[0].sources[0].channel, None);
+        let err = validate_mixer(&conf).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Mixer source for destination channel 0 needs a channel or a label."
+        );
+    }
+
+    #[test]
+    fn check_unmapped_destination_is_silent() {
+        let chans = MixerChannels { r#in: 2, out: 3 };
+        let map0 = MixerMapping {
+            dest: 0,
+            sources: vec![MixerSource {
+                channel: Some(0),
+                label: None,
+                gain: 0.0,
+                inverted: false,
+                mute: false,
+            }],
+            mute: false,
+        };
+        let map2 = MixerMapping {
+            dest: 2,
+            sources: vec![MixerSource {
+                channel: Some(1),
+                label: None,
+                gain: 0.0,
+                inverted: false,
+                mute: false,
+            }],
+            mute: false,
+        };
+        let conf = Mixer {
+            description: None,
+            swap: None,
+            channels: chans,
+            mapping: vec![map0, map2],
+        };
+        assert_eq!(unmapped_destinations(&conf), vec![1]);
+        // Only a warning, the config is still valid
+        assert!(validate_mixer(&conf).is_ok());
+        let input = AudioChunk::new(vec![vec![1.0, 2.0], vec![10.0, 20.0]], 20.0, 1.0, 2, 2);
+        let mut mix = mixer::Mixer::from_config("dummy".to_string(), conf);
+        let output = mix.process_chunk(&input);
+        assert_eq!(
+            output.waveforms,
+            vec![vec![1.0, 2.0], vec![0.0, 0.0], vec![10.0, 20.0]]
+        );
+    }
+
+    #[test]
+    fn check_opposite_polarity() {
+        let chans = MixerChannels { r#in: 2, out: 1 };
+        let make_source = |channel, inverted, mute| MixerSource {
+            channel: Some(channel),
+            label: None,
+            gain: -6.0,
+            inverted,
+            mute,
+        };
+        let map0 = MixerMapping {
+            dest: 0,
+            sources: vec![
+                make_source(0, false, false),
+                make_source(1, false, false),
+                make_source(0, true, false),
+            ],
+            mute: false,
+        };
+        let mut conf = Mixer {
+            description: None,
+            swap: None,
+            channels: chans,
+            mapping: vec![map0],
+        };
+        assert_eq!(opposite_polarity_sources(&conf), vec![(0, 0)]);
+        // Only a warning, the config is still valid
+        assert!(validate_mixer(&conf).is_ok());
+        // A muted source can't cancel anything
+        conf.mapping[0].sources[2].mute = true;
+        assert!(opposite_polarity_sources(&conf).is_empty());
+        // A stereo to mono mix with one inverted channel is fine
+        conf.mapping[0].sources = vec![make_source(0, false, false), make_source(1, true, false)];
+        assert!(opposite_polarity_sources(&conf).is_empty());
+    }
 }
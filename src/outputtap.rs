@@ -0,0 +1,245 @@
+use crate::audiodevice::AudioChunk;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Condvar, Mutex};
+
+#[cfg(unix)]
+use crate::Res;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::thread;
+
+/// Number of frames each reader of the tap can lag behind before the oldest data is dropped.
+pub const TAP_BUFFER_FRAMES: usize = 16384;
+
+/// Bounded buffer of interleaved chunks waiting to be sent to one reader.
+struct TapBuffer {
+    chunks: VecDeque<(usize, Vec<u8>)>,
+    frames: usize,
+    capacity: usize,
+    dropped: usize,
+    closed: bool,
+}
+
+impl TapBuffer {
+    fn new(capacity: usize) -> Self {
+        TapBuffer {
+            chunks: VecDeque::new(),
+            frames: 0,
+            capacity,
+            dropped: 0,
+            closed: false,
+        }
+    }
+
+    /// Add a chunk, dropping the oldest chunks until the new one fits.
+    fn push(&mut self, frames: usize, data: Vec<u8>) {
+        while self.frames + frames > self.capacity {
+            match self.chunks.pop_front() {
+                Some((old_frames, _)) => {
+                    self.frames -= old_frames;
+                    self.dropped += old_frames;
+                }
+                None => break,
+            }
+        }
+        self.frames += frames;
+        self.chunks.push_back((frames, data));
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let (frames, data) = self.chunks.pop_front()?;
+        self.frames -= frames;
+        Some(data)
+    }
+}
+
+/// A connected reader of the tap.
+pub struct TapReader {
+    buffer: Mutex<TapBuffer>,
+    available: Condvar,
+}
+
+impl TapReader {
+    fn new(capacity: usize) -> Self {
+        TapReader {
+            buffer: Mutex::new(TapBuffer::new(capacity)),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Block until there is data to send, and return it.
+    /// Returns None when the reader has been closed.
+    fn next(&self) -> Option<Vec<u8>> {
+        let mut buffer = self.buffer.lock().unwrap();
+        loop {
+            if buffer.closed {
+                return None;
+            }
+            if let Some(data) = buffer.pop() {
+                return Some(data);
+            }
+            buffer = self.available.wait(buffer).unwrap();
+        }
+    }
+
+    /// Send data to the reader until writing fails or the reader is closed.
+    pub fn serve<W: Write>(&self, mut stream: W) {
+        while let Some(data) = self.next() {
+            if let Err(err) = stream.write_all(&data) {
+                debug!("Tap reader disconnected: {}", err);
+                break;
+            }
+        }
+        self.close();
+    }
+
+    /// Stop serving this reader.
+    pub fn close(&self) {
+        self.buffer.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    /// Number of frames dropped because the reader was too slow.
+    pub fn dropped_frames(&self) -> usize {
+        self.buffer.lock().unwrap().dropped
+    }
+}
+
+/// Tap of the processed output.
+/// Every chunk is copied to all connected readers as interleaved 32-bit little-endian floats.
+pub struct OutputTap {
+    readers: Mutex<Vec<Arc<TapReader>>>,
+    capacity: usize,
+}
+
+impl OutputTap {
+    pub fn new(capacity: usize) -> Self {
+        OutputTap {
+            readers: Mutex::new(Vec::new()),
+            capacity,
+        }
+    }
+
+    /// Register a new reader.
+    pub fn add_reader(&self) -> Arc<TapReader> {
+        let reader = Arc::new(TapReader::new(self.capacity));
+        self.readers.lock().unwrap().push(reader.clone());
+        reader
+    }
+
+    /// Copy the valid frames of a chunk to all readers, and forget the ones that have closed.
+    pub fn push(&self, chunk: &AudioChunk) {
+        let mut readers = self.readers.lock().unwrap();
+        readers.retain(|reader| !reader.buffer.lock().unwrap().closed);
+        if readers.is_empty() {
+            return;
+        }
+        let data = interleave(chunk);
+        for reader in readers.iter() {
+            reader
+                .buffer
+                .lock()
+                .unwrap()
+                .push(chunk.valid_frames, data.clone());
+            reader.available.notify_one();
+        }
+    }
+}
+
+/// Interleave the valid frames of a chunk as 32-bit little-endian floats.
+/// Unused channels, that have empty waveforms, are filled with zeros.
+fn interleave(chunk: &AudioChunk) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 * chunk.valid_frames * chunk.channels);
+    for frame in 0..chunk.valid_frames {
+        for waveform in chunk.waveforms.iter() {
+            let value = waveform.get(frame).copied().unwrap_or(0.0) as f32;
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    data
+}
+
+/// Serve the tap on a Unix domain socket at the given path.
+/// A stale socket file left at the path is removed.
+#[cfg(unix)]
+pub fn start_tap_server(path: &str, tap: Arc<OutputTap>) -> Res<()> {
+    if std::fs::metadata(path).is_ok() && UnixStream::connect(path).is_err() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    debug!("Output tap listening on {}", path);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let reader = tap.add_reader();
+                    debug!("New tap reader connected");
+                    thread::spawn(move || reader.serve(stream));
+                }
+                Err(err) => warn!("Tap connection failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::outputtap::OutputTap;
+    use crate::PrcFmt;
+    use std::io::Read;
+    use std::thread;
+
+    fn make_chunk(frames: usize, value: PrcFmt) -> AudioChunk {
+        AudioChunk::new(
+            vec![vec![value; frames], Vec::new()],
+            1.0,
+            -1.0,
+            frames,
+            frames,
+        )
+    }
+
+    fn to_floats(data: &[u8]) -> Vec<f32> {
+        data.chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let tap = OutputTap::new(8);
+        let reader = tap.add_reader();
+        tap.push(&make_chunk(4, 0.1));
+        tap.push(&make_chunk(4, 0.2));
+        tap.push(&make_chunk(4, 0.3));
+        assert_eq!(reader.dropped_frames(), 4);
+        let first = to_floats(&reader.next().unwrap());
+        assert_eq!(first, vec![0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2, 0.0]);
+        let second = to_floats(&reader.next().unwrap());
+        assert_eq!(second[0], 0.3);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn serve_socket() {
+        let tap = OutputTap::new(64);
+        let reader = tap.add_reader();
+        let (mut client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || reader.serve(server));
+        tap.push(&make_chunk(2, 0.5));
+        let mut buf = [0u8; 16];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(to_floats(&buf), vec![0.5, 0.0, 0.5, 0.0]);
+        drop(client);
+        // The write fails once the client is gone, which ends the serving thread
+        while !handle.is_finished() {
+            tap.push(&make_chunk(2, 0.5));
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        handle.join().unwrap();
+    }
+}
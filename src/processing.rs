@@ -1,37 +1,204 @@
+use crate::analysis;
 use crate::audiodevice::*;
 use crate::config;
+use crate::conversions;
+use crate::countertimer;
 use crate::filters;
+use crate::inputlog;
+use crate::PrcFmt;
 use crate::ProcessingParameters;
+use crate::StatusMessage;
+use crate::StatusStructs;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier, RwLock};
 use std::thread;
 
+/// Feed one channel of a chunk to the spectrum analyzer, and send any new spectrum.
+fn probe_chunk(
+    chunk: &AudioChunk,
+    channel: usize,
+    analyzer: &mut analysis::SpectrumAnalyzer,
+    tx_status: &mpsc::Sender<StatusMessage>,
+) {
+    let waveform = &chunk.waveforms[channel];
+    if waveform.is_empty() {
+        // An unused capture channel
+        return;
+    }
+    if let Some(spectrum) = analyzer.push(&waveform[0..chunk.valid_frames]) {
+        tx_status
+            .send(StatusMessage::Spectrum(spectrum))
+            .unwrap_or(());
+    }
+}
+
+/// Pick up a new or removed config B, and the A/B selection, from the processing parameters.
+/// Config B is only used if it has the same devices as the active config.
+fn update_ab_switch(
+    ab_switch: &mut Option<filters::AbSwitch>,
+    ab_changes: &mut usize,
+    devices: &config::Devices,
+    processing_status: &Arc<RwLock<ProcessingParameters>>,
+) {
+    let (changes, use_b) = {
+        let params = processing_status.read().unwrap();
+        (params.config_b_changes, params.use_config_b)
+    };
+    if changes != *ab_changes {
+        *ab_changes = changes;
+        let config_b = processing_status.read().unwrap().config_b.clone();
+        *ab_switch = match config_b {
+            Some(conf) if &conf.devices == devices => {
+                debug!("Building pipeline for config B");
+                let fade_frames = devices.ab_crossfade_ms * devices.samplerate / 1000;
+                let pipeline_b = filters::Pipeline::from_config(conf, processing_status.clone());
+                Some(filters::AbSwitch::new(pipeline_b, use_b, fade_frames))
+            }
+            Some(_) => {
+                warn!("Config B doesn't use the same devices as the active config, dropping it");
+                // Only config A is played, so B must not be reported as loaded or selected
+                let mut params = processing_status.write().unwrap();
+                params.config_b = None;
+                params.use_config_b = false;
+                None
+            }
+            None => None,
+        };
+    }
+    if let Some(ab) = ab_switch.as_mut() {
+        ab.select(use_b);
+    }
+}
+
 pub fn run_processing(
     conf_proc: config::Configuration,
     barrier_proc: Arc<Barrier>,
     tx_pb: mpsc::SyncSender<AudioMessage>,
     rx_cap: mpsc::Receiver<AudioMessage>,
     rx_pipeconf: mpsc::Receiver<(config::ConfigChange, config::Configuration)>,
-    processing_status: Arc<RwLock<ProcessingParameters>>,
+    tx_status: mpsc::Sender<StatusMessage>,
+    status_structs: StatusStructs,
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
+        let processing_status = status_structs.processing;
+        let mut ramp = PlaybackRamp::new(
+            conf_proc.devices.samplerate,
+            conf_proc.devices.fadein_ms,
+            conf_proc.devices.fadeout_ms,
+        );
+        let crossfade_frames =
+            conf_proc.devices.reload_crossfade_ms * conf_proc.devices.samplerate / 1000;
+        let mut crossfade: Option<filters::Crossfade> = None;
+        let clip_limit = conf_proc
+            .devices
+            .output_clip_db
+            .map(|level| (10.0 as PrcFmt).powf(level / 20.0));
+        let mut probe = conf_proc.devices.probe.as_ref().map(|probe| {
+            (
+                probe.clone(),
+                analysis::SpectrumAnalyzer::new(probe.fft_size, &probe.window),
+            )
+        });
+        // Forget any spectrum from a previous config
+        status_structs.status.write().unwrap().spectrum.clear();
+        let playback_status = status_structs.playback.clone();
+        let mut clip_output = |mut chunk: AudioChunk| {
+            if let Some(limit) = clip_limit {
+                let clipped = conversions::clamp_chunk(&mut chunk, limit);
+                if clipped.total() > 0 {
+                    playback_status.write().unwrap().add_clipped(&clipped);
+                }
+            }
+            chunk
+        };
+        let mut input_log =
+            conf_proc.devices.input_log.as_ref().and_then(
+                |filename| match inputlog::InputLog::new(
+                    filename,
+                    conf_proc.devices.capture.channels(),
+                    conf_proc.devices.samplerate,
+                ) {
+                    Ok(log) => Some(log),
+                    Err(err) => {
+                        error!("Could not start input log '{}', error: {}", filename, err);
+                        None
+                    }
+                },
+            );
+        let devices = conf_proc.devices.clone();
+        let mut ab_switch: Option<filters::AbSwitch> = None;
+        let mut ab_changes = 0;
         let mut pipeline = filters::Pipeline::from_config(conf_proc, processing_status.clone());
         debug!("build filters, waiting to start processing loop");
         barrier_proc.wait();
         debug!("Processing loop starts now!");
+        let mut timer = countertimer::Stopwatch::new();
+        let mut time_avg = countertimer::Averager::new();
         loop {
             match rx_cap.recv() {
                 Ok(AudioMessage::Audio(mut chunk)) => {
                     //trace!("AudioMessage::Audio received");
-                    chunk = pipeline.process_chunk(chunk);
-                    let msg = AudioMessage::Audio(chunk);
-                    if tx_pb.send(msg).is_err() {
-                        info!("Playback thread has already stopped.");
-                        break;
+                    if let Some(log) = input_log.as_mut() {
+                        log.push(&chunk);
+                    }
+                    if let Some((conf, analyzer)) = probe.as_mut() {
+                        if conf.point == config::ProbePoint::Capture {
+                            probe_chunk(&chunk, conf.channel, analyzer, &tx_status);
+                        }
+                    }
+                    update_ab_switch(
+                        &mut ab_switch,
+                        &mut ab_changes,
+                        &devices,
+                        &processing_status,
+                    );
+                    let chunk_b = ab_switch.as_mut().map(|ab| ab.process_b(&chunk));
+                    chunk = match crossfade.as_mut() {
+                        Some(fade) => fade.process_chunk(&mut pipeline, chunk),
+                        None => pipeline.process_chunk(chunk),
+                    };
+                    if let (Some(ab), Some(chunk_b)) = (ab_switch.as_mut(), chunk_b) {
+                        chunk = ab.select_output(chunk, chunk_b);
+                    }
+                    if crossfade.as_ref().map(|fade| fade.is_done()) == Some(true) {
+                        debug!("Crossfade to the new pipeline done.");
+                        crossfade = None;
+                    }
+                    if let Some(tap) = &status_structs.tap {
+                        tap.push(&chunk);
+                    }
+                    if let Some((conf, analyzer)) = probe.as_mut() {
+                        if conf.point == config::ProbePoint::Playback {
+                            probe_chunk(&chunk, conf.channel, analyzer, &tx_status);
+                        }
+                    }
+                    // Time from when the chunk was captured until it has been processed.
+                    // This includes any time spent waiting in the queue from the capture thread.
+                    time_avg.add_value(chunk.timestamp.elapsed().as_secs_f64());
+                    if timer.larger_than_millis(
+                        status_structs.capture.read().unwrap().update_interval as u64,
+                    ) {
+                        if let Some(av_time) = time_avg.get_average() {
+                            timer.restart();
+                            time_avg.restart();
+                            tx_status
+                                .send(StatusMessage::ProcessingTime(1000.0 * av_time as f32))
+                                .unwrap_or(());
+                        }
+                    }
+                    if let Some(chunk) = ramp.process(chunk).map(&mut clip_output) {
+                        let msg = AudioMessage::Audio(chunk);
+                        if tx_pb.send(msg).is_err() {
+                            info!("Playback thread has already stopped.");
+                            break;
+                        }
                     }
                 }
                 Ok(AudioMessage::EndOfStream) => {
                     trace!("AudioMessage::EndOfStream received");
+                    if let Some(chunk) = ramp.finish().map(&mut clip_output) {
+                        tx_pb.send(AudioMessage::Audio(chunk)).unwrap_or(());
+                    }
                     let msg = AudioMessage::EndOfStream;
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -40,6 +207,9 @@ pub fn run_processing(
                 }
                 Ok(AudioMessage::Pause) => {
                     trace!("AudioMessage::Pause received");
+                    if let Some(chunk) = ramp.flush().map(&mut clip_output) {
+                        tx_pb.send(AudioMessage::Audio(chunk)).unwrap_or(());
+                    }
                     let msg = AudioMessage::Pause;
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -48,6 +218,9 @@ pub fn run_processing(
                 }
                 Err(err) => {
                     error!("Message channel error: {}", err);
+                    if let Some(chunk) = ramp.finish().map(&mut clip_output) {
+                        tx_pb.send(AudioMessage::Audio(chunk)).unwrap_or(());
+                    }
                     let msg = AudioMessage::EndOfStream;
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -57,7 +230,31 @@ pub fn run_processing(
             }
             if let Ok((diff, new_config)) = rx_pipeconf.try_recv() {
                 trace!("Message received on config channel");
+                if matches!(
+                    diff,
+                    config::ConfigChange::Pipeline | config::ConfigChange::FilterParameters { .. }
+                ) {
+                    // A new config decides again which filters are bypassed
+                    processing_status.write().unwrap().clear_filter_bypasses();
+                }
                 match diff {
+                    config::ConfigChange::Pipeline
+                    | config::ConfigChange::MixerParameters
+                    | config::ConfigChange::FilterParameters { .. }
+                        if crossfade_frames > 0 =>
+                    {
+                        // Build a complete new pipeline also for parameter changes,
+                        // since the old filters are needed until the crossfade is done.
+                        debug!("Rebuilding pipeline with crossfade.");
+                        let new_pipeline =
+                            filters::Pipeline::from_config(new_config, processing_status.clone());
+                        let old_pipeline = std::mem::replace(&mut pipeline, new_pipeline);
+                        crossfade = Some(filters::Crossfade::new(
+                            old_pipeline,
+                            crossfade.take(),
+                            crossfade_frames,
+                        ));
+                    }
                     config::ConfigChange::Pipeline | config::ConfigChange::MixerParameters => {
                         debug!("Rebuilding pipeline.");
                         let new_pipeline =
@@ -56,6 +56,8 @@ pub struct PulsePlaybackDevice {
     pub chunksize: usize,
     pub channels: usize,
     pub sample_format: SampleFormat,
+    pub open_retries: usize,
+    pub retry_delay_ms: u64,
 }
 
 pub struct PulseCaptureDevice {
@@ -67,8 +69,12 @@ pub struct PulseCaptureDevice {
     pub chunksize: usize,
     pub channels: usize,
     pub sample_format: SampleFormat,
+    pub soft_start_ms: usize,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
+    pub open_retries: usize,
+    pub retry_delay_ms: u64,
 }
 
 /// Open a PulseAudio device
@@ -86,14 +92,7 @@ fn open_pulse(
         Direction::Playback
     };
 
-    let pulse_format = match sample_format {
-        SampleFormat::S16LE => sample::Format::S16le,
-        SampleFormat::S24LE => sample::Format::S24_32le,
-        SampleFormat::S24LE3 => sample::Format::S24le,
-        SampleFormat::S32LE => sample::Format::S32le,
-        SampleFormat::FLOAT32LE => sample::Format::F32le,
-        _ => panic!("invalid format"),
-    };
+    let pulse_format = pulse_sample_format(sample_format)?;
 
     let bytes_per_sample = sample_format.bytes_per_sample();
 
@@ -102,7 +101,13 @@ fn open_pulse(
         channels,
         rate: samplerate,
     };
-    //assert!(spec.is_valid());
+    if !spec.is_valid() {
+        let msg = format!(
+            "PulseAudio can't use format {:?} with {} channels at {} Hz",
+            sample_format, channels, samplerate
+        );
+        return Err(DeviceError::new(&msg).into());
+    }
     let attr = pulse::def::BufferAttr {
         maxlength: std::u32::MAX,
         tlength: std::u32::MAX,
@@ -110,20 +115,11 @@ fn open_pulse(
         minreq: std::u32::MAX,
         fragsize: bytes_per_sample as u32,
     };
+    debug!(
+        "Opening PulseAudio device '{}' with spec {:?} and buffer attributes {:?}",
+        devname, spec, attr
+    );
 
-    println!("DEBUG PULSE DEVICE");
-    println!("For spec: ");
-    println!("format  = {:?}", spec.format);
-    println!("channels  = {}", spec.channels);
-    println!("rate  = {:?}", spec.rate);
-    println!("For attr: ");
-    println!("maxlength  = {}", attr.maxlength);
-    println!("tlength  = {}", attr.tlength);
-    println!("prebuf  = {}", attr.prebuf);
-    println!("minreq  = {}", attr.minreq);
-    println!("fragsize  = {}", attr.fragsize);
-
-    
     let pulsedev_res = Simple::new(
         None,           // Use the default server
         "CamillaDSP",   // Our application’s name
@@ -134,12 +130,44 @@ fn open_pulse(
         None,           // Use default channel map
         Some(&attr),    // Use default buffering attributes
     );
-    println!("result = {:?}", pulsedev_res);
     match pulsedev_res {
-        Err(err) => Err(PulseError::new(&err).into()),
+        Err(err) => {
+            // Name the requested stream, since the PulseAudio error alone doesn't tell what was asked for
+            let msg = format!(
+                "Could not open PulseAudio device '{}' with format {:?}, {} channels at {} Hz. {}",
+                devname,
+                sample_format,
+                channels,
+                samplerate,
+                PulseError::new(&err)
+            );
+            Err(DeviceError::new(&msg).into())
+        }
         Ok(pulsedev) => Ok(pulsedev),
     }
-    
+}
+
+/// Get the PulseAudio sample format corresponding to a sample format.
+/// PulseAudio converts between the stream format and the format of the server,
+/// so any of these can be used regardless of what the server runs at.
+fn pulse_sample_format(sample_format: &SampleFormat) -> Res<sample::Format> {
+    match sample_format {
+        SampleFormat::S16LE => Ok(sample::Format::S16le),
+        SampleFormat::S24LE => Ok(sample::Format::S24_32le),
+        SampleFormat::S24LE3 => Ok(sample::Format::S24le),
+        SampleFormat::S32LE => Ok(sample::Format::S32le),
+        SampleFormat::FLOAT32LE => Ok(sample::Format::F32le),
+        SampleFormat::S16BE => Ok(sample::Format::S16be),
+        SampleFormat::S24BE => Ok(sample::Format::S24_32be),
+        SampleFormat::S32BE => Ok(sample::Format::S32be),
+        _ => {
+            let msg = format!(
+                "The PulseAudio backend does not support the {:?} sample format",
+                sample_format
+            );
+            Err(DeviceError::new(&msg).into())
+        }
+    }
 }
 
 /// Start a playback thread listening for AudioMessages via a channel.
@@ -157,23 +185,25 @@ impl PlaybackDevice for PulsePlaybackDevice {
         let channels = self.channels;
         let store_bytes_per_sample = self.sample_format.bytes_per_sample();
         let sample_format = self.sample_format.clone();
+        let open_retries = self.open_retries;
+        let retry_delay_ms = self.retry_delay_ms;
         let handle = thread::Builder::new()
             .name("PulsePlayback".to_string())
             .spawn(move || {
-                match open_pulse(
-                    devname,
-                    samplerate as u32,
-                    channels as u8,
-                    &sample_format,
-                    false,
-                ) {
+                match open_with_retries(open_retries, retry_delay_ms, || {
+                    open_pulse(
+                        devname.clone(),
+                        samplerate as u32,
+                        channels as u8,
+                        &sample_format,
+                        false,
+                    )
+                }) {
                     Ok(pulsedevice) => {
-                        println!("break point 2");
                         match status_channel.send(StatusMessage::PlaybackReady) {
                             Ok(()) => {}
                             Err(_err) => {}
                         }
-                        println!("break point 3");
                         let mut conversion_result;
                         let mut chunk_stats;
                         let bytes_per_frame = channels * store_bytes_per_sample;
@@ -209,9 +239,11 @@ impl PlaybackDevice for PulsePlaybackDevice {
                                                 .unwrap();
                                         }
                                     };
-                                    if conversion_result.1 > 0 {
-                                        playback_status.write().unwrap().clipped_samples +=
-                                            conversion_result.1;
+                                    if conversion_result.1.total() > 0 {
+                                        playback_status
+                                            .write()
+                                            .unwrap()
+                                            .add_clipped(&conversion_result.1);
                                     }
                                     chunk_stats = chunk.get_stats();
                                     playback_status.write().unwrap().signal_rms =
@@ -303,7 +335,11 @@ impl CaptureDevice for PulseCaptureDevice {
         let resampler_conf = self.resampler_conf.clone();
         let async_src = resampler_is_async(&resampler_conf);
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
+        let soft_start_ms = self.soft_start_ms;
+        let open_retries = self.open_retries;
+        let retry_delay_ms = self.retry_delay_ms;
         let handle = thread::Builder::new()
             .name("PulseCapture".to_string())
             .spawn(move || {
@@ -319,13 +355,15 @@ impl CaptureDevice for PulseCaptureDevice {
                 } else {
                     None
                 };
-                match open_pulse(
-                    devname,
-                    capture_samplerate as u32,
-                    channels as u8,
-                    &sample_format,
-                    true,
-                ) {
+                match open_with_retries(open_retries, retry_delay_ms, || {
+                    open_pulse(
+                        devname.clone(),
+                        capture_samplerate as u32,
+                        channels as u8,
+                        &sample_format,
+                        true,
+                    )
+                }) {
                     Ok(pulsedevice) => {
                         match status_channel.send(StatusMessage::CaptureReady) {
                             Ok(()) => {}
@@ -337,7 +375,8 @@ impl CaptureDevice for PulseCaptureDevice {
                         let chunksize_bytes = channels * chunksize * store_bytes_per_sample;
                         let mut capture_bytes = chunksize_bytes;
                         let mut averager = countertimer::TimeAverage::new();
-                        let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
+                        let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, &silence_mode, capture_samplerate, chunksize);
+                        let mut soft_start = CaptureSoftStart::new(capture_samplerate, soft_start_ms, channels);
                         let mut value_range = 0.0;
                         let mut rate_adjust = 0.0;
                         let mut state = ProcessingState::Running;
@@ -411,6 +450,7 @@ impl CaptureDevice for PulseCaptureDevice {
                                 }
                             };
                             let mut chunk = buffer_to_chunk_rawbytes(&buf[0..capture_bytes],channels, &sample_format, capture_bytes, &capture_status.read().unwrap().used_channels);
+                            soft_start.process(&mut chunk);
                             chunk_stats = chunk.get_stats();
                             //trace!("Capture signal rms {:?}, peak {:?}", chunk_stats.rms_db(), chunk_stats.peak_db());
                             value_range = chunk.maxval - chunk.minval;
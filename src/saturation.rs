@@ -0,0 +1,103 @@
+use crate::config;
+use crate::filters::Filter;
+
+use crate::NewValue;
+use crate::PrcFmt;
+use crate::Res;
+
+#[derive(Clone, Debug)]
+pub struct Saturation {
+    pub name: String,
+    drive: PrcFmt,
+}
+
+impl Saturation {
+    /// Creates a Saturation filter from a config struct
+    pub fn from_config(name: String, conf: config::SaturationParameters) -> Self {
+        debug!(
+            "Creating saturation '{}', drive: {} dB",
+            name, conf.drive_db
+        );
+        Saturation {
+            name,
+            drive: PrcFmt::new(10.0).powf(conf.drive_db / 20.0),
+        }
+    }
+}
+
+impl Filter for Saturation {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            *item = (self.drive * *item).tanh();
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::Saturation {
+            parameters: conf, ..
+        } = conf
+        {
+            self.drive = PrcFmt::new(10.0).powf(conf.drive_db / 20.0);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a Saturation config.
+pub fn validate_config(conf: &config::SaturationParameters) -> Res<()> {
+    if conf.drive_db < -20.0 {
+        return Err(config::ConfigError::new("Drive must be larger than -20 dB").into());
+    } else if conf.drive_db > 40.0 {
+        return Err(config::ConfigError::new("Drive must be less than +40 dB").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SaturationParameters;
+    use crate::filters::Filter;
+    use crate::saturation::{validate_config, Saturation};
+    use crate::PrcFmt;
+
+    #[test]
+    fn monotonic_and_bounded() {
+        let conf = SaturationParameters { drive_db: 12.0 };
+        let mut sat = Saturation::from_config("test".to_string(), conf);
+        let input: Vec<PrcFmt> = (-400..=400).map(|n| n as PrcFmt / 100.0).collect();
+        let mut output = input.clone();
+        sat.process_waveform(&mut output).unwrap();
+        for pair in output.windows(2) {
+            assert!(pair[1] >= pair[0], "{} is lower than {}", pair[1], pair[0]);
+        }
+        assert!(output.iter().all(|val| val.abs() <= 1.0));
+        // Zero in gives zero out, and the curve is symmetric
+        assert_eq!(output[400], 0.0);
+        assert_eq!(output[0], -output[800]);
+    }
+
+    #[test]
+    fn small_signal_gain() {
+        let conf = SaturationParameters { drive_db: 6.0 };
+        let mut sat = Saturation::from_config("test".to_string(), conf);
+        let mut waveform = vec![0.001, -0.001];
+        sat.process_waveform(&mut waveform).unwrap();
+        let gain = (10.0 as PrcFmt).powf(6.0 / 20.0);
+        assert!((waveform[0] - 0.001 * gain).abs() < 1e-8);
+        assert!((waveform[1] + 0.001 * gain).abs() < 1e-8);
+    }
+
+    #[test]
+    fn check_config() {
+        assert!(validate_config(&SaturationParameters { drive_db: 0.0 }).is_ok());
+        assert!(validate_config(&SaturationParameters { drive_db: -30.0 }).is_err());
+        assert!(validate_config(&SaturationParameters { drive_db: 50.0 }).is_err());
+    }
+}
@@ -2,10 +2,12 @@ use clap::crate_version;
 #[cfg(feature = "secure-websocket")]
 use native_tls::{Identity, TlsAcceptor, TlsStream};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 #[cfg(feature = "secure-websocket")]
 use std::fs::File;
 #[cfg(feature = "secure-websocket")]
 use std::io::Read;
+use std::io::{BufRead, BufReader, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
@@ -16,11 +18,12 @@ use tungstenite::WebSocket;
 
 use crate::config;
 use crate::ExitRequest;
+use crate::PrcFmt;
 use crate::ProcessingState;
 use crate::Res;
 use crate::{
-    list_supported_devices, CaptureStatus, PlaybackStatus, ProcessingParameters, ProcessingStatus,
-    StopReason,
+    list_supported_devices, CaptureStatus, ClippedChannels, PlaybackStatus, ProcessingParameters,
+    ProcessingStatus, StopReason, XrunCounts, PROCESSING_BITS,
 };
 
 #[derive(Debug, Clone)]
@@ -65,6 +68,8 @@ enum WsCommand {
     GetCaptureSignalPeak,
     GetPlaybackSignalRms,
     GetPlaybackSignalPeak,
+    GetCaptureSignalLevels,
+    GetPlaybackSignalLevels,
     GetCaptureRate,
     GetUpdateInterval,
     SetUpdateInterval(usize),
@@ -72,13 +77,29 @@ enum WsCommand {
     SetVolume(f32),
     GetMute,
     SetMute(bool),
+    GetFilterBypass(String),
+    GetFilterBypasses,
+    SetFilterBypass(String, bool),
+    GetMixerGains(String),
+    SetConfigB(String),
+    ClearConfigB,
+    GetConfigB,
+    GetUseConfigB,
+    SetUseConfigB(bool),
     GetVersion,
     GetState,
     GetStopReason,
     GetRateAdjust,
     GetClippedSamples,
+    GetClippedChannels,
+    ResetClippedSamples,
     GetBufferLevel,
+    GetProcessingTime,
+    GetPlaybackLatency,
+    GetXruns,
+    GetSpectrum,
     GetSupportedDeviceTypes,
+    GetProcessingPrecision,
     Exit,
     Stop,
     None,
@@ -90,6 +111,21 @@ enum WsResult {
     Error,
 }
 
+#[derive(Debug, PartialEq, Serialize)]
+struct SignalLevels {
+    rms: Vec<f32>,
+    peak: Vec<f32>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct MixerGain {
+    dest: usize,
+    channel: Option<usize>,
+    gain: PrcFmt,
+    inverted: bool,
+    mute: bool,
+}
+
 #[derive(Debug, PartialEq, Serialize)]
 enum WsReply {
     SetConfigName {
@@ -152,6 +188,14 @@ enum WsReply {
         result: WsResult,
         value: Vec<f32>,
     },
+    GetCaptureSignalLevels {
+        result: WsResult,
+        value: SignalLevels,
+    },
+    GetPlaybackSignalLevels {
+        result: WsResult,
+        value: SignalLevels,
+    },
     GetCaptureRate {
         result: WsResult,
         value: usize,
@@ -177,6 +221,38 @@ enum WsReply {
         result: WsResult,
         value: bool,
     },
+    GetFilterBypass {
+        result: WsResult,
+        value: bool,
+    },
+    GetFilterBypasses {
+        result: WsResult,
+        value: BTreeMap<String, bool>,
+    },
+    SetFilterBypass {
+        result: WsResult,
+    },
+    GetMixerGains {
+        result: WsResult,
+        value: Vec<MixerGain>,
+    },
+    SetConfigB {
+        result: WsResult,
+    },
+    ClearConfigB {
+        result: WsResult,
+    },
+    GetConfigB {
+        result: WsResult,
+        value: String,
+    },
+    GetUseConfigB {
+        result: WsResult,
+        value: bool,
+    },
+    SetUseConfigB {
+        result: WsResult,
+    },
     GetVersion {
         result: WsResult,
         value: String,
@@ -201,10 +277,37 @@ enum WsReply {
         result: WsResult,
         value: usize,
     },
+    GetClippedChannels {
+        result: WsResult,
+        value: ClippedChannels,
+    },
+    ResetClippedSamples {
+        result: WsResult,
+    },
+    GetProcessingTime {
+        result: WsResult,
+        value: f32,
+    },
+    GetPlaybackLatency {
+        result: WsResult,
+        value: f32,
+    },
+    GetXruns {
+        result: WsResult,
+        value: XrunCounts,
+    },
+    GetSpectrum {
+        result: WsResult,
+        value: Vec<f32>,
+    },
     GetSupportedDeviceTypes {
         result: WsResult,
         value: (Vec<String>, Vec<String>),
     },
+    GetProcessingPrecision {
+        result: WsResult,
+        value: usize,
+    },
     Exit {
         result: WsResult,
     },
@@ -218,14 +321,16 @@ enum WsReply {
 
 fn parse_command(cmd: Message) -> Res<WsCommand> {
     match cmd {
-        Message::Text(command_str) => {
-            let command = serde_json::from_str::<WsCommand>(&command_str)?;
-            Ok(command)
-        }
+        Message::Text(command_str) => parse_command_str(&command_str),
         _ => Ok(WsCommand::None),
     }
 }
 
+fn parse_command_str(command_str: &str) -> Res<WsCommand> {
+    let command = serde_json::from_str::<WsCommand>(command_str)?;
+    Ok(command)
+}
+
 #[cfg(feature = "secure-websocket")]
 fn make_acceptor_with_cert(cert: &str, key: &str) -> Res<Arc<TlsAcceptor>> {
     let mut file = File::open(cert)?;
@@ -292,6 +397,69 @@ pub fn start_server(parameters: ServerParameters, shared_data: SharedData) {
     });
 }
 
+/// Start a plain TCP server, that takes the same commands as the websocket server.
+/// Each command is sent as JSON on a single line, and each reply is returned as a single line.
+pub fn start_tcp_server(address: &str, port: usize, shared_data: SharedData) {
+    let address = address.to_string();
+    debug!("Start TCP server on {}:{}", address, port);
+    thread::spawn(move || {
+        let tcp_result = TcpListener::bind(format!("{}:{}", address, port));
+        match tcp_result {
+            Ok(server) => {
+                for stream in server.incoming() {
+                    let shared_data_inst = shared_data.clone();
+                    thread::spawn(move || match stream {
+                        Ok(stream) => handle_plain_tcp(stream, &shared_data_inst),
+                        Err(err) => warn!("Connection failed: {}", err),
+                    });
+                }
+            }
+            Err(err) => error!("Failed to start TCP server: {}", err),
+        }
+    });
+}
+
+fn handle_plain_tcp(stream: TcpStream, shared_data_inst: &SharedData) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            warn!("Connection failed: {}", err);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Lost connection: {}", err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        trace!("received: {}", line);
+        let command = parse_command_str(&line);
+        debug!("parsed command: {:?}", command);
+        let reply = match command {
+            Ok(cmd) => handle_command(cmd, shared_data_inst),
+            Err(err) => Some(WsReply::Invalid {
+                error: err.to_string(),
+            }),
+        };
+        if let Some(rep) = reply {
+            let reply_str = serde_json::to_string(&rep).unwrap() + "\n";
+            if let Err(err) = writer.write_all(reply_str.as_bytes()) {
+                warn!("Failed to write: {}", err);
+                break;
+            }
+        } else {
+            debug!("Sending no reply");
+        }
+    }
+    debug!("Connection was closed");
+}
+
 macro_rules! make_handler {
     ($t:ty, $n:ident) => {
         fn $n(websocket_res: Res<WebSocket<$t>>, shared_data_inst: &SharedData) {
@@ -357,6 +525,72 @@ fn accept_plain_stream(
     Ok(ws)
 }
 
+/// Parse and validate a config B, that must use the same devices as the active config.
+fn load_config_b(shared_data_inst: &SharedData, config_yml: &str) -> Res<config::Configuration> {
+    let mut conf = serde_yaml::from_str::<config::Configuration>(config_yml)?;
+    config::validate_config(&mut conf, None)?;
+    match &*shared_data_inst.active_config.lock().unwrap() {
+        Some(active) if active.devices == conf.devices => Ok(conf),
+        Some(_) => Err(config::ConfigError::new(
+            "Config B must use the same devices as the active config",
+        )
+        .into()),
+        None => Err(config::ConfigError::new("There is no active config to compare with").into()),
+    }
+}
+
+/// Get the bypass of a filter in the active config, from the runtime setting if there is one.
+/// Returns None if there is no filter with this name.
+fn filter_bypass(shared_data_inst: &SharedData, name: &str) -> Option<bool> {
+    let active_config = shared_data_inst.active_config.lock().unwrap();
+    let in_config = active_config.as_ref()?.filters.get(name)?.is_bypassed();
+    let procstat = shared_data_inst.processing_status.read().unwrap();
+    Some(*procstat.bypassed_filters.get(name).unwrap_or(&in_config))
+}
+
+/// Get the bypass of all filters in the active config, with the runtime settings applied.
+fn filter_bypasses(shared_data_inst: &SharedData) -> BTreeMap<String, bool> {
+    let active_config = shared_data_inst.active_config.lock().unwrap();
+    let procstat = shared_data_inst.processing_status.read().unwrap();
+    match active_config.as_ref() {
+        Some(conf) => conf
+            .filters
+            .iter()
+            .map(|(name, filter)| {
+                let bypassed = procstat
+                    .bypassed_filters
+                    .get(name)
+                    .copied()
+                    .unwrap_or_else(|| filter.is_bypassed());
+                (name.clone(), bypassed)
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    }
+}
+
+/// Get the gains of all sources of a mixer in the active config.
+/// A source is reported as muted if either the source or its mapping is muted.
+/// Returns None if there is no mixer with this name.
+fn mixer_gains(shared_data_inst: &SharedData, name: &str) -> Option<Vec<MixerGain>> {
+    let active_config = shared_data_inst.active_config.lock().unwrap();
+    let mixer = active_config.as_ref()?.mixers.get(name)?;
+    let gains = mixer
+        .mapping
+        .iter()
+        .flat_map(|mapping| {
+            mapping.sources.iter().map(move |source| MixerGain {
+                dest: mapping.dest,
+                channel: source.channel,
+                gain: source.gain,
+                inverted: source.inverted,
+                mute: mapping.mute || source.mute,
+            })
+        })
+        .collect();
+    Some(gains)
+}
+
 fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<WsReply> {
     match command {
         WsCommand::Reload => {
@@ -409,6 +643,26 @@ fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<W
                 value: pbstat.signal_peak.clone(),
             })
         }
+        WsCommand::GetCaptureSignalLevels => {
+            let capstat = shared_data_inst.capture_status.read().unwrap();
+            Some(WsReply::GetCaptureSignalLevels {
+                result: WsResult::Ok,
+                value: SignalLevels {
+                    rms: capstat.signal_rms.clone(),
+                    peak: capstat.signal_peak.clone(),
+                },
+            })
+        }
+        WsCommand::GetPlaybackSignalLevels => {
+            let pbstat = shared_data_inst.playback_status.read().unwrap();
+            Some(WsReply::GetPlaybackSignalLevels {
+                result: WsResult::Ok,
+                value: SignalLevels {
+                    rms: pbstat.signal_rms.clone(),
+                    peak: pbstat.signal_peak.clone(),
+                },
+            })
+        }
         WsCommand::GetVersion => Some(WsReply::GetVersion {
             result: WsResult::Ok,
             value: crate_version!().to_string(),
@@ -442,6 +696,23 @@ fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<W
                 value: pbstat.clipped_samples,
             })
         }
+        WsCommand::GetClippedChannels => {
+            let pbstat = shared_data_inst.playback_status.read().unwrap();
+            Some(WsReply::GetClippedChannels {
+                result: WsResult::Ok,
+                value: pbstat.clipped_channels.clone(),
+            })
+        }
+        WsCommand::ResetClippedSamples => {
+            shared_data_inst
+                .playback_status
+                .write()
+                .unwrap()
+                .reset_clipped();
+            Some(WsReply::ResetClippedSamples {
+                result: WsResult::Ok,
+            })
+        }
         WsCommand::GetBufferLevel => {
             let pbstat = shared_data_inst.playback_status.read().unwrap();
             Some(WsReply::GetBufferLevel {
@@ -449,6 +720,31 @@ fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<W
                 value: pbstat.buffer_level,
             })
         }
+        WsCommand::GetProcessingTime => {
+            let stat = shared_data_inst.status.read().unwrap();
+            Some(WsReply::GetProcessingTime {
+                result: WsResult::Ok,
+                value: stat.processing_time,
+            })
+        }
+        WsCommand::GetPlaybackLatency => {
+            let stat = shared_data_inst.status.read().unwrap();
+            Some(WsReply::GetPlaybackLatency {
+                result: WsResult::Ok,
+                value: stat.playback_latency,
+            })
+        }
+        WsCommand::GetXruns => Some(WsReply::GetXruns {
+            result: WsResult::Ok,
+            value: shared_data_inst.status.read().unwrap().xruns.clone(),
+        }),
+        WsCommand::GetSpectrum => {
+            let stat = shared_data_inst.status.read().unwrap();
+            Some(WsReply::GetSpectrum {
+                result: WsResult::Ok,
+                value: stat.spectrum.clone(),
+            })
+        }
         WsCommand::GetUpdateInterval => {
             let capstat = shared_data_inst.capture_status.read().unwrap();
             Some(WsReply::GetUpdateInterval {
@@ -499,6 +795,91 @@ fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<W
                 result: WsResult::Ok,
             })
         }
+        WsCommand::SetConfigB(config_yml) => {
+            let result = match load_config_b(shared_data_inst, &config_yml) {
+                Ok(conf) => {
+                    let mut procstat = shared_data_inst.processing_status.write().unwrap();
+                    procstat.config_b = Some(conf);
+                    procstat.config_b_changes += 1;
+                    WsResult::Ok
+                }
+                Err(error) => {
+                    error!("Error setting config B: {}", error);
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetConfigB { result })
+        }
+        WsCommand::ClearConfigB => {
+            let mut procstat = shared_data_inst.processing_status.write().unwrap();
+            procstat.config_b = None;
+            procstat.config_b_changes += 1;
+            procstat.use_config_b = false;
+            Some(WsReply::ClearConfigB {
+                result: WsResult::Ok,
+            })
+        }
+        WsCommand::GetConfigB => Some(WsReply::GetConfigB {
+            result: WsResult::Ok,
+            value: serde_yaml::to_string(
+                &shared_data_inst.processing_status.read().unwrap().config_b,
+            )
+            .unwrap(),
+        }),
+        WsCommand::GetUseConfigB => {
+            let procstat = shared_data_inst.processing_status.read().unwrap();
+            Some(WsReply::GetUseConfigB {
+                result: WsResult::Ok,
+                value: procstat.use_config_b,
+            })
+        }
+        WsCommand::SetUseConfigB(use_b) => {
+            let mut procstat = shared_data_inst.processing_status.write().unwrap();
+            let result = if use_b && procstat.config_b.is_none() {
+                error!("Can't switch to config B, no config B has been loaded");
+                WsResult::Error
+            } else {
+                procstat.use_config_b = use_b;
+                WsResult::Ok
+            };
+            Some(WsReply::SetUseConfigB { result })
+        }
+        WsCommand::GetFilterBypass(name) => {
+            let bypassed = filter_bypass(shared_data_inst, &name);
+            Some(WsReply::GetFilterBypass {
+                result: if bypassed.is_some() {
+                    WsResult::Ok
+                } else {
+                    WsResult::Error
+                },
+                value: bypassed.unwrap_or(false),
+            })
+        }
+        WsCommand::SetFilterBypass(name, bypassed) => {
+            let result = if filter_bypass(shared_data_inst, &name).is_some() {
+                let mut procstat = shared_data_inst.processing_status.write().unwrap();
+                procstat.set_filter_bypass(name, bypassed);
+                WsResult::Ok
+            } else {
+                WsResult::Error
+            };
+            Some(WsReply::SetFilterBypass { result })
+        }
+        WsCommand::GetFilterBypasses => Some(WsReply::GetFilterBypasses {
+            result: WsResult::Ok,
+            value: filter_bypasses(shared_data_inst),
+        }),
+        WsCommand::GetMixerGains(name) => {
+            let gains = mixer_gains(shared_data_inst, &name);
+            Some(WsReply::GetMixerGains {
+                result: if gains.is_some() {
+                    WsResult::Ok
+                } else {
+                    WsResult::Error
+                },
+                value: gains.unwrap_or_default(),
+            })
+        }
         WsCommand::GetConfig => Some(WsReply::GetConfig {
             result: WsResult::Ok,
             value: serde_yaml::to_string(&*shared_data_inst.active_config.lock().unwrap()).unwrap(),
@@ -666,13 +1047,21 @@ fn handle_command(command: WsCommand, shared_data_inst: &SharedData) -> Option<W
                 value: devs,
             })
         }
+        WsCommand::GetProcessingPrecision => Some(WsReply::GetProcessingPrecision {
+            result: WsResult::Ok,
+            value: PROCESSING_BITS,
+        }),
         WsCommand::None => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::socketserver::{parse_command, WsCommand};
+    use crate::conversions::ClipCounter;
+    use crate::socketserver::{
+        parse_command, parse_command_str, MixerGain, SignalLevels, WsCommand, WsReply, WsResult,
+    };
+    use crate::{ClippedChannels, PlaybackStatus, XrunCounts};
     use tungstenite::Message;
 
     #[test]
@@ -690,4 +1079,124 @@ mod tests {
         let res = parse_command(cmd).unwrap();
         assert_eq!(res, WsCommand::SetConfigName("somefile".to_string()));
     }
+
+    #[test]
+    fn parse_command_strs() {
+        let res = parse_command_str("{\"SetVolume\": -10.0}").unwrap();
+        assert_eq!(res, WsCommand::SetVolume(-10.0));
+        let res = parse_command_str("\"GetVolume\"").unwrap();
+        assert_eq!(res, WsCommand::GetVolume);
+        assert!(parse_command_str("{\"SetVolume\": \"loud\"}").is_err());
+        let res = parse_command_str("{\"SetFilterBypass\": [\"eq\", true]}").unwrap();
+        assert_eq!(res, WsCommand::SetFilterBypass("eq".to_string(), true));
+        let res = parse_command_str("{\"GetMixerGains\": \"stereo\"}").unwrap();
+        assert_eq!(res, WsCommand::GetMixerGains("stereo".to_string()));
+    }
+
+    #[test]
+    fn serialize_xruns() {
+        let res = parse_command_str("\"GetXruns\"").unwrap();
+        assert_eq!(res, WsCommand::GetXruns);
+        let reply = WsReply::GetXruns {
+            result: WsResult::Ok,
+            value: XrunCounts {
+                playback: 2,
+                capture: 0,
+                last_playback_time: 1000.5,
+                last_capture_time: 0.0,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"GetXruns\":{\"result\":\"Ok\",\"value\":{\"playback\":2,\"capture\":0,\"last_playback_time\":1000.5,\"last_capture_time\":0.0}}}"
+        );
+    }
+
+    #[test]
+    fn serialize_processing_precision() {
+        let res = parse_command_str("\"GetProcessingPrecision\"").unwrap();
+        assert_eq!(res, WsCommand::GetProcessingPrecision);
+        let reply = WsReply::GetProcessingPrecision {
+            result: WsResult::Ok,
+            value: 64,
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"GetProcessingPrecision\":{\"result\":\"Ok\",\"value\":64}}"
+        );
+    }
+
+    #[test]
+    fn serialize_clipped_channels() {
+        let res = parse_command_str("\"GetClippedChannels\"").unwrap();
+        assert_eq!(res, WsCommand::GetClippedChannels);
+        let reply = WsReply::GetClippedChannels {
+            result: WsResult::Ok,
+            value: ClippedChannels {
+                samples: vec![0, 12],
+                peak: vec![0.0, 1.5],
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"GetClippedChannels\":{\"result\":\"Ok\",\"value\":{\"samples\":[0,12],\"peak\":[0.0,1.5]}}}"
+        );
+    }
+
+    #[test]
+    fn reset_clipped_samples() {
+        let res = parse_command_str("\"ResetClippedSamples\"").unwrap();
+        assert_eq!(res, WsCommand::ResetClippedSamples);
+        let mut status = PlaybackStatus {
+            update_interval: 1000,
+            clipped_samples: 0,
+            clipped_channels: ClippedChannels::default(),
+            buffer_level: 0,
+            signal_rms: Vec::new(),
+            signal_peak: Vec::new(),
+        };
+        status.add_clipped(&ClipCounter {
+            clipped: vec![0, 3],
+            peak: vec![0.0, 1.5],
+        });
+        assert_eq!(status.clipped_samples, 3);
+        status.reset_clipped();
+        assert_eq!(status.clipped_samples, 0);
+        assert_eq!(status.clipped_channels, ClippedChannels::default());
+    }
+
+    #[test]
+    fn serialize_mixer_gains() {
+        let reply = WsReply::GetMixerGains {
+            result: WsResult::Ok,
+            value: vec![MixerGain {
+                dest: 0,
+                channel: Some(1),
+                gain: -6.0,
+                inverted: false,
+                mute: true,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"GetMixerGains\":{\"result\":\"Ok\",\"value\":[{\"dest\":0,\"channel\":1,\"gain\":-6.0,\"inverted\":false,\"mute\":true}]}}"
+        );
+    }
+
+    #[test]
+    fn serialize_signal_levels() {
+        let res = parse_command_str("\"GetPlaybackSignalLevels\"").unwrap();
+        assert_eq!(res, WsCommand::GetPlaybackSignalLevels);
+        let reply = WsReply::GetPlaybackSignalLevels {
+            result: WsResult::Ok,
+            value: SignalLevels {
+                rms: vec![-20.0, -30.0],
+                peak: vec![-10.0, -12.5],
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"GetPlaybackSignalLevels\":{\"result\":\"Ok\",\"value\":{\"rms\":[-20.0,-30.0],\"peak\":[-10.0,-12.5]}}}"
+        );
+    }
 }
@@ -54,6 +54,7 @@ pub struct WasapiCaptureDevice {
     pub sample_format: SampleFormat,
     pub silence_threshold: PrcFmt,
     pub silence_timeout: PrcFmt,
+    pub silence_mode: config::SilenceMode,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
 }
@@ -720,9 +721,11 @@ impl PlaybackDevice for WasapiPlaybackDevice {
                                     break;
                                 }
                             }
-                            if conversion_result.1 > 0 {
-                                playback_status.write().unwrap().clipped_samples +=
-                                    conversion_result.1;
+                            if conversion_result.1.total() > 0 {
+                                playback_status
+                                    .write()
+                                    .unwrap()
+                                    .add_clipped(&conversion_result.1);
                             }
                         }
                         Ok(AudioMessage::Pause) => {
@@ -842,6 +845,7 @@ impl CaptureDevice for WasapiCaptureDevice {
         let resampler_conf = self.resampler_conf.clone();
         let async_src = resampler_is_async(&resampler_conf);
         let silence_timeout = self.silence_timeout;
+        let silence_mode = self.silence_mode.clone();
         let silence_threshold = self.silence_threshold;
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = (1000.0 * self.rate_measure_interval) as u64;
@@ -927,7 +931,7 @@ impl CaptureDevice for WasapiCaptureDevice {
                 let mut value_range = 0.0;
                 let mut chunk_stats;
                 let mut rate_adjust = 0.0;
-                let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
+                let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, &silence_mode, capture_samplerate, chunksize);
                 let mut state = ProcessingState::Running;
                 let mut saved_state = state;
                 let blockalign = bytes_per_sample*channels;